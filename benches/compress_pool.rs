@@ -0,0 +1,150 @@
+//! Latency of compression under load, inline and on the `--compress-workers` pool.
+//!
+//! A TX task is offered packets at a fixed rate for `RUN`. Inline, it compresses each
+//! before reading the next, so a slow compressor delays every packet behind it, and
+//! what it hasn't read by the end is lost. With the pool it queues them for the
+//! workers, sends them in order as they come back, and sends raw what the queue turns
+//! away. Latency runs from a packet's arrival to its payload being ready to seal.
+//!
+//! `cargo bench --bench compress_pool`
+
+// The modules are the tunnel's own; this only drives part of them.
+#![allow(dead_code)]
+
+#[path = "../src/compress_pool.rs"]
+mod compress_pool;
+#[path = "../src/compression.rs"]
+mod compression;
+#[path = "../src/dictionary.rs"]
+mod dictionary;
+
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+
+use compress_pool::CompressPool;
+use compression::{Algorithm, Codec, Outcome, Policy};
+
+/// How long packets are offered, and the gap between them (1200-byte packets: about 96 Mbit/s).
+const RUN: Duration = Duration::from_secs(2);
+const GAP: Duration = Duration::from_micros(100);
+const PACKETS: usize = (RUN.as_micros() / GAP.as_micros()) as usize;
+const PACKET_LEN: usize = 1200;
+
+/// HTTP-like text, different in every packet.
+fn packets() -> Vec<Vec<u8>> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    (0..PACKETS).map(|_| {
+        let mut packet = Vec::with_capacity(PACKET_LEN);
+        while packet.len() < PACKET_LEN {
+            let line = format!("GET /item/{}?page={} HTTP/1.1\r\nHost: cdn{}.example.com\r\nCookie: id={:x}\r\n\r\n",
+                rng.gen_range(0..100_000), rng.gen_range(0..50), rng.gen_range(0..8), rng.gen::<u32>());
+            packet.extend_from_slice(line.as_bytes());
+        }
+        packet.truncate(PACKET_LEN);
+        packet
+    }).collect()
+}
+
+/// Waits for `at`: sleeps while it is far off, then yields so the workers get the CPU.
+fn wait_until(at: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= at {
+            return;
+        }
+        if at - now > Duration::from_micros(500) {
+            thread::sleep(at - now - Duration::from_micros(200));
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+struct Run {
+    /// Arrival to payload ready, per packet, in order.
+    latencies: Vec<Duration>,
+    compressed: usize,
+}
+
+fn inline(codec: &Codec, packets: &[Vec<u8>]) -> Run {
+    let start = Instant::now();
+    let mut run = Run { latencies: Vec::with_capacity(packets.len()), compressed: 0 };
+    for (i, packet) in packets.iter().enumerate() {
+        if start.elapsed() >= RUN {
+            break;
+        }
+        let arrival = start + GAP * i as u32;
+        wait_until(arrival);
+        let (_, outcome) = codec.compress(packet);
+        run.compressed += usize::from(outcome == Outcome::Compressed);
+        run.latencies.push(arrival.elapsed());
+    }
+    run
+}
+
+fn pooled(codec: &Arc<Codec>, workers: usize, packets: &[Vec<u8>]) -> Run {
+    let pool = CompressPool::start(codec.clone(), workers);
+    // The sending half: takes results in the order the packets were read, as the TX task does.
+    let (order, queued) = mpsc::channel::<(Instant, Option<tokio::sync::oneshot::Receiver<compress_pool::Compressed>>)>();
+    let sender = thread::spawn(move || {
+        let mut run = Run { latencies: Vec::new(), compressed: 0 };
+        for (arrival, result) in queued {
+            // Turned away by a full queue: sent raw at once.
+            if let Some(Ok((_, outcome))) = result.map(|result| result.blocking_recv()) {
+                run.compressed += usize::from(outcome == Outcome::Compressed);
+            }
+            run.latencies.push(arrival.elapsed());
+        }
+        run
+    });
+    let start = Instant::now();
+    for (i, packet) in packets.iter().enumerate() {
+        if start.elapsed() >= RUN {
+            break;
+        }
+        let arrival = start + GAP * i as u32;
+        wait_until(arrival);
+        let _ = order.send((arrival, pool.submit(packet)));
+    }
+    drop(order);
+    sender.join().expect("sender thread panicked")
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn report(level: i32, workers: usize, run: Run) {
+    let mut sorted = run.latencies;
+    sorted.sort_unstable();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    println!("{:>5} {:>7} {:>5.1}% {:>10.0}% {:>9.2} {:>9.2} {:>9.2}",
+        level,
+        workers,
+        100.0 * sorted.len() as f64 / PACKETS as f64,
+        100.0 * run.compressed as f64 / sorted.len() as f64,
+        ms(percentile(&sorted, 0.5)),
+        ms(percentile(&sorted, 0.99)),
+        ms(*sorted.last().unwrap()));
+}
+
+fn main() {
+    let packets = packets();
+    println!("{} packets of {} bytes every {} us for {} s ({:.0} Mbit/s offered), {} CPU(s)",
+        PACKETS, PACKET_LEN, GAP.as_micros(), RUN.as_secs(), (PACKET_LEN * 8) as f64 / GAP.as_secs_f64() / 1e6,
+        thread::available_parallelism().map_or(1, |n| n.get()));
+    println!("{:>5} {:>7} {:>6} {:>11} {:>9} {:>9} {:>9}", "level", "workers", "read", "compressed", "p50 ms", "p99 ms", "max ms");
+    let policy = Policy { min_size: compression::DEFAULT_MIN_SIZE, min_savings: compression::DEFAULT_MIN_SAVINGS };
+    for level in [3, 19] {
+        let codec = Arc::new(Codec::new(Algorithm::Zstd, level, Algorithm::Zstd, policy, None, PACKET_LEN).expect("zstd codec"));
+        codec.negotiate(&[Algorithm::Zstd]);
+        report(level, 0, inline(&codec, &packets));
+        for workers in [1, 2] {
+            report(level, workers, pooled(&codec, workers, &packets));
+        }
+    }
+}
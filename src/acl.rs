@@ -0,0 +1,200 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+
+/// A source network: `10.0.0.0/8`, `2001:db8::/32`, or a bare address (one host).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("invalid address in '{}'", s))?;
+        // v4-mapped IPv6 (how a dual-stack socket reports IPv4 peers) is matched as IPv4.
+        let addr = addr.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().ok().filter(|p| *p <= max)
+                .ok_or_else(|| anyhow!("invalid prefix length in '{}' (0-{})", s, max))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => same_prefix(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => same_prefix(net.into(), ip.into(), 128, self.prefix),
+            _ => false,
+        }
+    }
+}
+
+/// True when the top `prefix` of `bits` bits agree.
+fn same_prefix(a: u128, b: u128, bits: u32, prefix: u8) -> bool {
+    let shift = bits - prefix as u32;
+    shift >= bits || a >> shift == b >> shift
+}
+
+/// Source networks allowed to reach the tunnel (`--allow`, `--allow-file`).
+///
+/// Checked on every datagram before it is decoded or decrypted, so traffic from
+/// anywhere else costs one lookup. Only the file is re-read on reload; `--allow`
+/// entries stay fixed for the life of the process.
+pub struct Allowlist {
+    fixed: Vec<Network>,
+    file: Option<PathBuf>,
+    networks: Mutex<Vec<Network>>,
+}
+
+impl Allowlist {
+    /// `None` when neither flag was given: every source is accepted.
+    pub fn load(fixed: Vec<Network>, file: Option<PathBuf>) -> Result<Option<Self>> {
+        if fixed.is_empty() && file.is_none() {
+            return Ok(None);
+        }
+        let list = Self { fixed, file, networks: Mutex::new(Vec::new()) };
+        list.reload()?;
+        Ok(Some(list))
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.networks.lock().iter().any(|n| n.contains(ip))
+    }
+
+    /// Re-reads the file and returns the number of networks now allowed.
+    /// On error the previous list stays in force.
+    pub fn reload(&self) -> Result<usize> {
+        let mut networks = self.fixed.clone();
+        if let Some(path) = &self.file {
+            networks.extend(read_file(path)?);
+        }
+        let count = networks.len();
+        *self.networks.lock() = networks;
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.lock().len()
+    }
+}
+
+/// One network per line; `#` starts a comment, blank lines are ignored.
+fn read_file(path: &Path) -> Result<Vec<Network>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read allowlist {}", path.display()))?;
+    text.lines().enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| Network::parse(line).with_context(|| format!("{}:{}", path.display(), i + 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn net(s: &str) -> Network {
+        Network::parse(s).unwrap()
+    }
+
+    /// A file of its own under the temp directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("resilinet-acl-{}-{}", std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn prefixes_match_up_to_their_boundaries() {
+        let cases = [
+            ("0.0.0.0/0", "255.255.255.255", true),
+            ("0.0.0.0/0", "2001:db8::1", false),
+            ("::/0", "2001:db8::1", true),
+            ("203.0.113.7/32", "203.0.113.7", true),
+            ("203.0.113.7/32", "203.0.113.6", false),
+            ("203.0.113.7", "203.0.113.8", false),
+            ("10.1.0.0/15", "10.0.255.255", true),
+            ("10.1.0.0/15", "10.2.0.0", false),
+            ("2001:db8::1/128", "2001:db8::1", true),
+            ("2001:db8::1/128", "2001:db8::2", false),
+            ("2001:db8::/32", "2001:db8:ffff::1", true),
+            ("2001:db8::/32", "2001:db9::", false),
+        ];
+        for (network, addr, expected) in cases {
+            assert_eq!(net(network).contains(ip(addr)), expected, "{} in {}", addr, network);
+        }
+    }
+
+    #[test]
+    fn families_only_meet_through_v4_mapped_addresses() {
+        // A dual-stack socket reports IPv4 peers v4-mapped.
+        assert!(net("192.0.2.0/24").contains(ip("::ffff:192.0.2.9")));
+        assert!(!net("192.0.2.0/24").contains(ip("::ffff:198.51.100.9")));
+        assert!(net("::ffff:192.0.2.9").contains(ip("192.0.2.9")));
+        // Otherwise an IPv4 network never takes an IPv6 address, even one ending alike.
+        for addr in ["::192.0.2.9", "2001:db8::c000:209", "::"] {
+            assert!(!net("192.0.2.0/24").contains(ip(addr)), "{}", addr);
+            assert!(!net("0.0.0.0/0").contains(ip(addr)), "{}", addr);
+        }
+        assert!(!net("::/0").contains(ip("192.0.2.9")));
+    }
+
+    #[test]
+    fn bad_networks_are_refused() {
+        for bad in ["10.0.0.0/33", "2001:db8::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0.0/8/8", "10.0.0/8", "host.example", ""] {
+            assert!(Network::parse(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(net("10.0.0.0/32"), net("10.0.0.0"));
+    }
+
+    #[test]
+    fn files_skip_comments_and_blank_lines_and_name_the_bad_line() {
+        let file = TempFile::new("comments", "# office\n\n  192.0.2.0/24   # VPN gateway\n\t\n2001:db8::/32\n#10.0.0.0/8\n");
+        assert_eq!(read_file(&file.0).unwrap(), [net("192.0.2.0/24"), net("2001:db8::/32")]);
+        let file = TempFile::new("bad-line", "192.0.2.0/24\n\n10.0.0.0/33\n");
+        let error = format!("{:#}", read_file(&file.0).unwrap_err());
+        assert!(error.contains(":3"), "{}", error);
+    }
+
+    #[test]
+    fn a_failed_reload_keeps_the_previous_list() {
+        let file = TempFile::new("reload", "192.0.2.0/24\n");
+        let list = Allowlist::load(vec![net("198.51.100.1")], Some(file.0.clone())).unwrap().unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.allows(ip("192.0.2.50")) && list.allows(ip("198.51.100.1")) && !list.allows(ip("203.0.113.1")));
+
+        std::fs::write(&file.0, "203.0.113.0/24\nnot-a-network\n").unwrap();
+        assert!(list.reload().is_err());
+        assert!(list.allows(ip("192.0.2.50")) && !list.allows(ip("203.0.113.1")));
+        std::fs::remove_file(&file.0).unwrap();
+        assert!(list.reload().is_err());
+        assert_eq!(list.len(), 2);
+
+        // A good file takes effect; the `--allow` entries stay throughout.
+        std::fs::write(&file.0, "203.0.113.0/24\n").unwrap();
+        assert_eq!(list.reload().unwrap(), 2);
+        assert!(list.allows(ip("203.0.113.1")) && !list.allows(ip("192.0.2.50")) && list.allows(ip("198.51.100.1")));
+        assert!(Allowlist::load(Vec::new(), None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::control::{self, ControlSender};
+use crate::logging::{tlog, Level};
+use crate::telemetry::TelemetrySender;
+
+/// Shortest token accepted from `--admin-token-file`.
+const MIN_TOKEN_LEN: usize = 16;
+/// A connection must authenticate within this long.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause before a failed login is answered, to slow down guessing.
+const AUTH_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+/// The control protocol over TCP (`--admin-listen`), for tunnels on remote hosts.
+///
+/// Same commands and replies as the Unix control socket, after a first line of
+/// `auth <token>`. There is no TLS: the token and everything after it cross the
+/// network in the clear, so bind it to loopback or a private network and reach it
+/// over SSH or a VPN. Every login and command is logged to telemetry.
+pub fn spawn_admin_listener(addr: SocketAddr, token_file: &Path, tx: ControlSender, stats: TelemetrySender) -> Result<()> {
+    let token = load_token(token_file)?;
+    let listener = std::net::TcpListener::bind(addr).with_context(|| format!("Failed to bind admin API on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    tlog!(stats, Level::Warn, "ADMIN: Listening on {} (token auth, no TLS)", addr);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tlog!(stats, Level::Warn, "ADMIN::AcceptErr: {}", e);
+                    continue;
+                }
+            };
+
+            let (tx, stats, token) = (tx.clone(), stats.clone(), token.clone());
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                let login = tokio::time::timeout(AUTH_TIMEOUT, lines.next_line()).await;
+                let Ok(Ok(Some(login))) = login else { return };
+                if !authenticates(&login, &token) {
+                    tlog!(stats, Level::Warn, "ADMIN: Rejected login from {}", remote);
+                    tokio::time::sleep(AUTH_FAILURE_DELAY).await;
+                    let _ = write.write_all(b"ERR unauthorized\n").await;
+                    return;
+                }
+                tlog!(stats, Level::Info, "ADMIN: {} logged in", remote);
+                if write.write_all(b"OK\n").await.is_err() {
+                    return;
+                }
+
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    // Logged before it runs: `shutdown` leaves no time afterwards.
+                    tlog!(stats, Level::Info, "ADMIN: {} ran '{}'", remote, line);
+                    let reply = control::execute(&tx, line).await;
+                    if write.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn load_token(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path).with_context(|| format!("Failed to read admin token {}", path.display()))?;
+    let token = token.trim().to_string();
+    if token.len() < MIN_TOKEN_LEN || token.contains(char::is_whitespace) {
+        bail!("Admin token in {} must be at least {} characters with no spaces", path.display(), MIN_TOKEN_LEN);
+    }
+    Ok(token)
+}
+
+/// `auth <token>`, compared in constant time for a given length.
+fn authenticates(line: &str, token: &str) -> bool {
+    let Some(given) = line.trim().strip_prefix("auth ") else { return false };
+    let (given, token) = (given.trim().as_bytes(), token.as_bytes());
+    given.len() == token.len() && given.iter().zip(token).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+/// Alert thresholds from the command line. A rule left unset never fires.
+#[derive(Debug, Clone, Copy)]
+pub struct AlertRules {
+    /// Loss rate above this percentage.
+    pub loss_pct: Option<f64>,
+    /// Smoothed RTT above this many milliseconds.
+    pub rtt_ms: Option<f64>,
+    /// Nothing received from the peer for this long.
+    pub rx_silence: Option<Duration>,
+    /// ARQ window completely full for this long.
+    pub window_stall: Option<Duration>,
+    /// A condition must stay healthy this long before its alert clears (hysteresis).
+    pub clear_after: Duration,
+}
+
+impl AlertRules {
+    pub fn any(&self) -> bool {
+        self.loss_pct.is_some() || self.rtt_ms.is_some() || self.rx_silence.is_some() || self.window_stall.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    Loss,
+    Rtt,
+    RxSilence,
+    WindowStall,
+}
+
+const KINDS: [AlertKind; 4] = [AlertKind::Loss, AlertKind::Rtt, AlertKind::RxSilence, AlertKind::WindowStall];
+
+impl AlertKind {
+    /// Short name used in `status` replies.
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertKind::Loss => "loss",
+            AlertKind::Rtt => "rtt",
+            AlertKind::RxSilence => "rx-silence",
+            AlertKind::WindowStall => "window-stall",
+        }
+    }
+}
+
+/// A firing alert and the reading that raised it, e.g. "loss 12.0% > 5.0%".
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub detail: String,
+}
+
+/// One sample of the link, taken every evaluation tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Observation {
+    pub loss_rate: Option<f64>,
+    pub srtt_ms: Option<f64>,
+    /// Time since the last frame from the peer (`None` while there is no peer).
+    pub rx_age: Option<Duration>,
+    pub window_full: bool,
+}
+
+/// Change in the alert set produced by one evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transition {
+    Raised(Alert),
+    Cleared(AlertKind),
+}
+
+/// Evaluates the rules against successive observations.
+///
+/// Alerts fire on the first breaching sample. They clear only once the condition has
+/// been healthy for `clear_after` without interruption, so a reading hovering around
+/// the threshold produces one alert rather than a stream of raise/clear pairs.
+pub struct AlertEngine {
+    rules: AlertRules,
+    active: Vec<Alert>,
+    /// Per kind (indexed like `KINDS`): when the condition last turned healthy while active.
+    healthy_since: [Option<Instant>; 4],
+    window_full_since: Option<Instant>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: AlertRules) -> Self {
+        Self { rules, active: Vec::new(), healthy_since: [None; 4], window_full_since: None }
+    }
+
+    pub fn active(&self) -> &[Alert] {
+        &self.active
+    }
+
+    pub fn evaluate(&mut self, obs: Observation, now: Instant) -> Vec<Transition> {
+        self.window_full_since = if obs.window_full { self.window_full_since.or(Some(now)) } else { None };
+
+        let mut transitions = Vec::new();
+        for (i, kind) in KINDS.into_iter().enumerate() {
+            let breach = self.breach(kind, obs, now);
+            let position = self.active.iter().position(|a| a.kind == kind);
+            match (breach, position) {
+                (Some(detail), None) => {
+                    let alert = Alert { kind, detail };
+                    self.active.push(alert.clone());
+                    transitions.push(Transition::Raised(alert));
+                }
+                (Some(detail), Some(p)) => {
+                    self.active[p].detail = detail;
+                    self.healthy_since[i] = None;
+                }
+                (None, Some(p)) => {
+                    let since = *self.healthy_since[i].get_or_insert(now);
+                    if now.duration_since(since) >= self.rules.clear_after {
+                        self.active.remove(p);
+                        self.healthy_since[i] = None;
+                        transitions.push(Transition::Cleared(kind));
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        transitions
+    }
+
+    /// Describes the breach of `kind`'s rule, if any.
+    fn breach(&self, kind: AlertKind, obs: Observation, now: Instant) -> Option<String> {
+        match kind {
+            AlertKind::Loss => {
+                let limit = self.rules.loss_pct?;
+                let pct = obs.loss_rate? * 100.0;
+                (pct > limit).then(|| format!("loss {:.1}% > {:.1}%", pct, limit))
+            }
+            AlertKind::Rtt => {
+                let limit = self.rules.rtt_ms?;
+                let rtt = obs.srtt_ms?;
+                (rtt > limit).then(|| format!("rtt {:.0}ms > {:.0}ms", rtt, limit))
+            }
+            AlertKind::RxSilence => {
+                let limit = self.rules.rx_silence?;
+                let age = obs.rx_age?;
+                (age >= limit).then(|| format!("no rx for {}s", age.as_secs()))
+            }
+            AlertKind::WindowStall => {
+                let limit = self.rules.window_stall?;
+                let full_for = now.duration_since(self.window_full_since?);
+                (full_for >= limit).then(|| format!("window full for {}s", full_for.as_secs()))
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use rand::{Rng, RngCore};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::compression::{self, Algorithm};
+use crate::crypto::CipherSuite;
+use crate::metrics::{ByteCategories, CounterSnapshot, Counters, PacketSizes};
+use crate::stats::{LinkQuality, Percentiles, RttHistogram};
+use crate::units::{format_rate, RateUnit};
+
+/// Options for `bench` mode: the tunnel runs with a synthetic source/sink in place of TUN.
+#[derive(clap::Args, Debug, Clone)]
+pub struct BenchOptions {
+    /// Generate traffic at the maximum rate the window allows (otherwise only measure what arrives)
+    #[arg(long)] pub send: bool,
+
+    /// Measurement period once traffic starts flowing (seconds)
+    #[arg(long, default_value_t = 10)] pub duration: u64,
+
+    /// Inner packet size in bytes (at most the tunnel MTU)
+    #[arg(long, default_value_t = 1200)] pub size: usize,
+
+    /// Keep the anti-timing-analysis jitter enabled (caps throughput at a few hundred packets/s)
+    #[arg(long)] pub jitter: bool,
+
+    /// Packet content: `random` (incompressible) or `text` (repetitive sensor readings, compresses well)
+    #[arg(long, value_enum, default_value_t = Payload::Random)] pub payload: Payload,
+}
+
+/// What the generated packets carry.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// Random bytes: nothing to compress
+    Random,
+    /// JSON sensor readings, as IoT telemetry looks
+    Text,
+}
+
+/// How long each algorithm is timed for in the codec comparison, each way.
+const CODEC_BENCH_TIME: Duration = Duration::from_millis(200);
+
+/// Byte/packet tallies of the synthetic endpoints.
+#[derive(Default)]
+pub struct Probe {
+    generated_packets: AtomicU64,
+    generated_bytes: AtomicU64,
+    delivered_packets: AtomicU64,
+    delivered_bytes: AtomicU64,
+}
+
+/// Builds the synthetic TUN replacement: a packet generator for the TX path and a
+/// counting sink for the RX path.
+pub fn pipeline(opts: &BenchOptions, mtu: usize) -> (Generator, Sink, Arc<Probe>) {
+    let probe = Arc::new(Probe::default());
+    let generator = Generator::new(opts.send, sample(opts, mtu), probe.clone());
+    (generator, Sink { probe: probe.clone() }, probe)
+}
+
+/// One packet as the generator sends it (bar the sequence stamp).
+pub fn sample(opts: &BenchOptions, mtu: usize) -> Vec<u8> {
+    let size = opts.size.clamp(16, mtu);
+    let mut template = match opts.payload {
+        Payload::Random => {
+            let mut bytes = vec![0u8; size];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes
+        }
+        Payload::Text => {
+            let mut rng = rand::thread_rng();
+            let mut text = String::with_capacity(size + 80);
+            while text.len() < size {
+                text.push_str(&format!(
+                    "{{\"sensor\":\"temp-{:02}\",\"ts\":{},\"value\":{:.1},\"unit\":\"C\"}}\n",
+                    rng.gen_range(0..16), 1_700_000_000 + rng.gen_range(0..3600), rng.gen_range(15.0..30.0)
+                ));
+            }
+            text.into_bytes()[..size].to_vec()
+        }
+    };
+    // Mirror the TUN framing so the data path sees what it would in production.
+    #[cfg(target_os = "linux")]
+    template[..4].copy_from_slice(&[0, 0, 0x08, 0x00]);
+    template
+}
+
+/// Endless source of `sample` packets, paced only by the caller's reads.
+pub struct Generator {
+    enabled: bool,
+    template: Vec<u8>,
+    seq: u64,
+    probe: Arc<Probe>,
+}
+
+impl Generator {
+    fn new(enabled: bool, template: Vec<u8>, probe: Arc<Probe>) -> Self {
+        Self { enabled, template, seq: 0, probe }
+    }
+}
+
+impl AsyncRead for Generator {
+    fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if !self.enabled {
+            // Receive-only side: a device that never has anything to send.
+            return Poll::Pending;
+        }
+        let this = &mut *self;
+        this.seq += 1;
+        let len = this.template.len().min(buf.remaining());
+        // Stamp a sequence number past the PI header so no two packets are identical.
+        this.template[4..12].copy_from_slice(&this.seq.to_be_bytes());
+        buf.put_slice(&this.template[..len]);
+        this.probe.generated_packets.fetch_add(1, Ordering::Relaxed);
+        this.probe.generated_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Accepts every delivered packet and counts it.
+pub struct Sink {
+    probe: Arc<Probe>,
+}
+
+impl AsyncWrite for Sink {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.probe.delivered_packets.fetch_add(1, Ordering::Relaxed);
+        self.probe.delivered_bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Steady-state figures over the measurement period.
+pub struct BenchReport {
+    pub elapsed: Duration,
+    pub generated_packets: u64,
+    pub generated_bytes: u64,
+    pub delivered_packets: u64,
+    pub delivered_bytes: u64,
+    pub counters: CounterSnapshot,
+    pub packet_sizes: PacketSizes,
+    pub quality: LinkQuality,
+    /// From pacing releasing a data frame to the socket taking it: compression and sealing.
+    pub tx_delay: Percentiles,
+    /// AEAD in use when the run ended.
+    pub cipher: Option<CipherSuite>,
+    /// Compression outgoing packets used when the run ended.
+    pub compression: Option<Algorithm>,
+    /// Inner bytes per data frame payload byte, in the direction traffic flowed.
+    pub compression_ratio: Option<f64>,
+    /// Every `--compress` option timed on the run's packets, on this host.
+    pub codecs: Vec<CodecRun>,
+}
+
+/// One algorithm of the codec comparison. Rates are inner bytes per second of one core.
+pub struct CodecRun {
+    pub algorithm: Algorithm,
+    pub level: i32,
+    /// Inner bytes per compressed byte (marker byte included).
+    pub ratio: f64,
+    pub compress_rate: f64,
+    pub decompress_rate: f64,
+}
+
+/// Waits for traffic (sent or received), then measures for `duration`.
+pub async fn measure(probe: &Probe, counters: &Counters, tx_delay: &RttHistogram, duration: Duration) -> BenchReport {
+    let active = || probe.generated_packets.load(Ordering::Relaxed) + probe.delivered_packets.load(Ordering::Relaxed) > 0;
+    while !active() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let read = |c: &AtomicU64| c.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let before = (read(&probe.generated_packets), read(&probe.generated_bytes), read(&probe.delivered_packets), read(&probe.delivered_bytes));
+    let counters_before = counters.snapshot();
+    let sizes_before = counters.packet_sizes();
+    let delay_before = tx_delay.snapshot();
+    let compressed = |bytes: &ByteCategories| (read(&bytes.inner), read(&bytes.payload));
+    let (tx_before, rx_before) = (compressed(&counters.tx_bytes), compressed(&counters.rx_bytes));
+
+    tokio::time::sleep(duration).await;
+
+    let counters_after = counters.snapshot();
+    let generated_packets = read(&probe.generated_packets) - before.0;
+    let ((inner, payload), (inner_before, payload_before)) = if generated_packets > 0 {
+        (compressed(&counters.tx_bytes), tx_before)
+    } else {
+        (compressed(&counters.rx_bytes), rx_before)
+    };
+    BenchReport {
+        elapsed: start.elapsed(),
+        generated_packets,
+        generated_bytes: read(&probe.generated_bytes) - before.1,
+        delivered_packets: read(&probe.delivered_packets) - before.2,
+        delivered_bytes: read(&probe.delivered_bytes) - before.3,
+        counters: counter_delta(counters_after, counters_before),
+        packet_sizes: counters.packet_sizes().since(&sizes_before),
+        quality: LinkQuality::default(),
+        tx_delay: tx_delay.snapshot().since(&delay_before).percentiles(),
+        cipher: None,
+        compression: None,
+        compression_ratio: (payload > payload_before).then(|| (inner - inner_before) as f64 / (payload - payload_before) as f64),
+        codecs: Vec::new(),
+    }
+}
+
+fn counter_delta(after: CounterSnapshot, before: CounterSnapshot) -> CounterSnapshot {
+    CounterSnapshot {
+        data_sent: after.data_sent - before.data_sent,
+        data_received: after.data_received - before.data_received,
+        acks_sent: after.acks_sent - before.acks_sent,
+        acks_received: after.acks_received - before.acks_received,
+        heartbeats_sent: after.heartbeats_sent - before.heartbeats_sent,
+        heartbeats_received: after.heartbeats_received - before.heartbeats_received,
+        handshakes_sent: after.handshakes_sent - before.handshakes_sent,
+        handshakes_received: after.handshakes_received - before.handshakes_received,
+        stats_sent: after.stats_sent - before.stats_sent,
+        stats_received: after.stats_received - before.stats_received,
+        chaff_sent: after.chaff_sent - before.chaff_sent,
+        chaff_received: after.chaff_received - before.chaff_received,
+        morph_split: after.morph_split - before.morph_split,
+        retransmits: after.retransmits - before.retransmits,
+        dns_redirected: after.dns_redirected - before.dns_redirected,
+        drop_decrypt: after.drop_decrypt - before.drop_decrypt,
+        drop_decompress: after.drop_decompress - before.drop_decompress,
+        drop_codec: after.drop_codec - before.drop_codec,
+        drop_oversize: after.drop_oversize - before.drop_oversize,
+        ecn_ce: after.ecn_ce - before.ecn_ce,
+        ecn_cuts: after.ecn_cuts - before.ecn_cuts,
+        drop_malformed: after.drop_malformed - before.drop_malformed,
+        drop_mimic: after.drop_mimic - before.drop_mimic,
+        drop_runt: after.drop_runt - before.drop_runt,
+        drop_replay: after.drop_replay - before.drop_replay,
+        drop_ack_auth: after.drop_ack_auth - before.drop_ack_auth,
+        drop_acl: after.drop_acl - before.drop_acl,
+        drop_quota: after.drop_quota - before.drop_quota,
+        drop_tun_backlog: after.drop_tun_backlog - before.drop_tun_backlog,
+        drop_iface: after.drop_iface - before.drop_iface,
+        drop_loop: after.drop_loop - before.drop_loop,
+        drop_reassembly: after.drop_reassembly - before.drop_reassembly,
+        compressed: after.compressed - before.compressed,
+        compress_too_small: after.compress_too_small - before.compress_too_small,
+        compress_entropy: after.compress_entropy - before.compress_entropy,
+        compress_no_gain: after.compress_no_gain - before.compress_no_gain,
+        compress_queue_full: after.compress_queue_full - before.compress_queue_full,
+    }
+}
+
+/// Times every `--compress` option on `packet` (zstd at `level`), so the report can
+/// show what each would cost and save on this traffic. Blocks for about a second.
+pub fn compare_codecs(packet: &[u8], level: i32) -> Vec<CodecRun> {
+    Algorithm::ALL.into_iter().filter_map(|algorithm| {
+        let encoded = compression::encode(algorithm, level, packet).ok()?;
+        Some(CodecRun {
+            algorithm,
+            level,
+            ratio: packet.len() as f64 / encoded.len() as f64,
+            compress_rate: timed_rate(|| compression::encode(algorithm, level, packet).map_or(0, |_| packet.len())),
+            decompress_rate: timed_rate(|| compression::decompress(&encoded, compression::MAX_DECOMPRESSED).map_or(0, |p| p.len())),
+        })
+    }).collect()
+}
+
+/// Bytes per second `run` gets through, repeated for `CODEC_BENCH_TIME`.
+fn timed_rate(mut run: impl FnMut() -> usize) -> f64 {
+    let start = Instant::now();
+    let mut bytes = 0;
+    while start.elapsed() < CODEC_BENCH_TIME {
+        bytes += run();
+    }
+    bytes as f64 / start.elapsed().as_secs_f64()
+}
+
+impl BenchReport {
+    /// Two-column summary printed when the run completes.
+    pub fn table(&self, unit: RateUnit) -> String {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let rate = |bytes: u64| format_rate(bytes as f64 / secs, unit);
+        let ratio = |num: u64, den: u64| if den == 0 { "-".to_string() } else { format!("{:.2}%", num as f64 / den as f64 * 100.0) };
+        let opt = |v: Option<f64>, unit: &str| v.map_or("-".to_string(), |v| format!("{:.2}{}", v, unit));
+
+        let rows = [
+            ("duration", format!("{:.2}s", secs)),
+            ("cipher", self.cipher.map_or("-", |c| c.name()).to_string()),
+            ("compression", self.compression.map_or("-", |a| a.name()).to_string()),
+            ("compression ratio", self.compression_ratio.map_or("-".to_string(), |r| format!("{:.2}x", r))),
+            ("compressed", format!(
+                "{} of sent (raw: {} too small, {} high entropy, {} no gain, {} queue full)",
+                ratio(self.counters.compressed, self.counters.data_sent),
+                self.counters.compress_too_small, self.counters.compress_entropy, self.counters.compress_no_gain, self.counters.compress_queue_full
+            )),
+            ("offered", format!("{} ({} pkts)", rate(self.generated_bytes), self.generated_packets)),
+            ("goodput", format!("{} ({} pkts)", rate(self.delivered_bytes), self.delivered_packets)),
+            ("data frames sent", self.counters.data_sent.to_string()),
+            ("data frames received", self.counters.data_received.to_string()),
+            ("retransmits", format!("{} ({} of sent)", self.counters.retransmits, ratio(self.counters.retransmits, self.counters.data_sent))),
+            ("duplicates dropped", self.counters.drop_replay.to_string()),
+            ("tun backlog dropped", self.counters.drop_tun_backlog.to_string()),
+            ("loss (arq)", opt(self.quality.loss_rate.map(|l| l * 100.0), "%")),
+            ("srtt", opt(self.quality.srtt_ms, "ms")),
+            ("jitter", opt(self.quality.jitter_ms, "ms")),
+            ("tx pipeline", self.tx_delay.to_string()),
+        ];
+
+        let mut out = String::from("RESILINET BENCH\n");
+        for (name, value) in rows {
+            out.push_str(&format!("  {:<22}{}\n", name, value));
+        }
+
+        let (sent, delivered) = (self.packet_sizes.tx.iter().sum(), self.packet_sizes.rx.iter().sum());
+        out.push_str("  packet sizes (sent / delivered)\n");
+        for (label, tx, rx) in self.packet_sizes.rows() {
+            out.push_str(&format!("    {:<20}{:>8} ({:>7}) / {:>8} ({:>7})\n", label, tx, ratio(tx, sent), rx, ratio(rx, delivered)));
+        }
+
+        if !self.codecs.is_empty() {
+            out.push_str("  codecs on these packets, one core (ratio, compress, decompress)\n");
+            for codec in &self.codecs {
+                out.push_str(&format!(
+                    "    {:<20}{:>6.2}x  {:>14}  {:>14}\n",
+                    codec.algorithm.label(codec.level), codec.ratio, format_rate(codec.compress_rate, unit), format_rate(codec.decompress_rate, unit)
+                ));
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,84 @@
+use rand::Rng;
+use tokio::time::Duration;
+
+/// Highest `--chaff-rate`: cover traffic must never turn into a load of its own.
+pub const MAX_RATE: u32 = 100;
+
+/// How often `fill` tops the rate up.
+const FILL_TICK: Duration = Duration::from_millis(100);
+
+/// Smallest chaff payload: below it, a frame could not pass for a data packet.
+const MIN_LEN: usize = 64;
+
+/// `--chaff`: when cover frames go out.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ChaffProfile {
+    /// One frame every 1/rate seconds.
+    Constant,
+    /// Random gaps averaging 1/rate seconds (Poisson arrivals), like independent requests.
+    Poisson,
+    /// Pads quiet stretches up to the rate, counting real data frames towards it.
+    Fill,
+}
+
+impl ChaffProfile {
+    pub fn label(self) -> &'static str {
+        match self {
+            ChaffProfile::Constant => "constant",
+            ChaffProfile::Poisson => "poisson",
+            ChaffProfile::Fill => "fill",
+        }
+    }
+}
+
+/// Cover traffic schedule.
+///
+/// **Yielding**: chaff only ever fills silence. A `constant` or `poisson` slot in which a
+/// real data frame went out is skipped, and `fill` counts data frames against its rate,
+/// so a busy tunnel sends no chaff at all and real packets never wait behind it.
+pub struct Schedule {
+    profile: ChaffProfile,
+    rate: f64,
+    /// `fill`: frames owed, carried between ticks.
+    credit: f64,
+}
+
+impl Schedule {
+    pub fn new(profile: ChaffProfile, rate: u32) -> Self {
+        Self { profile, rate: rate.min(MAX_RATE) as f64, credit: 0.0 }
+    }
+
+    /// Time until the next decision.
+    pub fn next_gap(&self, rng: &mut impl Rng) -> Duration {
+        match self.profile {
+            ChaffProfile::Constant => Duration::from_secs_f64(1.0 / self.rate),
+            // Inverse transform sampling of the exponential distribution.
+            ChaffProfile::Poisson => Duration::from_secs_f64(-(1.0 - rng.gen::<f64>()).ln() / self.rate),
+            ChaffProfile::Fill => FILL_TICK,
+        }
+    }
+
+    /// Chaff frames to send now, given the data frames sent over the `elapsed` since
+    /// the previous decision.
+    pub fn due(&mut self, data_frames: u64, elapsed: Duration) -> u32 {
+        match self.profile {
+            ChaffProfile::Constant | ChaffProfile::Poisson => u32::from(data_frames == 0),
+            ChaffProfile::Fill => {
+                // A pause in the schedule (a stalled task) earns at most two ticks' worth.
+                let cap = self.rate * FILL_TICK.as_secs_f64() * 2.0;
+                self.credit = (self.credit + self.rate * elapsed.as_secs_f64() - data_frames as f64).clamp(0.0, cap);
+                let frames = self.credit.floor();
+                self.credit -= frames;
+                frames as u32
+            }
+        }
+    }
+}
+
+/// Random filler for one chaff frame, sized like a data packet of up to `max_len` bytes.
+/// Sealed, it reads the same as any other payload.
+pub fn padding(rng: &mut impl Rng, max_len: usize) -> Vec<u8> {
+    let mut filler = vec![0u8; rng.gen_range(MIN_LEN..=max_len.max(MIN_LEN))];
+    rng.fill(&mut filler[..]);
+    filler
+}
@@ -0,0 +1,63 @@
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use crate::compression::{Codec, Outcome};
+
+/// Payloads each worker may have queued before `submit` turns packets away: kept
+/// short, as every one adds a compression time to the latency of those behind it.
+const QUEUE_PER_WORKER: usize = 2;
+
+/// A compressed payload and what became of it, as `Codec::compress` returns them.
+pub type Compressed = (Vec<u8>, Outcome);
+
+struct Job {
+    data: Vec<u8>,
+    reply: oneshot::Sender<Compressed>,
+}
+
+/// Compression off the TX tasks (`--compress-workers`).
+///
+/// **Hot Path Safety**: `submit` only copies the packet into a bounded queue; the
+/// compressor runs on dedicated threads. When they fall behind, `submit` refuses the
+/// packet instead of waiting, and the caller sends it raw.
+pub struct CompressPool {
+    tx: mpsc::SyncSender<Job>,
+    depth: usize,
+}
+
+impl CompressPool {
+    /// Starts `workers` threads sharing one queue. They exit once the pool is dropped.
+    pub fn start(codec: Arc<Codec>, workers: usize) -> Self {
+        let depth = workers * QUEUE_PER_WORKER;
+        let (tx, rx) = mpsc::sync_channel::<Job>(depth);
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..workers {
+            let (codec, rx) = (codec.clone(), rx.clone());
+            let spawned = thread::Builder::new().name(format!("compress-{}", i)).spawn(move || loop {
+                // The lock is only held while waiting for a job, never while compressing.
+                let Ok(job) = rx.lock().recv() else { break };
+                let _ = job.reply.send(codec.compress(&job.data));
+            });
+            if spawned.is_err() {
+                break;
+            }
+        }
+        Self { tx, depth }
+    }
+
+    /// Packets that may be waiting for a worker at once.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Queues `data` for compression; the result arrives on the returned channel.
+    /// `None` when the queue is full (or no worker could be started).
+    pub fn submit(&self, data: &[u8]) -> Option<oneshot::Receiver<Compressed>> {
+        let (reply, result) = oneshot::channel();
+        self.tx.try_send(Job { data: data.to_vec(), reply }).ok()?;
+        Some(result)
+    }
+}
@@ -1,7 +1,74 @@
-use std::io::Cursor;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Cursor};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
 use zstd::stream::copy_encode;
-use zstd::stream::copy_decode;
 use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::dictionary::Dictionary;
+
+/// Payload markers: the first byte of every sealed payload names the algorithm the rest is encoded with.
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+const LZ4: u8 = 2;
+/// Zstd against the dictionary both ends advertised (`--compress-dict`).
+const ZSTD_DICT: u8 = 3;
+
+/// Largest packet a payload may ever expand to (the IP length limit). The tunnel
+/// holds its own peer to the largest TUN frame (`Codec::new`), far below this.
+pub const MAX_DECOMPRESSED: usize = u16::MAX as usize;
+
+/// `--compress-level` default: the usual sweet spot for real-time traffic.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// `--compress-min-size` default: below this, the call costs more than the bytes it could save.
+pub const DEFAULT_MIN_SIZE: usize = 100;
+/// `--compress-min-savings` default, in percent of the raw payload.
+pub const DEFAULT_MIN_SAVINGS: u8 = 5;
+
+/// Bytes the entropy estimate looks at: enough to tell ciphertext from text, cheap next to a compressor call.
+const ENTROPY_SAMPLE: usize = 512;
+/// A sample whose byte entropy reaches this share of the most it could have (8 bits,
+/// or log2 of a shorter sample's length) is treated as random: TLS, QUIC, SSH, media.
+const ENTROPY_SKIP_SHARE: f64 = 0.85;
+
+/// Packets shorter than this are compressed against the dictionary, once one is agreed.
+const DICT_MAX_LEN: usize = 512;
+/// Below this even a dictionary can't win back the zstd frame header.
+const DICT_MIN_LEN: usize = 20;
+/// Dictionary candidates shorter than this skip the entropy estimate: too few bytes to judge.
+const DICT_ENTROPY_MIN_LEN: usize = 128;
+/// Every this many dictionary packets, one is also compressed without it, to measure the gain.
+const DICT_SAMPLE_EVERY: u64 = 16;
+
+/// Span the windowed ratio and cost are measured over.
+const STATS_WINDOW: Duration = Duration::from_secs(10);
+/// Spacing of the totals the window is measured from.
+const STATS_WINDOW_STEP: Duration = Duration::from_secs(1);
+
+/// Span `--compress-auto` measures savings and cost over.
+const AUTO_WINDOW: Duration = Duration::from_secs(5);
+/// While `--compress-auto` has compression suspended, every this many packets one is
+/// compressed anyway, to notice the traffic becoming compressible again.
+const AUTO_PROBE_EVERY: u64 = 32;
+
+/// Zstd levels `--compress-adapt` steps through, above raw. The level given with
+/// `--compress-level` joins them if it isn't one.
+const ADAPT_ZSTD_LEVELS: [i32; 7] = [1, 3, 6, 9, 12, 15, 19];
+/// Share of the compressing threads' time spent compressing at which the level costs
+/// throughput: past it, with the link keeping up, the level steps down.
+const ADAPT_CPU_HIGH: f64 = 0.5;
+/// Below this share, there is CPU to spare for a costlier level.
+const ADAPT_CPU_SPARE: f64 = 0.25;
+/// Share of the time the send window was full for the link to count as the bottleneck.
+const ADAPT_LINK_BOUND: f64 = 0.3;
+/// Consecutive ticks a verdict must hold before the level moves.
+const ADAPT_HOLD: u32 = 3;
+/// Percent the current level must save for a costlier one to be worth trying.
+const ADAPT_MIN_SAVINGS: f64 = 2.0;
 
 /// Known high-entropy headers.
 /// If we see these, we skip compression to save CPU cycles.
@@ -12,56 +79,1136 @@ const MAGIC_HEADERS: &[&[u8]] = &[
     &[0x1F, 0x8B],             // GZIP
 ];
 
-/// Heuristic check for high entropy data.
-fn is_high_entropy(data: &[u8]) -> bool {
-    if data.len() < 4 { return false; }
-    for magic in MAGIC_HEADERS {
-        if data.starts_with(magic) { return true; }
+/// Compression for outgoing payloads (`--compress`). Each end advertises the one it
+/// uses in its Hello and accepts only that (and raw) in return.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Send everything uncompressed
+    Off,
+    /// LZ4: lowest latency and CPU cost, modest ratio
+    Lz4,
+    /// Zstandard at --compress-level: better ratio, more CPU
+    Zstd,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [Algorithm::Off, Algorithm::Lz4, Algorithm::Zstd];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Off => "off",
+            Algorithm::Lz4 => "lz4",
+            Algorithm::Zstd => "zstd",
+        }
+    }
+
+    /// The name, with the level for zstd (`zstd-3`).
+    pub fn label(self, level: i32) -> String {
+        match self {
+            Algorithm::Zstd => format!("zstd-{}", level),
+            other => other.name().to_string(),
+        }
+    }
+
+    fn marker(self) -> u8 {
+        match self {
+            Algorithm::Off => RAW,
+            Algorithm::Lz4 => LZ4,
+            Algorithm::Zstd => ZSTD,
+        }
+    }
+
+    fn from_marker(marker: u8) -> Option<Algorithm> {
+        Algorithm::ALL.into_iter().find(|a| a.marker() == marker)
+    }
+}
+
+/// `--compress-tx`: `ALGORITHM[:LEVEL]`, the level only for zstd (1-19).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxSetting {
+    pub algorithm: Algorithm,
+    pub level: Option<i32>,
+}
+
+impl TxSetting {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let algorithm = <Algorithm as clap::ValueEnum>::from_str(name, true)?;
+        let level = match level {
+            None => None,
+            Some(level) => Some(check_level(algorithm, level.parse().map_err(|_| format!("invalid level '{}'", level))?)?),
+        };
+        Ok(Self { algorithm, level })
+    }
+}
+
+/// Whether `level` is one `algorithm` has: zstd's 1-19; lz4 and off have none.
+pub fn check_level(algorithm: Algorithm, level: i32) -> Result<i32, String> {
+    match algorithm {
+        Algorithm::Zstd if (1..=19).contains(&level) => Ok(level),
+        Algorithm::Zstd => Err(format!("zstd levels are 1-19, not {}", level)),
+        other => Err(format!("{} has no levels", other.name())),
+    }
+}
+
+/// When a payload is worth compressing (`--compress-min-size`, `--compress-min-savings`).
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// Payloads shorter than this go out raw without trying.
+    pub min_size: usize,
+    /// Percent of the raw payload the compressed one (marker included) must save to be sent.
+    pub min_savings: u8,
+}
+
+/// What became of one outgoing payload. Each has its own counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Sent compressed.
+    Compressed,
+    /// Sent raw: compression is off, or the peer doesn't decode our algorithm.
+    Disabled,
+    /// Sent raw: shorter than `Policy::min_size`.
+    TooSmall,
+    /// Sent raw without trying: a known compressed format, or random-looking bytes.
+    HighEntropy,
+    /// Compressed, but not by `Policy::min_savings`, so sent raw.
+    NoGain,
+    /// Sent raw: `--compress-auto` found compression not worth it on this traffic, or
+    /// `--compress-adapt` stepped down to raw.
+    Suspended,
+    /// Sent raw: the `--compress-workers` queue was full.
+    QueueFull,
+}
+
+/// This end's compression: the algorithm it sends with, the one it decodes, and
+/// whether the peer has said it decodes ours.
+pub struct Codec {
+    algorithm: Algorithm,
+    /// Zstd only; moved at runtime by `--compress-adapt`.
+    level: AtomicI32,
+    /// What incoming payloads may use besides raw (`--compress-rx`).
+    inbound: Algorithm,
+    policy: Policy,
+    /// What outgoing payloads use: `algorithm` once the peer's Hello lists it, `Off`
+    /// until then (raw is the one encoding every peer decodes).
+    outbound: AtomicU8,
+    /// Zstd only: the dictionary for small packets, if one is loaded.
+    dictionary: Option<DictCodec>,
+    /// Most bytes an incoming payload may decompress to.
+    max_packet: usize,
+    /// `--compress-auto` found compression not worth it: only probes are compressed.
+    suspended: AtomicBool,
+    probes: AtomicU64,
+    tx_stats: DirectionCounters,
+    rx_stats: DirectionCounters,
+}
+
+impl Codec {
+    /// Sends with `algorithm` and decodes `inbound`. `dictionary` is only kept if either
+    /// is `Algorithm::Zstd`. `max_packet` is the largest packet the peer can legitimately
+    /// send us, the TUN frame for our MTU: anything decoding to more is `Oversized`.
+    pub fn new(algorithm: Algorithm, level: i32, inbound: Algorithm, policy: Policy, dictionary: Option<Dictionary>, max_packet: usize) -> Result<Self> {
+        let dictionary = match dictionary.filter(|_| algorithm == Algorithm::Zstd || inbound == Algorithm::Zstd) {
+            Some(dict) => Some(DictCodec::new(&dict, level)?),
+            None => None,
+        };
+        Ok(Self {
+            algorithm,
+            level: AtomicI32::new(level),
+            inbound,
+            policy,
+            outbound: AtomicU8::new(RAW),
+            dictionary,
+            max_packet: max_packet.min(MAX_DECOMPRESSED),
+            suspended: AtomicBool::new(false),
+            probes: AtomicU64::new(0),
+            tx_stats: DirectionCounters::default(),
+            rx_stats: DirectionCounters::default(),
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level.load(Ordering::Relaxed)
+    }
+
+    /// Compresses at `level` from the next packet on (`--compress-adapt`).
+    pub fn set_level(&self, level: i32) {
+        self.level.store(level, Ordering::Relaxed);
+        if let Some(dict) = &self.dictionary {
+            // Keeps its old level if zstd refuses: the packets still decode either way.
+            let _ = dict.compressor.lock().set_compression_level(level);
+        }
+    }
+
+    /// What incoming payloads may use besides raw.
+    pub fn inbound(&self) -> Algorithm {
+        self.inbound
+    }
+
+    /// Whether either direction compresses at all.
+    pub fn enabled(&self) -> bool {
+        self.algorithm != Algorithm::Off || self.inbound != Algorithm::Off
+    }
+
+    /// Algorithms to advertise: what this end decodes besides raw.
+    pub fn accepted(&self) -> Vec<Algorithm> {
+        if self.inbound == Algorithm::Off { vec![] } else { vec![self.inbound] }
+    }
+
+    pub fn outbound(&self) -> Algorithm {
+        Algorithm::from_marker(self.outbound.load(Ordering::Relaxed)).unwrap_or(Algorithm::Off)
+    }
+
+    /// Compresses with our algorithm if the peer accepts it, raw otherwise.
+    /// Returns the outbound algorithm before and after.
+    pub fn negotiate(&self, theirs: &[Algorithm]) -> (Algorithm, Algorithm) {
+        let agreed = if theirs.contains(&self.algorithm) { self.algorithm } else { Algorithm::Off };
+        let previous = Algorithm::from_marker(self.outbound.swap(agreed.marker(), Ordering::Relaxed)).unwrap_or(Algorithm::Off);
+        (previous, agreed)
+    }
+
+    /// Our dictionary's id, to advertise (`None`: no dictionary).
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.dictionary.as_ref().map(|d| d.id)
+    }
+
+    /// Uses the dictionary only if the peer advertised the same id: one it doesn't have,
+    /// or a different one of the same name, would decode to garbage. Returns whether it
+    /// is in use, or `None` without a dictionary of our own.
+    pub fn negotiate_dictionary(&self, theirs: Option<u32>) -> Option<bool> {
+        let dict = self.dictionary.as_ref()?;
+        let agreed = theirs == Some(dict.id);
+        dict.agreed.store(agreed, Ordering::Relaxed);
+        Some(agreed)
+    }
+
+    /// Small packets go out against the dictionary.
+    pub fn dictionary_agreed(&self) -> bool {
+        self.dictionary.as_ref().is_some_and(|d| d.agreed.load(Ordering::Relaxed))
+    }
+
+    pub fn dictionary_stats(&self) -> Option<DictionaryStats> {
+        self.dictionary.as_ref().map(DictCodec::stats)
+    }
+
+    /// Session totals of what went through `compress` and `decompress`.
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats { tx: self.tx_stats.totals(), rx: self.rx_stats.totals() }
+    }
+
+    /// Compress only every `AUTO_PROBE_EVERY`th packet (`--compress-auto`, or
+    /// `--compress-adapt` at raw), or all again.
+    pub fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+    }
+
+    pub fn suspended(&self) -> bool {
+        self.suspended.load(Ordering::Relaxed)
+    }
+
+    /// Back to raw, without the dictionary, until the next peer says what it decodes.
+    pub fn reset(&self) {
+        self.outbound.store(RAW, Ordering::Relaxed);
+        if let Some(dict) = &self.dictionary {
+            dict.agreed.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// `[FLAG (1B) | PAYLOAD]` with the outbound algorithm, and what was decided. A failed
+    /// compression still goes out flagged as raw, never as bare bytes.
+    /// While suspended, packets other than probes go out raw and uncounted, so the
+    /// totals show what the probes achieve.
+    pub fn compress(&self, data: &[u8]) -> (Vec<u8>, Outcome) {
+        if self.suspended.load(Ordering::Relaxed) && !self.probes.fetch_add(1, Ordering::Relaxed).is_multiple_of(AUTO_PROBE_EVERY) {
+            return (uncompressed(data), Outcome::Suspended);
+        }
+        let started = Instant::now();
+        let (out, outcome) = self.encode_outbound(data);
+        let skipped = matches!(outcome, Outcome::TooSmall | Outcome::HighEntropy | Outcome::NoGain);
+        self.tx_stats.record(data.len(), out.len(), skipped, started.elapsed());
+        (out, outcome)
+    }
+
+    fn encode_outbound(&self, data: &[u8]) -> (Vec<u8>, Outcome) {
+        let outbound = self.outbound();
+        if let Some(dict) = &self.dictionary {
+            if outbound == Algorithm::Zstd && data.len() < DICT_MAX_LEN && dict.agreed.load(Ordering::Relaxed) {
+                return dict.compress(data, self.level(), &self.policy);
+            }
+        }
+        compress(outbound, self.level(), &self.policy, data).unwrap_or_else(|_| (uncompressed(data), Outcome::NoGain))
+    }
+
+    /// Whether the payload's marker is raw or an encoding we advertised. Anything
+    /// else was never negotiated and is not decoded at all.
+    pub fn accepts(&self, payload: &[u8]) -> bool {
+        match payload.first() {
+            None | Some(&RAW) => true,
+            Some(&ZSTD_DICT) => self.inbound == Algorithm::Zstd && self.dictionary.is_some(),
+            Some(&marker) => self.inbound != Algorithm::Off && marker == self.inbound.marker(),
+        }
+    }
+
+    /// `decompress` up to `max_packet`, plus payloads compressed against our dictionary.
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let out = match (data.first(), &self.dictionary) {
+            (Some(&ZSTD_DICT), Some(dict)) => {
+                zstd_result(dict.decompressor.lock().decompress(&data[1..], self.max_packet), self.max_packet, "ZstdDict::DecodeFail")
+            }
+            _ => decompress(data, self.max_packet),
+        }?;
+        self.rx_stats.record(out.len(), data.len(), data.first() == Some(&RAW), started.elapsed());
+        Ok(out)
+    }
+}
+
+/// Totals for one direction through the codec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirectionTotals {
+    pub packets: u64,
+    /// Inner packet bytes: into the compressor, or out of the decompressor.
+    pub raw_bytes: u64,
+    /// The payloads on the wire side, marker byte included.
+    pub wire_bytes: u64,
+    /// Outgoing: sent raw by the heuristics (too small, high entropy, no gain).
+    /// Incoming: arrived raw.
+    pub uncompressed: u64,
+    /// Time spent compressing or decompressing.
+    pub nanos: u64,
+}
+
+impl DirectionTotals {
+    /// Raw over wire bytes: below 1.0 when the marker byte costs more than compression saves.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.wire_bytes > 0).then(|| self.raw_bytes as f64 / self.wire_bytes as f64)
+    }
+
+    pub fn micros_per_packet(&self) -> Option<f64> {
+        (self.packets > 0).then(|| self.nanos as f64 / 1000.0 / self.packets as f64)
+    }
+
+    /// Percent of the raw bytes compression saved; negative when the marker byte costs more.
+    pub fn savings(&self) -> Option<f64> {
+        (self.raw_bytes > 0).then(|| 100.0 * (1.0 - self.wire_bytes as f64 / self.raw_bytes as f64))
+    }
+
+    pub fn since(&self, base: &Self) -> Self {
+        Self {
+            packets: self.packets.saturating_sub(base.packets),
+            raw_bytes: self.raw_bytes.saturating_sub(base.raw_bytes),
+            wire_bytes: self.wire_bytes.saturating_sub(base.wire_bytes),
+            uncompressed: self.uncompressed.saturating_sub(base.uncompressed),
+            nanos: self.nanos.saturating_sub(base.nanos),
+        }
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        Self {
+            packets: self.packets + other.packets,
+            raw_bytes: self.raw_bytes + other.raw_bytes,
+            wire_bytes: self.wire_bytes + other.wire_bytes,
+            uncompressed: self.uncompressed + other.uncompressed,
+            nanos: self.nanos + other.nanos,
+        }
+    }
+}
+
+/// Both directions, outgoing (`tx`) and incoming (`rx`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionStats {
+    pub tx: DirectionTotals,
+    pub rx: DirectionTotals,
+}
+
+impl CompressionStats {
+    fn since(&self, base: &Self) -> Self {
+        Self { tx: self.tx.since(&base.tx), rx: self.rx.since(&base.rx) }
+    }
+}
+
+/// The session so far, and the last `STATS_WINDOW` of it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionReport {
+    pub session: CompressionStats,
+    pub window: CompressionStats,
+}
+
+/// Derives the windowed figures from successive session totals, the way
+/// `stats::LatencyTracker` does for RTT: the window is the difference from totals
+/// about `STATS_WINDOW` old.
+pub struct StatsTracker {
+    /// Baselines, oldest first, `STATS_WINDOW_STEP` apart.
+    baselines: VecDeque<(Instant, CompressionStats)>,
+}
+
+impl StatsTracker {
+    pub fn new() -> Self {
+        Self { baselines: VecDeque::new() }
+    }
+
+    pub fn update(&mut self, now: Instant, current: CompressionStats) -> CompressionReport {
+        if self.baselines.back().is_none_or(|(at, _)| now.duration_since(*at) >= STATS_WINDOW_STEP) {
+            self.baselines.push_back((now, current));
+        }
+        while self.baselines.len() > 1 && now.duration_since(self.baselines[1].0) >= STATS_WINDOW {
+            self.baselines.pop_front();
+        }
+        let window = match self.baselines.front() {
+            Some((at, base)) if now.duration_since(*at) >= STATS_WINDOW => current.since(base),
+            _ => current,
+        };
+        CompressionReport { session: current, window }
+    }
+}
+
+/// Tunables of `--compress-auto`. Separate thresholds and hold times each way are the
+/// hysteresis: traffic that hovers around one threshold doesn't flap.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoPolicy {
+    /// Compression is suspended once the savings (percent of raw bytes) stay below
+    /// `off_below` for `off_after`...
+    pub off_below: f64,
+    pub off_after: Duration,
+    /// ...and resumed once the probes save at least `on_above` for `on_after`.
+    pub on_above: f64,
+    pub on_after: Duration,
+}
+
+/// What `--compress-auto` measured over the last `AUTO_WINDOW`, and what it decided.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoStatus {
+    /// Compressing; `false` while suspended, with only probes compressed.
+    pub active: bool,
+    /// `DirectionTotals::savings` of the window; `None` without traffic.
+    pub savings: Option<f64>,
+    pub micros_per_packet: Option<f64>,
+}
+
+/// Automatic compression switch (`--compress-auto`).
+///
+/// Fed the outgoing totals tick by tick, it keeps the last `AUTO_WINDOW` of them and
+/// judges the savings over that window. Compressing, savings below `off_below` on
+/// every tick for `off_after` suspend compression. Suspended, the codec still
+/// compresses a probe every `AUTO_PROBE_EVERY` packets, and probes saving `on_above`
+/// for `on_after` resume it. A tick on the other side of the threshold, or a window
+/// without traffic, restarts the clock.
+pub struct AutoSwitch {
+    policy: AutoPolicy,
+    active: bool,
+    /// Per-tick totals, oldest first.
+    samples: VecDeque<(Instant, DirectionTotals)>,
+    /// Since when the window has been past the threshold for a switch.
+    crossed_since: Option<Instant>,
+}
+
+impl AutoSwitch {
+    pub fn new(policy: AutoPolicy) -> Self {
+        Self { policy, active: true, samples: VecDeque::new(), crossed_since: None }
+    }
+
+    /// Feeds the outgoing totals since the previous call. Returns whether compression
+    /// is now on, and the savings that decided it, if it switched.
+    pub fn observe(&mut self, sample: DirectionTotals, now: Instant) -> Option<(bool, f64)> {
+        self.samples.push_back((now, sample));
+        while self.samples.front().is_some_and(|(at, _)| now.duration_since(*at) >= AUTO_WINDOW) {
+            self.samples.pop_front();
+        }
+        let Some(savings) = self.window().savings() else {
+            self.crossed_since = None;
+            return None;
+        };
+        let (crossed, hold) = if self.active {
+            (savings < self.policy.off_below, self.policy.off_after)
+        } else {
+            (savings >= self.policy.on_above, self.policy.on_after)
+        };
+        if !crossed {
+            self.crossed_since = None;
+            return None;
+        }
+        let since = *self.crossed_since.get_or_insert(now);
+        if now.duration_since(since) < hold {
+            return None;
+        }
+        self.active = !self.active;
+        self.crossed_since = None;
+        // The window measured the other regime: every packet, or only the probes.
+        self.samples.clear();
+        Some((self.active, savings))
+    }
+
+    pub fn status(&self) -> AutoStatus {
+        let window = self.window();
+        AutoStatus { active: self.active, savings: window.savings(), micros_per_packet: window.micros_per_packet() }
+    }
+
+    fn window(&self) -> DirectionTotals {
+        self.samples.iter().fold(DirectionTotals::default(), |sum, (_, sample)| sum.plus(sample))
     }
-    false
+}
+
+/// What `--compress-adapt` saw over its last tick, and where it has the level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptStatus {
+    /// `None`: stepped down to raw, with only probes compressed.
+    pub level: Option<i32>,
+    /// Share of the compressing threads' time spent compressing; `None` without traffic.
+    pub cpu: Option<f64>,
+    /// Share of the time the send window was full.
+    pub window_full: f64,
+}
+
+/// One tick of what `LevelGovernor` judges by.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptSample {
+    /// Outgoing totals over the tick.
+    pub totals: DirectionTotals,
+    pub elapsed: Duration,
+    /// Threads compressing: the `--compress-workers`, or the TX tasks compressing inline.
+    pub threads: usize,
+    /// Share of the tick the send window was full, from 0 to 1.
+    pub window_full: f64,
+    /// Packets the `--compress-workers` queue turned away: the pool can't keep up.
+    pub queue_full: u64,
+}
+
+/// Why the level moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bottleneck {
+    /// Compressing takes the CPU time and the link is keeping up: a cheaper level.
+    Cpu,
+    /// The send window stays full with CPU to spare: a costlier level, fewer bytes.
+    Link,
+}
+
+/// Throughput-aware compression level (`--compress-adapt`).
+///
+/// A ladder of levels with raw at the bottom. Each tick is judged on two figures:
+/// how busy the compressing threads are, and how much of the time the send window
+/// was full. Busy threads with a window to spare mean compression is what holds the
+/// sender back, so the level steps down, to raw at the last. A full window with CPU
+/// to spare means the link is, so it steps up, as long as the current level still
+/// saves something. A verdict has to hold for `ADAPT_HOLD` ticks in a row; a tick
+/// without traffic, or a mixed one, restarts the count.
+pub struct LevelGovernor {
+    /// `None` is raw.
+    ladder: Vec<Option<i32>>,
+    rung: usize,
+    streak: Option<(Bottleneck, u32)>,
+    status: AdaptStatus,
+}
+
+impl LevelGovernor {
+    /// Starts at `level`. Lz4 has no levels: its ladder is raw and lz4.
+    pub fn new(algorithm: Algorithm, level: i32) -> Self {
+        let mut levels = match algorithm {
+            Algorithm::Zstd => ADAPT_ZSTD_LEVELS.to_vec(),
+            _ => vec![level],
+        };
+        if !levels.contains(&level) {
+            levels.push(level);
+            levels.sort_unstable();
+        }
+        let ladder: Vec<_> = std::iter::once(None).chain(levels.into_iter().map(Some)).collect();
+        let rung = ladder.iter().position(|&rung| rung == Some(level)).unwrap_or(1);
+        Self { ladder, rung, streak: None, status: AdaptStatus { level: Some(level), cpu: None, window_full: 0.0 } }
+    }
+
+    /// Judges one tick. Returns the new level (`None`: raw) and why, if it moved.
+    pub fn observe(&mut self, sample: AdaptSample) -> Option<(Option<i32>, Bottleneck)> {
+        let busy = sample.elapsed.as_secs_f64() * sample.threads.max(1) as f64;
+        let cpu = (sample.totals.packets > 0 && busy > 0.0).then(|| sample.totals.nanos as f64 / 1e9 / busy);
+        self.status.cpu = cpu;
+        self.status.window_full = sample.window_full;
+        let Some(cpu) = cpu else {
+            self.streak = None;
+            return None;
+        };
+        let link_bound = sample.window_full >= ADAPT_LINK_BOUND;
+        let verdict = if (cpu >= ADAPT_CPU_HIGH || sample.queue_full > 0) && !link_bound && self.rung > 0 {
+            Some(Bottleneck::Cpu)
+        } else if link_bound && cpu < ADAPT_CPU_SPARE && sample.queue_full == 0 && self.rung + 1 < self.ladder.len()
+            && sample.totals.savings().is_some_and(|savings| savings >= ADAPT_MIN_SAVINGS) {
+            Some(Bottleneck::Link)
+        } else {
+            None
+        };
+        let Some(verdict) = verdict else {
+            self.streak = None;
+            return None;
+        };
+        let ticks = match self.streak {
+            Some((previous, ticks)) if previous == verdict => ticks + 1,
+            _ => 1,
+        };
+        if ticks < ADAPT_HOLD {
+            self.streak = Some((verdict, ticks));
+            return None;
+        }
+        self.streak = None;
+        self.rung = match verdict {
+            Bottleneck::Cpu => self.rung - 1,
+            Bottleneck::Link => self.rung + 1,
+        };
+        self.status.level = self.ladder[self.rung];
+        Some((self.status.level, verdict))
+    }
+
+    pub fn status(&self) -> AdaptStatus {
+        self.status
+    }
+}
+
+/// Lock-free `DirectionTotals`, updated once per packet.
+#[derive(Default)]
+struct DirectionCounters {
+    packets: AtomicU64,
+    raw_bytes: AtomicU64,
+    wire_bytes: AtomicU64,
+    uncompressed: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl DirectionCounters {
+    fn record(&self, raw: usize, wire: usize, uncompressed: bool, elapsed: Duration) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.raw_bytes.fetch_add(raw as u64, Ordering::Relaxed);
+        self.wire_bytes.fetch_add(wire as u64, Ordering::Relaxed);
+        if uncompressed {
+            self.uncompressed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn totals(&self) -> DirectionTotals {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        DirectionTotals {
+            packets: get(&self.packets),
+            raw_bytes: get(&self.raw_bytes),
+            wire_bytes: get(&self.wire_bytes),
+            uncompressed: get(&self.uncompressed),
+            nanos: get(&self.nanos),
+        }
+    }
+}
+
+/// Small packets against the dictionary, and how they would have fared without it.
+/// Byte counts include the marker byte; a packet the dictionary couldn't shrink
+/// enough counts at its raw size, as sent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DictionaryStats {
+    pub id: u32,
+    /// The peer advertised the same dictionary, so it is in use.
+    pub agreed: bool,
+    /// Small packets that went through the dictionary path.
+    pub packets: u64,
+    pub raw_bytes: u64,
+    pub sent_bytes: u64,
+    /// The sample of those packets also compressed without the dictionary: their raw
+    /// size, what plain zstd would have sent, and what the dictionary did send.
+    pub sampled_raw: u64,
+    pub sampled_plain: u64,
+    pub sampled_dict: u64,
+}
+
+impl DictionaryStats {
+    /// Raw over sent bytes for every dictionary packet.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.sent_bytes > 0).then(|| self.raw_bytes as f64 / self.sent_bytes as f64)
+    }
+
+    /// Raw over sent bytes for the sample, without and with the dictionary: the second
+    /// above the first is what the dictionary is worth on this traffic.
+    pub fn sampled_ratios(&self) -> Option<(f64, f64)> {
+        (self.sampled_plain > 0 && self.sampled_dict > 0)
+            .then(|| (self.sampled_raw as f64 / self.sampled_plain as f64, self.sampled_raw as f64 / self.sampled_dict as f64))
+    }
+}
+
+/// The dictionary loaded into reusable zstd contexts (loading it per packet would
+/// cost more than compressing), with its agreement flag and measurements.
+struct DictCodec {
+    id: u32,
+    agreed: AtomicBool,
+    compressor: Mutex<zstd::bulk::Compressor<'static>>,
+    decompressor: Mutex<zstd::bulk::Decompressor<'static>>,
+    packets: AtomicU64,
+    raw_bytes: AtomicU64,
+    sent_bytes: AtomicU64,
+    sampled_raw: AtomicU64,
+    sampled_plain: AtomicU64,
+    sampled_dict: AtomicU64,
+}
+
+impl DictCodec {
+    fn new(dict: &Dictionary, level: i32) -> Result<Self> {
+        Ok(Self {
+            id: dict.id,
+            agreed: AtomicBool::new(false),
+            compressor: Mutex::new(zstd::bulk::Compressor::with_dictionary(level, &dict.bytes).context("ZstdDict::LoadFail")?),
+            decompressor: Mutex::new(zstd::bulk::Decompressor::with_dictionary(&dict.bytes).context("ZstdDict::LoadFail")?),
+            packets: AtomicU64::new(0),
+            raw_bytes: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            sampled_raw: AtomicU64::new(0),
+            sampled_plain: AtomicU64::new(0),
+            sampled_dict: AtomicU64::new(0),
+        })
+    }
+
+    /// Like `compress`, with `DICT_MIN_LEN` in place of `--compress-min-size` (the
+    /// dictionary is what makes short packets worth trying) and no entropy estimate on
+    /// samples too short for one. `--compress-min-savings` still applies.
+    fn compress(&self, data: &[u8], level: i32, policy: &Policy) -> (Vec<u8>, Outcome) {
+        if data.len() < DICT_MIN_LEN {
+            return (uncompressed(data), Outcome::TooSmall);
+        }
+        if is_known_compressed(data) || (data.len() >= DICT_ENTROPY_MIN_LEN && looks_random(data)) {
+            return (uncompressed(data), Outcome::HighEntropy);
+        }
+
+        let raw_len = data.len() + 1;
+        let encoded = self.compressor.lock().compress(data);
+        let (out, outcome) = match encoded {
+            Ok(body) if saves_enough(raw_len, body.len() + 1, policy.min_savings) => {
+                let mut out = Vec::with_capacity(body.len() + 1);
+                out.push(ZSTD_DICT);
+                out.extend_from_slice(&body);
+                (out, Outcome::Compressed)
+            }
+            _ => (uncompressed(data), Outcome::NoGain),
+        };
+
+        let packets = self.packets.fetch_add(1, Ordering::Relaxed) + 1;
+        self.raw_bytes.fetch_add(raw_len as u64, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(out.len() as u64, Ordering::Relaxed);
+        if packets.is_multiple_of(DICT_SAMPLE_EVERY) {
+            let plain = match encode(Algorithm::Zstd, level, data) {
+                Ok(plain) if saves_enough(raw_len, plain.len(), policy.min_savings) => plain.len(),
+                _ => raw_len,
+            };
+            self.sampled_raw.fetch_add(raw_len as u64, Ordering::Relaxed);
+            self.sampled_plain.fetch_add(plain as u64, Ordering::Relaxed);
+            self.sampled_dict.fetch_add(out.len() as u64, Ordering::Relaxed);
+        }
+        (out, outcome)
+    }
+
+    fn stats(&self) -> DictionaryStats {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        DictionaryStats {
+            id: self.id,
+            agreed: self.agreed.load(Ordering::Relaxed),
+            packets: get(&self.packets),
+            raw_bytes: get(&self.raw_bytes),
+            sent_bytes: get(&self.sent_bytes),
+            sampled_raw: get(&self.sampled_raw),
+            sampled_plain: get(&self.sampled_plain),
+            sampled_dict: get(&self.sampled_dict),
+        }
+    }
+}
+
+/// Why `data` should go out raw without calling the compressor, if it should:
+/// too short to be worth the call, or content that won't shrink.
+pub fn screen(data: &[u8], policy: &Policy) -> Option<Outcome> {
+    if data.len() < policy.min_size.max(1) {
+        return Some(Outcome::TooSmall);
+    }
+    if is_known_compressed(data) || looks_random(data) {
+        return Some(Outcome::HighEntropy);
+    }
+    None
+}
+
+/// Starts with the header of an already-compressed format.
+fn is_known_compressed(data: &[u8]) -> bool {
+    MAGIC_HEADERS.iter().any(|magic| data.starts_with(magic))
+}
+
+/// Whether an encoding of `encoded` bytes saves at least `min_savings` percent over `raw`.
+pub fn saves_enough(raw: usize, encoded: usize, min_savings: u8) -> bool {
+    encoded * 100 <= raw * (100 - min_savings.min(100) as usize)
+}
+
+/// Shannon entropy of the leading bytes against the most a sample that long can reach.
+/// Only a cheap estimate: it misses structured ciphertext, but catches the TLS and
+/// QUIC records that make up most tunnelled traffic.
+fn looks_random(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(ENTROPY_SAMPLE)];
+    if sample.len() < 2 {
+        return false;
+    }
+    let mut histogram = [0u32; 256];
+    for &byte in sample {
+        histogram[byte as usize] += 1;
+    }
+    let len = sample.len() as f64;
+    let entropy: f64 = histogram.iter().filter(|&&n| n > 0).map(|&n| {
+        let p = n as f64 / len;
+        -p * p.log2()
+    }).sum();
+    entropy >= ENTROPY_SKIP_SHARE * len.min(256.0).log2()
 }
 
 /// "Adaptive" Compression.
-/// 
+///
 /// STRATEGY:
-/// 1. Check if data looks like it's already compressed (Images, Zip).
-/// 2. If yes, skip Zstd (CPU expensive, 0% gain).
-/// 3. If no, compress with Zstd Level 3 (Sweet spot for real-time traffic).
+/// 1. Skip payloads too short to be worth it, and those that look already compressed
+///    or encrypted (known headers, high byte entropy): CPU expensive, 0% gain.
+/// 2. Compress the rest with `algorithm` (`level` only applies to Zstd).
+/// 3. Keep the result only if it saves `policy.min_savings` percent over the raw framing.
 ///
-/// Returns: [FLAG (1B) | PAYLOAD]
-pub fn adaptive_compress(data: &[u8]) -> Result<Vec<u8>> {
-    // Flag: 0 = Raw, 1 = Compressed
-    
-    if is_high_entropy(data) {
-        let mut out = Vec::with_capacity(data.len() + 1);
-        out.push(0u8); 
-        out.extend_from_slice(data);
-        return Ok(out);
+/// Returns: [FLAG (1B) | PAYLOAD], and which of these applied.
+pub fn compress(algorithm: Algorithm, level: i32, policy: &Policy, data: &[u8]) -> Result<(Vec<u8>, Outcome)> {
+    if algorithm == Algorithm::Off {
+        return Ok((uncompressed(data), Outcome::Disabled));
+    }
+    if let Some(skip) = screen(data, policy) {
+        return Ok((uncompressed(data), skip));
     }
 
+    let out = encode(algorithm, level, data)?;
+    // Raw framing is one byte longer than the packet.
+    if !saves_enough(data.len() + 1, out.len(), policy.min_savings) {
+        return Ok((uncompressed(data), Outcome::NoGain));
+    }
+    Ok((out, Outcome::Compressed))
+}
+
+/// [FLAG (1B) | PAYLOAD] with `algorithm`, whatever the data and however little it saves.
+pub fn encode(algorithm: Algorithm, level: i32, data: &[u8]) -> Result<Vec<u8>> {
     let mut out = Vec::with_capacity(data.len());
-    out.push(1u8); 
-    
-    // Zstd Level 3 is standard. 
-    // TODO: Make compression level configurable via TunOptions.
-    copy_encode(Cursor::new(data), &mut out, 3).context("Zstd::EncodeFail")?;
-    
+    out.push(algorithm.marker());
+    match algorithm {
+        Algorithm::Zstd => copy_encode(Cursor::new(data), &mut out, level).context("Zstd::EncodeFail")?,
+        // Block format behind the inner packet's length (u32 LE), which bounds decoding.
+        Algorithm::Lz4 => out.extend_from_slice(&lz4_flex::compress_prepend_size(data)),
+        Algorithm::Off => out.extend_from_slice(data),
+    }
     Ok(out)
 }
 
-pub fn adaptive_decompress(data: &[u8]) -> Result<Vec<u8>> {
+/// Frames `data` with the raw flag (compression disabled or not worthwhile).
+pub fn uncompressed(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 1);
+    out.push(RAW);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Decodes `[FLAG (1B) | PAYLOAD]` strictly by its flag: the content is never sniffed,
+/// so an inner packet that happens to look like a zstd frame passes through untouched.
+/// Whether the flag was negotiated is `Codec::accepts`' call.
+///
+/// Output is capped at `limit` bytes, checked before anything that size is allocated:
+/// zstd decodes in one pass into a buffer of exactly `limit` and stops when it is
+/// full, and LZ4's declared size is checked first. A payload that would run past it
+/// fails with `Oversized`.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
     if data.is_empty() { return Ok(vec![]); }
 
     let flag = data[0];
     let content = &data[1..];
 
     match flag {
-        0 => Ok(content.to_vec()), // Pass-through
-        1 => {
-            let mut out = Vec::new();
-            copy_decode(Cursor::new(content), &mut out).context("Zstd::DecodeFail")?;
-            Ok(out)
+        RAW => Ok(content.to_vec()), // Pass-through
+        ZSTD => zstd_result(zstd::bulk::decompress(content, limit), limit, "Zstd::DecodeFail"),
+        LZ4 => {
+            let (size, block) = content.split_first_chunk::<4>().context("Lz4::Truncated")?;
+            let size = u32::from_le_bytes(*size) as usize;
+            if size > limit {
+                return Err(Oversized { limit }.into());
+            }
+            lz4_flex::decompress(block, size).context("Lz4::DecodeFail")
         }
         _ => anyhow::bail!("Compression::UnknownFlag: {}", flag),
     }
 }
+
+/// A payload that would decompress to more than the largest packet. Authenticated is
+/// not the same as harmless (a compromised peer, or anyone holding the key): a few
+/// bytes of zstd can describe megabytes of output.
+#[derive(Debug)]
+pub struct Oversized {
+    pub limit: usize,
+}
+
+impl fmt::Display for Oversized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compression::Oversized: decodes to more than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for Oversized {}
+
+/// zstd reports a full output buffer as its own error (only as text through the
+/// `zstd` crate); that is an `Oversized` payload, anything else a corrupt one.
+fn zstd_result(result: io::Result<Vec<u8>>, limit: usize, context: &'static str) -> Result<Vec<u8>> {
+    let dst_too_small = (zstd::zstd_safe::zstd_sys::ZSTD_ErrorCode::ZSTD_error_dstSize_tooSmall as usize).wrapping_neg();
+    match result {
+        Err(e) if e.to_string() == zstd::zstd_safe::get_error_name(dst_too_small) => Err(Oversized { limit }.into()),
+        other => other.context(context),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+
+    const POLICY: Policy = Policy { min_size: DEFAULT_MIN_SIZE, min_savings: DEFAULT_MIN_SAVINGS };
+    const LIMIT: usize = 1500;
+
+    /// An IPv4 header and a repetitive HTTP body: the kind of packet compression is for.
+    fn text_packet() -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0x05, 0xdc, 0, 0, 0x40, 0, 0x40, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        while packet.len() < 1200 {
+            packet.extend_from_slice(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n");
+        }
+        packet
+    }
+
+    /// Stands in for TLS or QUIC records: bytes no compressor can shrink.
+    fn random_packet(len: usize) -> Vec<u8> {
+        let mut packet = vec![0; len];
+        rand::rngs::StdRng::seed_from_u64(7).fill_bytes(&mut packet);
+        packet
+    }
+
+    fn codec(algorithm: Algorithm) -> Codec {
+        let codec = Codec::new(algorithm, DEFAULT_ZSTD_LEVEL, algorithm, POLICY, None, LIMIT).unwrap();
+        codec.negotiate(&[algorithm]);
+        codec
+    }
+
+    #[test]
+    fn every_payload_leads_with_its_marker() {
+        let packet = text_packet();
+        for (algorithm, marker) in [(Algorithm::Off, RAW), (Algorithm::Lz4, LZ4), (Algorithm::Zstd, ZSTD)] {
+            let encoded = encode(algorithm, DEFAULT_ZSTD_LEVEL, &packet).unwrap();
+            assert_eq!(encoded[0], marker, "{:?}", algorithm);
+            assert_eq!(decompress(&encoded, LIMIT).unwrap(), packet, "{:?}", algorithm);
+        }
+        assert_eq!(uncompressed(&packet)[0], RAW);
+        assert!(decompress(&[0x7f, 1, 2, 3], LIMIT).is_err());
+    }
+
+    #[test]
+    fn raw_payloads_are_never_sniffed() {
+        // Inner packets that start like a compressed payload, or like a zstd frame.
+        let zstd_frame = zstd::bulk::compress(&text_packet(), DEFAULT_ZSTD_LEVEL).unwrap();
+        let lookalikes = [vec![ZSTD, 0x28, 0xb5, 0x2f, 0xfd, 0], vec![LZ4, 4, 0, 0, 0, 0x40], zstd_frame, vec![ZSTD_DICT; 30]];
+        for inner in lookalikes {
+            let payload = uncompressed(&inner);
+            assert_eq!(decompress(&payload, LIMIT).unwrap(), inner);
+            assert_eq!(codec(Algorithm::Zstd).decompress(&payload).unwrap(), inner);
+        }
+    }
+
+    #[test]
+    fn only_negotiated_markers_are_accepted() {
+        let zstd = encode(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, &text_packet()).unwrap();
+        let lz4 = encode(Algorithm::Lz4, 0, &text_packet()).unwrap();
+        let raw = uncompressed(&text_packet());
+        for (algorithm, accepted) in [(Algorithm::Off, [true, false, false]), (Algorithm::Lz4, [true, false, true]), (Algorithm::Zstd, [true, true, false])] {
+            let codec = codec(algorithm);
+            assert_eq!([codec.accepts(&raw), codec.accepts(&zstd), codec.accepts(&lz4)], accepted, "{:?}", algorithm);
+            assert!(!codec.accepts(&[ZSTD_DICT, 1, 2]), "{:?} has no dictionary", algorithm);
+            assert!(!codec.accepts(&[0x7f]));
+        }
+    }
+
+    #[test]
+    fn screen_sends_tiny_and_random_payloads_raw_untried() {
+        assert_eq!(screen(&[], &POLICY), Some(Outcome::TooSmall));
+        assert_eq!(screen(&text_packet()[..DEFAULT_MIN_SIZE - 1], &POLICY), Some(Outcome::TooSmall));
+        assert_eq!(screen(&random_packet(1200), &POLICY), Some(Outcome::HighEntropy));
+        // A short sample is judged against the most entropy its length allows.
+        assert_eq!(screen(&random_packet(DEFAULT_MIN_SIZE), &POLICY), Some(Outcome::HighEntropy));
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF];
+        jpeg.resize(1200, 0);
+        assert_eq!(screen(&jpeg, &POLICY), Some(Outcome::HighEntropy));
+        assert_eq!(screen(&vec![0; 1200], &POLICY), None);
+        assert_eq!(screen(&text_packet(), &POLICY), None);
+        // `--compress-min-size 0` still never calls the compressor on nothing.
+        assert_eq!(screen(&[], &Policy { min_size: 0, ..POLICY }), Some(Outcome::TooSmall));
+    }
+
+    #[test]
+    fn compress_decides_per_payload() {
+        for algorithm in [Algorithm::Lz4, Algorithm::Zstd] {
+            let decide = |data: &[u8]| compress(algorithm, DEFAULT_ZSTD_LEVEL, &POLICY, data).unwrap();
+            for packet in [vec![0; 1200], text_packet()] {
+                let (out, outcome) = decide(&packet);
+                assert_eq!(outcome, Outcome::Compressed, "{:?}", algorithm);
+                assert!(out.len() < packet.len() / 2, "{:?}", algorithm);
+            }
+            assert_eq!(decide(&random_packet(1200)), (uncompressed(&random_packet(1200)), Outcome::HighEntropy));
+            assert_eq!(decide(&[0; 40]), (uncompressed(&[0; 40]), Outcome::TooSmall));
+            // Compressible, but not by what the policy asks.
+            let strict = Policy { min_size: 0, min_savings: 99 };
+            let (out, outcome) = compress(algorithm, DEFAULT_ZSTD_LEVEL, &strict, &text_packet()).unwrap();
+            assert_eq!((out, outcome), (uncompressed(&text_packet()), Outcome::NoGain));
+        }
+        assert_eq!(compress(Algorithm::Off, 0, &POLICY, &text_packet()).unwrap().1, Outcome::Disabled);
+    }
+
+    #[test]
+    fn savings_count_the_marker_byte() {
+        assert!(saves_enough(100, 95, 5));
+        assert!(!saves_enough(100, 96, 5));
+        assert!(saves_enough(100, 100, 0));
+        assert!(!saves_enough(100, 1, 100));
+        // A 101-byte raw framing (marker included) needs 96 bytes or fewer at 5%.
+        assert!(saves_enough(101, 95, 5));
+        assert!(!saves_enough(101, 96, 5));
+    }
+
+    fn is_oversized(result: Result<Vec<u8>>) -> bool {
+        result.is_err_and(|e| e.is::<Oversized>())
+    }
+
+    #[test]
+    fn decompression_stops_at_the_limit() {
+        for algorithm in [Algorithm::Lz4, Algorithm::Zstd] {
+            let fits = encode(algorithm, DEFAULT_ZSTD_LEVEL, &[0; LIMIT]).unwrap();
+            assert_eq!(decompress(&fits, LIMIT).unwrap().len(), LIMIT, "{:?}", algorithm);
+            let over = encode(algorithm, DEFAULT_ZSTD_LEVEL, &[0; LIMIT + 1]).unwrap();
+            assert!(is_oversized(decompress(&over, LIMIT)), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn bombs_are_refused_without_the_memory() {
+        // 64 MiB of zeros in well under a kilobyte of zstd.
+        let bomb = encode(Algorithm::Zstd, 19, &vec![0; 64 << 20]).unwrap();
+        assert!(bomb.len() < 4096, "{} bytes", bomb.len());
+        assert!(is_oversized(decompress(&bomb, LIMIT)));
+        // LZ4 states its size up front: 4 GiB is refused before anything is allocated.
+        let mut lz4 = vec![LZ4];
+        lz4.extend_from_slice(&u32::MAX.to_le_bytes());
+        lz4.extend_from_slice(&[0x1f, 0, 1, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert!(is_oversized(decompress(&lz4, LIMIT)));
+        // A size that fits, over a block that claims more, is corrupt rather than oversized.
+        lz4[1..5].copy_from_slice(&100u32.to_le_bytes());
+        let corrupt = decompress(&lz4, LIMIT);
+        assert!(corrupt.is_err() && !is_oversized(corrupt));
+    }
+
+    #[test]
+    fn codec_drops_a_bomb_and_decodes_what_follows() {
+        let dictionary = crate::dictionary::Dictionary::load(&crate::dictionary::DictSource::Builtin).unwrap().unwrap();
+        let mut dict_bomb = vec![ZSTD_DICT];
+        dict_bomb.extend(zstd::bulk::Compressor::with_dictionary(3, &dictionary.bytes).unwrap().compress(&vec![0; 16 << 20]).unwrap());
+        let codec = Codec::new(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, Algorithm::Zstd, POLICY, Some(dictionary), LIMIT).unwrap();
+        let bomb = encode(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, &vec![0; 16 << 20]).unwrap();
+        for payload in [bomb, dict_bomb] {
+            assert!(codec.accepts(&payload));
+            assert!(is_oversized(codec.decompress(&payload)));
+            let next = encode(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, &text_packet()).unwrap();
+            assert_eq!(codec.decompress(&next).unwrap(), text_packet());
+        }
+        // Refused payloads are not counted as received.
+        assert_eq!(codec.stats().rx.packets, 2);
+    }
+
+    #[test]
+    fn the_limit_never_exceeds_an_ip_packet() {
+        let codec = Codec::new(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, Algorithm::Zstd, POLICY, None, usize::MAX).unwrap();
+        let over = encode(Algorithm::Zstd, DEFAULT_ZSTD_LEVEL, &vec![0; MAX_DECOMPRESSED + 1]).unwrap();
+        assert!(is_oversized(codec.decompress(&over)));
+    }
+
+    const AUTO: AutoPolicy = AutoPolicy {
+        off_below: 5.0,
+        off_after: Duration::from_secs(3),
+        on_above: 10.0,
+        on_after: Duration::from_secs(3),
+    };
+
+    /// One second of outgoing traffic that saved `savings` percent.
+    fn tick(savings: f64) -> DirectionTotals {
+        DirectionTotals { packets: 10, raw_bytes: 10_000, wire_bytes: (10_000.0 * (1.0 - savings / 100.0)) as u64, uncompressed: 0, nanos: 50_000 }
+    }
+
+    /// Feeds one tick a second from `start`, returning the second each switch came at.
+    fn run(switch: &mut AutoSwitch, start: Instant, from: u64, ticks: &[DirectionTotals]) -> Vec<(u64, bool)> {
+        ticks.iter().zip(from..).filter_map(|(sample, second)| {
+            switch.observe(*sample, start + Duration::from_secs(second)).map(|(on, _)| (second, on))
+        }).collect()
+    }
+
+    #[test]
+    fn auto_suspends_after_holding_below_the_threshold() {
+        let mut switch = AutoSwitch::new(AUTO);
+        let start = Instant::now();
+        assert_eq!(run(&mut switch, start, 0, &[tick(30.0); 5]), vec![]);
+        // The window falls below 5% once the 30% seconds have left it (at 9s), then holds 3s.
+        assert_eq!(run(&mut switch, start, 5, &[tick(1.0); 12]), vec![(12, false)]);
+        assert!(!switch.status().active);
+    }
+
+    #[test]
+    fn auto_holds_between_the_thresholds() {
+        let start = Instant::now();
+        let mut switch = AutoSwitch::new(AUTO);
+        // 7% is no reason to stop compressing...
+        assert_eq!(run(&mut switch, start, 0, &[tick(7.0); 30]), vec![]);
+        assert!(switch.status().active);
+        // ...nor, once stopped, to start again.
+        assert_eq!(run(&mut switch, start, 30, &[tick(0.0); 6]), vec![(34, false)]);
+        assert_eq!(run(&mut switch, start, 36, &[tick(7.0); 30]), vec![]);
+        assert!(!switch.status().active);
+    }
+
+    #[test]
+    fn auto_restarts_the_clock_when_the_window_recovers() {
+        let start = Instant::now();
+        let mut switch = AutoSwitch::new(AUTO);
+        // Two seconds below, then a burst of compressible traffic lifts the window.
+        assert_eq!(run(&mut switch, start, 0, &[tick(0.0), tick(0.0), tick(60.0)]), vec![]);
+        // The burst keeps the window above 5% until it leaves it (5s), then 3s more.
+        assert_eq!(run(&mut switch, start, 3, &[tick(0.0); 8]), vec![(10, false)]);
+        // A whole window without traffic: no verdict, and no clock running.
+        let mut switch = AutoSwitch::new(AutoPolicy { off_after: Duration::from_secs(10), ..AUTO });
+        assert_eq!(run(&mut switch, start, 0, &[tick(0.0); 2]), vec![]);
+        assert_eq!(run(&mut switch, start, 2, &[DirectionTotals::default(); 5]), vec![]);
+        assert_eq!(switch.status().savings, None);
+        assert_eq!(run(&mut switch, start, 7, &[tick(0.0); 11]), vec![(17, false)]);
+    }
+
+    #[test]
+    fn auto_resumes_on_probes_that_save() {
+        let start = Instant::now();
+        let mut switch = AutoSwitch::new(AUTO);
+        assert_eq!(run(&mut switch, start, 0, &[tick(0.0); 4]), vec![(3, false)]);
+        // The window starts afresh: what the probes save, not the traffic before.
+        assert_eq!(switch.status().savings, None);
+        assert_eq!(run(&mut switch, start, 4, &[tick(25.0); 4]), vec![(7, true)]);
+        assert!(switch.status().active);
+    }
+
+    #[test]
+    fn suspended_codec_compresses_only_probes() {
+        let codec = codec(Algorithm::Zstd);
+        codec.set_suspended(true);
+        let outcomes: Vec<_> = (0..2 * AUTO_PROBE_EVERY).map(|_| codec.compress(&text_packet()).1).collect();
+        for (i, outcome) in outcomes.iter().enumerate() {
+            let probe = (i as u64).is_multiple_of(AUTO_PROBE_EVERY);
+            assert_eq!(*outcome, if probe { Outcome::Compressed } else { Outcome::Suspended }, "packet {}", i);
+        }
+        // Only the probes are measured, so the totals show what compression would achieve.
+        assert_eq!(codec.stats().tx.packets, 2);
+        codec.set_suspended(false);
+        assert_eq!(codec.compress(&text_packet()).1, Outcome::Compressed);
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+use crate::handshake::HandshakeState;
+
+/// Where the tunnel stands, as reported to the operator: the dashboard badge, the
+/// control socket's `state=` and systemd's `STATUS=` all show `name()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No peer: waiting for one to contact us.
+    #[default]
+    Listening,
+    /// A peer is set but has not been heard from yet.
+    Handshaking,
+    /// The peer is up and talking.
+    Established,
+    /// The peer is up but has gone quiet for longer than a heartbeat interval.
+    Stale,
+    /// The session was lost and a new handshake is under way.
+    Reconnecting,
+    /// The session was lost, or the handshake gave up; nothing is trying any more.
+    Dead,
+    /// A TUN interface vanished and is being recreated: nothing is forwarded meanwhile.
+    TunDown,
+}
+
+impl ConnectionState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConnectionState::Listening => "LISTENING",
+            ConnectionState::Handshaking => "HANDSHAKING",
+            ConnectionState::Established => "ESTABLISHED",
+            ConnectionState::Stale => "STALE",
+            ConnectionState::Reconnecting => "RECONNECTING",
+            ConnectionState::Dead => "DEAD",
+            ConnectionState::TunDown => "TUN_DOWN",
+        }
+    }
+}
+
+/// What the core looks like right now, as far as the connection state is concerned.
+pub struct Observation<'a> {
+    /// An interface has vanished and is not back yet.
+    pub tun_down: bool,
+    pub has_peer: bool,
+    /// The liveness flag: set by any frame from the peer, cleared after the peer timeout.
+    pub peer_up: bool,
+    /// Time since the last frame from the peer.
+    pub silent_for: Duration,
+    pub handshake: &'a HandshakeState,
+}
+
+/// The current state and when it was entered, derived from periodic observations.
+pub struct Tracker {
+    state: ConnectionState,
+    since: Instant,
+    /// Silence that turns an established session stale.
+    stale_after: Duration,
+    /// A session has been up before, so a new handshake is a reconnect.
+    was_established: bool,
+}
+
+impl Tracker {
+    pub fn new(stale_after: Duration) -> Self {
+        Self { state: ConnectionState::Listening, since: Instant::now(), stale_after, was_established: false }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// How long the tunnel has been in the current state.
+    pub fn elapsed(&self) -> Duration {
+        self.since.elapsed()
+    }
+
+    /// Records an observation and returns the resulting state.
+    pub fn observe(&mut self, obs: &Observation) -> ConnectionState {
+        let next = self.classify(obs);
+        self.was_established |= next == ConnectionState::Established;
+        if next != self.state {
+            self.state = next;
+            self.since = Instant::now();
+        }
+        next
+    }
+
+    fn classify(&self, obs: &Observation) -> ConnectionState {
+        if obs.tun_down {
+            return ConnectionState::TunDown;
+        }
+        if !obs.has_peer {
+            return ConnectionState::Listening;
+        }
+        if obs.peer_up {
+            return if obs.silent_for >= self.stale_after { ConnectionState::Stale } else { ConnectionState::Established };
+        }
+        match obs.handshake {
+            HandshakeState::Failed { .. } => ConnectionState::Dead,
+            HandshakeState::AwaitingReply { .. } if self.was_established => ConnectionState::Reconnecting,
+            _ if self.was_established => ConnectionState::Dead,
+            _ => ConnectionState::Handshaking,
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Runtime control interface.
+//!
+//! **Protocol** (`--control-socket <path>`): one command per line, one reply line per
+//! command. Replies start with `OK` or `ERR`, followed by `key=value` pairs or a message.
+//!
+//! | Command | Effect |
+//! | :--- | :--- |
+//! | `set-peer <ip:port>` | Point the tunnel at a new peer address |
+//! | `get-stats` | Peer, link quality, window and pacing state |
+//! | `status` | Frame and drop counters (same figures as the dashboard panel), plus `alerts=` (firing alert names or `none`) |
+//! | `pause` / `resume` | Stop / restart forwarding data (session stays alive) |
+//! | `rekey <key>` | Rotate to a new pre-shared key (64 hex digits) once the peer has it too; the old key is accepted for a grace window |
+//! | `toggle-compression` | Switch outbound compression (`--compress`) on/off; the peer is told |
+//! | `toggle-peer-compression` | Ask the peer to switch its outbound compression on/off |
+//! | `toggle-jitter` | Switch outbound timing jitter on/off |
+//! | `set-jitter off\|<min>-<max>ms` | Switch jitter off, or on with this range at full obfuscation (`--jitter`) |
+//! | `toggle-pacing` | Switch pacing of data frames across the RTT (`--pacing`) on/off |
+//! | `reconnect` | Re-run the handshake with the current peer |
+//! | `heartbeat` | Send a keep-alive immediately |
+//! | `quota` | Current session usage (goodput and wire bytes, packets), limits, remaining quota and `state=` (`ok`, `throttled`, `disconnected`) |
+//! | `reset-quota` | Zero the session's usage and lift throttling or disconnection |
+//! | `reset-stats` | Zero the dashboard's ingress/egress totals (`z`); its rates, the counters and the JSON snapshot totals keep counting |
+//! | `reload-acl` | Re-read `--allow-file` (same as SIGHUP); the old list stays on error |
+//! | `morph` | `--morph` frame sizes so far against the target, bin by bin, with the overhead paid |
+//! | `shutdown` | Stop the tunnel |
+//!
+//! Example: `echo get-stats | socat - UNIX-CONNECT:/run/resilinet.sock`
+//!
+//! The same commands are served over TCP by `--admin-listen` (see `admin.rs`), after
+//! an `auth <token>` line.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::logging::{tlog, Level};
+use crate::obfuscation::JitterSetting;
+use crate::telemetry::TelemetrySender;
+
+/// A runtime command for the networking core.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    SetPeer(SocketAddr),
+    GetStats,
+    Status,
+    Pause,
+    Resume,
+    /// `None`: the key was left out (the dashboard's `r` has none to give).
+    Rekey(Option<NewKey>),
+    ToggleCompression,
+    TogglePeerCompression,
+    ToggleJitter,
+    SetJitter(JitterSetting),
+    TogglePacing,
+    Reconnect,
+    Heartbeat,
+    Quota,
+    ResetQuota,
+    ResetStats,
+    ReloadAcl,
+    Morph,
+    Shutdown,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut parts = line.split_whitespace();
+        let verb = parts.next().ok_or_else(|| anyhow!("empty command"))?;
+        let cmd = match verb {
+            "set-peer" => {
+                let addr = parts.next().ok_or_else(|| anyhow!("usage: set-peer <ip:port>"))?;
+                Command::SetPeer(addr.parse().with_context(|| format!("bad address '{}'", addr))?)
+            }
+            "get-stats" => Command::GetStats,
+            "status" => Command::Status,
+            "pause" => Command::Pause,
+            "resume" => Command::Resume,
+            "rekey" => match parts.next() {
+                Some(hex) => Command::Rekey(Some(NewKey::parse(hex)?)),
+                None => Command::Rekey(None),
+            },
+            "toggle-compression" => Command::ToggleCompression,
+            "toggle-peer-compression" => Command::TogglePeerCompression,
+            "toggle-jitter" => Command::ToggleJitter,
+            "set-jitter" => {
+                let spec = parts.next().ok_or_else(|| anyhow!("usage: set-jitter off|<min>-<max>ms"))?;
+                Command::SetJitter(JitterSetting::parse(spec).map_err(|e| anyhow!(e))?)
+            }
+            "toggle-pacing" => Command::TogglePacing,
+            "reconnect" => Command::Reconnect,
+            "heartbeat" => Command::Heartbeat,
+            "quota" => Command::Quota,
+            "reset-quota" => Command::ResetQuota,
+            "reset-stats" => Command::ResetStats,
+            "reload-acl" => Command::ReloadAcl,
+            "morph" => Command::Morph,
+            "shutdown" => Command::Shutdown,
+            other => bail!("unknown command '{}'", other),
+        };
+        if parts.next().is_some() {
+            bail!("unexpected arguments to '{}'", verb);
+        }
+        Ok(cmd)
+    }
+}
+
+/// A pre-shared key for `rekey`. Kept out of `Debug` output, so it never reaches a log.
+#[derive(Clone, PartialEq)]
+pub struct NewKey(pub [u8; 32]);
+
+impl NewKey {
+    fn parse(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex).map_err(|_| anyhow!("malformed hex key"))?;
+        let key = bytes.try_into().map_err(|_| anyhow!("key must be exactly 32 bytes"))?;
+        Ok(NewKey(key))
+    }
+}
+
+impl fmt::Debug for NewKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NewKey(..)")
+    }
+}
+
+/// A command plus the channel its textual reply goes back on.
+pub struct ControlRequest {
+    pub command: Command,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Sending half of the core's control channel. Shared by every control front-end.
+pub type ControlSender = mpsc::Sender<ControlRequest>;
+
+/// Submits a command and waits for the core's reply line.
+pub async fn request(tx: &ControlSender, command: Command) -> String {
+    let (reply, rx) = oneshot::channel();
+    if tx.send(ControlRequest { command, reply }).await.is_err() {
+        return "ERR core is not running".to_string();
+    }
+    rx.await.unwrap_or_else(|_| "ERR no reply".to_string())
+}
+
+/// Parses one command line and runs it: the reply line, or `ERR` for a bad command.
+pub async fn execute(tx: &ControlSender, line: &str) -> String {
+    match Command::parse(line) {
+        Ok(cmd) => request(tx, cmd).await,
+        Err(e) => format!("ERR {}", e),
+    }
+}
+
+/// Listens on a Unix domain socket and forwards line commands into the core.
+///
+/// A stale socket file from a previous run is removed before binding.
+pub fn spawn_control_socket(path: &Path, tx: ControlSender, stats: TelemetrySender) -> Result<PathBuf> {
+    if path.exists() {
+        std::fs::remove_file(path).with_context(|| format!("Failed to remove stale control socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tlog!(stats, Level::Warn, "CTRL::AcceptErr: {}", e);
+                    continue;
+                }
+            };
+
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let reply = execute(&tx, &line).await;
+                    if write.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(path.to_path_buf())
+}
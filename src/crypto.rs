@@ -1,17 +1,204 @@
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload, rand_core::RngCore, stream::{DecryptorBE32, EncryptorBE32}},
     ChaCha20Poly1305, Nonce, Key
 };
-use anyhow::{Result, anyhow};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use parking_lot::RwLock;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-/// Wrapper around ChaCha20Poly1305 AEAD.
+/// Plaintext bytes per chunk of a sealed stream.
+pub const STREAM_CHUNK: usize = 16 * 1024;
+
+/// Length of the random nonce prefix that opens every sealed stream.
+/// STREAM-BE32 fills the remaining 5 nonce bytes with a chunk counter and a last-chunk flag.
+pub const STREAM_HEADER_LEN: usize = 7;
+
+/// AEAD tag appended to every sealed packet and stream chunk.
+const TAG_LEN: usize = 16;
+
+/// Bytes `encrypt` adds to what it seals: the nonce in front and the tag behind.
+pub const SEAL_OVERHEAD: usize = 12 + TAG_LEN;
+
+/// AEAD constructions a session can seal packets with. Both take the same 32-byte key
+/// and 12-byte nonce, so packets look alike on the wire whichever is in use.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    /// Understood by every version of the tunnel: seals Hellos, and data until a
+    /// suite is negotiated.
+    pub const BASELINE: CipherSuite = CipherSuite::ChaCha20Poly1305;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => "chacha20-poly1305",
+            CipherSuite::Aes256Gcm => "aes-256-gcm",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        if v == CipherSuite::Aes256Gcm as u8 { CipherSuite::Aes256Gcm } else { CipherSuite::ChaCha20Poly1305 }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => CipherSuite::Aes256Gcm,
+            CipherSuite::Aes256Gcm => CipherSuite::ChaCha20Poly1305,
+        }
+    }
+
+    /// Picks the suite for data in both directions; both ends arrive at the same answer.
+    /// A suite both sides list first is fast at both ends. Otherwise ChaCha20-Poly1305,
+    /// which is never slow, and failing that whatever they have in common.
+    pub fn negotiate(ours: &[CipherSuite], theirs: &[CipherSuite]) -> Option<CipherSuite> {
+        let common = |s: &CipherSuite| ours.contains(s) && theirs.contains(s);
+        match (ours.first(), theirs.first()) {
+            (Some(a), Some(b)) if a == b => Some(*a),
+            _ => [CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm].into_iter().find(common),
+        }
+    }
+}
+
+/// Which suites to offer (`--cipher`).
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CipherPolicy {
+    /// AES-256-GCM when this CPU has AES instructions (and the peer's too), else ChaCha20-Poly1305.
+    Auto,
+    /// ChaCha20-Poly1305 only.
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    /// AES-256-GCM only (the peer must accept it too).
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl CipherPolicy {
+    /// Suites to advertise, most preferred first.
+    pub fn preference(self) -> Vec<CipherSuite> {
+        match self {
+            CipherPolicy::Auto if aes_accelerated() => vec![CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305],
+            CipherPolicy::Auto => vec![CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm],
+            CipherPolicy::ChaCha20Poly1305 => vec![CipherSuite::ChaCha20Poly1305],
+            CipherPolicy::Aes256Gcm => vec![CipherSuite::Aes256Gcm],
+        }
+    }
+}
+
+/// Whether this CPU has the instructions (AES rounds plus carry-less multiply for
+/// GHASH) that make AES-GCM faster than ChaCha20-Poly1305. `aes-gcm` uses them on its
+/// own when present; without them it falls back to a much slower bitsliced software
+/// implementation.
+pub fn aes_accelerated() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("aes") && std::arch::is_aarch64_feature_detected!("pmull")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+/// The session's AEADs: ChaCha20Poly1305, and AES-256-GCM where both ends have AES hardware.
 /// 
 /// **AEAD Selection Rationale**:
-/// We utilize ChaCha20Poly1305 over AES-GCM for two primary reasons:
+/// ChaCha20Poly1305 is the default for two primary reasons:
 /// 1. **Performance**: Superior throughput on ARMv8/mobile architecture lacking specialized AES extensions.
 /// 2. **Security**: Constant-time execution in software prevents cache-timing side channels.
+///
+/// With AES-NI (or the ARMv8 crypto extensions) on both ends, AES-GCM is faster still and
+/// just as constant-time, so `--cipher auto` negotiates it in the Hello exchange.
+///
+/// **Key rotation**: the pre-shared key can be replaced at runtime (`rekey`). The new
+/// key is installed as `next` and opens frames at once, but frames are still sealed
+/// with the current one until `promote`, once the peer has the new key too. The old
+/// key then keeps opening frames sealed before the switch for a grace window.
 pub struct SessionGuard {
+    keys: RwLock<KeyRing>,
+    /// Suite outgoing packets are sealed with (`CipherSuite as u8`).
+    active: AtomicU8,
+}
+
+/// A key and the AEADs built from it.
+struct KeyMaterial {
     cipher: ChaCha20Poly1305,
+    aes: Aes256Gcm,
+    fingerprint: u32,
+}
+
+impl KeyMaterial {
+    fn new(key_bytes: &[u8; 32]) -> Arc<Self> {
+        let key = Key::from_slice(key_bytes);
+        Arc::new(Self { cipher: ChaCha20Poly1305::new(key), aes: Aes256Gcm::new(key), fingerprint: key_fingerprint(key_bytes) })
+    }
+
+    fn open(&self, suite: CipherSuite, nonce: &Nonce, payload: Payload) -> Result<Vec<u8>, chacha20poly1305::aead::Error> {
+        match suite {
+            CipherSuite::ChaCha20Poly1305 => self.cipher.decrypt(nonce, payload),
+            CipherSuite::Aes256Gcm => self.aes.decrypt(nonce, payload),
+        }
+    }
+}
+
+/// The key frames are sealed with, plus the ones that still (or already) open them.
+struct KeyRing {
+    current: Arc<KeyMaterial>,
+    /// Installed, not yet sealing: opens frames from a peer that switched first.
+    next: Option<Arc<KeyMaterial>>,
+    /// Replaced by `current`; opens frames sealed before the switch until the deadline.
+    previous: Option<(Arc<KeyMaterial>, Instant)>,
+}
+
+impl KeyRing {
+    fn previous(&self) -> Option<&Arc<KeyMaterial>> {
+        self.previous.as_ref().filter(|(_, until)| Instant::now() < *until).map(|(key, _)| key)
+    }
+}
+
+/// Fingerprints of the keys in use, for display. Never the keys themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStatus {
+    /// The key outgoing frames are sealed with.
+    pub current: u32,
+    /// Installed by `rekey`, waiting for the peer to have it too.
+    pub next: Option<u32>,
+    /// Switched away from, still opening frames during the grace window.
+    pub previous: Option<u32>,
+}
+
+impl KeyStatus {
+    /// e.g. `5e0c19a2`, `5e0c19a2, next 8d41f7b3 (waiting for peer)`, `8d41f7b3, 5e0c19a2 still accepted`.
+    pub fn label(&self) -> String {
+        let mut label = format!("{:08x}", self.current);
+        if let Some(next) = self.next {
+            label.push_str(&format!(", next {:08x} (waiting for peer)", next));
+        }
+        if let Some(previous) = self.previous {
+            label.push_str(&format!(", {:08x} still accepted", previous));
+        }
+        label
+    }
+}
+
+/// Identifies a key without revealing it: the first four bytes of the ChaCha20
+/// keystream under it, at a nonce no frame uses (frames draw theirs at random).
+pub fn key_fingerprint(key_bytes: &[u8; 32]) -> u32 {
+    let sealed = ChaCha20Poly1305::new(Key::from_slice(key_bytes))
+        .encrypt(&Nonce::default(), [0u8; 4].as_slice())
+        .expect("sealing four bytes cannot fail");
+    u32::from_be_bytes([sealed[0], sealed[1], sealed[2], sealed[3]])
 }
 
 impl SessionGuard {
@@ -20,21 +207,81 @@ impl SessionGuard {
     /// FIXME: Hardcoded for prototype. Integrate Diffie-Hellman (Noise IK) for production
     /// to ensure Perfect Forward Secrecy (PFS) and eliminate static key distribution.
     pub fn new(key_bytes: &[u8; 32]) -> Self {
-        let key = Key::from_slice(key_bytes);
-        let cipher = ChaCha20Poly1305::new(key);
-        Self { cipher }
+        let keys = KeyRing { current: KeyMaterial::new(key_bytes), next: None, previous: None };
+        Self { keys: RwLock::new(keys), active: AtomicU8::new(CipherSuite::BASELINE as u8) }
     }
 
-    /// Encrypts data into a wire-ready packet.
+    pub fn key_status(&self) -> KeyStatus {
+        let keys = self.keys.read();
+        KeyStatus {
+            current: keys.current.fingerprint,
+            next: keys.next.as_ref().map(|k| k.fingerprint),
+            previous: keys.previous().map(|k| k.fingerprint),
+        }
+    }
+
+    /// Installs the key to rotate to, replacing one installed earlier. It opens frames
+    /// straight away and seals them after `promote`. Returns its fingerprint.
+    pub fn install_next(&self, key_bytes: &[u8; 32]) -> u32 {
+        let key = KeyMaterial::new(key_bytes);
+        let fingerprint = key.fingerprint;
+        self.keys.write().next = Some(key);
+        fingerprint
+    }
+
+    /// Starts sealing with the installed key `fingerprint`, if that is the one installed.
+    /// The current key keeps opening frames for `grace`. Returns the replaced key's
+    /// fingerprint.
+    pub fn promote(&self, fingerprint: u32, grace: Duration) -> Option<u32> {
+        let mut keys = self.keys.write();
+        if keys.next.as_ref().is_none_or(|k| k.fingerprint != fingerprint) {
+            return None;
+        }
+        let next = keys.next.take()?;
+        let old = std::mem::replace(&mut keys.current, next);
+        let replaced = old.fingerprint;
+        keys.previous = Some((old, Instant::now() + grace));
+        Some(replaced)
+    }
+
+    pub fn active(&self) -> CipherSuite {
+        CipherSuite::from_u8(self.active.load(Ordering::Relaxed))
+    }
+
+    /// Switches the suite outgoing packets use, returning the previous one.
+    pub fn set_active(&self, suite: CipherSuite) -> CipherSuite {
+        CipherSuite::from_u8(self.active.swap(suite as u8, Ordering::Relaxed))
+    }
+
+    /// Encrypts data into a wire-ready packet with the active suite.
     /// Packet Structure: `[NONCE (12B) | CIPHERTEXT (N) | TAG (16B)]`
-    /// Note: The Poly1305 tag is appended automatically by the AEAD crate.
+    /// Note: The tag is appended automatically by the AEAD crate.
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with(self.active(), data)
+    }
+
+    /// Encrypts with a specific suite, e.g. the baseline for a Hello.
+    pub fn encrypt_with(&self, suite: CipherSuite, data: &[u8]) -> Result<Vec<u8>> {
+        self.seal(suite, data, &[])
+    }
+
+    /// Encrypts with the active suite, binding `aad` (which travels elsewhere, e.g. in a
+    /// clear header) into the tag. Empty `aad` is the same as `encrypt`.
+    pub fn encrypt_bound(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.seal(self.active(), data, aad)
+    }
+
+    fn seal(&self, suite: CipherSuite, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
         // Unique nonce generation per packet to strictly strictly prevent key-stream reuse.
         // Trade-off: 12-byte expansion per frame vs. stateful counter synchronization execution complexity.
         let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); 
         
-        let ciphertext = self.cipher.encrypt(&nonce, data)
-            .map_err(|e| anyhow!("Encryption Failure: {}", e))?;
+        let payload = Payload { msg: data, aad };
+        let key = self.keys.read().current.clone();
+        let ciphertext = match suite {
+            CipherSuite::ChaCha20Poly1305 => key.cipher.encrypt(&nonce, payload),
+            CipherSuite::Aes256Gcm => key.aes.encrypt(&nonce, payload),
+        }.map_err(|e| anyhow!("Encryption Failure: {}", e))?;
         
         // Prefix nonce to allow stateless decryption by the receiver
         let mut packet = nonce.to_vec();
@@ -45,7 +292,16 @@ impl SessionGuard {
 
     /// Decrypts a wire packet.
     /// Expects: `[NONCE (12B) | ...]`
+    ///
+    /// **Migration**: The active suite is tried first, then the other, so packets sealed
+    /// before a switch (in flight, retransmitted) and Hellos still open. Likewise the
+    /// current key first, then one being rotated to or away from.
     pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_bound(data, &[])
+    }
+
+    /// Decrypts a packet sealed by `encrypt_bound` with the same `aad`.
+    pub fn decrypt_bound(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
         if data.len() < 12 {
             return Err(anyhow!("Protocol Violation: Insufficient packet length ({} bytes)", data.len()));
         }
@@ -53,9 +309,306 @@ impl SessionGuard {
         let nonce = Nonce::from_slice(&data[0..12]);
         let ciphertext = &data[12..];
 
-        let plaintext = self.cipher.decrypt(nonce, ciphertext)
+        let active = self.active();
+        let keys = self.keys.read();
+        let open = |key: &KeyMaterial| key.open(active, nonce, Payload { msg: ciphertext, aad })
+            .or_else(|_| key.open(active.other(), nonce, Payload { msg: ciphertext, aad }));
+        let plaintext = open(&keys.current)
+            .or_else(|e| keys.next.as_deref().map_or(Err(e), open))
+            .or_else(|e| keys.previous().map_or(Err(e), |key| open(key)))
             .map_err(|e| anyhow!("Decryption Failure: {}", e))?;
 
         Ok(plaintext)
     }
 }
+
+/// Chunked sealing for control-plane payloads too large for one AEAD call (session dumps,
+/// key-list exchange). Each chunk carries its own tag; the STREAM construction binds
+/// chunk order and marks the final chunk, so reordering, splicing or truncation all fail
+/// to open. Not used on the per-packet data path.
+///
+/// Serialized layout (`seal_to` / `open_from`):
+/// `[NONCE PREFIX (7B)] ([LEN (u32 BE) | CIPHERTEXT | TAG (16B)])+`
+#[allow(dead_code)] // Consumed by the control-plane messages as they land.
+impl SessionGuard {
+    /// Starts a sealed stream. The header must reach the receiver before the first chunk.
+    pub fn seal_stream(&self) -> (StreamSealer, [u8; STREAM_HEADER_LEN]) {
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        OsRng.fill_bytes(&mut header);
+        let inner = EncryptorBE32::from_aead(self.keys.read().current.cipher.clone(), (&header).into());
+        (StreamSealer { inner }, header)
+    }
+
+    /// Resumes a stream started by the peer's `seal_stream`.
+    pub fn open_stream(&self, header: &[u8]) -> Result<StreamOpener> {
+        let header: &[u8; STREAM_HEADER_LEN] = header.try_into()
+            .map_err(|_| anyhow!("Protocol Violation: Stream header is {} bytes, expected {}", header.len(), STREAM_HEADER_LEN))?;
+        let inner = DecryptorBE32::from_aead(self.keys.read().current.cipher.clone(), header.into());
+        Ok(StreamOpener { inner })
+    }
+
+    /// Seals everything `input` yields into `output`, holding at most two chunks in memory.
+    pub fn seal_to(&self, mut input: impl Read, mut output: impl Write) -> Result<()> {
+        let (mut sealer, header) = self.seal_stream();
+        output.write_all(&header)?;
+        let mut chunk = read_chunk(&mut input)?;
+        loop {
+            // One chunk of lookahead: the last chunk is sealed differently.
+            let next = read_chunk(&mut input)?;
+            if next.is_empty() {
+                write_frame(&mut output, &sealer.seal_last(&chunk)?)?;
+                return Ok(output.flush()?);
+            }
+            write_frame(&mut output, &sealer.seal_next(&chunk)?)?;
+            chunk = next;
+        }
+    }
+
+    /// Opens a stream written by `seal_to`. Plaintext is written chunk by chunk as it
+    /// verifies; on error the output holds a verified prefix and must be discarded.
+    pub fn open_from(&self, mut input: impl Read, mut output: impl Write) -> Result<()> {
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        input.read_exact(&mut header)?;
+        let mut opener = self.open_stream(&header)?;
+        let Some(mut frame) = read_frame(&mut input)? else {
+            bail!("Protocol Violation: Sealed stream has no chunks");
+        };
+        loop {
+            match read_frame(&mut input)? {
+                Some(next) => {
+                    output.write_all(&opener.open_next(&frame)?)?;
+                    frame = next;
+                }
+                None => {
+                    output.write_all(&opener.open_last(&frame)?)?;
+                    return Ok(output.flush()?);
+                }
+            }
+        }
+    }
+}
+
+/// Sending half of a sealed stream.
+pub struct StreamSealer {
+    inner: EncryptorBE32<ChaCha20Poly1305>,
+}
+
+#[allow(dead_code)]
+impl StreamSealer {
+    /// Seals a chunk that is not the last one.
+    pub fn seal_next(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.inner.encrypt_next(chunk).map_err(|e| anyhow!("Encryption Failure: {}", e))
+    }
+
+    /// Seals the final chunk (may be empty) and ends the stream.
+    pub fn seal_last(self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.inner.encrypt_last(chunk).map_err(|e| anyhow!("Encryption Failure: {}", e))
+    }
+}
+
+/// Receiving half of a sealed stream. Chunks must be opened in the order they were sealed.
+pub struct StreamOpener {
+    inner: DecryptorBE32<ChaCha20Poly1305>,
+}
+
+#[allow(dead_code)]
+impl StreamOpener {
+    pub fn open_next(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.inner.decrypt_next(chunk).map_err(|e| anyhow!("Decryption Failure: {}", e))
+    }
+
+    /// Opens the final chunk. Fails if the sender did not seal it as last (truncated stream).
+    pub fn open_last(self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.inner.decrypt_last(chunk).map_err(|e| anyhow!("Decryption Failure: {}", e))
+    }
+}
+
+/// Reads up to `STREAM_CHUNK` bytes; short only at end of input.
+fn read_chunk(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+    input.take(STREAM_CHUNK as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn write_frame(output: &mut impl Write, sealed: &[u8]) -> io::Result<()> {
+    output.write_all(&(sealed.len() as u32).to_be_bytes())?;
+    output.write_all(sealed)
+}
+
+/// Next length-prefixed chunk, or `None` at end of input.
+fn read_frame(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        // A torn length prefix ends the stream early; `open_last` then rejects the previous chunk.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    // Bound the allocation before trusting the length: no honest chunk is larger.
+    if !(TAG_LEN..=STREAM_CHUNK + TAG_LEN).contains(&len) {
+        bail!("Protocol Violation: Stream chunk of {} bytes", len);
+    }
+    let mut sealed = vec![0u8; len];
+    input.read_exact(&mut sealed)?;
+    Ok(Some(sealed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: [u8; 32] = [1; 32];
+    const NEW: [u8; 32] = [2; 32];
+    const GRACE: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn installed_key_opens_before_it_seals() {
+        let guard = SessionGuard::new(&OLD);
+        let fingerprint = guard.install_next(&NEW);
+        assert_eq!(fingerprint, key_fingerprint(&NEW));
+        assert_eq!(guard.key_status(), KeyStatus { current: key_fingerprint(&OLD), next: Some(fingerprint), previous: None });
+
+        // A peer that switched first is understood...
+        let switched = SessionGuard::new(&NEW);
+        assert_eq!(guard.decrypt(&switched.encrypt(b"from the new key").unwrap()).unwrap(), b"from the new key");
+        // ...but we keep sealing for one that hasn't.
+        let sealed = guard.encrypt(b"still the old key").unwrap();
+        assert_eq!(SessionGuard::new(&OLD).decrypt(&sealed).unwrap(), b"still the old key");
+        assert!(switched.decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn promote_switches_sealing_and_keeps_the_old_key_for_the_grace() {
+        let guard = SessionGuard::new(&OLD);
+        let in_flight = guard.encrypt(b"sealed before the switch").unwrap();
+        let in_flight_aes = guard.encrypt_with(CipherSuite::Aes256Gcm, b"and with AES").unwrap();
+        let fingerprint = guard.install_next(&NEW);
+        assert_eq!(guard.promote(fingerprint, GRACE), Some(key_fingerprint(&OLD)));
+        assert_eq!(guard.key_status(), KeyStatus { current: fingerprint, next: None, previous: Some(key_fingerprint(&OLD)) });
+
+        let sealed = guard.encrypt(b"after the switch").unwrap();
+        assert_eq!(SessionGuard::new(&NEW).decrypt(&sealed).unwrap(), b"after the switch");
+        assert!(SessionGuard::new(&OLD).decrypt(&sealed).is_err());
+        assert_eq!(guard.decrypt(&in_flight).unwrap(), b"sealed before the switch");
+        assert_eq!(guard.decrypt(&in_flight_aes).unwrap(), b"and with AES");
+        // Nothing left to promote.
+        assert_eq!(guard.promote(fingerprint, GRACE), None);
+    }
+
+    #[test]
+    fn old_key_stops_opening_after_the_grace() {
+        let guard = SessionGuard::new(&OLD);
+        let in_flight = guard.encrypt(b"sealed before the switch").unwrap();
+        let fingerprint = guard.install_next(&NEW);
+        guard.promote(fingerprint, Duration::ZERO).unwrap();
+        assert_eq!(guard.key_status().previous, None);
+        assert!(guard.decrypt(&in_flight).is_err());
+        assert!(guard.decrypt(&SessionGuard::new(&NEW).encrypt(b"new").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn promote_refuses_a_key_that_is_not_installed() {
+        let guard = SessionGuard::new(&OLD);
+        assert_eq!(guard.promote(key_fingerprint(&NEW), GRACE), None);
+        // A later install replaces the earlier one, which can no longer be promoted.
+        let replaced = guard.install_next(&NEW);
+        let installed = guard.install_next(&[3; 32]);
+        assert_eq!(guard.promote(replaced, GRACE), None);
+        assert_eq!(guard.key_status(), KeyStatus { current: key_fingerprint(&OLD), next: Some(installed), previous: None });
+        assert!(guard.decrypt(&SessionGuard::new(&NEW).encrypt(b"replaced").unwrap()).is_err());
+        assert_eq!(guard.promote(installed, GRACE), Some(key_fingerprint(&OLD)));
+    }
+
+    #[test]
+    fn status_label_names_fingerprints_only() {
+        let status = KeyStatus { current: 0x5e0c19a2, next: Some(0x8d41f7b3), previous: None };
+        assert_eq!(status.label(), "5e0c19a2, next 8d41f7b3 (waiting for peer)");
+        let status = KeyStatus { current: 0x8d41f7b3, next: None, previous: Some(0x5e0c19a2) };
+        assert_eq!(status.label(), "8d41f7b3, 5e0c19a2 still accepted");
+        assert_eq!(KeyStatus { current: 0x0000_00ff, next: None, previous: None }.label(), "000000ff");
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    /// The chunks of a stream sealed one by one, ahead of its header.
+    fn sealed_chunks(guard: &SessionGuard, chunks: &[&[u8]]) -> ([u8; STREAM_HEADER_LEN], Vec<Vec<u8>>) {
+        let (mut sealer, header) = guard.seal_stream();
+        let (last, rest) = chunks.split_last().unwrap();
+        let mut sealed: Vec<Vec<u8>> = rest.iter().map(|chunk| sealer.seal_next(chunk).unwrap()).collect();
+        sealed.push(sealer.seal_last(last).unwrap());
+        (header, sealed)
+    }
+
+    #[test]
+    fn streams_round_trip_chunk_by_chunk() {
+        let guard = SessionGuard::new(&OLD);
+        for len in [0, 1, STREAM_CHUNK - 1, STREAM_CHUNK, STREAM_CHUNK + 1, 3 * STREAM_CHUNK + 5] {
+            let plain = message(len);
+            let mut sealed = Vec::new();
+            guard.seal_to(&plain[..], &mut sealed).unwrap();
+            // Header, then one length-prefixed and tagged frame per chunk (at least one).
+            let chunks = len.div_ceil(STREAM_CHUNK).max(1);
+            assert_eq!(sealed.len(), STREAM_HEADER_LEN + len + chunks * (4 + TAG_LEN), "{} bytes", len);
+            let mut opened = Vec::new();
+            guard.open_from(&sealed[..], &mut opened).unwrap();
+            assert_eq!(opened, plain, "{} bytes", len);
+        }
+        // The same stream under another key does not open.
+        let mut sealed = Vec::new();
+        guard.seal_to(&message(100)[..], &mut sealed).unwrap();
+        assert!(SessionGuard::new(&NEW).open_from(&sealed[..], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reordered_truncated_or_tampered_chunks_do_not_open() {
+        let guard = SessionGuard::new(&OLD);
+        let parts = [message(50), message(60), message(70)];
+        let (header, sealed) = sealed_chunks(&guard, &[&parts[0], &parts[1], &parts[2]]);
+        let open = |order: &[usize], last: usize| -> Result<Vec<u8>> {
+            let mut opener = guard.open_stream(&header)?;
+            let mut plain = Vec::new();
+            for &i in order {
+                plain.extend(opener.open_next(&sealed[i])?);
+            }
+            plain.extend(opener.open_last(&sealed[last])?);
+            Ok(plain)
+        };
+        assert_eq!(open(&[0, 1], 2).unwrap(), parts.concat());
+        assert!(open(&[1, 0], 2).is_err(), "reordered");
+        // Cut after a chunk not sealed as the last.
+        assert!(open(&[0], 1).is_err(), "truncated");
+        // A chunk spliced in from another stream under the same key.
+        let (_, other) = sealed_chunks(&guard, &[&parts[0], &parts[1], &parts[2]]);
+        let mut opener = guard.open_stream(&header).unwrap();
+        assert!(opener.open_next(&other[0]).is_err(), "spliced");
+        for at in [0, 49, sealed[0].len() - 1] {
+            let mut tampered = sealed[0].clone();
+            tampered[at] ^= 1;
+            let mut opener = guard.open_stream(&header).unwrap();
+            assert!(opener.open_next(&tampered).is_err(), "byte {} flipped", at);
+        }
+        assert!(guard.open_stream(&header[1..]).is_err());
+    }
+
+    #[test]
+    fn cut_or_corrupted_serialized_streams_do_not_open() {
+        let guard = SessionGuard::new(&OLD);
+        let mut sealed = Vec::new();
+        guard.seal_to(&message(2 * STREAM_CHUNK + 10)[..], &mut sealed).unwrap();
+        let first_frame = STREAM_HEADER_LEN + 4 + STREAM_CHUNK + TAG_LEN;
+        // Cut at a chunk boundary, inside a length prefix, inside a chunk, before any chunk.
+        for cut in [first_frame, first_frame + 2, first_frame + 100, STREAM_HEADER_LEN, 3] {
+            assert!(guard.open_from(&sealed[..cut], &mut Vec::new()).is_err(), "cut at {}", cut);
+        }
+        // A length no honest chunk has is refused before anything is allocated for it.
+        let mut oversized = sealed.clone();
+        oversized[STREAM_HEADER_LEN..STREAM_HEADER_LEN + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(guard.open_from(&oversized[..], &mut Vec::new()).is_err());
+        let mut flipped = sealed.clone();
+        flipped[first_frame + 10] ^= 0x80;
+        assert!(guard.open_from(&flipped[..], &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+/// A decrypted packet waiting for the TUN device.
+pub struct Delivery {
+    pub packet: Vec<u8>,
+}
+
+/// Bounded hand-off between the RX loop and the TUN writer.
+///
+/// Receiving and ACKing must never wait on the kernel: when the interface backs up
+/// the queue fills instead, and past `capacity` the oldest packet is discarded. The
+/// newest traffic is the most useful to deliver, and anything older has already been
+/// ACKed, so inner protocols recover it the same way as any other loss on the path.
+pub struct DeliveryQueue {
+    queue: Mutex<VecDeque<Delivery>>,
+    capacity: usize,
+    ready: Notify,
+}
+
+impl DeliveryQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self { queue: Mutex::new(VecDeque::with_capacity(capacity)), capacity: capacity.max(1), ready: Notify::new() }
+    }
+
+    /// Queues `delivery`, returning the packet it displaced if the queue was full.
+    pub fn push(&self, delivery: Delivery) -> Option<Delivery> {
+        let evicted = {
+            let mut queue = self.queue.lock();
+            let evicted = if queue.len() >= self.capacity { queue.pop_front() } else { None };
+            queue.push_back(delivery);
+            evicted
+        };
+        self.ready.notify_one();
+        evicted
+    }
+
+    /// Waits for the oldest queued packet.
+    pub async fn pop(&self) -> Delivery {
+        loop {
+            if let Some(delivery) = self.queue.lock().pop_front() {
+                return delivery;
+            }
+            // `notify_one` leaves a permit when nobody is waiting, so a push between
+            // the check above and this await is not lost.
+            self.ready.notified().await;
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+
+/// Largest `--compress-dict` file accepted. Trained dictionaries are usually ~100KB.
+const MAX_DICT_LEN: usize = 1 << 20;
+
+/// Where the zstd dictionary comes from (`--compress-dict`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum DictSource {
+    /// The small dictionary compiled in (`builtin`, the default).
+    Builtin,
+    /// No dictionary (`none`).
+    Disabled,
+    /// A file: a dictionary from `zstd --train`, or any sample content.
+    File(PathBuf),
+}
+
+impl DictSource {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "builtin" => DictSource::Builtin,
+            "none" => DictSource::Disabled,
+            "" => bail!("expected builtin, none or a file path"),
+            path => DictSource::File(PathBuf::from(path)),
+        })
+    }
+}
+
+/// A zstd dictionary and the id both ends compare in their Hellos.
+pub struct Dictionary {
+    /// FNV-1a of the contents: equal ids mean equal dictionaries, whatever the file was called.
+    pub id: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn load(source: &DictSource) -> Result<Option<Dictionary>> {
+        let bytes = match source {
+            DictSource::Disabled => return Ok(None),
+            DictSource::Builtin => builtin(),
+            DictSource::File(path) => {
+                let bytes = std::fs::read(path).with_context(|| format!("Failed to read dictionary {}", path.display()))?;
+                if bytes.is_empty() || bytes.len() > MAX_DICT_LEN {
+                    bail!("Dictionary {} must be 1 byte to {} KiB", path.display(), MAX_DICT_LEN / 1024);
+                }
+                bytes
+            }
+        };
+        Ok(Some(Dictionary { id: fnv1a(&bytes), bytes }))
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5u32, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Raw-content dictionary of what small tunnelled packets are made of: IP and
+/// transport headers, DNS queries, TLS handshakes, HTTP/1 headers. zstd matches
+/// against it like earlier data in the same stream, so a 60-byte DNS query finds its
+/// header and label patterns instead of starting from nothing. Later content is
+/// cheaper to reference, so the most common patterns come last.
+///
+/// Changing a byte changes the id: peers on different builds then stop using it
+/// (and say so) rather than misdecode.
+fn builtin() -> Vec<u8> {
+    let mut d = Vec::with_capacity(2048);
+    // HTTP/1.1 requests and responses.
+    d.extend_from_slice(
+        b"HTTP/1.1 200 OK\r\nDate: \r\nServer: nginx\r\nContent-Type: text/html; charset=utf-8\r\n\
+Content-Length: \r\nCache-Control: no-cache, no-store, max-age=0\r\nConnection: keep-alive\r\n\r\n\
+POST /api/v1/ HTTP/1.1\r\nContent-Type: application/json\r\nAuthorization: Bearer \r\n\
+GET / HTTP/1.1\r\nHost: www.\r\nUser-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\nAccept-Language: en-US,en;q=0.9\r\n\
+Accept-Encoding: gzip, deflate, br\r\nCookie: \r\n\r\n",
+    );
+    // TLS 1.3 ClientHello: record and handshake headers, cipher suites, common extensions.
+    d.extend_from_slice(&[
+        0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03, 0x00, 0x20, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03,
+        0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8, 0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00,
+        0x9d, 0x00, 0x2f, 0x00, 0x35, 0x01, 0x00, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0xff, 0x01, 0x00, 0x01, 0x00, 0x00,
+        0x0a, 0x00, 0x0c, 0x00, 0x0a, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00, 0x19, 0x01, 0x00, 0x00, 0x0b, 0x00, 0x02,
+        0x01, 0x00, 0x00, 0x23, 0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x0c, 0x02, b'h', b'2', 0x08, b'h', b't', b't',
+        b'p', b'/', b'1', b'.', b'1', 0x00, 0x05, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x12, 0x00,
+        0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08, 0x06, 0x06, 0x01, 0x00, 0x2b,
+        0x00, 0x05, 0x04, 0x03, 0x04, 0x03, 0x03, 0x00, 0x2d, 0x00, 0x02, 0x01, 0x01, 0x00, 0x33, 0x00, 0x26, 0x00, 0x24,
+        0x00, 0x1d, 0x00, 0x20,
+    ]);
+    // TLS records after the handshake: ChangeCipherSpec, then application data headers.
+    d.extend_from_slice(&[0x14, 0x03, 0x03, 0x00, 0x01, 0x01, 0x17, 0x03, 0x03, 0x00, 0x00, 0x17, 0x03, 0x03]);
+    // DNS: IPv4/UDP to port 53, a recursive query with EDNS, common labels and types.
+    d.extend_from_slice(&[
+        0x45, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x08, 0x08, 0x08,
+        0x08, 0xc0, 0x00, 0x00, 0x35, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01,
+    ]);
+    d.extend_from_slice(b"\x03www\x06google\x03com\x00\x00\x01\x00\x01\x03api\x06github\x03com\x00\x00\x1c\x00\x01\x03cdn\x0acloudflare\x03net\x00");
+    d.extend_from_slice(&[0x00, 0x41, 0x00, 0x01, 0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    // IPv6 with TCP and UDP, link-local and ULA prefixes.
+    d.extend_from_slice(&[
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x20, 0x11, 0x40, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00,
+        0x00, 0x00, 0x28, 0x06, 0x40, 0xfd, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01,
+    ]);
+    // IPv4/TCP SYN with the usual Linux options: MSS, SACK permitted, timestamps, window scale.
+    d.extend_from_slice(&[
+        0x45, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00,
+        0x02, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02, 0xfa, 0xf0, 0x00, 0x00,
+        0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x03, 0x03, 0x07,
+    ]);
+    // IPv4/TCP pure ACKs and pushes with timestamps: the most frequent small packets of all.
+    for flags in [0x18u8, 0x10] {
+        d.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x34, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00,
+            0x00, 0x02, 0x00, 0x50, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, flags, 0x01, 0xf5,
+            0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x08, 0x0a,
+        ]);
+    }
+    d
+}
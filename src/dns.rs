@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+
+use crate::packet::UdpHeader;
+
+pub const DNS_PORT: u16 = 53;
+
+/// A query's redirect is remembered this long after its last packet, for the reply.
+const FLOW_TIMEOUT: Duration = Duration::from_secs(30);
+/// Remembered queries beyond which expired ones are swept on the next insert.
+const FLOW_SWEEP_AT: usize = 4096;
+
+/// Sends every DNS query read from TUN (UDP to port 53) to one resolver on the far
+/// side of the tunnel (`--dns-through-tunnel`), whatever server the client asked.
+///
+/// It's destination NAT on the inner packets: the query's destination becomes the
+/// resolver, and the reply's source is put back to the server the client asked, so
+/// stub resolvers accept it. Only traffic routed into the TUN device is seen; queries
+/// the host sends elsewhere are beyond its reach. A query of the other IP family than
+/// the resolver can't be rewritten and is forwarded as it was.
+pub struct DnsRedirect {
+    resolver: IpAddr,
+    /// (client address, client port) -> the server the query was addressed to.
+    flows: Mutex<HashMap<(IpAddr, u16), Flow>>,
+}
+
+struct Flow {
+    server: IpAddr,
+    seen: Instant,
+}
+
+impl DnsRedirect {
+    pub fn new(resolver: IpAddr) -> Self {
+        Self { resolver, flows: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn resolver(&self) -> IpAddr {
+        self.resolver
+    }
+
+    /// Redirects `ip` if it's a query for some other server. Returns the server it
+    /// was addressed to when the packet was rewritten.
+    pub fn outbound(&self, ip: &mut [u8]) -> Option<IpAddr> {
+        let udp = UdpHeader::parse(ip).filter(|u| u.dst_port == DNS_PORT && u.dst != self.resolver)?;
+        if !udp.set_dst(ip, self.resolver) {
+            return None;
+        }
+        let now = Instant::now();
+        let mut flows = self.flows.lock();
+        if flows.len() >= FLOW_SWEEP_AT {
+            flows.retain(|_, flow| now.duration_since(flow.seen) < FLOW_TIMEOUT);
+        }
+        flows.insert((udp.src, udp.src_port), Flow { server: udp.dst, seen: now });
+        Some(udp.dst)
+    }
+
+    /// Gives a reply from the resolver back the address of the server its query
+    /// was meant for. Anything else is left alone.
+    pub fn inbound(&self, ip: &mut [u8]) {
+        let Some(udp) = UdpHeader::parse(ip).filter(|u| u.src_port == DNS_PORT && u.src == self.resolver) else {
+            return;
+        };
+        let server = self.flows.lock().get(&(udp.dst, udp.dst_port))
+            .filter(|flow| flow.seen.elapsed() < FLOW_TIMEOUT)
+            .map(|flow| flow.server);
+        if let Some(server) = server {
+            udp.set_src(ip, server);
+        }
+    }
+}
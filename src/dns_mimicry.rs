@@ -0,0 +1,478 @@
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::obfuscation::{Obfuscator, Unwrapped};
+
+/// DNS message header: id, flags, and the four section counts.
+const HEADER: usize = 12;
+
+/// Longest QNAME on the wire, length bytes and root label included (RFC 1035 2.3.4).
+const MAX_NAME: usize = 255;
+
+/// Longest label.
+const MAX_LABEL: usize = 63;
+
+/// Largest message sent: the UDP payload size the queries advertise in their OPT
+/// record, as resolvers commonly do.
+const MAX_MESSAGE: usize = 4096;
+
+/// A TXT character-string's length byte caps it at this.
+const MAX_STRING: usize = 255;
+
+/// An answer record's fixed part: name pointer, type, class, TTL and RDATA length.
+const ANSWER_FIXED: usize = 12;
+
+/// Length of `push_opt`'s record.
+const OPT_LEN: usize = 11;
+
+/// Smallest frame a query must carry: a Hello (about 134 bytes), with room to spare.
+const MIN_QUERY_FRAME: usize = 144;
+
+const TYPE_TXT: u16 = 16;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+/// Recursion desired: what a stub resolver's query carries.
+const FLAGS_QUERY: u16 = 0x0100;
+/// Response, recursion desired and available, no error.
+const FLAGS_RESPONSE: u16 = 0x8180;
+const FLAG_QR: u16 = 0x8000;
+
+/// A name pointer to the question's QNAME, right after the header.
+const POINTER_TO_QNAME: [u8; 2] = [0xc0, HEADER as u8];
+
+/// RFC 4648 base32, lowercased: letters and digits only, safe in any label.
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// `--mimic dns`: every frame dressed as a DNS message about names under `--mimic-domain`.
+///
+/// The end with `--peer` sends queries: the frame is base32-encoded into the labels of
+/// a TXT query's QNAME, as a stub resolver would send it. The other end answers: the
+/// frame travels as the TXT record of a response, echoing the last query's id and
+/// question. Either shape is accepted from either end, so two ends that both have
+/// `--peer` still understand each other.
+///
+/// **Capacity**: a QNAME holds at most 255 bytes, so a query carries about 150 bytes of
+/// frame (less under a longer domain), and larger data payloads are split by
+/// `morph::Fitter`. A response carries whatever fits in `MAX_MESSAGE`.
+///
+/// This is mimicry, not tunnelling through resolvers: both ends still talk directly,
+/// and the messages only have to parse as DNS.
+pub struct DnsMimic {
+    /// `--mimic-domain`, as lowercase labels.
+    domain: Vec<String>,
+    /// This end sends queries (it has `--peer`); otherwise responses.
+    queries: bool,
+    /// The id and QNAME of the last query received, for the next response to echo.
+    last_query: Mutex<Option<(u16, Vec<u8>)>>,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl DnsMimic {
+    pub fn new(domain: &str, queries: bool, rng: Arc<Mutex<StdRng>>) -> Result<Self> {
+        let domain: Vec<String> = domain.trim_end_matches('.').split('.').map(str::to_ascii_lowercase).collect();
+        for label in &domain {
+            if label.is_empty() || label.len() > MAX_LABEL || !label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+                bail!("--mimic-domain '{}' is not a domain name", domain.join("."));
+            }
+        }
+        let mimic = Self { domain, queries, last_query: Mutex::new(None), rng };
+        let room = mimic.query_capacity();
+        if room < MIN_QUERY_FRAME {
+            bail!("--mimic-domain '{}' is too long: a query could carry only {} bytes of frame, at least {} are needed", mimic.domain.join("."), room, MIN_QUERY_FRAME);
+        }
+        Ok(mimic)
+    }
+
+    /// Wire length of the domain's labels, without the root label.
+    fn domain_len(&self) -> usize {
+        self.domain.iter().map(|label| label.len() + 1).sum()
+    }
+
+    /// Largest frame one query's QNAME carries.
+    fn query_capacity(&self) -> usize {
+        let room = (MAX_NAME - 1).saturating_sub(self.domain_len());
+        // Every label of up to 63 characters costs one more byte for its length.
+        let chars = room - room.div_ceil(MAX_LABEL + 1);
+        chars * 5 / 8
+    }
+
+    /// Largest frame one response carries, with the longest question it might echo.
+    fn response_capacity(&self) -> usize {
+        let room = MAX_MESSAGE - HEADER - MAX_NAME - 4 - ANSWER_FIXED - OPT_LEN;
+        room - room.div_ceil(MAX_STRING + 1)
+    }
+
+    fn query(&self, frame: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HEADER + MAX_NAME + 4 + OPT_LEN);
+        push_header(&mut message, self.rng.lock().gen(), FLAGS_QUERY, 0);
+        let encoded = base32(frame);
+        for label in encoded.chunks(MAX_LABEL) {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label);
+        }
+        self.push_domain(&mut message);
+        push_question_tail(&mut message);
+        push_opt(&mut message);
+        message
+    }
+
+    fn response(&self, frame: &[u8]) -> Vec<u8> {
+        let (id, qname) = match self.last_query.lock().clone() {
+            Some(query) => query,
+            None => {
+                // Nothing asked yet: a question of our own, with a random label.
+                let mut rng = self.rng.lock();
+                let label: Vec<u8> = (0..rng.gen_range(8..=16)).map(|_| BASE32[rng.gen_range(0..32)]).collect();
+                let mut qname = vec![label.len() as u8];
+                qname.extend_from_slice(&label);
+                self.push_domain(&mut qname);
+                (rng.gen(), qname)
+            }
+        };
+        let strings = frame.len().div_ceil(MAX_STRING).max(1);
+        let mut message = Vec::with_capacity(HEADER + qname.len() + 4 + ANSWER_FIXED + strings + frame.len() + OPT_LEN);
+        push_header(&mut message, id, FLAGS_RESPONSE, 1);
+        message.extend_from_slice(&qname);
+        push_question_tail(&mut message);
+        message.extend_from_slice(&POINTER_TO_QNAME);
+        message.extend_from_slice(&TYPE_TXT.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&self.rng.lock().gen_range(30u32..=300).to_be_bytes());
+        message.extend_from_slice(&((strings + frame.len()) as u16).to_be_bytes());
+        for string in frame.chunks(MAX_STRING) {
+            message.push(string.len() as u8);
+            message.extend_from_slice(string);
+        }
+        // TXT RDATA holds at least one string, if an empty one.
+        if frame.is_empty() {
+            message.push(0);
+        }
+        push_opt(&mut message);
+        message
+    }
+
+    /// The domain's labels and the root label.
+    fn push_domain(&self, name: &mut Vec<u8>) {
+        for label in &self.domain {
+            name.push(label.len() as u8);
+            name.extend_from_slice(label.as_bytes());
+        }
+        name.push(0);
+    }
+
+    /// Reads a message as one of ours: the frame it carries, or `None` if it isn't.
+    ///
+    /// **Strict parsing**: one question under our domain, records that cover the
+    /// datagram exactly, and nothing but base32 in a query's data labels.
+    fn parse(&self, message: &[u8]) -> Option<Vec<u8>> {
+        let header = message.get(..HEADER)?;
+        let field = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]);
+        let (id, flags, questions, answers, authority, additional) = (field(0), field(2), field(4), field(6), field(8), field(10));
+        if questions != 1 || authority != 0 {
+            return None;
+        }
+        let mut reader = Reader { message, at: HEADER };
+        let labels = reader.name()?;
+        let qname = message[HEADER..reader.at].to_vec();
+        let data_labels = labels.len().checked_sub(self.domain.len())?;
+        if !labels[data_labels..].iter().zip(&self.domain).all(|(label, ours)| label.eq_ignore_ascii_case(ours.as_bytes())) {
+            return None;
+        }
+        if reader.u16()? != TYPE_TXT || reader.u16()? != CLASS_IN {
+            return None;
+        }
+        let frame = if flags & FLAG_QR == 0 {
+            if answers != 0 {
+                return None;
+            }
+            let encoded: Vec<u8> = labels[..data_labels].concat();
+            let frame = unbase32(&encoded)?;
+            *self.last_query.lock() = Some((id, qname));
+            frame
+        } else {
+            if answers != 1 {
+                return None;
+            }
+            reader.name()?;
+            let (kind, _class) = (reader.u16()?, reader.u16()?);
+            reader.take(4)?;
+            let rdata_len = reader.u16()? as usize;
+            let mut rdata = reader.take(rdata_len)?;
+            if kind != TYPE_TXT {
+                return None;
+            }
+            let mut frame = Vec::with_capacity(rdata_len);
+            while let Some((&len, rest)) = rdata.split_first() {
+                frame.extend_from_slice(rest.get(..len as usize)?);
+                rdata = &rest[len as usize..];
+            }
+            frame
+        };
+        for _ in 0..additional {
+            reader.name()?;
+            reader.take(8)?;
+            let len = reader.u16()? as usize;
+            reader.take(len)?;
+        }
+        (reader.at == message.len()).then_some(frame)
+    }
+}
+
+impl Obfuscator for DnsMimic {
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        Cow::Owned(if self.queries { self.query(frame) } else { self.response(frame) })
+    }
+
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        if self.queries {
+            let chars = (frame_len * 8).div_ceil(5);
+            HEADER + chars + chars.div_ceil(MAX_LABEL) + self.domain_len() + 1 + 4 + OPT_LEN
+        } else {
+            let qname = self.last_query.lock().as_ref().map_or(self.domain_len() + 18, |(_, qname)| qname.len());
+            HEADER + qname + 4 + ANSWER_FIXED + frame_len.div_ceil(MAX_STRING).max(1) + frame_len + OPT_LEN
+        }
+    }
+
+    fn unwrap_incoming(&self, datagram: &mut [u8], _from: SocketAddr) -> Unwrapped {
+        match self.parse(datagram) {
+            Some(frame) => {
+                datagram[..frame.len()].copy_from_slice(&frame);
+                Unwrapped::Frame(frame.len())
+            }
+            None => Unwrapped::Foreign("not a DNS message under --mimic-domain (peer lacks --mimic dns, or has another domain?)"),
+        }
+    }
+
+    fn max_frame(&self) -> Option<usize> {
+        Some(if self.queries { self.query_capacity() } else { self.response_capacity() })
+    }
+
+    fn needs_datagrams(&self) -> bool {
+        true
+    }
+}
+
+fn push_header(message: &mut Vec<u8>, id: u16, flags: u16, answers: u16) {
+    for field in [id, flags, 1, answers, 0, 1] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+}
+
+/// OPT pseudo-record advertising `MAX_MESSAGE` (EDNS0): root name, type, payload size,
+/// extended rcode and flags, no options.
+fn push_opt(message: &mut Vec<u8>) {
+    message.push(0);
+    message.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    message.extend_from_slice(&(MAX_MESSAGE as u16).to_be_bytes());
+    message.extend_from_slice(&[0; 6]);
+}
+
+/// QTYPE TXT, QCLASS IN.
+fn push_question_tail(message: &mut Vec<u8>) {
+    message.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+}
+
+/// A cursor over a received message.
+struct Reader<'a> {
+    message: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.message.get(self.at..self.at + len)?;
+        self.at += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A name's labels. A compression pointer ends the name and yields no labels of its
+    /// own: only answers use one, and only to point back at the question.
+    fn name(&mut self) -> Option<Vec<&'a [u8]>> {
+        let mut labels = Vec::new();
+        let start = self.at;
+        loop {
+            let len = *self.take(1)?.first()? as usize;
+            match len {
+                0 => break,
+                len if len & 0xc0 == 0xc0 => {
+                    self.take(1)?;
+                    break;
+                }
+                len if len > MAX_LABEL => return None,
+                len => labels.push(self.take(len)?),
+            }
+        }
+        (self.at - start <= MAX_NAME).then_some(labels)
+    }
+}
+
+fn base32(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31]);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31]);
+    }
+    out
+}
+
+/// Case-insensitive, as DNS names are. `None` on anything outside the alphabet.
+fn unbase32(chars: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(chars.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &c in chars {
+        let value = match c.to_ascii_lowercase() {
+            c @ b'a'..=b'z' => c - b'a',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obfuscation;
+
+    const DOMAIN: &str = "t.example.com";
+
+    fn mimic(queries: bool) -> DnsMimic {
+        mimic_under(DOMAIN, queries)
+    }
+
+    fn mimic_under(domain: &str, queries: bool) -> DnsMimic {
+        DnsMimic::new(domain, queries, Arc::new(Mutex::new(obfuscation::traffic_rng(Some(4))))).unwrap()
+    }
+
+    fn from() -> SocketAddr {
+        "192.0.2.1:53".parse().unwrap()
+    }
+
+    fn frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 + 0xC1) as u8).collect()
+    }
+
+    /// ID, flags and the four counts.
+    fn header(message: &[u8]) -> [u16; 6] {
+        std::array::from_fn(|i| u16::from_be_bytes([message[2 * i], message[2 * i + 1]]))
+    }
+
+    /// The question's labels, checked against what a resolver accepts.
+    fn question(message: &[u8]) -> Vec<&[u8]> {
+        let mut reader = Reader { message, at: HEADER };
+        let labels = reader.name().unwrap();
+        assert!(reader.at - HEADER <= MAX_NAME && labels.iter().all(|label| (1..=MAX_LABEL).contains(&label.len())));
+        assert_eq!((reader.u16(), reader.u16()), (Some(TYPE_TXT), Some(CLASS_IN)));
+        labels
+    }
+
+    fn unwrap(mimic: &DnsMimic, mut message: Vec<u8>) -> Option<Vec<u8>> {
+        match mimic.unwrap_incoming(&mut message, from()) {
+            Unwrapped::Frame(len) => Some(message[..len].to_vec()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn queries_parse_as_dns_and_round_trip() {
+        let (client, server) = (mimic(true), mimic(false));
+        let max = client.max_frame().unwrap();
+        assert!(max >= MIN_QUERY_FRAME);
+        for len in [0, 1, 5, 64, 100, max] {
+            let frame = frame(len);
+            let query = client.wrap_outgoing(&frame).into_owned();
+            assert_eq!(query.len(), client.wrapped_len(len), "{} bytes", len);
+            let [_, flags, questions, answers, authority, additional] = header(&query);
+            assert_eq!((flags, questions, answers, authority, additional), (FLAGS_QUERY, 1, 0, 0, 1));
+            let labels = question(&query);
+            let (data, domain) = labels.split_at(labels.len() - 3);
+            assert_eq!(domain.concat(), b"texamplecom");
+            assert!(data.iter().all(|label| label.iter().all(|c| BASE32.contains(c))), "{} bytes", len);
+            assert_eq!(unwrap(&server, query).unwrap(), frame, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn responses_answer_the_last_query_and_round_trip() {
+        let (client, server) = (mimic(true), mimic(false));
+        let query = client.wrap_outgoing(&frame(140)).into_owned();
+        let id = header(&query)[0];
+        unwrap(&server, query.clone()).unwrap();
+        let max = server.max_frame().unwrap();
+        for len in [0, 1, MAX_STRING, MAX_STRING + 1, 1400, max] {
+            let frame = frame(len);
+            let response = server.wrap_outgoing(&frame).into_owned();
+            assert_eq!(response.len(), server.wrapped_len(len), "{} bytes", len);
+            assert!(response.len() <= MAX_MESSAGE);
+            assert_eq!(header(&response), [id, FLAGS_RESPONSE, 1, 1, 0, 1]);
+            // The question echoed as asked, and the answer pointing back at it.
+            let question_end = HEADER + question(&response).iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+            assert_eq!(response[HEADER..question_end + 4], query[HEADER..question_end + 4]);
+            assert_eq!(response[question_end + 4..question_end + 6], POINTER_TO_QNAME);
+            assert_eq!(unwrap(&client, response).unwrap(), frame, "{} bytes", len);
+        }
+        // Before any query, a response asks a question of its own under the domain.
+        let response = mimic(false).wrap_outgoing(&frame(10)).into_owned();
+        assert_eq!(question(&response)[1..].concat(), b"texamplecom");
+        assert_eq!(unwrap(&client, response).unwrap(), frame(10));
+    }
+
+    #[test]
+    fn truncated_and_malformed_messages_are_foreign() {
+        let (client, server) = (mimic(true), mimic(false));
+        let query = client.wrap_outgoing(&frame(100)).into_owned();
+        unwrap(&server, query.clone()).unwrap();
+        let response = server.wrap_outgoing(&frame(600)).into_owned();
+        for cut in 0..query.len() {
+            assert!(unwrap(&server, query[..cut].to_vec()).is_none(), "query cut at {}", cut);
+        }
+        for cut in 0..response.len() {
+            assert!(unwrap(&client, response[..cut].to_vec()).is_none(), "response cut at {}", cut);
+        }
+        let first_label = HEADER + 1;
+        let mut edits: Vec<Vec<u8>> = Vec::new();
+        // Trailing bytes, two questions, a character outside base32, a label over 63.
+        edits.push([&query[..], &[0]].concat());
+        edits.push({ let mut m = query.clone(); m[5] = 2; m });
+        edits.push({ let mut m = query.clone(); m[first_label] = b'-'; m });
+        edits.push({ let mut m = query.clone(); m[HEADER] = 64; m });
+        // Another domain, and a question that is not TXT.
+        edits.push(mimic_under("t.example.org", true).wrap_outgoing(&frame(100)).into_owned());
+        edits.push({ let mut m = query.clone(); let at = m.len() - OPT_LEN - 3; m[at] = 1; m });
+        for (i, edit) in edits.into_iter().enumerate() {
+            assert!(unwrap(&server, edit).is_none(), "edit {}", i);
+        }
+        // A TXT string running past its record: 255, 255, then 90 bytes, said to be 91.
+        let last_string = response.len() - OPT_LEN - 91;
+        let mut overrun = response.clone();
+        assert_eq!(overrun[last_string], 90);
+        overrun[last_string] = 91;
+        assert!(unwrap(&client, overrun).is_none());
+        assert!(unwrap(&client, frame(100)).is_none());
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+
+/// Smallest the congestion window is cut to: two frames keep ACKs (and so RTT
+/// samples and growth) flowing.
+const MIN_WINDOW: f64 = 2.0;
+
+/// The ECN field: the low two bits of the IPv4 TOS / IPv6 traffic class byte (RFC 3168).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    NotEct,
+    Ect1,
+    Ect0,
+    /// Congestion Experienced: a router on the path marked the packet instead of dropping it.
+    Ce,
+}
+
+impl Ecn {
+    pub fn from_tos(tos: u8) -> Ecn {
+        match tos & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+/// Carries an outer CE mark over to the inner packet (RFC 6040 decapsulation), so
+/// the connections inside the tunnel slow down as well. Only ECN-capable packets
+/// are marked: for the rest the tunnel's own window is the only reaction. Returns
+/// whether the packet was marked.
+pub fn mark_ce(ip: &mut [u8]) -> bool {
+    match ip.first().map(|b| b >> 4) {
+        Some(4) if ip.len() >= 20 => {
+            if matches!(Ecn::from_tos(ip[1]), Ecn::NotEct | Ecn::Ce) {
+                return false;
+            }
+            ip[1] |= Ecn::Ce.bits();
+            let header_len = (usize::from(ip[0] & 0x0f) * 4).clamp(20, ip.len());
+            ip[10..12].fill(0);
+            let sum = ip[..header_len].chunks(2)
+                .map(|word| u32::from(word[0]) << 8 | u32::from(word.get(1).copied().unwrap_or(0)))
+                .sum::<u32>();
+            let folded = (sum & 0xffff) + (sum >> 16);
+            let checksum = !(((folded & 0xffff) + (folded >> 16)) as u16);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            true
+        }
+        // The traffic class straddles the first two bytes; its ECN bits are 0x30 of the second.
+        Some(6) if ip.len() >= 40 => {
+            if matches!(Ecn::from_tos(ip[1] >> 4), Ecn::NotEct | Ecn::Ce) {
+                return false;
+            }
+            ip[1] |= Ecn::Ce.bits() << 4;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// How many frames may be in flight (`--ecn`), below the fixed ARQ window.
+///
+/// AIMD: each ACK grows the window by one frame per window's worth (about one
+/// frame per RTT), and a CE mark the peer reports halves it. All the marks from
+/// one window's worth of frames describe the same congestion, so the window is cut
+/// at most once per RTT. Without ECN no mark ever arrives and the window stays at
+/// its maximum.
+pub struct CongestionWindow {
+    max: usize,
+    state: Mutex<WindowState>,
+}
+
+struct WindowState {
+    cwnd: f64,
+    last_cut: Option<Instant>,
+    /// The peer's running count of CE-marked frames, as last reported.
+    peer_marks: u64,
+}
+
+impl CongestionWindow {
+    pub fn new(max: usize) -> Self {
+        Self { max, state: Mutex::new(WindowState { cwnd: max as f64, last_cut: None, peer_marks: 0 }) }
+    }
+
+    /// Frames that may be in flight right now.
+    pub fn limit(&self) -> usize {
+        self.state.lock().cwnd as usize
+    }
+
+    pub fn on_ack(&self) {
+        let mut state = self.state.lock();
+        if state.cwnd < self.max as f64 {
+            state.cwnd = (state.cwnd + 1.0 / state.cwnd).min(self.max as f64);
+        }
+    }
+
+    /// The peer's count of CE-marked frames it received from us. Returns whether the
+    /// window was cut. `rto` stands in for the RTT until one is measured.
+    pub fn on_marks(&self, marks: u64, srtt: Option<Duration>, rto: Duration, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        // A lower count is a restarted peer counting from zero again.
+        let new = if marks >= state.peer_marks { marks - state.peer_marks } else { marks };
+        state.peer_marks = marks;
+        if new == 0 || state.last_cut.is_some_and(|at| now.duration_since(at) < srtt.unwrap_or(rto)) {
+            return false;
+        }
+        state.cwnd = (state.cwnd / 2.0).max(MIN_WINDOW);
+        state.last_cut = Some(now);
+        true
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+/// Backlog of events a slow subscriber may fall behind by before it starts missing them.
+const BUS_CAPACITY: usize = 64;
+
+/// Tunnel lifecycle events.
+///
+/// These are low-frequency, state-changing events (not per-packet telemetry).
+/// Producers publish them once on the `EventBus`; consumers (TUI log, hook runner)
+/// subscribe independently so no call site has to know who is listening.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TunnelEvent {
+    /// First valid frame from a peer after a period of silence (or ever).
+    PeerEstablished { peer: SocketAddr },
+    /// The peer has been silent for longer than the liveness timeout.
+    PeerLost { peer: SocketAddr },
+    /// The peer's source address changed (NAT rebinding, mobile handover).
+    Roamed { from: SocketAddr, to: SocketAddr },
+    /// The peer never answered our Hello within `--handshake-attempts`.
+    HandshakeFailed { peer: SocketAddr, reason: String },
+    /// The session went over `--quota-bytes` / `--quota-packets` and is now throttled or cut off.
+    QuotaExceeded { peer: SocketAddr, reason: String },
+    /// Session key material was replaced.
+    #[allow(dead_code)] // Emitted once key rotation lands.
+    Rekeyed,
+    /// The process is shutting down.
+    Shutdown,
+}
+
+impl TunnelEvent {
+    /// Stable, script-friendly identifier (exported as `GT_EVENT`).
+    pub fn name(&self) -> &'static str {
+        match self {
+            TunnelEvent::PeerEstablished { .. } => "peer-established",
+            TunnelEvent::PeerLost { .. } => "peer-lost",
+            TunnelEvent::Roamed { .. } => "roamed",
+            TunnelEvent::HandshakeFailed { .. } => "handshake-failed",
+            TunnelEvent::QuotaExceeded { .. } => "quota-exceeded",
+            TunnelEvent::Rekeyed => "rekeyed",
+            TunnelEvent::Shutdown => "shutdown",
+        }
+    }
+
+    /// The peer address this event concerns, if any.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        match self {
+            TunnelEvent::PeerEstablished { peer }
+            | TunnelEvent::PeerLost { peer }
+            | TunnelEvent::HandshakeFailed { peer, .. }
+            | TunnelEvent::QuotaExceeded { peer, .. } => Some(*peer),
+            TunnelEvent::Roamed { to, .. } => Some(*to),
+            TunnelEvent::Rekeyed | TunnelEvent::Shutdown => None,
+        }
+    }
+}
+
+impl fmt::Display for TunnelEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TunnelEvent::PeerEstablished { peer } => write!(f, "Peer established ({})", peer),
+            TunnelEvent::PeerLost { peer } => write!(f, "Peer lost ({})", peer),
+            TunnelEvent::Roamed { from, to } => write!(f, "Peer roamed {} -> {}", from, to),
+            TunnelEvent::HandshakeFailed { peer, reason } => write!(f, "Handshake with {} failed ({})", peer, reason),
+            TunnelEvent::QuotaExceeded { peer, reason } => write!(f, "Session with {} over quota ({})", peer, reason),
+            TunnelEvent::Rekeyed => write!(f, "Session rekeyed"),
+            TunnelEvent::Shutdown => write!(f, "Shutting down"),
+        }
+    }
+}
+
+/// Fan-out channel for `TunnelEvent`s.
+///
+/// Cloning the bus is cheap; every clone publishes into the same channel.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<TunnelEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        Self { tx }
+    }
+
+    /// Publish an event. Having no subscribers is not an error.
+    pub fn publish(&self, event: TunnelEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TunnelEvent> {
+        self.tx.subscribe()
+    }
+}
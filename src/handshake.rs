@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// Where session establishment with the current peer stands.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum HandshakeState {
+    /// No handshake started (no peer yet, or we only answer).
+    #[default]
+    Idle,
+    /// Our Hello has gone out `attempt` times without proof the peer holds the key.
+    AwaitingReply { attempt: u32 },
+    /// The peer sent something that passed authentication.
+    Established,
+    /// Gave up; stays here until a reconnect or a new peer address starts over.
+    Failed { reason: String },
+}
+
+impl HandshakeState {
+    /// Short name used in `get-stats` replies and the JSON stream.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HandshakeState::Idle => "idle",
+            HandshakeState::AwaitingReply { .. } => "awaiting-reply",
+            HandshakeState::Established => "established",
+            HandshakeState::Failed { .. } => "failed",
+        }
+    }
+}
+
+/// Hello retransmission schedule: exponential backoff from `initial` up to `max_interval`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub initial: Duration,
+    pub max_interval: Duration,
+    /// Hellos sent before giving up; `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+/// What the initiator should do next.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Send Hello number `attempt`, then wait about `wait` before asking again.
+    Send { attempt: u32, wait: Duration },
+    /// Finished, successfully or not.
+    Done(HandshakeState),
+}
+
+/// Initiator side of the handshake, free of I/O: the caller sends, sleeps and
+/// reports whether the peer has authenticated in the meantime.
+pub struct Initiator {
+    policy: RetryPolicy,
+    attempts: u32,
+    interval: Duration,
+}
+
+impl Initiator {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy, attempts: 0, interval: policy.initial }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn next(&mut self, authenticated: bool) -> Step {
+        if authenticated {
+            return Step::Done(HandshakeState::Established);
+        }
+        if self.policy.max_attempts.is_some_and(|max| self.attempts >= max) {
+            let reason = format!("no authenticated reply after {} attempts", self.attempts);
+            return Step::Done(HandshakeState::Failed { reason });
+        }
+        self.attempts += 1;
+        let wait = self.interval;
+        self.interval = (self.interval * 2).min(self.policy.max_interval);
+        Step::Send { attempt: self.attempts, wait }
+    }
+}
+
+/// The shared handshake state plus a generation number. Starting a handshake bumps
+/// the generation, so an older attempt still running (superseded by a reconnect or a
+/// new address) notices and stops instead of overwriting the newer one's state.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    generation: u64,
+    state: HandshakeState,
+}
+
+impl Tracker {
+    pub fn state(&self) -> &HandshakeState {
+        &self.state
+    }
+
+    pub fn begin(&mut self) -> u64 {
+        self.generation += 1;
+        self.state = HandshakeState::AwaitingReply { attempt: 0 };
+        self.generation
+    }
+
+    /// Records `state` for the attempt started as `generation`. False if it was superseded.
+    pub fn update(&mut self, generation: u64, state: HandshakeState) -> bool {
+        if generation != self.generation {
+            return false;
+        }
+        self.state = state;
+        true
+    }
+
+    /// The peer opened a session with us (we answered its Hello). A running attempt
+    /// of ours sees the same authenticated frame and finishes on its own.
+    pub fn answered(&mut self) {
+        self.state = HandshakeState::Established;
+    }
+}
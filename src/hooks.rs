@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::{broadcast, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+use crate::events::TunnelEvent;
+use crate::logging::{tlog, Level};
+use crate::telemetry::TelemetrySender;
+
+/// Max hook processes alive at once. Events arriving beyond this are skipped, not queued,
+/// so a hung script can never build up an unbounded backlog of processes.
+const MAX_CONCURRENT_HOOKS: usize = 4;
+/// Hooks running longer than this are killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Static context exported to every hook invocation.
+pub struct HookContext {
+    pub program: PathBuf,
+    pub tun_name: String,
+    pub tun_ip: String,
+    pub bind: String,
+}
+
+/// Runs the `--on-event` program for every event on the bus.
+///
+/// **Isolation**: Hooks run as detached child processes on their own tasks. Failures,
+/// timeouts and non-zero exits are reported as telemetry logs and never propagate
+/// back into the data path.
+///
+/// The returned task finishes after the `Shutdown` event, once every in-flight hook
+/// has exited (or been killed), so callers can await it for a clean exit.
+pub fn spawn_hook_runner(
+    ctx: HookContext,
+    mut events: broadcast::Receiver<TunnelEvent>,
+    stats: TelemetrySender,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let ctx = Arc::new(ctx);
+        let slots = Arc::new(Semaphore::new(MAX_CONCURRENT_HOOKS));
+
+        loop {
+            let event = match events.recv().await {
+                Ok(ev) => ev,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tlog!(stats, Level::Warn, "HOOK: Missed {} events (bus lagged)", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            let is_shutdown = event == TunnelEvent::Shutdown;
+
+            match slots.clone().try_acquire_owned() {
+                Ok(permit) => {
+                    let ctx = ctx.clone();
+                    let stats = stats.clone();
+                    tokio::spawn(async move {
+                        run_hook(&ctx, &event, &stats).await;
+                        drop(permit);
+                    });
+                }
+                Err(_) => {
+                    tlog!(stats, Level::Warn, "HOOK: Skipped '{}' ({} hooks already running)", event.name(), MAX_CONCURRENT_HOOKS);
+                }
+            }
+
+            if is_shutdown {
+                break;
+            }
+        }
+
+        // Drain: wait until every running hook has released its slot.
+        let _ = slots.acquire_many(MAX_CONCURRENT_HOOKS as u32).await;
+    })
+}
+
+async fn run_hook(ctx: &HookContext, event: &TunnelEvent, stats: &TelemetrySender) {
+    let mut cmd = Command::new(&ctx.program);
+    cmd.env("GT_EVENT", event.name())
+        .env("GT_TUN_NAME", &ctx.tun_name)
+        .env("GT_TUN_IP", &ctx.tun_ip)
+        .env("GT_BIND", &ctx.bind)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    if let Some(peer) = event.peer() {
+        cmd.env("GT_PEER_ADDR", peer.to_string());
+    }
+    if let TunnelEvent::Roamed { from, .. } = event {
+        cmd.env("GT_PREV_PEER_ADDR", from.to_string());
+    }
+    if let TunnelEvent::HandshakeFailed { reason, .. } | TunnelEvent::QuotaExceeded { reason, .. } = event {
+        cmd.env("GT_REASON", reason);
+    }
+
+    let mut child = match cmd.spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            tlog!(stats, Level::Warn, "HOOK::SpawnErr ({}): {}", event.name(), e);
+            return;
+        }
+    };
+
+    match timeout(HOOK_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => {
+            tlog!(stats, Level::Warn, "HOOK: '{}' exited with {}", event.name(), status);
+        }
+        Ok(Err(e)) => {
+            tlog!(stats, Level::Warn, "HOOK::WaitErr ({}): {}", event.name(), e);
+        }
+        Err(_) => {
+            let _ = child.kill().await;
+            tlog!(stats, Level::Warn, "HOOK: '{}' killed after {:?} timeout", event.name(), HOOK_TIMEOUT);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::Ipv4Addr;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Most interfaces one process multiplexes over its transport (`--tun`).
+pub const MAX_INTERFACES: usize = 16;
+
+/// Longest interface name Linux accepts (`IFNAMSIZ` less the terminating NUL).
+const MAX_NAME_LEN: usize = 15;
+
+/// One local TUN interface, from `--tun [NAME=]IP[:PEER]`.
+///
+/// Its id on the wire is its position among the `--tun` flags, so both ends must list
+/// their interfaces in the same order: packets read from our second interface come
+/// out of the peer's second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TunSpec {
+    /// Interface name to request (`None`: the OS picks one, e.g. `tun0`).
+    pub name: Option<String>,
+    pub ip: Ipv4Addr,
+    /// The far end's virtual IP, set as the point-to-point destination (`None`: `ip`,
+    /// as before it could be given).
+    pub peer: Option<Ipv4Addr>,
+}
+
+impl TunSpec {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (name, ip) = match s.split_once('=') {
+            Some((name, ip)) => (Some(name), ip),
+            None => (None, s),
+        };
+        if let Some(name) = name {
+            if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains(|c: char| c == '/' || c.is_whitespace()) {
+                bail!("invalid interface name in '{}' (1-{} characters, no '/' or spaces)", s, MAX_NAME_LEN);
+            }
+        }
+        let (ip, peer) = match ip.split_once(':') {
+            Some((ip, peer)) => (ip, Some(peer)),
+            None => (ip, None),
+        };
+        let ip = ip.parse().with_context(|| format!("invalid IPv4 address in '{}'", s))?;
+        let peer = peer.map(|peer| peer.parse()).transpose().with_context(|| format!("invalid peer IPv4 address in '{}'", s))?;
+        Self::new(name.map(str::to_string), ip, peer)
+    }
+
+    /// The single interface of a run without `--tun`, from `--tun-ip` and `--tun-peer-ip`.
+    pub fn from_tun_ip(ip: &str, peer: Option<&str>) -> Result<Self> {
+        let ip = ip.parse().map_err(|_| anyhow!("invalid --tun-ip '{}'", ip))?;
+        let peer = peer.map(|peer| peer.parse().map_err(|_| anyhow!("invalid --tun-peer-ip '{}'", peer))).transpose()?;
+        Self::new(None, ip, peer)
+    }
+
+    fn new(name: Option<String>, ip: Ipv4Addr, peer: Option<Ipv4Addr>) -> Result<Self> {
+        if peer == Some(ip) {
+            bail!("the peer's virtual IP {} is this end's own", ip);
+        }
+        Ok(Self { name, ip, peer })
+    }
+
+    /// `10.0.0.1`, or `10.0.0.1 -> 10.0.0.2` with a peer.
+    pub fn label(&self) -> String {
+        match self.peer {
+            Some(peer) => format!("{} -> {}", self.ip, peer),
+            None => self.ip.to_string(),
+        }
+    }
+
+    /// The point-to-point destination: the peer's virtual IP, or our own without one.
+    pub fn destination(&self) -> Ipv4Addr {
+        self.peer.unwrap_or(self.ip)
+    }
+}
+
+/// Associated data that binds a data frame's interface id into its payload's tag, so
+/// rewriting the id in a clear header fails authentication instead of moving the packet
+/// to another interface. Interface 0 binds nothing, as before interfaces existed.
+pub fn interface_aad(iface: u8) -> Vec<u8> {
+    if iface == 0 { Vec::new() } else { vec![iface] }
+}
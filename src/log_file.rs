@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use clap::ValueEnum;
+
+use crate::logging::{tlog, Level};
+use crate::telemetry::{TelemetryReceiver, TelemetrySender};
+use crate::tui::TelemetryUpdate;
+
+/// How often the writer looks for `finish` while waiting for telemetry.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes read per wanted line when replaying the end of a file, so a large log is not
+/// read whole at startup. Lines longer than this on average just replay fewer.
+const TAIL_BYTES_PER_LINE: u64 = 256;
+
+/// Written when a session starts and when it shuts down. A history whose last line
+/// is not `SESSION_ENDED` was cut short: a crash, a kill or a power cut.
+const SESSION_STARTED: &str = "LOG: Session started";
+const SESSION_ENDED: &str = "LOG: Session ended";
+
+/// Log lines persisted to a file (`--log-file`), so the history of a session survives
+/// a crash or a restart. Each line is `<RFC 3339 time> <LEVEL> <message>`.
+///
+/// Like the CSV export, it interposes on the telemetry channel and writes on its own
+/// thread. Every line goes out in a single write as soon as it arrives, so a killed
+/// process leaves a file that ends in its last complete line. Once the file reaches
+/// the size cap, `<path>.1` becomes `<path>.2` and so on up to the number kept, the
+/// file itself becomes `<path>.1` and a fresh one is started.
+pub struct LogFile {
+    finish: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl LogFile {
+    /// Opens `path` (appending) and starts the tap; updates continue to the returned receiver.
+    pub fn spawn(
+        rx: TelemetryReceiver,
+        path: &Path,
+        max_bytes: u64,
+        keep: usize,
+        stats: TelemetrySender,
+    ) -> Result<(Self, TelemetryReceiver)> {
+        let mut out = RotatingFile::open(path.to_path_buf(), max_bytes, keep)?;
+        out.append(Level::Info, &format!("{} (pid {})", SESSION_STARTED, std::process::id()))?;
+        let (forward_tx, forward_rx) = rx.relay();
+        let finish = Arc::new(AtomicBool::new(false));
+        let stop = finish.clone();
+        let handle = thread::Builder::new()
+            .name("log-file".to_string())
+            .spawn(move || writer_loop(rx, forward_tx, out, &stop, &stats))
+            .context("Failed to spawn log file writer thread")?;
+        Ok((Self { finish, handle }, forward_rx))
+    }
+
+    /// Writes what is still queued, then the end-of-session line.
+    pub fn finish(self) {
+        self.finish.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+fn writer_loop(
+    rx: TelemetryReceiver,
+    forward_tx: TelemetrySender,
+    mut out: RotatingFile,
+    finish: &AtomicBool,
+    stats: &TelemetrySender,
+) {
+    let mut failed = false;
+    let mut write = |out: &mut RotatingFile, update: &TelemetryUpdate| {
+        let result = match update {
+            TelemetryUpdate::Log { level, msg } => out.append(*level, msg),
+            TelemetryUpdate::Shutdown { reason } => out.append(Level::Error, &format!("FATAL: {}", reason)),
+            _ => return,
+        };
+        // Report the first failure only; later lines keep trying (the disk may recover).
+        match result {
+            Ok(()) => failed = false,
+            Err(e) if !failed => {
+                failed = true;
+                tlog!(stats, Level::Error, "LOG::WriteErr: {:#}", e);
+            }
+            Err(_) => {}
+        }
+    };
+
+    loop {
+        if finish.load(Ordering::Relaxed) {
+            while let Ok(update) = rx.try_recv() {
+                write(&mut out, &update);
+            }
+            write(&mut out, &TelemetryUpdate::Log { level: Level::Info, msg: SESSION_ENDED.to_string() });
+            break;
+        }
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(update) => {
+                write(&mut out, &update);
+                let _ = forward_tx.send(update);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// The log file, rotated at `max_bytes` into `keep` numbered generations.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    out: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> Result<Self> {
+        let mut out = OpenOptions::new().read(true).create(true).append(true).open(&path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let mut written = out.metadata()?.len();
+        // A process killed mid-write can leave a partial last line; start ours on a fresh one.
+        if written > 0 {
+            let mut last = [0u8];
+            out.seek(SeekFrom::End(-1))?;
+            out.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                out.write_all(b"\n")?;
+                written += 1;
+            }
+        }
+        Ok(Self { path, max_bytes, keep, out, written })
+    }
+
+    /// Appends one line in a single write, rotating first if the file is full.
+    fn append(&mut self, level: Level, msg: &str) -> Result<()> {
+        let line = format!(
+            "{} {:<5} {}\n",
+            chrono::Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            level.label(),
+            msg.replace(['\r', '\n'], " "),
+        );
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.out.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts every generation up by one (dropping the oldest), then starts a new file.
+    /// Each step is a rename, so a kill mid-rotation loses no lines.
+    fn rotate(&mut self) -> Result<()> {
+        for n in (1..self.keep).rev() {
+            let from = generation(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, generation(&self.path, n + 1))
+                    .with_context(|| format!("Failed to rotate {}", from.display()))?;
+            }
+        }
+        fs::rename(&self.path, generation(&self.path, 1))
+            .with_context(|| format!("Failed to rotate {}", self.path.display()))?;
+        *self = Self::open(self.path.clone(), self.max_bytes, self.keep)?;
+        Ok(())
+    }
+}
+
+/// `<path>.<n>`: the nth most recent rotated file.
+fn generation(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.to_path_buf().into_os_string();
+    rotated.push(format!(".{}", n));
+    rotated.into()
+}
+
+/// A line read back from a log file.
+pub struct Entry {
+    pub level: Level,
+    /// Local time, with the date: replayed lines can be days old.
+    pub time: String,
+    pub msg: String,
+}
+
+/// The end of the log left by earlier sessions, for the dashboard to show on startup.
+pub struct History {
+    /// Oldest first.
+    pub entries: Vec<Entry>,
+    /// The last session wrote its end line; false after a crash or kill.
+    pub ended_cleanly: bool,
+}
+
+impl History {
+    /// Reads up to `limit` of the most recent lines from `path` and, if that is not
+    /// enough, `<path>.1`. Missing files are an empty history.
+    pub fn load(path: &Path, limit: usize) -> History {
+        let mut lines = tail_lines(path, limit);
+        if lines.len() < limit {
+            let mut older = tail_lines(&generation(path, 1), limit - lines.len());
+            older.extend(lines);
+            lines = older;
+        }
+        let entries: Vec<Entry> = lines.iter().map(|line| parse(line)).collect();
+        let ended_cleanly = entries.last().is_none_or(|e| e.msg == SESSION_ENDED);
+        History { entries, ended_cleanly }
+    }
+}
+
+/// The last `limit` complete lines of `path`, reading only its end.
+fn tail_lines(path: &Path, limit: usize) -> VecDeque<String> {
+    let mut lines = VecDeque::new();
+    let Ok(mut file) = File::open(path) else { return lines };
+    let len = file.metadata().map_or(0, |m| m.len());
+    let start = len.saturating_sub(limit as u64 * TAIL_BYTES_PER_LINE);
+    let mut bytes = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut bytes).is_err() {
+        return lines;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    // Reading from the middle of the file starts inside a line; skip to the next.
+    let skip = usize::from(start > 0);
+    for line in text.lines().skip(skip).filter(|l| !l.is_empty()) {
+        if lines.len() == limit {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+    }
+    lines
+}
+
+/// Splits `<time> <LEVEL> <message>`; anything else is kept whole as an info line.
+fn parse(line: &str) -> Entry {
+    let parsed = line.split_once(' ').and_then(|(time, rest)| {
+        let time = chrono::DateTime::parse_from_rfc3339(time).ok()?;
+        let (label, msg) = rest.split_once(' ')?;
+        let level = Level::from_str(label, true).ok()?;
+        Some(Entry {
+            level,
+            time: time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            msg: msg.trim_start().to_string(),
+        })
+    });
+    parsed.unwrap_or_else(|| Entry { level: Level::Info, time: String::new(), msg: line.to_string() })
+}
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Severity of a log line. Ordered: a threshold admits its own level and everything above.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Per-packet diagnostics (send errors, drops, retransmissions).
+    Debug,
+    /// Session lifecycle and operator actions.
+    Info,
+    /// Degraded but still working.
+    Warn,
+    /// Something stopped working.
+    Error,
+}
+
+/// Lowest level currently emitted. Shared by the core (which skips formatting below it)
+/// and the dashboard (which cycles it at runtime).
+static THRESHOLD: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+impl Level {
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+
+    /// Lowercase form used on the command line and in the JSON stream.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    /// Next threshold when cycling from the dashboard (wraps to the most verbose).
+    pub fn cycle(self) -> Self {
+        match self {
+            Level::Debug => Level::Info,
+            Level::Info => Level::Warn,
+            Level::Warn => Level::Error,
+            Level::Error => Level::Debug,
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Level::Debug,
+            1 => Level::Info,
+            2 => Level::Warn,
+            _ => Level::Error,
+        }
+    }
+}
+
+pub fn threshold() -> Level {
+    Level::from_u8(THRESHOLD.load(Ordering::Relaxed))
+}
+
+pub fn set_threshold(level: Level) {
+    THRESHOLD.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level >= threshold()
+}
+
+/// Sends a log line to the telemetry channel if `level` passes the threshold.
+/// The message is only formatted when it will actually be shown.
+macro_rules! tlog {
+    ($tx:expr, $level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::logging::enabled(level) {
+            let _ = $tx.send($crate::tui::TelemetryUpdate::Log { level, msg: format!($($arg)*) });
+        }
+    }};
+}
+pub(crate) use tlog;
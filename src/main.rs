@@ -1,14 +1,13 @@
 use clap::Parser;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::collections::HashMap;
 use anyhow::{Context, Result};
-use tokio::net::UdpSocket;
 use tokio::time::{Instant, sleep, Duration};
 use tun::Configuration;
 use parking_lot::Mutex;
-use std::sync::mpsc; // Sync channel for TUI interaction
+use rand::Rng;
 
 // Internal Modules
 mod protocol;
@@ -16,10 +15,59 @@ mod crypto;
 mod compression;
 mod tui;
 mod obfuscation;
+mod events;
+mod hooks;
+mod stats;
+mod supervisor;
+mod shutdown;
+mod control;
+mod admin;
+mod packet;
+mod pcap;
+mod metrics;
+mod replay;
+mod serial;
+mod resolve;
+mod transport;
+mod bench;
+mod profile;
+mod telemetry_json;
+mod telemetry_csv;
+mod logging;
+mod theme;
+mod alerts;
+mod handshake;
+mod telemetry;
+mod delivery;
+mod units;
+mod acl;
+mod quota;
+mod dns;
+mod connection;
+mod notify;
+mod interfaces;
+mod dictionary;
+mod pacing;
+mod log_file;
+mod tcp;
+mod ecn;
+mod compress_pool;
+#[cfg(target_os = "linux")]
+mod tunqueue;
+mod tunwatch;
+mod chaff;
+mod mimicry;
+mod morph;
+mod dns_mimicry;
+mod quic_mimicry;
+mod wireguard_mimicry;
 
-use protocol::{WireFrame, FrameType};
+use protocol::{WireFrame, FrameType, WireFormat, Hello, LossReport, PeerControl};
 use tui::TelemetryUpdate;
-use tokio::io::{AsyncReadExt, AsyncWriteExt}; 
+use events::{EventBus, TunnelEvent};
+use logging::{tlog, Level};
+use tun::Device as _;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The maximum transmission unit.
 /// TODO: Implement Path MTU Discovery (PMTUD) instead of hardcoding.
@@ -29,21 +77,263 @@ const MTU: usize = 1280;
 const WINDOW_SIZE: usize = 50;
 /// Retransmission Timeout.
 const RTO: Duration = Duration::from_millis(200);
+/// Over TCP frames are never resent; one unacknowledged this long (its stream broke)
+/// is given up on so it stops holding a window slot.
+const STREAM_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Least time between two reports of CE marks to the peer (`--ecn`).
+const ECN_ECHO_INTERVAL: Duration = Duration::from_millis(20);
+/// After switching to a new pre-shared key (`rekey`), how long the old one still opens
+/// frames: the peer's own switch, retransmissions and reordered frames land within it.
+const KEY_GRACE: Duration = Duration::from_secs(30);
+/// How often a newly installed key is announced until the peer has it too.
+const KEY_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval between keep-alive heartbeats to the active peer.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Silence after which an established peer is considered lost.
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Silence after which an established peer is shown as stale: a heartbeat is overdue.
+const STALE_AFTER: Duration = Duration::from_secs(15);
+/// NAT-punch retry schedule: starts fast, backs off to a steady probe.
+const PUNCH_INITIAL_INTERVAL: Duration = Duration::from_millis(500);
+const PUNCH_MAX_INTERVAL: Duration = Duration::from_secs(5);
+/// First retry after a failed peer lookup; doubles up to the re-resolve interval.
+const RESOLVE_RETRY_INITIAL: Duration = Duration::from_secs(1);
+/// Interval between `Stats` frames in `--loss-echo` mode.
+const LOSS_ECHO_INTERVAL: Duration = Duration::from_secs(5);
+/// An unreachable peer is reported at most this often; repeats in between are debug lines.
+const UNREACHABLE_WARN_INTERVAL: Duration = Duration::from_secs(10);
+/// A routing loop is reported at most this often; repeats in between are debug lines.
+const LOOP_WARN_INTERVAL: Duration = Duration::from_secs(10);
 
-// Map<Seq, (SendTime, EncodedFrame)>
-type PendingPackets = Arc<Mutex<HashMap<u64, (Instant, Vec<u8>)>>>;
+/// Interval at which link quality is pushed to the dashboard.
+const QUALITY_REPORT_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval at which byte and packet counts are pushed to the dashboard (one graph sample).
+const THROUGHPUT_REPORT_INTERVAL: Duration = Duration::from_millis(250);
+/// Interval at which session usage is checked against `--quota-*` and published.
+const QUOTA_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval at which the connection state is re-evaluated.
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+/// Tries at recreating a vanished TUN interface before shutting down, and the pause
+/// before the next one (multiplied by the tries so far).
+const TUN_RECREATE_ATTEMPTS: u32 = 5;
+const TUN_RECREATE_BACKOFF: Duration = Duration::from_secs(1);
+/// Interval at which each TUN interface is checked to still exist.
+const TUN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the socket left by `--rotate-port` is still read, for datagrams the peer
+/// sent to it (ACKs, mostly) before it saw the new port.
+const PORT_DRAIN: Duration = Duration::from_secs(3);
+/// Once a stop is requested, how long the networking tasks, and then the telemetry
+/// thread, get to wind down before `main` moves on without them.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A data frame awaiting acknowledgment.
+struct PendingFrame {
+    /// Time of the most recent transmission (drives RTO).
+    sent_at: Instant,
+    /// Number of times this frame has been retransmitted.
+    /// RTT is only sampled when this is zero (Karn's algorithm).
+    retransmits: u32,
+    /// Wire-encoded frame, ready to resend.
+    data: Vec<u8>,
+}
+
+// Map<Seq, PendingFrame>
+type PendingPackets = Arc<Mutex<HashMap<u64, PendingFrame>>>;
+
+// TUN halves live behind async locks so a restarted task can pick them back up.
+// Boxed so `bench` mode can substitute a synthetic source/sink.
+type TunReader = Arc<tokio::sync::Mutex<Box<dyn AsyncRead + Unpin + Send>>>;
+type TunWriter = Arc<tokio::sync::Mutex<Box<dyn AsyncWrite + Unpin + Send>>>;
+/// One (reader, writer) pair per TUN queue.
+type TunQueues = Vec<(TunReader, TunWriter)>;
+/// An interface opened by `open_tun`: name, queues, the multi-queue device that owns
+/// them (if any) and the MTU the kernel applied.
+type OpenedTun = (String, TunQueues, Option<tun::platform::Device>, Option<usize>);
+
+/// State shared by every networking task. Cloning is cheap (all handles are `Arc`s),
+/// which is what lets the watchdog respawn a task from scratch.
+#[derive(Clone)]
+struct Shared {
+    socket: Arc<transport::Transport>,
+    /// Current remote endpoint (updated on roam).
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+    /// Shared state for ARQ (Automatic Repeat Request)
+    pending: PendingPackets,
+    /// We share the cipher primitive across threads.
+    /// Arc<T> is cheap here, and ChaCha state is immutable until encryption.
+    cipher: Arc<crypto::SessionGuard>,
+    /// Sequence number for basic replay protection (monotonic counter)
+    tx_seq: Arc<AtomicU64>,
+    /// Wire format negotiation: advertise what we can decode, speak the best common format.
+    supported_formats: Arc<Vec<WireFormat>>,
+    /// AEADs we accept for data, most preferred first; `cipher` seals with the agreed one.
+    ciphers: Arc<Vec<crypto::CipherSuite>>,
+    wire_format: Arc<Mutex<WireFormat>>,
+    /// `--auth-acks`, and whether the peer's Hello asked for it too: only then are ACKs tagged and checked.
+    auth_acks: bool,
+    acks_signed: Arc<AtomicBool>,
+    /// Loss / jitter estimation, fed by the ARQ paths.
+    monitor: Arc<Mutex<stats::LinkMonitor>>,
+    /// Every RTT sample of the session, for percentiles (lock-free to record).
+    rtt: Arc<stats::RttHistogram>,
+    /// Time each data frame took from leaving pacing to reaching the socket (compression, sealing).
+    tx_delay: Arc<stats::RttHistogram>,
+    /// Liveness: when we last heard a valid frame, and whether the peer counts as up.
+    last_rx: Arc<Mutex<Instant>>,
+    peer_up: Arc<AtomicBool>,
+    /// When a frame from the peer last passed AEAD verification (`None`: never).
+    last_auth: Arc<Mutex<Option<Instant>>>,
+    /// Source-address changes seen this session.
+    roam_count: Arc<AtomicU64>,
+    /// Data forwarding suspended by operator (`pause`); control traffic continues.
+    paused: Arc<AtomicBool>,
+    /// Outbound pipeline stages, switchable at runtime.
+    compression: Arc<AtomicBool>,
+    /// Whether the peer compresses what it sends, from its Hello and `Control` frames.
+    /// `None` until a Hello says, or for a peer without control frames.
+    peer_compression: Arc<Mutex<Option<bool>>>,
+    /// The algorithm and level the peer's Hello says it sends with (`None`: it didn't say).
+    peer_codec: Arc<Mutex<Option<(compression::Algorithm, i32)>>>,
+    /// `--compress`, and whether the peer decodes it.
+    codec: Arc<compression::Codec>,
+    /// `--compress-auto`: what the switch last measured and decided (`None` without it).
+    compress_auto: Arc<Mutex<Option<compression::AutoStatus>>>,
+    /// `--compress-adapt`'s last verdict (`None` without it).
+    compress_adapt: Arc<Mutex<Option<compression::AdaptStatus>>>,
+    /// Microseconds TX tasks spent waiting on a full send window, summed over them.
+    window_stalls: Arc<AtomicU64>,
+    /// `--compress-workers`: the threads outgoing packets are compressed on (`None`: inline).
+    compress_pool: Option<Arc<compress_pool::CompressPool>>,
+    jitter: Arc<AtomicBool>,
+    /// What jitter draws from at full intensity (`--jitter`, `set-jitter`).
+    jitter_range: Arc<Mutex<obfuscation::JitterRange>>,
+    /// `--jitter-below`: jitter stands aside while the packet rate is above it.
+    jitter_gate: Option<Arc<obfuscation::BulkGate>>,
+    /// `--chaff` and `--chaff-rate`.
+    chaff: Option<(chaff::ChaffProfile, u32)>,
+    /// The peer's Hello says it understands chaff frames.
+    peer_chaff: Arc<AtomicBool>,
+    /// `--mimic`: what every datagram is dressed as.
+    mimic: Option<mimicry::Mimicry>,
+    /// The obfuscation stack: TLS dressing and timing jitter, built from `--mimic`,
+    /// `--obfs` and `--jitter`. The send and receive paths go through it.
+    obfuscator: Arc<dyn obfuscation::Obfuscator>,
+    /// Splits data payloads too large for one datagram of the dressing (`--mimic dns`).
+    fitter: Option<Arc<Mutex<morph::Fitter>>>,
+    /// `--morph` / `--morph-histogram`: shapes data frames to a size distribution.
+    morph: Option<Arc<Mutex<morph::Morpher>>>,
+    /// Morphed payloads waiting for the rest of their fragments.
+    reassembly: Arc<Mutex<morph::Reassembly>>,
+    /// Spaces data frames across the RTT (`--pacing`, toggled with `toggle-pacing`).
+    pacer: Arc<pacing::Pacer>,
+    /// Frames allowed in flight: `WINDOW_SIZE`, cut by ECN marks the peer reports (`--ecn`).
+    cwnd: Arc<ecn::CongestionWindow>,
+    /// When CE marks were last reported to the peer.
+    ecn_echoed: Arc<Mutex<Option<Instant>>>,
+    /// Randomness for jitter, ClientHello mimicry and punch timing (seedable via `--seed`).
+    rng: Arc<Mutex<rand::rngs::StdRng>>,
+    /// Jitter intensity (`obfuscation::Intensity`), fixed by `--obfs` or driven by the adapter.
+    obfs: Arc<AtomicU8>,
+    /// `--obfs adaptive`: the adapter task owns `obfs`.
+    obfs_adaptive: bool,
+    /// Plaintext capture of inner packets (`--pcap`).
+    pcap: Option<pcap::PcapTap>,
+    /// Source networks allowed to send to us (`--allow`); `None` accepts everyone.
+    allowlist: Option<Arc<acl::Allowlist>>,
+    /// Rewrites inner DNS queries to the `--dns-through-tunnel` resolver (and replies back).
+    dns: Option<Arc<dns::DnsRedirect>>,
+    /// Usage of the current peer session, and `--quota-*` enforcement.
+    quota: Arc<quota::Session>,
+    /// Frame and drop counters (dashboard, `status`, `--loss-echo` reports).
+    counters: Arc<metrics::Counters>,
+    /// Duplicate suppression for received data frames.
+    replay: Arc<Mutex<replay::ReplayWindow>>,
+    echo_loss: Arc<Mutex<stats::EchoLossTracker>>,
+    /// Alerts currently firing (`--alert-*`), for `status`.
+    alerts: Arc<Mutex<Vec<alerts::Alert>>>,
+    /// Session establishment with the current peer, and how Hellos are retried.
+    handshake: Arc<Mutex<handshake::Tracker>>,
+    handshake_policy: handshake::RetryPolicy,
+    /// MTU of the TUN interface as the kernel applied it (the largest, with several): the limit for inner packets.
+    mtu: usize,
+    /// Local interfaces multiplexed over the transport (`--tun`), for the Hello.
+    interface_count: u8,
+    /// Per interface: up, or being recreated after it vanished.
+    tun_health: Arc<Vec<tunwatch::TunWatch>>,
+    /// Operator-facing state (dashboard badge, `state=`, systemd `STATUS=`).
+    connection: Arc<Mutex<connection::Tracker>>,
+    /// Our Hello session id, and the last one the peer sent us.
+    session_id: u64,
+    peer_session: Arc<Mutex<Option<u64>>>,
+    /// Protocol version and features settled with the peer (`None` before its first Hello).
+    agreement: Arc<Mutex<Option<protocol::Agreement>>>,
+    bus: EventBus,
+    stats: telemetry::TelemetrySender,
+    shutdown: shutdown::Shutdown,
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about)]
 struct TunnelOptions {
-    /// Interface bind address (e.g., 0.0.0.0:8000)
-    #[arg(long)] bind: String,
+    /// Interface bind address (e.g., 0.0.0.0:8000). Optional with --peer: an ephemeral port on the wildcard address of the peer's family
+    #[arg(long)] bind: Option<String>,
+
+    /// Carry frames over UDP, or over a TCP stream where UDP is blocked (slower: see the README)
+    #[arg(long, value_enum, default_value_t = transport::TransportKind::Udp)] transport: transport::TransportKind,
+    
+    /// Serve IPv4 and IPv6 peers from one IPv6 socket (bind to `[::]:port` or `0.0.0.0:port`)
+    #[arg(long)] dual_stack: bool,
     
-    /// Initial peer address to connect to (optional)
-    #[arg(long)] peer: Option<String>,
+    /// DSCP code point (0-63) for the tunnel's own UDP packets, e.g. 46 (EF) for interactive use
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=63))] dscp: Option<u8>,
+
+    /// Mark UDP packets ECN-capable and halve the send window when the path marks them, not only on loss (Linux; both ends)
+    #[arg(long)] ecn: bool,
+
+    /// Send to the peer over a connected socket, so ICMP errors (unreachable, too big) are reported (Linux)
+    #[arg(long)] connect_peer: bool,
+
+    /// Move to a new random source port about every this many seconds, so the flow is harder to track (initiating end only; see the README on NATs)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(5..))] rotate_port: Option<u64>,
+    
+    /// Initial peer to connect to (optional): ip:port, or hostname:port to resolve and track via DNS
+    #[arg(long, visible_alias = "peer-hostname")] peer: Option<String>,
+
+    /// Seconds between DNS re-resolutions of a hostname peer (follows dynamic DNS moves)
+    #[arg(long, default_value_t = 60)] peer_resolve_interval: u64,
+
+    /// Accept datagrams only from this source network (CIDR or address; repeatable). Others are dropped before decryption
+    #[arg(long = "allow", value_parser = acl::Network::parse)] allow: Vec<acl::Network>,
+
+    /// File of allowed source networks, one per line (`#` comments). Re-read on SIGHUP or `reload-acl`
+    #[arg(long)] allow_file: Option<std::path::PathBuf>,
+
+    /// Cap on one session's data: inner bytes, both directions together (e.g. 500M, 10G)
+    #[arg(long, value_parser = units::parse_size)] quota_bytes: Option<u64>,
+
+    /// Cap on one session's inner packets, both directions together
+    #[arg(long)] quota_packets: Option<u64>,
+
+    /// What happens to a session over its quota: throttle, or disconnect (stop forwarding)
+    #[arg(long, value_enum, default_value_t = quota::QuotaAction::Disconnect)] quota_action: quota::QuotaAction,
+
+    /// Bytes per second a throttled session is held to, both directions together (e.g. 64K)
+    #[arg(long, default_value = "64K", value_parser = units::parse_size)] quota_throttle: u64,
+
+    /// Send DNS queries (UDP/53) read from TUN to this resolver on the far side, whatever server they were addressed to
+    #[arg(long, value_name = "RESOLVER")] dns_through_tunnel: Option<std::net::IpAddr>,
+
+    /// Hellos sent without an authenticated reply before the handshake is declared failed (0 = retry forever)
+    #[arg(long, default_value_t = 10)] handshake_attempts: u32,
     
     /// Virtual IP for the TUN interface
     #[arg(long, default_value = "10.0.0.1")] tun_ip: String,
+
+    /// The peer's virtual IP: the TUN interface's point-to-point destination (default: --tun-ip)
+    #[arg(long, conflicts_with = "tuns")] tun_peer_ip: Option<String>,
+
+    /// A TUN interface as [NAME=]IP[:PEER], in place of --tun-ip and --tun-peer-ip. Repeat to multiplex several over this tunnel; the peer must list its own in the same order
+    #[arg(long = "tun", value_name = "[NAME=]IP[:PEER]", value_parser = interfaces::TunSpec::parse, conflicts_with = "tun_ip")] tuns: Vec<interfaces::TunSpec>,
     
     /// Pre-shared key (32 bytes hex). 
     /// FIXME: Replace with ephemeral key exchange (Noise Protocol).
@@ -51,244 +341,2965 @@ struct TunnelOptions {
     
     /// Enable chaos mode (simulated packet loss)
     #[arg(long)] chaos: bool,
+
+    /// Seed for jitter, fake ClientHello and punch timing, making those choices reproducible (debugging only)
+    #[arg(long)] seed: Option<u64>,
+
+    /// Program to execute on tunnel lifecycle events (details passed via GT_* env vars)
+    #[arg(long)] on_event: Option<std::path::PathBuf>,
+
+    /// Wire encoding: `auto` negotiates compact framing with capable peers and falls back to bincode
+    #[arg(long, value_enum, default_value_t = WireFormatPolicy::Auto)] wire_format: WireFormatPolicy,
+
+    /// Tag every ACK with a MAC of the sequence number it acknowledges, and drop untagged ones (both ends)
+    #[arg(long)] auth_acks: bool,
+
+    /// Data AEAD: `auto` uses AES-256-GCM when both ends have AES hardware, ChaCha20-Poly1305 otherwise
+    #[arg(long, value_enum, default_value_t = crypto::CipherPolicy::Auto)] cipher: crypto::CipherPolicy,
+
+    /// Payload compression; the peer must run the same algorithm, or packets go out uncompressed
+    #[arg(long, value_enum, default_value_t = compression::Algorithm::Zstd)] compress: compression::Algorithm,
+
+    /// Start with outbound compression switched off, still negotiated so `c` or the peer can switch it on
+    #[arg(long)] no_compress: bool,
+
+    /// Zstd compression level, 1-19 (higher: better ratio, more CPU) [default: 3]
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=19))] compress_level: Option<i32>,
+
+    /// What this end sends with, overriding --compress: ALGORITHM[:LEVEL], e.g. `zstd:19` (levels for zstd only)
+    #[arg(long, value_name = "ALGORITHM[:LEVEL]", value_parser = compression::TxSetting::parse)] compress_tx: Option<compression::TxSetting>,
+
+    /// What this end decodes from the peer, overriding --compress (`off`: the peer must send raw)
+    #[arg(long, value_enum)] compress_rx: Option<compression::Algorithm>,
+
+    /// Packets shorter than this (bytes) are sent uncompressed without trying
+    #[arg(long, default_value_t = compression::DEFAULT_MIN_SIZE)] compress_min_size: usize,
+
+    /// Percent a packet must shrink by to be sent compressed; otherwise it goes out raw
+    #[arg(long, default_value_t = compression::DEFAULT_MIN_SAVINGS, value_parser = clap::value_parser!(u8).range(0..=99))] compress_min_savings: u8,
+
+    /// Zstd dictionary for small packets: builtin, none, or a file (e.g. from `zstd --train`); the peer must have the same one
+    #[arg(long, value_name = "builtin|none|PATH", default_value = "builtin", value_parser = dictionary::DictSource::parse)] compress_dict: dictionary::DictSource,
+
+    /// Suspend compression while it saves too little, compressing a sample of packets to tell when it pays again
+    #[arg(long)] compress_auto: bool,
+
+    /// Automatic compression: percent saved below which compression is suspended
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(0..=99))] compress_auto_off_below: u8,
+
+    /// Automatic compression: seconds the savings must stay below that before suspending
+    #[arg(long, default_value_t = 10)] compress_auto_off_after: u64,
+
+    /// Automatic compression: percent the sampled packets must save to resume (at least --compress-auto-off-below)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=99))] compress_auto_on_above: u8,
+
+    /// Automatic compression: seconds the sampled packets must keep saving that before resuming
+    #[arg(long, default_value_t = 5)] compress_auto_on_after: u64,
+
+    /// Adapt the compression level to the bottleneck: cheaper (down to raw) while compressing holds the sender back, costlier while the link does
+    #[arg(long, conflicts_with = "compress_auto")] compress_adapt: bool,
+
+    /// Threads that compress outgoing packets, off the TX path (0: compress inline); a packet they can't take in time goes out raw
+    #[arg(long, default_value_t = 0)] compress_workers: usize,
+
+    /// Pace data frames across the round trip instead of sending the window in bursts (toggle at runtime with `P`)
+    #[arg(long)] pacing: bool,
+
+    /// Periodically echo received frame counts to the peer so both ends can measure true loss
+    #[arg(long)] loss_echo: bool,
+
+    /// Timing obfuscation: off, light, full, or adaptive (relax on a clean link, back to full if it goes silent). Turns jitter on
+    #[arg(long, value_enum)] obfs: Option<obfuscation::ObfsMode>,
+
+    /// Timing jitter on each outgoing packet: off, or the range at full obfuscation, e.g. 2-8ms (default: off, or 0-15ms with --obfs)
+    #[arg(long, value_parser = obfuscation::JitterSetting::parse)] jitter: Option<obfuscation::JitterSetting>,
+
+    /// Jitter only while fewer than this many packets per second are sent, sparing bulk transfers
+    #[arg(long, value_name = "PPS", value_parser = clap::value_parser!(u32).range(1..))] jitter_below: Option<u32>,
+
+    /// Cover traffic while the tunnel is quiet: constant, poisson, or fill (pad gaps up to --chaff-rate). Needs a peer that understands it
+    #[arg(long, value_enum)] chaff: Option<chaff::ChaffProfile>,
+
+    /// Chaff frames per second (1-100); never exceeded, and real data frames count against it
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..=chaff::MAX_RATE as i64))] chaff_rate: u32,
+
+    /// Dress every datagram as TLS (the decoy ClientHello is answered, then frames travel in ApplicationData records), DNS (TXT queries from the end with --peer, responses from the other), QUIC (an Initial each way, then short-header packets) or WireGuard (handshake initiation and response, then transport data). UDP; both ends need it
+    #[arg(long, value_enum)] mimic: Option<mimicry::Mimicry>,
+
+    /// Domain the --mimic dns queries ask about: the frame is encoded in labels under it. Both ends need the same one
+    #[arg(long, default_value = "example.com")] mimic_domain: String,
+
+    /// Shape data frame sizes to a traffic profile (https, video), padding small packets and splitting large ones. Needs a peer that understands it
+    #[arg(long, value_enum, conflicts_with = "morph_histogram")] morph: Option<morph::MorphProfile>,
+
+    /// Like --morph, with the target size distribution read from a JSON file: [{"up_to": 120, "weight": 35}, ...]
+    #[arg(long, value_name = "FILE")] morph_histogram: Option<std::path::PathBuf>,
+
+    /// Most bytes morphing may add, as a percentage of what the frames would cost unmorphed
+    #[arg(long, value_name = "PERCENT", default_value_t = morph::DEFAULT_OVERHEAD, value_parser = clap::value_parser!(u8).range(1..=200))] morph_overhead: u8,
+
+    /// Adaptive obfuscation: seconds of clean traffic before each step down
+    #[arg(long, default_value_t = 60)] obfs_relax_after: u64,
+
+    /// Adaptive obfuscation: highest loss rate (0.0-1.0) still considered clean
+    #[arg(long, default_value_t = 0.01)] obfs_max_loss: f64,
+
+    /// Adaptive obfuscation: seconds of sending without any reply that count as blocking
+    #[arg(long, default_value_t = 3)] obfs_blackout: u64,
+
+    /// Dashboard log lines kept for scrollback (oldest are dropped beyond this)
+    #[arg(long, default_value_t = 10_000)] log_history: usize,
+
+    /// Time span of the dashboard graphs, e.g. 30s, 5m, 1h (+/- cycle presets at runtime)
+    #[arg(long, default_value = "30s", value_parser = tui::parse_window)] graph_window: Duration,
+
+    /// Dashboard tick in milliseconds: graph sample period and idle redraw interval. Raise it to save CPU on slow machines or links
+    #[arg(long, default_value_t = 250, value_parser = clap::value_parser!(u64).range(50..=5000))] ui_tick_ms: u64,
+
+    /// Parallel TUN queues, each with its own TX/RX task pair (Linux only; 0 = one per CPU core)
+    #[arg(long, default_value_t = 1)] tun_queues: usize,
+
+    /// Received packets buffered per TUN queue while the interface is busy; beyond this the oldest are dropped
+    #[arg(long, default_value_t = 512)] tun_backlog: usize,
+
+    /// Lowest log level shown (and formatted at all); cycle at runtime with 'l'. `debug` adds per-packet diagnostics
+    #[arg(long, value_enum, default_value_t = Level::Info)] ui_log_level: Level,
+
+    /// Dashboard colors: dark, light or mono (no color). Defaults to mono when NO_COLOR is set, else dark
+    #[arg(long, value_enum)] theme: Option<theme::ThemeName>,
+
+    /// Draw the dashboard in the normal screen instead of the alternate screen, so the last frame stays in scrollback
+    #[arg(long)] inline: bool,
+
+    /// Leave the mouse to the terminal (native text selection) instead of scrolling logs and clicking tabs
+    #[arg(long)] no_mouse: bool,
+
+    /// Show throughput rates in bits or bytes per second. Defaults to bytes on the dashboard (toggle with 'b') and bits in the bench report
+    #[arg(long, value_enum)] units: Option<units::RateUnit>,
+
+    /// Log goodput (inner packets, each counted once) against wire throughput every this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))] log_throughput: Option<u64>,
+
+    /// Alert when ARQ loss exceeds this percentage
+    #[arg(long)] alert_loss: Option<f64>,
+
+    /// Alert when the smoothed RTT exceeds this many milliseconds
+    #[arg(long)] alert_rtt: Option<f64>,
+
+    /// Alert when nothing arrives from the peer for this many seconds
+    #[arg(long)] alert_rx_silence: Option<u64>,
+
+    /// Alert when the ARQ window stays full for this many seconds
+    #[arg(long)] alert_window_stall: Option<u64>,
+
+    /// Seconds a condition must stay healthy before its alert clears
+    #[arg(long, default_value_t = 10)] alert_clear_after: u64,
+
+    /// Ring the terminal bell when an alert fires
+    #[arg(long)] alert_bell: bool,
+
+    /// Run without the dashboard; log lines go to stderr
+    #[arg(long)] no_tui: bool,
+
+    /// Write every telemetry event as a JSON line to this file (`-` for stdout, which implies --no-tui)
+    #[arg(long)] telemetry_json: Option<std::path::PathBuf>,
+
+    /// Append a row of stats (totals, rates, RTT, loss, retransmits, window) to this CSV file at a fixed interval
+    #[arg(long)] stats_csv: Option<std::path::PathBuf>,
+
+    /// Seconds between `--stats-csv` rows
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))] stats_csv_interval: u64,
+
+    /// Rotate the `--stats-csv` file to `<path>.1` once it reaches this size (MiB)
+    #[arg(long, default_value_t = 100)] stats_csv_max_mb: u64,
+
+    /// Append log lines to this file so they survive a restart; the dashboard replays its end on startup
+    #[arg(long)] log_file: Option<std::path::PathBuf>,
+
+    /// Rotate the `--log-file` once it reaches this size (MiB)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))] log_file_max_mb: u64,
+
+    /// Rotated `--log-file` generations kept (`<path>.1` is the newest)
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..=99))] log_file_keep: u64,
+
+    /// Directory for dashboard snapshot dumps ('s' key)
+    #[arg(long, default_value = ".")] dump_dir: std::path::PathBuf,
+
+    /// Unix domain socket accepting runtime commands (see `control.rs` for the protocol)
+    #[arg(long)] control_socket: Option<std::path::PathBuf>,
+
+    /// Serve the control commands over TCP on this address. Security-sensitive: token auth only, no TLS
+    #[arg(long, requires = "admin_token_file")] admin_listen: Option<SocketAddr>,
+
+    /// File holding the `--admin-listen` token (at least 16 characters); keep it private
+    #[arg(long)] admin_token_file: Option<std::path::PathBuf>,
+
+    /// Capture decrypted inner packets (both directions) to a pcap file. Contains plaintext traffic!
+    #[arg(long)] pcap: Option<std::path::PathBuf>,
+
+    /// Stop capturing once the pcap file reaches this size (MiB)
+    #[arg(long, default_value_t = 100)] pcap_max_mb: u64,
+
+    #[command(subcommand)] mode: Option<Mode>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Mode {
+    /// Measure throughput over the full data plane, with a synthetic generator/sink instead of TUN
+    Bench(bench::BenchOptions),
+    /// Measure the link to --peer (running `bench`) and print suggested settings
+    Profile(profile::ProfileOptions),
+}
+
+/// Which wire formats this node is willing to speak.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum WireFormatPolicy {
+    /// Start on bincode, upgrade to compact once the peer advertises it.
+    Auto,
+    /// Legacy framing only (never upgrade).
+    Bincode,
+    /// Compact framing only (breaks peers that predate it).
+    Compact,
+    /// Whole frames sealed, headers included, so frame types can't be told apart (both ends need it).
+    Sealed,
+}
+
+impl WireFormatPolicy {
+    fn supported(self) -> Vec<WireFormat> {
+        match self {
+            WireFormatPolicy::Auto => vec![WireFormat::Bincode, WireFormat::Compact],
+            WireFormatPolicy::Bincode => vec![WireFormat::Bincode],
+            WireFormatPolicy::Compact => vec![WireFormat::Compact],
+            WireFormatPolicy::Sealed => vec![WireFormat::Sealed],
+        }
+    }
+
+    fn initial(self) -> WireFormat {
+        match self {
+            WireFormatPolicy::Compact => WireFormat::Compact,
+            WireFormatPolicy::Sealed => WireFormat::Sealed,
+            _ => WireFormat::Bincode,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = TunnelOptions::parse();
+    logging::set_threshold(opts.ui_log_level);
+    let peer_spec = opts.peer.as_deref().map(resolve::PeerSpec::parse).transpose()?;
+    // Only the initiating end can do without a fixed address: peers never need to find it.
+    // A hostname peer is looked up first for its family; one that doesn't resolve yet
+    // gets a dual-stack socket, which reaches whichever family it turns out to have.
+    let (bind, dual_stack) = match (&opts.bind, &peer_spec) {
+        (Some(bind), _) => (bind.clone(), opts.dual_stack),
+        (None, Some(spec)) => {
+            let v6 = match spec {
+                resolve::PeerSpec::Addr(addr) => Some(addr.is_ipv6()),
+                resolve::PeerSpec::Host { host, port } => resolve::needs_v6(host, *port).await,
+            };
+            match v6 {
+                Some(false) => ("0.0.0.0:0".to_string(), opts.dual_stack),
+                Some(true) => ("[::]:0".to_string(), opts.dual_stack),
+                None => ("[::]:0".to_string(), opts.dual_stack || opts.transport == transport::TransportKind::Udp),
+            }
+        }
+        (None, None) => anyhow::bail!("--bind is needed without --peer: peers reach a listening end at a fixed address (e.g. --bind 0.0.0.0:8000)"),
+    };
 
     // Telemetry Channel -> TUI Thread
-    let (stats_tx, stats_rx) = mpsc::channel::<TelemetryUpdate>();
-    let tui_handle = tui::spawn_dashboard(stats_rx);
+    let (stats_tx, stats_rx) = telemetry::channel();
+    // Stop requests (dashboard, signals, control, watchdog) -> every long-running part.
+    let shutdown = shutdown::Shutdown::new(stats_tx.clone());
+    shutdown.on_signals();
+    // Control channel: every front-end (socket, dashboard) funnels into one dispatcher.
+    let (control_tx, control_rx) = tokio::sync::mpsc::channel::<control::ControlRequest>(16);
+    let profile_opts = match &opts.mode {
+        Some(Mode::Profile(p)) => Some(p.clone()),
+        _ => None,
+    };
+    // `profile` runs its measurements on the bench pipeline.
+    let bench_opts = match &opts.mode {
+        Some(Mode::Bench(b)) => Some(b.clone()),
+        Some(Mode::Profile(p)) => Some(p.bench()),
+        None => None,
+    };
+    if profile_opts.is_some() && opts.peer.is_none() {
+        anyhow::bail!("profile needs --peer: the far end, running `bench`");
+    }
+    if opts.rotate_port.is_some() && opts.peer.is_none() {
+        anyhow::bail!("--rotate-port needs --peer: peers find a listening end by its port, so only the initiating end can move");
+    }
+    if opts.rotate_port.is_some() && (opts.transport == transport::TransportKind::Tcp || opts.connect_peer) {
+        anyhow::bail!("--rotate-port applies to UDP without --connect-peer");
+    }
+    if opts.compress_auto_on_above < opts.compress_auto_off_below {
+        anyhow::bail!("--compress-auto-on-above must be at least --compress-auto-off-below, or compression would flap");
+    }
+    // Each direction's algorithm: `--compress`, unless `--compress-tx` / `--compress-rx` say otherwise.
+    let tx_algorithm = opts.compress_tx.map_or(opts.compress, |tx| tx.algorithm);
+    let rx_algorithm = opts.compress_rx.unwrap_or(opts.compress);
+    if let Some(level) = opts.compress_level {
+        compression::check_level(tx_algorithm, level).map_err(|e| anyhow::anyhow!("--compress-level: {}", e))?;
+    }
+    let tx_level = opts.compress_tx.and_then(|tx| tx.level).or(opts.compress_level).unwrap_or(compression::DEFAULT_ZSTD_LEVEL);
+    // `bench` stands in for a single interface, whatever `--tun` says.
+    let tun_specs = match (&bench_opts, opts.tuns.is_empty()) {
+        (Some(_), _) | (None, true) => vec![interfaces::TunSpec::from_tun_ip(&opts.tun_ip, opts.tun_peer_ip.as_deref())?],
+        (None, false) => opts.tuns.clone(),
+    };
+    let multi_interface = bench_opts.is_none() && tun_specs.len() > 1;
+    if tun_specs.len() > interfaces::MAX_INTERFACES {
+        anyhow::bail!("at most {} --tun interfaces", interfaces::MAX_INTERFACES);
+    }
+    if multi_interface && opts.wire_format == WireFormatPolicy::Bincode {
+        anyhow::bail!("several --tun interfaces need compact or sealed framing: bincode frames have no interface id");
+    }
+    // Bincode framing can't carry the interface id, so several interfaces never fall back to it.
+    let mut supported_formats = opts.wire_format.supported();
+    let mut initial_format = opts.wire_format.initial();
+    if multi_interface {
+        supported_formats.retain(|f| *f != WireFormat::Bincode);
+        initial_format = initial_format.max(WireFormat::Compact);
+    }
+    let stats_rx = match &opts.telemetry_json {
+        Some(path) => telemetry_json::spawn_tap(stats_rx, telemetry_json::open_output(path)?),
+        None => stats_rx,
+    };
+    let (csv_export, stats_rx) = match &opts.stats_csv {
+        Some(path) => {
+            let interval = Duration::from_secs(opts.stats_csv_interval);
+            let (export, rx) = telemetry_csv::CsvExport::spawn(stats_rx, path, interval, opts.stats_csv_max_mb * 1024 * 1024, stats_tx.clone())?;
+            (Some(export), rx)
+        }
+        None => (None, stats_rx),
+    };
+    let json_on_stdout = opts.telemetry_json.as_deref() == Some(std::path::Path::new("-"));
+    let headless = bench_opts.is_some() || opts.no_tui || json_on_stdout;
+    // Read before this session starts appending to it.
+    let log_history = match &opts.log_file {
+        Some(path) if !headless => Some(log_file::History::load(path, opts.log_history)),
+        _ => None,
+    };
+    let (log_file, stats_rx) = match &opts.log_file {
+        Some(path) => {
+            let max_bytes = opts.log_file_max_mb * 1024 * 1024;
+            let (log, rx) = log_file::LogFile::spawn(stats_rx, path, max_bytes, opts.log_file_keep as usize, stats_tx.clone())?;
+            (Some(log), rx)
+        }
+        None => (None, stats_rx),
+    };
+    let tui_handle = if headless {
+        tui::spawn_headless(stats_rx, shutdown.clone())
+    } else {
+        tui::spawn_dashboard(stats_rx, tui::DashboardConfig {
+            dump_dir: opts.dump_dir.clone(),
+            control: control_tx.clone(),
+            log_history: opts.log_history,
+            history: log_history,
+            graph_window: opts.graph_window,
+            alert_bell: opts.alert_bell,
+            palette: theme::Palette::new(theme::ThemeName::resolve(opts.theme)),
+            inline: opts.inline,
+            units: opts.units.unwrap_or(units::RateUnit::Bytes),
+            tick: Duration::from_millis(opts.ui_tick_ms),
+            mouse: !opts.no_mouse,
+            shutdown: shutdown.clone(),
+        })
+    };
+    // If setup below fails and main returns early, don't leave the dashboard's raw-mode screen behind.
+    let _terminal_guard = (!headless).then_some(tui::TerminalGuard);
+
+    // Lifecycle Event Bus -> TUI log + hooks
+    let bus = EventBus::new();
+    let mut bus_log = bus.subscribe();
+    let log_stats = stats_tx.clone();
+    tokio::spawn(async move {
+        loop {
+            match bus_log.recv().await {
+                Ok(event) => { tlog!(log_stats, Level::Info, "EVT: {}", event); }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
 
     // Crypto Setup
     let key_bytes = hex::decode(&opts.key).context("Found malformed hex key")?;
     let key_arr: [u8; 32] = key_bytes.try_into().map_err(|_| anyhow::anyhow!("Key must be exactly 32 bytes"))?;
-    
-    // We share the cipher primitive across threads. 
-    // Arc<T> is cheap here, and ChaCha state is immutable until encryption.
-    let cipher_enc = Arc::new(crypto::SessionGuard::new(&key_arr));
-    let cipher_dec = cipher_enc.clone();
+    let cipher = Arc::new(crypto::SessionGuard::new(&key_arr));
+    tlog!(stats_tx, Level::Info, "CRYPTO: Pre-shared key {:08x}", crypto::key_fingerprint(&key_arr));
+    let ciphers = opts.cipher.preference();
+    tlog!(stats_tx, Level::Info, "CRYPTO: AES hardware {}; offering {}",
+        if crypto::aes_accelerated() { "detected" } else { "not detected" },
+        ciphers.iter().map(|c| c.name()).collect::<Vec<_>>().join(", "));
 
-    // TUN Interface Setup
-    // We use a small MTU to avoid fragmentation issues over UDP overlays.
-    let mut config = Configuration::default();
-    config.address(opts.tun_ip.parse::<std::net::Ipv4Addr>()?)
-          .destination(opts.tun_ip.parse::<std::net::Ipv4Addr>()?)
-          .netmask((255, 255, 255, 0))
-          .mtu(MTU as i32)
-          .up();
-    
-    #[cfg(target_os = "linux")]
-    config.platform(|c| { c.packet_information(true); });
+    // TUN Interface Setup (or the synthetic stand-in for `bench`)
+    let queue_count = match opts.tun_queues {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    // One entry per interface, in `--tun` order: its id on the wire is its index here.
+    let (tun_names, tun_io, tun_devices, bench_probe, mtu) = match &bench_opts {
+        Some(b) => {
+            let (generator, sink, probe) = bench::pipeline(b, MTU);
+            (vec!["bench".to_string()], vec![vec![tun_pair(generator, sink)]], Vec::new(), Some(probe), MTU)
+        }
+        None => {
+            let (mut names, mut io, mut devices, mut mtu) = (Vec::new(), Vec::new(), Vec::new(), 0);
+            for spec in &tun_specs {
+                let (name, queues, device, actual_mtu) = open_tun(spec, queue_count)?;
+                let interface_mtu = match actual_mtu {
+                    Some(actual) if actual != MTU => {
+                        tlog!(stats_tx, Level::Warn, "TUN: {} has MTU {} instead of the requested {}; framing follows the interface", name, actual, MTU);
+                        actual
+                    }
+                    Some(_) => MTU,
+                    None => {
+                        tlog!(stats_tx, Level::Warn, "TUN: Cannot read back the MTU of {}; assuming {}", name, MTU);
+                        MTU
+                    }
+                };
+                if multi_interface {
+                    tlog!(stats_tx, Level::Info, "TUN: {} ({}) up as interface {} with {} queue(s), MTU {}", name, spec.label(), names.len(), queues.len(), interface_mtu);
+                } else {
+                    let peer = spec.peer.map_or(String::new(), |peer| format!(", peer {}", peer));
+                    tlog!(stats_tx, Level::Info, "TUN: {} up with {} queue(s), MTU {}{}", name, queues.len(), interface_mtu, peer);
+                }
+                mtu = mtu.max(interface_mtu);
+                names.push(name);
+                io.push(queues);
+                devices.push(device);
+            }
+            (names, io, devices, None, mtu)
+        }
+    };
 
-    let tun_dev = tun::create_as_async(&config).context("Failed to open TUN device. Do you have root privileges?")?;
-    let (mut tun_reader, mut tun_writer) = tokio::io::split(tun_dev);
+    // Jitter caps each flow at a few hundred packets per second, so it is opt-in:
+    // `--obfs` or `--jitter` turns it on (`bench` wants its own `--jitter`).
+    let obfs = opts.obfs.unwrap_or(obfuscation::ObfsMode::Full);
+    let jitter_range = match opts.jitter {
+        Some(obfuscation::JitterSetting::Range(range)) => range,
+        _ => obfuscation::JitterRange::DEFAULT,
+    };
+    let jitter_on = match (&bench_opts, opts.jitter) {
+        (_, Some(obfuscation::JitterSetting::Off)) => false,
+        (Some(b), _) => b.jitter,
+        (None, Some(obfuscation::JitterSetting::Range(_))) => true,
+        (None, None) => opts.obfs.is_some(),
+    };
+    if let Some(pps) = opts.jitter_below {
+        tlog!(stats_tx, Level::Info, "OBSF: Jitter stands aside above {} packets/s", pps);
+    }
 
-    // UDP Socket Setup
-    let socket = UdpSocket::bind(&opts.bind).await.context("Failed to bind UDP socket")?;
+    if let Some(seed) = opts.seed {
+        tlog!(stats_tx, Level::Warn, "RNG: Seeded with {}: jitter and handshake padding are predictable", seed);
+    }
+    let jitter = Arc::new(AtomicBool::new(jitter_on));
+    let jitter_range = Arc::new(Mutex::new(jitter_range));
+    let jitter_gate = opts.jitter_below.map(|pps| Arc::new(obfuscation::BulkGate::new(pps)));
+    let obfs_level = Arc::new(AtomicU8::new(obfs.initial() as u8));
+    let rng = Arc::new(Mutex::new(obfuscation::traffic_rng(opts.seed)));
+
+    // Obfuscation, innermost first: the TLS dressing, then timing. `--obfs off` leaves
+    // timing alone, unless `profile` is to measure with jitter.
+    let timing: Box<dyn obfuscation::Obfuscator> = if obfs == obfuscation::ObfsMode::Off && profile_opts.is_none() {
+        Box::new(obfuscation::Null)
+    } else {
+        Box::new(obfuscation::Jitter {
+            on: jitter.clone(),
+            range: jitter_range.clone(),
+            intensity: obfs_level.clone(),
+            gate: jitter_gate.clone(),
+            rng: rng.clone(),
+        })
+    };
+    // A TLS decoy would give the other protocols away: under `--mimic dns`, `quic` or
+    // `wireguard` that layer replaces it.
+    let dressing: Box<dyn obfuscation::Obfuscator> = match opts.mimic {
+        Some(mimicry::Mimicry::Dns) => {
+            if opts.morph.is_some() || opts.morph_histogram.is_some() {
+                anyhow::bail!("--morph cannot shape datagrams that --mimic dns sizes");
+            }
+            Box::new(dns_mimicry::DnsMimic::new(&opts.mimic_domain, opts.peer.is_some(), rng.clone())?)
+        }
+        Some(mimicry::Mimicry::Quic) => Box::new(quic_mimicry::QuicMimic::new(opts.peer.is_some(), rng.clone())),
+        Some(mimicry::Mimicry::Wireguard) => Box::new(wireguard_mimicry::WireGuardMimic::new(rng.clone())),
+        mimic => Box::new(mimicry::TlsMimic::new(mimic == Some(mimicry::Mimicry::Tls), rng.clone())),
+    };
+    let obfuscator: Arc<dyn obfuscation::Obfuscator> = Arc::new(obfuscation::Stack(vec![dressing, timing]));
+
+    // Transport Setup (UDP socket, or TCP listener with --transport tcp)
+    let socket = transport::Transport::bind(opts.transport, &bind, dual_stack, opts.dscp, opts.connect_peer, opts.ecn, obfuscator.clone()).await?;
     let socket = Arc::new(socket);
-    
-    // Pre-flight: Send random junk to punch NAT or confuse DPI before real handshake.
-    if let Some(peer_str) = &opts.peer {
-        let fake_hello = obfuscation::mimic_tls_client_hello();
-        if let Ok(addr) = peer_str.parse::<SocketAddr>() {
-             let _ = socket.send_to(&fake_hello, addr).await;
-             let _ = stats_tx.send(TelemetryUpdate::Log("OBSF: Injection of Traffic Jitter (Gaussian)".to_string()));
+    match (&opts.bind, socket.local_addr()) {
+        (None, Some(local)) => tlog!(stats_tx, Level::Info, "NET: No --bind given; sending to the peer from {}", local),
+        (None, None) => tlog!(stats_tx, Level::Info, "NET: No --bind given; dialling the peer from an ephemeral port"),
+        (Some(_), Some(local)) if opts.peer.is_none() && bind.ends_with(":0") => {
+            tlog!(stats_tx, Level::Warn, "NET: Bound to {}, a port the kernel picked; peers won't know where to find this end", local);
         }
+        _ => {}
     }
 
-    let initial_peer: Option<SocketAddr> = opts.peer.as_deref().map(|p| p.parse()).transpose()?;
-    let active_peer = Arc::new(Mutex::new(initial_peer));
+    let hook_handle = opts.on_event.clone().map(|program| {
+        let ctx = hooks::HookContext {
+            program,
+            tun_name: tun_names.join(","),
+            tun_ip: tun_specs.iter().map(|spec| spec.ip.to_string()).collect::<Vec<_>>().join(","),
+            bind: bind.clone(),
+        };
+        hooks::spawn_hook_runner(ctx, bus.subscribe(), stats_tx.clone())
+    });
     
-    // Sequence number for basic replay protection (monotonic counter)
-    let tx_seq = Arc::new(AtomicU64::new(1));
+    // A hostname peer is filled in by the resolver task once DNS answers.
+    let initial_peer = match peer_spec {
+        Some(resolve::PeerSpec::Addr(addr)) => Some(addr),
+        _ => None,
+    };
+
+    let pcap = match &opts.pcap {
+        Some(path) => {
+            let (tap, handle) = pcap::PcapTap::create(path, opts.pcap_max_mb * 1024 * 1024, stats_tx.clone())?;
+            tlog!(stats_tx, Level::Info, "PCAP: Capturing decrypted traffic to {}", path.display());
+            Some((tap, handle))
+        }
+        None => None,
+    };
 
-    // Shared state for ARQ (Automatic Repeat Request)
-    let pending_packets: PendingPackets = Arc::new(Mutex::new(HashMap::new()));
+    let quota_limits = (opts.quota_bytes.is_some() || opts.quota_packets.is_some()).then_some(quota::Limits {
+        bytes: opts.quota_bytes,
+        packets: opts.quota_packets,
+        action: opts.quota_action,
+        throttle_rate: opts.quota_throttle,
+    });
 
-    // ----------------------------------------------------------------
-    // RETRANSMISSION TASK
-    // Resends dropped packets if RTO is exceeded.
-    // ----------------------------------------------------------------
-    let rtx_socket = socket.clone();
-    let rtx_peer = active_peer.clone();
-    let rtx_pending = pending_packets.clone();
-    let rtx_stats = stats_tx.clone();
+    let allowlist = acl::Allowlist::load(opts.allow.clone(), opts.allow_file.clone())?.map(Arc::new);
+    if let Some(acl) = &allowlist {
+        tlog!(stats_tx, Level::Info, "ACL: Accepting datagrams from {} networks only", acl.len());
+    }
 
-    tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_millis(10)).await; // Check every 10ms
+    let morph_target = match (opts.morph, &opts.morph_histogram) {
+        (Some(profile), _) => Some(morph::Histogram::builtin(profile)),
+        (None, Some(path)) => Some(morph::Histogram::load(path)?),
+        (None, None) => None,
+    };
 
-            let now = Instant::now();
-            let mut retransmits = Vec::new();
+    let dns = opts.dns_through_tunnel.map(|resolver| Arc::new(dns::DnsRedirect::new(resolver)));
+    if let Some(dns) = &dns {
+        tlog!(stats_tx, Level::Info, "DNS: Sending queries from TUN to {} through the tunnel", dns.resolver());
+    }
 
-            // Scope for lock
-            {
-                let lock = rtx_pending.lock();
-                for (seq, (sent_time, data)) in lock.iter() {
-                    if now.duration_since(*sent_time) > RTO {
-                        retransmits.push((*seq, data.clone()));
-                    }
-                }
-            }
 
-            if !retransmits.is_empty() {
-                let target = *rtx_peer.lock();
-                if let Some(remote_addr) = target {
-                    for (seq, data) in retransmits {
-                        // TODO: Implement exponential backoff for RTO
-                        if let Err(e) = rtx_socket.send_to(&data, remote_addr).await {
-                             let _ = rtx_stats.send(TelemetryUpdate::Log(format!("RTX::Err: {}", e)));
-                        } else {
-                             // Update timestamp (reset RTO)
-                             let mut lock = rtx_pending.lock();
-                             if let Some(entry) = lock.get_mut(&seq) {
-                                 entry.0 = Instant::now();
-                             }
-                        }
-                    }
-                }
+    let dictionary = dictionary::Dictionary::load(&opts.compress_dict)?;
+    match &dictionary {
+        Some(dict) if tx_algorithm == compression::Algorithm::Zstd || rx_algorithm == compression::Algorithm::Zstd => {
+            tlog!(stats_tx, Level::Info, "COMPRESS: Dictionary {:08x} ({} bytes) for small packets", dict.id, dict.bytes.len());
+        }
+        Some(_) if matches!(opts.compress_dict, dictionary::DictSource::File(_)) => {
+            tlog!(stats_tx, Level::Warn, "COMPRESS: --compress-dict only applies to zstd; ignored with --compress {}", tx_algorithm.name());
+        }
+        _ => {}
+    }
+    if opts.compress_auto && tx_algorithm == compression::Algorithm::Off {
+        tlog!(stats_tx, Level::Warn, "COMPRESS: --compress-auto ignored with --compress off");
+    }
+    if opts.compress_adapt && tx_algorithm == compression::Algorithm::Off {
+        tlog!(stats_tx, Level::Warn, "COMPRESS: --compress-adapt ignored with --compress off");
+    }
+    if opts.compress_workers > 0 && tx_algorithm == compression::Algorithm::Off {
+        tlog!(stats_tx, Level::Warn, "COMPRESS: --compress-workers ignored with --compress off");
+    }
+    if tx_algorithm != rx_algorithm {
+        tlog!(stats_tx, Level::Info, "COMPRESS: Sending {}, decoding {}", tx_algorithm.label(tx_level), rx_algorithm.name());
+    }
+    let codec = compression::Codec::new(
+        tx_algorithm,
+        tx_level,
+        rx_algorithm,
+        compression::Policy { min_size: opts.compress_min_size, min_savings: opts.compress_min_savings },
+        dictionary,
+        packet::max_frame_len(mtu),
+    )?;
+
+    let codec = Arc::new(codec);
+    let compress_pool = (opts.compress_workers > 0 && tx_algorithm != compression::Algorithm::Off)
+        .then(|| Arc::new(compress_pool::CompressPool::start(codec.clone(), opts.compress_workers)));
+    if let Some(pool) = &compress_pool {
+        tlog!(stats_tx, Level::Info, "COMPRESS: {} worker threads, up to {} packets queued", opts.compress_workers, pool.depth());
+    }
+
+    let shared = Shared {
+        socket: socket.clone(),
+        peer: Arc::new(Mutex::new(initial_peer)),
+        pending: Arc::new(Mutex::new(HashMap::new())),
+        cipher: cipher.clone(),
+        tx_seq: Arc::new(AtomicU64::new(1)),
+        supported_formats: Arc::new(supported_formats),
+        ciphers: Arc::new(ciphers),
+        wire_format: Arc::new(Mutex::new(initial_format)),
+        auth_acks: opts.auth_acks,
+        acks_signed: Arc::new(AtomicBool::new(false)),
+        monitor: Arc::new(Mutex::new(stats::LinkMonitor::new())),
+        rtt: Arc::new(stats::RttHistogram::new()),
+        tx_delay: Arc::new(stats::RttHistogram::new()),
+        last_rx: Arc::new(Mutex::new(Instant::now())),
+        peer_up: Arc::new(AtomicBool::new(false)),
+        last_auth: Arc::new(Mutex::new(None)),
+        roam_count: Arc::new(AtomicU64::new(0)),
+        // `profile` holds data back until it has timed the handshake.
+        paused: Arc::new(AtomicBool::new(profile_opts.is_some())),
+        compression: Arc::new(AtomicBool::new(!opts.no_compress)),
+        peer_compression: Arc::new(Mutex::new(None)),
+        peer_codec: Arc::new(Mutex::new(None)),
+        codec,
+        compress_auto: Arc::new(Mutex::new(None)),
+        compress_adapt: Arc::new(Mutex::new(None)),
+        window_stalls: Arc::new(AtomicU64::new(0)),
+        compress_pool,
+        jitter,
+        jitter_range: jitter_range.clone(),
+        jitter_gate,
+        chaff: opts.chaff.map(|profile| (profile, opts.chaff_rate)),
+        mimic: opts.mimic,
+        fitter: obfuscator.max_frame().map(|max| Arc::new(Mutex::new(morph::Fitter::new(max)))),
+        obfuscator,
+        // A chunk never outgrows an uncompressed full-size packet (plus its codec byte).
+        morph: morph_target.map(|target| Arc::new(Mutex::new(morph::Morpher::new(target, opts.morph_overhead, packet::max_frame_len(mtu) + 1)))),
+        reassembly: Arc::new(Mutex::new(morph::Reassembly::new())),
+        peer_chaff: Arc::new(AtomicBool::new(false)),
+        pacer: Arc::new(pacing::Pacer::new(opts.pacing, WINDOW_SIZE)),
+        cwnd: Arc::new(ecn::CongestionWindow::new(WINDOW_SIZE)),
+        ecn_echoed: Arc::new(Mutex::new(None)),
+        rng,
+        obfs: obfs_level,
+        obfs_adaptive: obfs == obfuscation::ObfsMode::Adaptive,
+        pcap: pcap.as_ref().map(|(tap, _)| tap.clone()),
+        allowlist: allowlist.clone(),
+        dns,
+        quota: Arc::new(quota::Session::new(quota_limits)),
+        counters: Arc::new(metrics::Counters::default()),
+        replay: Arc::new(Mutex::new(replay::ReplayWindow::new())),
+        echo_loss: Arc::new(Mutex::new(stats::EchoLossTracker::new())),
+        alerts: Arc::new(Mutex::new(Vec::new())),
+        handshake: Arc::new(Mutex::new(handshake::Tracker::default())),
+        mtu,
+        interface_count: tun_io.len() as u8,
+        tun_health: Arc::new(tun_io.iter().map(|_| tunwatch::TunWatch::new()).collect()),
+        connection: Arc::new(Mutex::new(connection::Tracker::new(STALE_AFTER))),
+        handshake_policy: handshake::RetryPolicy {
+            initial: PUNCH_INITIAL_INTERVAL,
+            max_interval: PUNCH_MAX_INTERVAL,
+            max_attempts: (opts.handshake_attempts > 0).then_some(opts.handshake_attempts),
+        },
+        session_id: rand::random(),
+        peer_session: Arc::new(Mutex::new(None)),
+        agreement: Arc::new(Mutex::new(None)),
+        bus: bus.clone(),
+        stats: stats_tx.clone(),
+        shutdown: shutdown.clone(),
+    };
+
+    if let Some(addr) = initial_peer {
+        follow_peer(&shared, addr);
+        if jitter_on {
+            tlog!(stats_tx, Level::Info, "OBSF: Injecting {} before each packet", jitter_range.lock().label(obfs.initial()));
+        }
+        tokio::spawn(punch_loop(shared.clone(), addr));
+    }
+
+    if let Some(resolve::PeerSpec::Host { host, port }) = peer_spec {
+        let interval = Duration::from_secs(opts.peer_resolve_interval.max(1));
+        tokio::spawn(peer_resolver(shared.clone(), host, port, interval));
+    }
+
+    tokio::spawn(quality_reporter(shared.clone()));
+    tokio::spawn(quota_enforcer(shared.clone()));
+    let notifier = notify::Notifier::from_env();
+    tokio::spawn(connection_monitor(shared.clone(), notifier));
+    tokio::spawn(throughput_reporter(shared.clone(), opts.log_throughput.map(Duration::from_secs), opts.units.unwrap_or(units::RateUnit::Bytes)));
+    let alert_rules = alerts::AlertRules {
+        loss_pct: opts.alert_loss,
+        rtt_ms: opts.alert_rtt,
+        rx_silence: opts.alert_rx_silence.map(Duration::from_secs),
+        window_stall: opts.alert_window_stall.map(Duration::from_secs),
+        clear_after: Duration::from_secs(opts.alert_clear_after),
+    };
+    if alert_rules.any() {
+        tokio::spawn(alert_monitor(shared.clone(), alert_rules));
+    }
+    if obfs == obfuscation::ObfsMode::Adaptive {
+        let policy = obfuscation::AdaptivePolicy {
+            relax_after: Duration::from_secs(opts.obfs_relax_after),
+            max_loss: opts.obfs_max_loss,
+            blackout: Duration::from_secs(opts.obfs_blackout),
+        };
+        tokio::spawn(obfs_adapter(shared.clone(), policy));
+    }
+    if opts.compress_auto && tx_algorithm != compression::Algorithm::Off {
+        let policy = compression::AutoPolicy {
+            off_below: opts.compress_auto_off_below.into(),
+            off_after: Duration::from_secs(opts.compress_auto_off_after),
+            on_above: opts.compress_auto_on_above.into(),
+            on_after: Duration::from_secs(opts.compress_auto_on_after),
+        };
+        tokio::spawn(compression_switch(shared.clone(), policy));
+    }
+    if opts.compress_adapt && tx_algorithm != compression::Algorithm::Off {
+        // Inline, each TX task compresses its own packets; otherwise the workers do.
+        let tx_tasks = tun_io.iter().map(Vec::len).sum::<usize>();
+        let threads = if opts.compress_workers > 0 { opts.compress_workers } else { tx_tasks };
+        tokio::spawn(compression_governor(shared.clone(), threads, tx_tasks));
+    }
+
+    tokio::spawn(control_dispatcher(shared.clone(), control_rx));
+    // Only with an allowlist: otherwise SIGHUP keeps its default meaning (terminal hung up, exit).
+    if allowlist.is_some() {
+        tokio::spawn(acl_reloader(control_tx.clone(), stats_tx.clone()));
+    }
+    if let (Some(addr), Some(token_file)) = (opts.admin_listen, &opts.admin_token_file) {
+        admin::spawn_admin_listener(addr, token_file, control_tx.clone(), stats_tx.clone())?;
+    }
+    let control_path = match &opts.control_socket {
+        Some(path) => Some(control::spawn_control_socket(path, control_tx, stats_tx.clone())?),
+        None => None,
+    };
+    if opts.loss_echo {
+        tokio::spawn(loss_echo_loop(shared.clone()));
+    }
+    if let Some((profile, rate)) = shared.chaff {
+        tlog!(stats_tx, Level::Info, "OBSF: Chaff {} at up to {} frames/s while the tunnel is quiet", profile.label(), rate);
+        tokio::spawn(chaff_loop(shared.clone(), chaff::Schedule::new(profile, rate)));
+    }
+    match shared.mimic {
+        Some(mimicry::Mimicry::Tls) => {
+            tlog!(stats_tx, Level::Info, "OBSF: Every datagram sent as TLS records; the peer needs --mimic tls too");
+            if opts.wire_format != WireFormatPolicy::Sealed {
+                tlog!(stats_tx, Level::Warn, "OBSF: Frame headers show inside the TLS records outside --wire-format sealed");
             }
         }
-    });
+        Some(mimicry::Mimicry::Dns) => {
+            let shape = if opts.peer.is_some() { "queries" } else { "responses" };
+            let max = shared.obfuscator.max_frame().unwrap_or_default();
+            tlog!(stats_tx, Level::Info, "OBSF: Every datagram sent as DNS {} under {} (frames of up to {} bytes); the peer needs --mimic dns too", shape, opts.mimic_domain, max);
+        }
+        Some(mimicry::Mimicry::Quic) => {
+            let role = if opts.peer.is_some() { "client" } else { "server" };
+            tlog!(stats_tx, Level::Info, "OBSF: Every datagram sent as QUIC v1 packets from the {} side; the peer needs --mimic quic too", role);
+            if opts.wire_format != WireFormatPolicy::Sealed {
+                tlog!(stats_tx, Level::Warn, "OBSF: Frame headers show inside the QUIC packets outside --wire-format sealed");
+            }
+        }
+        Some(mimicry::Mimicry::Wireguard) => {
+            tlog!(stats_tx, Level::Info, "OBSF: Every datagram sent as WireGuard messages; the peer needs --mimic wireguard too");
+            if opts.wire_format != WireFormatPolicy::Sealed {
+                tlog!(stats_tx, Level::Warn, "OBSF: Frame headers show inside the WireGuard transport data outside --wire-format sealed");
+            }
+        }
+        None => {}
+    }
+    if let Some(morpher) = &shared.morph {
+        tlog!(stats_tx, Level::Info, "OBSF: Shaping data frames to the {} size profile, adding at most {}%", morpher.lock().target().label(), opts.morph_overhead);
+    }
+    if let Some(secs) = opts.rotate_port {
+        tlog!(stats_tx, Level::Info, "UDP: Changing source port about every {}s", secs);
+        tokio::spawn(port_rotation(shared.clone(), Duration::from_secs(secs)));
+    }
 
-    // ----------------------------------------------------------------
-    // TX LOOP: TUN Interface -> UDP Socket
-    // Reads IP packets, compresses, encrypts, and blasts them over UDP.
-    // ----------------------------------------------------------------
-    let socket_tx = socket.clone();
-    let peer_tx = active_peer.clone();
-    let stats_tx_1 = stats_tx.clone();
-    let pending_tx = pending_packets.clone();
-    
-    let _tx_task = tokio::spawn(async move {
-        let mut frame_buffer = [0u8; 4096]; // Oversized buffer for safety
-        loop {
-            // Flow Control: Don't read from TUN if window is full
-            let is_full = {
-                 let lock = pending_tx.lock();
-                 lock.len() >= WINDOW_SIZE
-            };
+    // Every long-running networking task runs under the watchdog, which also stops it on shutdown.
+    let mut supervised = Vec::new();
+    let sh = shared.clone();
+    supervised.push(supervisor::supervise("LIVENESS", move || tokio::spawn(liveness_loop(sh.clone())), stats_tx.clone(), shutdown.clone()));
+    let sh = shared.clone();
+    supervised.push(supervisor::supervise("RTX", move || tokio::spawn(retransmit_loop(sh.clone())), stats_tx.clone(), shutdown.clone()));
+    // Per interface and TUN queue: a TX task, and a TUN writer fed by its own backlog.
+    let queues_per_interface = tun_io.iter().map(Vec::len).max().unwrap_or(1);
+    let multi_queue = queues_per_interface > 1;
+    let label = |role: &str, queue: usize, iface: Option<usize>| {
+        let mut label = role.to_string();
+        if multi_queue {
+            label.push_str(&queue.to_string());
+        }
+        if let Some(iface) = iface.filter(|_| multi_interface) {
+            label.push('@');
+            label.push_str(&tun_names[iface]);
+        }
+        label
+    };
+    // A vanished interface is recreated in place (not for `bench`, which has none).
+    for (iface, device) in tun_devices.into_iter().enumerate() {
+        let (spec, name, queues) = (tun_specs[iface].clone(), tun_names[iface].clone(), tun_io[iface].clone());
+        tokio::spawn(tun_recovery(shared.clone(), iface, spec, name, queues, device));
+    }
+    // Backlogs by queue, then by interface id.
+    let mut backlogs = vec![Vec::new(); queues_per_interface];
+    for (iface, queues) in tun_io.into_iter().enumerate() {
+        for (queue, (tun_reader, tun_writer)) in queues.into_iter().enumerate() {
+            let sh = shared.clone();
+            supervised.push(supervisor::supervise(label("TX", queue, Some(iface)), move || tokio::spawn(tx_loop(sh.clone(), tun_reader.clone(), iface as u8)), stats_tx.clone(), shutdown.clone()));
+            // RX hands packets to a separate TUN writer so a slow interface never delays receiving and ACKing.
+            let deliveries = Arc::new(delivery::DeliveryQueue::new(opts.tun_backlog));
+            let sh = shared.clone();
+            let queue_backlog = deliveries.clone();
+            supervised.push(supervisor::supervise(label("TUNW", queue, Some(iface)), move || tokio::spawn(tun_write_loop(sh.clone(), queue_backlog.clone(), tun_writer.clone(), iface)), stats_tx.clone(), shutdown.clone()));
+            backlogs[queue].push(deliveries);
+        }
+    }
+    // One RX task per queue, sharing the UDP socket (each datagram wakes exactly one). It
+    // hands a packet to its queue's backlog of the interface the frame names.
+    for (queue, deliveries) in backlogs.into_iter().enumerate() {
+        let sh = shared.clone();
+        let deliveries = Arc::new(deliveries);
+        supervised.push(supervisor::supervise(label("RX", queue, None), move || tokio::spawn(rx_loop(sh.clone(), deliveries.clone())), stats_tx.clone(), shutdown.clone()));
+    }
 
-            if is_full {
-                 sleep(Duration::from_millis(1)).await;
-                 continue;
-            }
-
-            match tun_reader.read(&mut frame_buffer).await {
-                Ok(n) if n > 0 => {
-                    let target = *peer_tx.lock();
-                    if let Some(remote_addr) = target {
-                        let ip_packet = &frame_buffer[..n];
-                        
-                        // Introduce jitter to mitigate timing analysis correlation
-                        obfuscation::jitter_sleep().await;
-
-                        // Pipeline: Compress -> Encrypt -> Wrap
-                        let processed = compression::adaptive_compress(ip_packet).unwrap_or(ip_packet.to_vec());
-                        let encrypted = cipher_enc.encrypt(&processed).unwrap();
-                        
-                        let seq = tx_seq.fetch_add(1, Ordering::Relaxed);
-                        let frame = WireFrame::new_data(seq, encrypted);
-                        
-                        // Serialization (Bincode is fast, but we might want Protobuf later for schema evolution)
-                        let encoded = bincode::serialize(&frame).unwrap();
-
-                        // Buffer for reliability
-                        {
-                            let mut lock = pending_tx.lock();
-                            lock.insert(seq, (Instant::now(), encoded.clone()));
-                        }
-
-                        if let Err(e) = socket_tx.send_to(&encoded, remote_addr).await {
-                             let _ = stats_tx_1.send(TelemetryUpdate::Log(format!("UDP::SendErr: {}", e)));
-                        } else {
-                             let _ = stats_tx_1.send(TelemetryUpdate::Throughput { 
-                                 tx_bytes: n as u64, 
-                                 rx_bytes: 0 
-                             });
-                        }
-                    }
-                }
-                Ok(_) => break, // EOF from TUN usually means interface went down
-                Err(e) => {
-                    let _ = stats_tx_1.send(TelemetryUpdate::Log(format!("TUN::ReadErr: {}", e)));
-                    // Cool-down to prevent CPU spin loop on device errors
-                    sleep(Duration::from_millis(10)).await;
-                    break;
+    let stopped = if let (Some(p), Some(probe)) = (&profile_opts, &bench_probe) {
+        let findings = run_profile(&shared, probe, p).await;
+        println!("{}", findings.report(opts.units.unwrap_or(units::RateUnit::Bits)));
+        None
+    } else if let (Some(b), Some(probe)) = (&bench_opts, &bench_probe) {
+        let mut report = bench::measure(probe, &shared.counters, &shared.tx_delay, Duration::from_secs(b.duration)).await;
+        report.quality = shared.monitor.lock().snapshot(Instant::now().into_std());
+        report.cipher = Some(shared.cipher.active());
+        report.compression = Some(shared.codec.outbound());
+        let (packet, level) = (bench::sample(b, shared.mtu), shared.codec.level());
+        report.codecs = tokio::task::spawn_blocking(move || bench::compare_codecs(&packet, level)).await.unwrap_or_default();
+        println!("{}", report.table(opts.units.unwrap_or(units::RateUnit::Bits)));
+        None
+    } else {
+        Some(shutdown.wait().await)
+    };
+
+    // However the run ended, stop the networking tasks, then the telemetry thread (which
+    // restores the terminal), each with a deadline so a stuck one can't hold up exit.
+    shutdown.request(shutdown::StopReason::Finished);
+    let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+        for task in supervised {
+            let _ = task.await;
+        }
+    }).await;
+    let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, tokio::task::spawn_blocking(move || tui_handle.join())).await;
+
+    if let Some(reason) = stopped {
+        if reason != shutdown::StopReason::Quit {
+            eprintln!("Tunnel stopped: {}", reason);
+        }
+        let rtt = shared.rtt.snapshot().percentiles();
+        if rtt.samples > 0 {
+            eprintln!("Session RTT: {}", rtt);
+        }
+        let overhead = shared.counters.overhead(shared.socket.bytes_sent(), shared.socket.bytes_received());
+        for (direction, bytes) in [("sent", overhead.tx), ("received", overhead.rx)] {
+            if let Some(summary) = overhead_summary(&bytes) {
+                eprintln!("Wire bytes {}: {}", direction, summary);
+            }
+        }
+        if shared.codec.enabled() {
+            let totals = shared.codec.stats();
+            for (direction, totals, raw_why) in [("sent", totals.tx, "sent raw by heuristics"), ("received", totals.rx, "arrived raw")] {
+                if let Some(summary) = codec_summary(&totals, raw_why) {
+                    eprintln!("Compression {}: {}", direction, summary);
                 }
             }
         }
-    });
+    }
 
-    // ----------------------------------------------------------------
-    // RX LOOP: UDP Socket -> TUN Interface
-    // Listens for encrypted frames, validates, decrypts, writes to kernel.
-    // ----------------------------------------------------------------
-    let socket_rx = socket.clone();
-    let peer_rx = active_peer.clone();
-    let stats_tx_2 = stats_tx.clone();
-    let pending_rx = pending_packets.clone();
-
-    let _rx_task = tokio::spawn(async move {
-        let mut udp_buffer = [0u8; 65535]; // Max UDP size
-        loop {
-            match socket_rx.recv_from(&mut udp_buffer).await {
-                Ok((size, src_addr)) => {
-                    // "Roam" the peer address (Mobility support)
-                    // If we receive a valid packet from a new IP, update our target.
-                    {
-                        let mut lock = peer_rx.lock();
-                        if lock.is_none() || *lock != Some(src_addr) {
-                             *lock = Some(src_addr);
-                             let _ = stats_tx_2.send(TelemetryUpdate::Log(format!("NET: Peer roamed to {}", src_addr)));
-                        }
-                    }
+    // Give lifecycle hooks a chance to observe the shutdown before the runtime goes away.
+    bus.publish(TunnelEvent::Shutdown);
+    if let Some(handle) = hook_handle {
+        let _ = tokio::time::timeout(Duration::from_secs(15), handle).await;
+    }
+    if let Some(path) = control_path {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Some(export) = csv_export {
+        let _ = tokio::time::timeout(Duration::from_secs(5), tokio::task::spawn_blocking(move || export.finish())).await;
+    }
+    if let Some(log) = log_file {
+        let _ = tokio::time::timeout(Duration::from_secs(5), tokio::task::spawn_blocking(move || log.finish())).await;
+    }
+    if let Some((tap, handle)) = pcap {
+        tap.close();
+        let _ = tokio::time::timeout(Duration::from_secs(5), tokio::task::spawn_blocking(move || handle.join())).await;
+    }
+    Ok(())
+}
 
-                    // Deserialize & Unwrap
-                    if let Ok(frame) = bincode::deserialize::<WireFrame>(&udp_buffer[..size]) {
-                        match frame.header.frame_type {
-                            FrameType::Transport => {
-                                // 1. Send ACK immediately
-                                let ack_frame = WireFrame::new_ack(0, frame.header.seq);
-                                if let Ok(ack_bytes) = bincode::serialize(&ack_frame) {
-                                    let _ = socket_rx.send_to(&ack_bytes, src_addr).await;
-                                }
+fn tun_pair(reader: impl AsyncRead + Unpin + Send + 'static, writer: impl AsyncWrite + Unpin + Send + 'static) -> (TunReader, TunWriter) {
+    (Arc::new(tokio::sync::Mutex::new(Box::new(reader))), Arc::new(tokio::sync::Mutex::new(Box::new(writer))))
+}
 
-                                if let Ok(decrypted) = cipher_dec.decrypt(&frame.payload) {
-                                    // If decryption passes, we trust the logic (Authenticated Encryption)
-                                    if let Ok(decompressed) = compression::adaptive_decompress(&decrypted) {
-                                        if tun_writer.write_all(&decompressed).await.is_ok() {
-                                            let _ = stats_tx_2.send(TelemetryUpdate::Throughput { 
-                                                tx_bytes: 0, 
-                                                rx_bytes: size as u64 
-                                            });
-                                        }
-                                    }
-                                }
-                                // Note: Silently drop decryption failures (prevent oracle attacks)
-                            },
-                            FrameType::Ack => {
-                                // Process ACK: Remove from buffer
-                                let mut lock = pending_rx.lock();
-                                if lock.remove(&frame.header.ack_num).is_some() {
-                                    // Consider logging RTT here if debugging
-                                }
-                            },
-                            _ => {} // Ignore heartbeats/handshakes for now
-                        }
+/// Brings up a TUN interface as one async device, or as `queues` parallel queues
+/// (Linux multi-queue). In the multi-queue case the device itself is returned too:
+/// it owns the underlying descriptors and must stay alive as long as the queues.
+/// Also returns the MTU the interface ended up with: some platforms clamp or ignore the requested one.
+fn open_tun(spec: &interfaces::TunSpec, queues: usize) -> Result<OpenedTun> {
+    // We use a small MTU to avoid fragmentation issues over UDP overlays.
+    let mut config = Configuration::default();
+    config.address(spec.ip)
+          .destination(spec.destination())
+          .netmask((255, 255, 255, 0))
+          .mtu(MTU as i32)
+          .up();
+    if let Some(name) = &spec.name {
+        config.name(name);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        config.platform(|c| { c.packet_information(true); });
+        if queues > 1 {
+            let (name, opened, device) = tunqueue::open(&mut config, queues)?;
+            let mtu = device.mtu().ok().and_then(|mtu| usize::try_from(mtu).ok());
+            let io = opened.into_iter().map(|queue| {
+                let (reader, writer) = tokio::io::split(queue);
+                tun_pair(reader, writer)
+            }).collect();
+            return Ok((name, io, Some(device), mtu));
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = queues; // Multi-queue is Linux-only: fall back to a single queue.
+
+    let tun_dev = tun::create_as_async(&config).context("Failed to open TUN device. Do you have root privileges?")?;
+    let tun_name = tun_dev.get_ref().name().unwrap_or_default();
+    let mtu = tun_dev.get_ref().mtu().ok().and_then(|mtu| usize::try_from(mtu).ok());
+    let (tun_reader, tun_writer) = tokio::io::split(tun_dev);
+    Ok((tun_name, vec![tun_pair(tun_reader, tun_writer)], None, mtu))
+}
+
+// ----------------------------------------------------------------
+// TUN RECOVERY
+// Recreates an interface that vanished under the tunnel (deleted by the operator,
+// or removed on a device event) with its original configuration, and swaps the new
+// queues in behind the TX and TUN writer tasks. Shuts down if that keeps failing.
+// ----------------------------------------------------------------
+async fn tun_recovery(sh: Shared, iface: usize, spec: interfaces::TunSpec, mut name: String, queues: TunQueues, mut device: Option<tun::platform::Device>) {
+    let watch = &sh.tun_health[iface];
+    let mut health = watch.subscribe();
+    loop {
+        // Wait for a TUN task to hit a vanished error, or for the name to disappear.
+        loop {
+            tokio::select! {
+                recreating = async { health.wait_for(|&(_, h)| h == tunwatch::TunHealth::Recreating).await.is_ok() } => {
+                    if !recreating {
+                        return;
+                    }
+                    break;
+                }
+                _ = sleep(TUN_CHECK_INTERVAL) => {
+                    if !name.is_empty() && !tunwatch::exists(&name) {
+                        let generation = health.borrow().0;
+                        watch.report_lost(generation);
                     }
-                },
-                Err(e) => {
-                    let _ = stats_tx_2.send(TelemetryUpdate::Log(format!("UDP::RecvErr: {}", e)));
-                    sleep(Duration::from_millis(10)).await;
                 }
             }
         }
-    });
+        tlog!(sh.stats, Level::Error, "TUN: {} vanished; recreating it", name);
+        let mut attempt = 0;
+        let opened = loop {
+            attempt += 1;
+            match open_tun(&spec, queues.len()) {
+                Ok(opened) if opened.1.len() == queues.len() => break Some(opened),
+                Ok(opened) => tlog!(sh.stats, Level::Warn, "TUN: {} came back with {} queue(s) instead of {} ({}/{})", name, opened.1.len(), queues.len(), attempt, TUN_RECREATE_ATTEMPTS),
+                Err(e) => tlog!(sh.stats, Level::Warn, "TUN: Recreating {} failed ({}/{}): {:#}", name, attempt, TUN_RECREATE_ATTEMPTS, e),
+            }
+            if attempt >= TUN_RECREATE_ATTEMPTS {
+                break None;
+            }
+            sleep(TUN_RECREATE_BACKOFF * attempt).await;
+        };
+        let Some((new_name, fresh, new_device, _)) = opened else {
+            watch.failed();
+            let reason = format!("TUN {} vanished and could not be recreated ({} attempts)", name, TUN_RECREATE_ATTEMPTS);
+            tlog!(sh.stats, Level::Error, "TUN: Giving up: {}", reason);
+            sh.shutdown.request(shutdown::StopReason::Core(reason));
+            return;
+        };
+        for ((reader, writer), (new_reader, new_writer)) in queues.iter().zip(&fresh) {
+            std::mem::swap(&mut *reader.lock().await, &mut *new_reader.lock().await);
+            std::mem::swap(&mut *writer.lock().await, &mut *new_writer.lock().await);
+        }
+        // `fresh` now holds the dead queues: close them before the device that owned them.
+        drop(fresh);
+        drop(std::mem::replace(&mut device, new_device));
+        watch.recreated();
+        if new_name == name {
+            tlog!(sh.stats, Level::Info, "TUN: {} recreated", name);
+        } else {
+            tlog!(sh.stats, Level::Warn, "TUN: {} recreated as {}", name, new_name);
+            name = new_name;
+        }
+    }
+}
 
+// ----------------------------------------------------------------
+// PROFILE
+// Times the handshake, then measures the link on the bench pipeline: once at
+// full rate without jitter, once with full timing obfuscation.
+// ----------------------------------------------------------------
+async fn run_profile(sh: &Shared, probe: &bench::Probe, opts: &profile::ProfileOptions) -> profile::Findings {
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(opts.handshake_timeout);
+    let mut outcome = sh.handshake.lock().state().clone();
+    while !matches!(outcome, handshake::HandshakeState::Established | handshake::HandshakeState::Failed { .. }) && Instant::now() < deadline {
+        sleep(Duration::from_millis(20)).await;
+        outcome = sh.handshake.lock().state().clone();
+    }
+    let established = outcome == handshake::HandshakeState::Established;
+    let peer = sh.peer.lock().unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+
+    // The largest datagram: a full-MTU packet, sealed and framed, plus IP and UDP headers.
+    let ip_udp_headers = if peer.is_ipv4() { 28 } else { 48 };
+    let format = *sh.wire_format.lock();
+    let sealed = seal_payload(sh, format, &compression::uncompressed(&vec![0; sh.mtu])).unwrap_or_default();
+    let framed = format.encode(&WireFrame::new_data(0, 0, sealed), &sh.cipher).map_or(sh.mtu, |f| f.len());
+
+    let mut findings = profile::Findings {
+        peer,
+        established_after: established.then(|| started.elapsed()),
+        hellos: sh.counters.handshakes_sent.load(Ordering::Relaxed),
+        failure: match outcome {
+            handshake::HandshakeState::Failed { reason } => Some(reason),
+            _ => None,
+        },
+        cipher: established.then(|| sh.cipher.active()),
+        path_mtu: profile::path_mtu(peer),
+        max_datagram: framed + ip_udp_headers,
+        clear: None,
+        obfuscated: None,
+    };
+    if !established {
+        return findings;
+    }
+
+    tlog!(sh.stats, Level::Info, "PROFILE: Handshake done, measuring for {}s", opts.duration);
+    sh.paused.store(false, Ordering::Relaxed);
+    let mut clear = bench::measure(probe, &sh.counters, &sh.tx_delay, Duration::from_secs(opts.duration)).await;
+    clear.quality = sh.monitor.lock().snapshot(Instant::now().into_std());
+    findings.clear = Some(clear);
+
+    if opts.obfs_duration > 0 {
+        tlog!(sh.stats, Level::Info, "PROFILE: Measuring with full obfuscation for {}s", opts.obfs_duration);
+        obfuscation::Intensity::Full.store(&sh.obfs);
+        sh.jitter.store(true, Ordering::Relaxed);
+        let mut obfuscated = bench::measure(probe, &sh.counters, &sh.tx_delay, Duration::from_secs(opts.obfs_duration)).await;
+        obfuscated.quality = sh.monitor.lock().snapshot(Instant::now().into_std());
+        findings.obfuscated = Some(obfuscated);
+    }
+    sh.paused.store(true, Ordering::Relaxed);
+    findings
+}
+
+// ----------------------------------------------------------------
+// NAT PUNCH TASK
+// Pre-flight: Send random junk to punch NAT or confuse DPI before real handshake,
+// followed by our Hello. Retries with backoff until the peer proves it holds the
+// key, or gives up after `--handshake-attempts` and reports the handshake failed.
+// Starting a new punch (reconnect, new address) supersedes one still running.
+// ----------------------------------------------------------------
+async fn punch_loop(sh: Shared, addr: SocketAddr) {
+    let generation = sh.handshake.lock().begin();
+    // Whoever answers may not speak the suite agreed with the last peer; its reply renegotiates.
+    sh.cipher.set_active(crypto::CipherSuite::BASELINE);
+    sh.codec.reset();
+    let mut initiator = handshake::Initiator::new(sh.handshake_policy);
+
+    loop {
+        let authenticated = sh.last_auth.lock().is_some();
+        let (attempt, wait) = match initiator.next(authenticated) {
+            handshake::Step::Send { attempt, wait } => (attempt, wait),
+            handshake::Step::Done(outcome) => {
+                if !sh.handshake.lock().update(generation, outcome.clone()) {
+                    return;
+                }
+                match outcome {
+                    handshake::HandshakeState::Failed { reason } => {
+                        tlog!(sh.stats, Level::Error, "NET: Handshake with {} failed: {}", addr, reason);
+                        sh.bus.publish(TunnelEvent::HandshakeFailed { peer: addr, reason });
+                    }
+                    _ => tlog!(sh.stats, Level::Info, "NET: Session with {} authenticated after {} punch attempts", addr, initiator.attempts()),
+                }
+                return;
+            }
+        };
+        if !sh.handshake.lock().update(generation, handshake::HandshakeState::AwaitingReply { attempt }) {
+            return;
+        }
+
+        for decoy in sh.obfuscator.preamble() {
+            if sh.socket.send_raw(&decoy, addr).await.is_ok() {
+                metrics::Counters::add(&sh.counters.tx_bytes.padding, decoy.len() as u64);
+            }
+        }
+
+        match build_hello(&sh, false) {
+            Ok(hello) => {
+                if sh.socket.send_to(&hello, addr).await.is_ok() {
+                    metrics::Counters::inc(&sh.counters.handshakes_sent);
+                    metrics::Counters::add(&sh.counters.tx_bytes.control, hello.len() as u64);
+                }
+            }
+            Err(e) => { tlog!(sh.stats, Level::Error, "HELLO::Err: {}", e); }
+        }
+
+        if attempt > 1 && attempt.is_power_of_two() {
+            tlog!(sh.stats, Level::Warn, "NET: No reply from {} after {} punch attempts", addr, attempt);
+        }
+
+        // Randomize the period (+/-25%) so retries don't form a clean beacon signature.
+        let spread = wait.as_millis() as u64 / 4;
+        let wobble = sh.rng.lock().gen_range(0..=spread * 2);
+        sleep(wait - Duration::from_millis(spread) + Duration::from_millis(wobble)).await;
+    }
+}
+
+// ----------------------------------------------------------------
+// PEER RESOLVER (`--peer <hostname>:<port>`)
+// Resolves the peer's name, then re-resolves periodically so a server behind
+// dynamic DNS can move. A new address gets a fresh punch + handshake.
+// Lookup failures never stop the tunnel: retry with backoff.
+// ----------------------------------------------------------------
+async fn peer_resolver(sh: Shared, host: String, port: u16, interval: Duration) {
+    let mut resolved: Option<SocketAddr> = None;
+    let mut retry = RESOLVE_RETRY_INITIAL;
+    loop {
+        match resolve::resolve(&host, port, sh.socket.is_ipv6()).await {
+            Ok(addr) => {
+                retry = RESOLVE_RETRY_INITIAL;
+                // Compare with the last answer, not the live peer: roaming may have moved
+                // the peer since, and an unchanged DNS record is no reason to re-handshake.
+                if resolved != Some(addr) {
+                    match resolved.replace(addr) {
+                        None => tlog!(sh.stats, Level::Info, "DNS: {} resolved to {}", host, addr),
+                        Some(old) => tlog!(sh.stats, Level::Info, "DNS: {} moved {} -> {}", host, old, addr),
+                    }
+                    let previous = sh.peer.lock().replace(addr);
+                    follow_peer(&sh, addr);
+                    if let Some(old_addr) = previous.filter(|old| *old != addr) {
+                        sh.bus.publish(TunnelEvent::Roamed { from: old_addr, to: addr });
+                    }
+                    *sh.last_auth.lock() = None;
+                    tokio::spawn(punch_loop(sh.clone(), addr));
+                }
+                sleep(interval).await;
+            }
+            Err(e) => {
+                tlog!(sh.stats, Level::Warn, "DNS: {:#} (retrying in {}s)", e, retry.as_secs());
+                sleep(retry).await;
+                retry = (retry * 2).min(interval);
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// QUALITY REPORTER
+// Periodically publishes link quality, peer and window state, counters and
+// packet sizes to the dashboard.
+// ----------------------------------------------------------------
+async fn quality_reporter(sh: Shared) {
+    let mut last_retransmits = sh.counters.retransmits.load(Ordering::Relaxed);
+    let mut last_marks = (sh.counters.ecn_ce.load(Ordering::Relaxed), sh.counters.data_received.load(Ordering::Relaxed));
+    let mut last_at = Instant::now();
+    let mut latency = stats::LatencyTracker::new();
+    let mut compression = compression::StatsTracker::new();
+    loop {
+        sleep(QUALITY_REPORT_INTERVAL).await;
+        let quality = sh.monitor.lock().snapshot(Instant::now().into_std());
+        let _ = sh.stats.send(TelemetryUpdate::LinkQuality(quality));
+        let _ = sh.stats.send(TelemetryUpdate::Latency(latency.update(Instant::now().into_std(), sh.rtt.snapshot())));
+        let _ = sh.stats.send(TelemetryUpdate::PeerState(tui::PeerInfo {
+            addr: *sh.peer.lock(),
+            established: sh.peer_up.load(Ordering::Relaxed),
+            last_auth_age: sh.last_auth.lock().map(|t| t.elapsed()),
+            wire_format: Some(*sh.wire_format.lock()),
+            protocol: sh.agreement.lock().map(|agreement| agreement.version),
+            cipher: Some(sh.cipher.active()),
+            key: Some(sh.cipher.key_status()),
+            roam_count: sh.roam_count.load(Ordering::Relaxed),
+            handshake: sh.handshake.lock().state().clone(),
+        }));
+        let _ = sh.stats.send(TelemetryUpdate::Settings(tui::Settings {
+            jitter: sh.jitter.load(Ordering::Relaxed),
+            jitter_range: *sh.jitter_range.lock(),
+            jitter_bulk: sh.jitter_gate.as_ref().map(|gate| gate.is_bulk()),
+            chaff: sh.chaff,
+            peer_chaff: sh.peer_chaff.load(Ordering::Relaxed),
+            mimic: sh.mimic,
+            morph: sh.morph.as_ref().map(|morpher| {
+                let report = morpher.lock().report();
+                let agreed = sh.agreement.lock().is_some_and(|agreement| agreement.morphing);
+                morph::MorphStatus { distance: report.distance, overhead: report.overhead, agreed }
+            }),
+            obfs: obfuscation::Intensity::load(&sh.obfs),
+            adaptive: sh.obfs_adaptive,
+            compression: sh.compression.load(Ordering::Relaxed),
+            peer_compression: *sh.peer_compression.lock(),
+            inbound: sh.codec.inbound(),
+            peer_algorithm: *sh.peer_codec.lock(),
+            algorithm: sh.codec.algorithm(),
+            level: sh.codec.level(),
+            outbound: sh.codec.outbound(),
+            dictionary: sh.codec.dictionary_agreed(),
+            auto: *sh.compress_auto.lock(),
+            adapt: *sh.compress_adapt.lock(),
+            pacing: sh.pacer.enabled(),
+            connected_socket: sh.socket.is_connected(),
+        }));
+        let (in_flight, bytes) = {
+            let pending = sh.pending.lock();
+            (pending.len(), pending.values().map(|p| p.data.len()).sum())
+        };
+        let retransmits = sh.counters.retransmits.load(Ordering::Relaxed);
+        let marks = (sh.counters.ecn_ce.load(Ordering::Relaxed), sh.counters.data_received.load(Ordering::Relaxed));
+        let secs = last_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        let _ = sh.stats.send(TelemetryUpdate::Window(tui::WindowStats {
+            in_flight,
+            capacity: sh.cwnd.limit(),
+            bytes,
+            retransmit_rate: (retransmits - last_retransmits) as f64 / secs,
+            pacing_rate: sh.pacer.rate(sh.monitor.lock().srtt()),
+            ecn_mark_rate: sh.socket.is_ect().then(|| (marks.0 - last_marks.0) as f64 / (marks.1 - last_marks.1).max(1) as f64),
+        }));
+        last_retransmits = retransmits;
+        last_marks = marks;
+        last_at = Instant::now();
+        let _ = sh.stats.send(TelemetryUpdate::Counters(sh.counters.snapshot()));
+        let _ = sh.stats.send(TelemetryUpdate::PacketSizes(sh.counters.packet_sizes()));
+        let _ = sh.stats.send(TelemetryUpdate::Overhead(sh.counters.overhead(sh.socket.bytes_sent(), sh.socket.bytes_received())));
+        if let Some(dictionary) = sh.codec.dictionary_stats() {
+            let _ = sh.stats.send(TelemetryUpdate::Dictionary(dictionary));
+        }
+        if sh.codec.enabled() {
+            let report = compression.update(Instant::now().into_std(), sh.codec.stats());
+            let _ = sh.stats.send(TelemetryUpdate::Compression(report));
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// QUOTA ENFORCER
+// Compares session usage with `--quota-*` and publishes it. The data path
+// only counts; throttling or cutting off starts here.
+// ----------------------------------------------------------------
+async fn quota_enforcer(sh: Shared) {
+    loop {
+        sleep(QUOTA_CHECK_INTERVAL).await;
+        let wire_tx = sh.socket.bytes_sent();
+        if let Some(state) = sh.quota.check(wire_tx) {
+            let reason = state.reason().unwrap_or_default().to_string();
+            let action = match state {
+                quota::QuotaState::Throttled { .. } => "throttling",
+                _ => "no longer forwarding data",
+            };
+            tlog!(sh.stats, Level::Warn, "QUOTA: Session {}: {}", action, reason);
+            if let Some(peer) = *sh.peer.lock() {
+                sh.bus.publish(TunnelEvent::QuotaExceeded { peer, reason });
+            }
+        }
+        let _ = sh.stats.send(TelemetryUpdate::Quota(sh.quota.status(wire_tx)));
+    }
+}
+
+// ----------------------------------------------------------------
+// CONNECTION STATE
+// Condenses peer, liveness and handshake into one operator-facing state, and
+// reports each change to the log, the dashboard and systemd.
+// ----------------------------------------------------------------
+async fn connection_monitor(sh: Shared, notifier: notify::Notifier) {
+    let mut reported = None;
+    loop {
+        let handshake = sh.handshake.lock().state().clone();
+        let observation = connection::Observation {
+            tun_down: sh.tun_health.iter().any(|watch| watch.health() != tunwatch::TunHealth::Up),
+            has_peer: sh.peer.lock().is_some(),
+            peer_up: sh.peer_up.load(Ordering::Relaxed),
+            silent_for: sh.last_rx.lock().elapsed(),
+            handshake: &handshake,
+        };
+        let state = sh.connection.lock().observe(&observation);
+        if reported != Some(state) {
+            match reported {
+                Some(previous) => tlog!(sh.stats, Level::Info, "STATE: {} -> {}", previous.name(), state.name()),
+                None => tlog!(sh.stats, Level::Info, "STATE: {}", state.name()),
+            }
+            let _ = sh.stats.send(TelemetryUpdate::Connection(state));
+            notifier.status(state.name());
+            if reported.is_none() {
+                notifier.ready();
+            }
+            reported = Some(state);
+        }
+        sleep(CONNECTION_CHECK_INTERVAL).await;
+    }
+}
+
+// ----------------------------------------------------------------
+// THROUGHPUT REPORTER
+// The data path only bumps byte and packet counters; this turns them into
+// one telemetry update per interval instead of one per packet.
+// ----------------------------------------------------------------
+async fn throughput_reporter(sh: Shared, log_every: Option<Duration>, unit: units::RateUnit) {
+    // Goodput is inner packets, each counted once; the wire is every UDP payload, overhead and all.
+    let totals = |sh: &Shared| {
+        let (c, get) = (&sh.counters, |v: &AtomicU64| v.load(Ordering::Relaxed));
+        [get(&c.tx_bytes.inner), get(&c.rx_bytes.inner), get(&c.packets_sent), get(&c.packets_received), sh.socket.bytes_sent(), sh.socket.bytes_received()]
+    };
+    let mut last = [0; 6];
+    let mut logged = (Instant::now(), totals(&sh));
+    loop {
+        sleep(THROUGHPUT_REPORT_INTERVAL).await;
+        let now = totals(&sh);
+        let [tx_bytes, rx_bytes, tx_packets, rx_packets, tx_wire, rx_wire] = [0, 1, 2, 3, 4, 5].map(|i| now[i] - last[i]);
+        // A delta the channel had no room for is folded into the next one.
+        let update = TelemetryUpdate::Throughput { tx_bytes, rx_bytes, tx_packets, rx_packets, tx_wire, rx_wire };
+        if now != last && sh.stats.send(update).is_ok() {
+            last = now;
+        }
+
+        let (since, then) = logged;
+        if log_every.is_some_and(|every| since.elapsed() >= every) {
+            let secs = since.elapsed().as_secs_f64();
+            let rate = |i: usize| (now[i] - then[i]) as f64 / secs;
+            let direction = |goodput: f64, wire: f64| format!(
+                "{} goodput, {} wire ({})",
+                units::format_rate(goodput, unit), units::format_rate(wire, unit),
+                if wire > 0.0 { format!("{:.0}%", goodput / wire * 100.0) } else { "-".to_string() }
+            );
+            tlog!(sh.stats, Level::Info, "RATE: Out {} | in {} over {:.0}s", direction(rate(0), rate(4)), direction(rate(1), rate(5)), secs);
+            logged = (Instant::now(), now);
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// ALERT MONITOR (`--alert-*`)
+// Checks the link against the configured thresholds every report tick.
+// Evaluated here rather than in the dashboard so headless runs and the
+// control socket see the same alert state.
+// ----------------------------------------------------------------
+async fn alert_monitor(sh: Shared, rules: alerts::AlertRules) {
+    let mut engine = alerts::AlertEngine::new(rules);
+    loop {
+        sleep(QUALITY_REPORT_INTERVAL).await;
+        let now = Instant::now().into_std();
+        let quality = sh.monitor.lock().snapshot(now);
+        let observation = alerts::Observation {
+            loss_rate: quality.loss_rate,
+            srtt_ms: quality.srtt_ms,
+            rx_age: sh.peer.lock().is_some().then(|| sh.last_rx.lock().elapsed()),
+            window_full: sh.pending.lock().len() >= sh.cwnd.limit(),
+        };
+        let transitions = engine.evaluate(observation, now);
+        // Firing alerts are re-sent every tick so their readings stay current.
+        if transitions.is_empty() && engine.active().is_empty() {
+            continue;
+        }
+        for transition in transitions {
+            match transition {
+                alerts::Transition::Raised(alert) => tlog!(sh.stats, Level::Error, "ALERT: {}", alert.detail),
+                alerts::Transition::Cleared(kind) => tlog!(sh.stats, Level::Info, "ALERT: {} cleared", kind.name()),
+            }
+        }
+        *sh.alerts.lock() = engine.active().to_vec();
+        let _ = sh.stats.send(TelemetryUpdate::Alerts(engine.active().to_vec()));
+    }
+}
+
+// ----------------------------------------------------------------
+// OBFUSCATION ADAPTER (`--obfs adaptive`)
+// Trades jitter for latency while the link is clean; restores it when the
+// link goes dark in the way DPI blocking does.
+// ----------------------------------------------------------------
+async fn obfs_adapter(sh: Shared, policy: obfuscation::AdaptivePolicy) {
+    let mut adapter = obfuscation::Adapter::new(policy, obfuscation::Intensity::load(&sh.obfs));
+    let mut last = sh.counters.snapshot();
+    loop {
+        sleep(QUALITY_REPORT_INTERVAL).await;
+        let now = Instant::now().into_std();
+        let counters = sh.counters.snapshot();
+        let sample = obfuscation::LinkSample {
+            sent: counters.data_sent - last.data_sent,
+            acked: counters.acks_received - last.acks_received,
+            loss_rate: sh.monitor.lock().snapshot(now).loss_rate,
+            auth_age: sh.last_auth.lock().map(|t| t.elapsed()),
+        };
+        last = counters;
+        if let Some((level, reason)) = adapter.observe(sample, now) {
+            level.store(&sh.obfs);
+            tlog!(sh.stats, Level::Info, "OBFS: Intensity -> {} ({})", level.label(), reason);
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// COMPRESSION SWITCH (`--compress-auto`)
+// Suspends compression while it saves too little on the traffic at hand,
+// and resumes it once sampled packets show it pays again.
+// ----------------------------------------------------------------
+async fn compression_switch(sh: Shared, policy: compression::AutoPolicy) {
+    let mut switch = compression::AutoSwitch::new(policy);
+    *sh.compress_auto.lock() = Some(switch.status());
+    let mut last = sh.codec.stats().tx;
+    loop {
+        sleep(QUALITY_REPORT_INTERVAL).await;
+        let totals = sh.codec.stats().tx;
+        let sample = totals.since(&last);
+        last = totals;
+        // Switched off by hand, or not agreed with the peer: nothing compressed to judge by.
+        if !sh.compression.load(Ordering::Relaxed) || sh.codec.outbound() != sh.codec.algorithm() {
+            continue;
+        }
+        if let Some((active, savings)) = switch.observe(sample, Instant::now().into_std()) {
+            sh.codec.set_suspended(!active);
+            if active {
+                tlog!(sh.stats, Level::Info, "COMPRESS: Auto on: sampled packets save {:.1}% (resume at {}%)", savings, policy.on_above);
+            } else {
+                tlog!(sh.stats, Level::Info, "COMPRESS: Auto off: saving {:.1}% (suspend below {}%), sampling packets", savings, policy.off_below);
+            }
+        }
+        *sh.compress_auto.lock() = Some(switch.status());
+    }
+}
+
+// ----------------------------------------------------------------
+// COMPRESSION LEVEL (`--compress-adapt`)
+// Steps the level down while compressing is the bottleneck, and up while
+// the link is, judged from compression time and send-window stalls.
+// ----------------------------------------------------------------
+async fn compression_governor(sh: Shared, threads: usize, tx_tasks: usize) {
+    let mut governor = compression::LevelGovernor::new(sh.codec.algorithm(), sh.codec.level());
+    *sh.compress_adapt.lock() = Some(governor.status());
+    let mut last = (sh.codec.stats().tx, sh.window_stalls.load(Ordering::Relaxed), sh.counters.compress_queue_full.load(Ordering::Relaxed));
+    let mut ticked = Instant::now();
+    loop {
+        sleep(QUALITY_REPORT_INTERVAL).await;
+        let now = (sh.codec.stats().tx, sh.window_stalls.load(Ordering::Relaxed), sh.counters.compress_queue_full.load(Ordering::Relaxed));
+        let elapsed = ticked.elapsed();
+        ticked = Instant::now();
+        let window_full = ((now.1 - last.1) as f64 / 1e6 / elapsed.as_secs_f64() / tx_tasks.max(1) as f64).min(1.0);
+        let sample = compression::AdaptSample { totals: now.0.since(&last.0), elapsed, threads, window_full, queue_full: now.2 - last.2 };
+        last = now;
+        // Switched off by hand, or not agreed with the peer: nothing compressed to judge by.
+        if !sh.compression.load(Ordering::Relaxed) || sh.codec.outbound() != sh.codec.algorithm() {
+            continue;
+        }
+        if let Some((level, bottleneck)) = governor.observe(sample) {
+            let from = if sh.codec.suspended() { "raw".to_string() } else { sh.codec.algorithm().label(sh.codec.level()) };
+            if let Some(level) = level {
+                sh.codec.set_level(level);
+            }
+            sh.codec.set_suspended(level.is_none());
+            let to = level.map_or("raw".to_string(), |level| sh.codec.algorithm().label(level));
+            let status = governor.status();
+            let cpu = status.cpu.map_or("-".to_string(), |cpu| format!("{:.0}%", cpu * 100.0));
+            match bottleneck {
+                compression::Bottleneck::Cpu => tlog!(sh.stats, Level::Info, "COMPRESS: Level {} -> {}: CPU-bound (compressing {} of the time, window full {:.0}%)", from, to, cpu, status.window_full * 100.0),
+                compression::Bottleneck::Link => tlog!(sh.stats, Level::Info, "COMPRESS: Level {} -> {}: link-bound (window full {:.0}%, compressing {} of the time)", from, to, status.window_full * 100.0, cpu),
+            }
+        }
+        *sh.compress_adapt.lock() = Some(governor.status());
+    }
+}
+
+// ----------------------------------------------------------------
+// CONTROL DISPATCHER
+// Applies runtime commands from the control socket (and other front-ends).
+// ----------------------------------------------------------------
+async fn control_dispatcher(sh: Shared, mut requests: tokio::sync::mpsc::Receiver<control::ControlRequest>) {
+    use control::Command;
+
+    while let Some(req) = requests.recv().await {
+        let reply = match req.command {
+            Command::SetPeer(addr) => {
+                let previous = sh.peer.lock().replace(addr);
+                follow_peer(&sh, addr);
+                if let Some(old_addr) = previous.filter(|old| *old != addr) {
+                    sh.bus.publish(TunnelEvent::Roamed { from: old_addr, to: addr });
+                }
+                // Re-handshake against the new address.
+                *sh.last_auth.lock() = None;
+                tokio::spawn(punch_loop(sh.clone(), addr));
+                format!("OK peer={}", addr)
+            }
+            Command::GetStats => {
+                let quality = sh.monitor.lock().snapshot(Instant::now().into_std());
+                let opt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.3}", v));
+                let connection = sh.connection.lock();
+                let keys = sh.cipher.key_status();
+                format!(
+                    "OK peer={} established={} paused={} wire_format={:?} protocol={} cipher={} key={:08x} next_key={} srtt_ms={} jitter_ms={} loss={} in_flight={}/{} pacing={} ecn={} auth_acks={} compression={} compress_tx={} compress_rx={} compress_adapt={} peer_compression={} data_sent={} data_received={} roams={} obfs={} jitter_range={} chaff={} mimic={} morph={} handshake={} state={} state_secs={}",
+                    sh.peer.lock().map_or("-".to_string(), |p| p.to_string()),
+                    sh.peer_up.load(Ordering::Relaxed),
+                    sh.paused.load(Ordering::Relaxed),
+                    *sh.wire_format.lock(),
+                    sh.agreement.lock().map_or("-".to_string(), |agreement| format!("v{}", agreement.version)),
+                    sh.cipher.active().name(),
+                    keys.current,
+                    keys.next.map_or("-".to_string(), |k| format!("{:08x}", k)),
+                    opt(quality.srtt_ms),
+                    opt(quality.jitter_ms),
+                    opt(quality.loss_rate),
+                    sh.pending.lock().len(),
+                    sh.cwnd.limit(),
+                    match sh.pacer.rate(sh.monitor.lock().srtt()) {
+                        Some(rate) => format!("{:.0}/s", rate),
+                        None if sh.pacer.enabled() => "on".to_string(),
+                        None => "off".to_string(),
+                    },
+                    if sh.socket.is_ect() { "on" } else { "off" },
+                    if sh.acks_signed.load(Ordering::Relaxed) { "on" } else { "off" },
+                    if sh.compression.load(Ordering::Relaxed) { "on" } else { "off" },
+                    sh.codec.algorithm().label(sh.codec.level()),
+                    sh.codec.inbound().name(),
+                    sh.compress_adapt.lock().map_or("off".to_string(), |adapt| adapt.level.map_or("raw".to_string(), |level| sh.codec.algorithm().label(level))),
+                    sh.peer_compression.lock().map_or("-", |on| if on { "on" } else { "off" }),
+                    sh.counters.data_sent.load(Ordering::Relaxed),
+                    sh.counters.data_received.load(Ordering::Relaxed),
+                    sh.roam_count.load(Ordering::Relaxed),
+                    obfuscation::Intensity::load(&sh.obfs).label(),
+                    if sh.jitter.load(Ordering::Relaxed) { sh.jitter_range.lock().spec() } else { "off".to_string() },
+                    sh.chaff.map_or("off".to_string(), |(profile, rate)| format!("{}/{}", profile.label(), rate)),
+                    sh.mimic.map_or("off", |mimic| mimic.label()),
+                    sh.morph.as_ref().map_or("off".to_string(), |morpher| morpher.lock().target().label().to_string()),
+                    sh.handshake.lock().state().name(),
+                    connection.state().name(),
+                    connection.elapsed().as_secs(),
+                )
+            }
+            Command::Status => {
+                let rows = sh.counters.snapshot().rows();
+                let mut fields: Vec<String> = rows.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+                let active: Vec<&str> = sh.alerts.lock().iter().map(|a| a.kind.name()).collect();
+                fields.push(format!("alerts={}", if active.is_empty() { "none".to_string() } else { active.join(",") }));
+                fields.push(format!("state={}", sh.connection.lock().state().name()));
+                format!("OK {}", fields.join(" "))
+            }
+            Command::Pause => {
+                sh.paused.store(true, Ordering::Relaxed);
+                tlog!(sh.stats, Level::Info, "CTRL: Data forwarding paused");
+                "OK paused".to_string()
+            }
+            Command::Resume => {
+                sh.paused.store(false, Ordering::Relaxed);
+                tlog!(sh.stats, Level::Info, "CTRL: Data forwarding resumed");
+                "OK resumed".to_string()
+            }
+            Command::Rekey(None) => "ERR usage: rekey <key> (64 hex digits; install the same key at the peer)".to_string(),
+            Command::Rekey(Some(key)) => {
+                let status = sh.cipher.key_status();
+                if crypto::key_fingerprint(&key.0) == status.current {
+                    "ERR that key is already in use".to_string()
+                } else {
+                    let fingerprint = sh.cipher.install_next(&key.0);
+                    tlog!(sh.stats, Level::Info, "CRYPTO: Key {:08x} installed; switching from {:08x} once the peer has it", fingerprint, status.current);
+                    tokio::spawn(announce_key(sh.clone(), fingerprint));
+                    format!("OK key={:08x} installed, switching once the peer has it", fingerprint)
+                }
+            }
+            Command::ToggleCompression => {
+                let enabled = !sh.compression.fetch_xor(true, Ordering::Relaxed);
+                if sh.peer_compression.lock().is_some() {
+                    tokio::spawn(send_control(sh.clone(), PeerControl::Compression(enabled)));
+                }
+                format!("OK compression={}", if enabled { "on" } else { "off" })
+            }
+            Command::TogglePeerCompression => {
+                let known = *sh.peer_compression.lock();
+                match known {
+                    Some(current) => {
+                        tokio::spawn(send_control(sh.clone(), PeerControl::SetCompression(!current)));
+                        format!("OK peer-compression={} requested", if current { "off" } else { "on" })
+                    }
+                    None => "ERR peer cannot switch compression remotely (no Hello yet, or it predates control frames)".to_string(),
+                }
+            }
+            Command::ToggleJitter => {
+                let enabled = !sh.jitter.fetch_xor(true, Ordering::Relaxed);
+                format!("OK jitter={}", if enabled { "on" } else { "off" })
+            }
+            Command::SetJitter(setting) => match setting {
+                obfuscation::JitterSetting::Off => {
+                    sh.jitter.store(false, Ordering::Relaxed);
+                    "OK jitter=off".to_string()
+                }
+                obfuscation::JitterSetting::Range(range) => {
+                    *sh.jitter_range.lock() = range;
+                    sh.jitter.store(true, Ordering::Relaxed);
+                    format!("OK jitter={}", range.spec())
+                }
+            },
+            Command::TogglePacing => {
+                let enabled = sh.pacer.toggle();
+                format!("OK pacing={}", if enabled { "on" } else { "off" })
+            }
+            Command::Reconnect => {
+                let target = *sh.peer.lock();
+                match target {
+                    Some(addr) => {
+                        *sh.last_auth.lock() = None;
+                        tokio::spawn(punch_loop(sh.clone(), addr));
+                        format!("OK reconnecting peer={}", addr)
+                    }
+                    None => "ERR no peer to reconnect to".to_string(),
+                }
+            }
+            Command::Heartbeat => {
+                let target = *sh.peer.lock();
+                match target {
+                    Some(addr) => match send_heartbeat(&sh, addr).await {
+                        Ok(()) => format!("OK heartbeat sent peer={}", addr),
+                        Err(e) => format!("ERR heartbeat failed: {}", e),
+                    },
+                    None => "ERR no peer to send a heartbeat to".to_string(),
+                }
+            }
+            Command::Quota => {
+                let status = sh.quota.status(sh.socket.bytes_sent());
+                let opt = |v: Option<u64>| v.map_or("-".to_string(), |v| v.to_string());
+                let u = status.usage;
+                format!(
+                    "OK state={} goodput_tx={} goodput_rx={} wire_tx={} wire_rx={} packets_tx={} packets_rx={} bytes_limit={} bytes_remaining={} packets_limit={} packets_remaining={}",
+                    status.state.name(), u.goodput_tx, u.goodput_rx, u.wire_tx, u.wire_rx, u.packets_tx, u.packets_rx,
+                    opt(status.bytes_limit), opt(status.bytes_remaining()), opt(status.packets_limit), opt(status.packets_remaining()),
+                )
+            }
+            Command::ResetQuota => {
+                sh.quota.reset(sh.socket.bytes_sent());
+                tlog!(sh.stats, Level::Info, "QUOTA: Usage reset via control interface");
+                "OK quota reset".to_string()
+            }
+            Command::ResetStats => match sh.stats.send(TelemetryUpdate::StatsReset) {
+                Ok(()) => "OK stats reset".to_string(),
+                Err(_) => "ERR telemetry queue full, nothing reset".to_string(),
+            },
+            Command::ReloadAcl => match &sh.allowlist {
+                Some(acl) => match acl.reload() {
+                    Ok(count) => {
+                        tlog!(sh.stats, Level::Info, "ACL: Reloaded, {} networks allowed", count);
+                        format!("OK acl networks={}", count)
+                    }
+                    Err(e) => {
+                        tlog!(sh.stats, Level::Warn, "ACL: Reload failed, keeping {} networks: {:#}", acl.len(), e);
+                        format!("ERR reload failed: {:#}", e)
+                    }
+                },
+                None => "ERR no allowlist configured (--allow / --allow-file)".to_string(),
+            },
+            Command::Morph => match &sh.morph {
+                Some(morpher) => {
+                    let morpher = morpher.lock();
+                    let report = morpher.report();
+                    // Each bin as `<up_to>:<target %>/<achieved %>`.
+                    let bins: Vec<_> = report.bins.iter()
+                        .map(|bin| format!("{}:{:.1}/{:.1}", bin.up_to, bin.target * 100.0, bin.achieved * 100.0))
+                        .collect();
+                    format!(
+                        "OK profile={} frames={} split={} overhead={:.1}% cap={:.0}% distance={} bins={}",
+                        morpher.target().label(), report.frames, sh.counters.morph_split.load(Ordering::Relaxed),
+                        report.overhead * 100.0, report.cap * 100.0, report.distance.map_or("-".to_string(), |d| format!("{:.3}", d)), bins.join(","),
+                    )
+                }
+                None => "ERR morphing is off (--morph / --morph-histogram)".to_string(),
+            },
+            Command::Shutdown => {
+                sh.shutdown.request(shutdown::StopReason::Core("shutdown requested via control interface".to_string()));
+                "OK shutting down".to_string()
+            }
+        };
+        let _ = req.reply.send(reply);
+    }
+}
+
+// ----------------------------------------------------------------
+// ACL RELOADER
+// SIGHUP re-reads the allowlist, via the same control command as `reload-acl`.
+// ----------------------------------------------------------------
+async fn acl_reloader(control: control::ControlSender, stats: telemetry::TelemetrySender) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tlog!(stats, Level::Warn, "ACL: SIGHUP unavailable, reload via control socket only: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        // The dispatcher logs the outcome.
+        control::request(&control, control::Command::ReloadAcl).await;
+    }
+}
+
+// ----------------------------------------------------------------
+// LOSS ECHO TASK
+// Reports our cumulative frame counters to the peer (`--loss-echo`).
+// ----------------------------------------------------------------
+async fn loss_echo_loop(sh: Shared) {
+    loop {
+        sleep(LOSS_ECHO_INTERVAL).await;
+
+        let target = *sh.peer.lock();
+        let Some(remote_addr) = target else { continue };
+        if sh.last_auth.lock().is_none() {
+            continue;
+        }
+
+        let report = local_loss_report(&sh);
+        let Ok(plain) = bincode::serialize(&report) else { continue };
+        let format = *sh.wire_format.lock();
+        let Ok(payload) = seal_payload(&sh, format, &plain) else { continue };
+        if let Ok(bytes) = format.encode(&WireFrame::new_stats(payload), &sh.cipher) {
+            if sh.socket.send_to(&bytes, remote_addr).await.is_ok() {
+                metrics::Counters::inc(&sh.counters.stats_sent);
+                metrics::Counters::add(&sh.counters.tx_bytes.control, bytes.len() as u64);
+            }
+        }
+    }
+}
+
+/// "1.3 MB carrying 1.2 MB of inner packets (92.3% efficient): payload 93.0%, ...", or `None` if nothing moved.
+fn overhead_summary(bytes: &metrics::WireBytes) -> Option<String> {
+    let efficiency = bytes.efficiency()?;
+    let categories: Vec<String> = bytes.rows().iter()
+        .map(|(name, n)| format!("{} {:.1}%", name, *n as f64 / bytes.wire as f64 * 100.0))
+        .collect();
+    Some(format!(
+        "{} carrying {} of inner packets ({:.1}% efficient): {}",
+        units::format_bytes(bytes.wire), units::format_bytes(bytes.inner), efficiency * 100.0, categories.join(", ")
+    ))
+}
+
+/// e.g. `12.0 MiB of packets as 6.1 MiB (1.97x) in 9000 packets, 38.5 µs/packet, 1200 sent raw by heuristics`.
+fn codec_summary(totals: &compression::DirectionTotals, raw_why: &str) -> Option<String> {
+    let ratio = totals.ratio()?;
+    Some(format!(
+        "{} of packets as {} ({:.2}x) in {} packets, {:.1} µs/packet, {} {}",
+        units::format_bytes(totals.raw_bytes),
+        units::format_bytes(totals.wire_bytes),
+        ratio,
+        totals.packets,
+        totals.micros_per_packet().unwrap_or_default(),
+        totals.uncompressed,
+        raw_why,
+    ))
+}
+
+/// Moves the `--connect-peer` socket to a new active peer. On failure sends to the peer
+/// just go out unconnected, without ICMP feedback.
+fn follow_peer(sh: &Shared, addr: SocketAddr) {
+    if let Err(e) = sh.socket.connect_peer(addr) {
+        tlog!(sh.stats, Level::Warn, "UDP: Cannot connect socket to {}: {}", addr, e);
+    }
+}
+
+/// "Roam" the peer address (Mobility support): a frame from a new address moves the
+/// tunnel there. Only once the frame has authenticated, or a spoofed source address
+/// would steer our traffic (and the `--connect-peer` socket) wherever it liked.
+fn roam(sh: &Shared, src_addr: SocketAddr) {
+    let mut lock = sh.peer.lock();
+    if *lock != Some(src_addr) {
+        follow_peer(sh, src_addr);
+        if let Some(old_addr) = lock.replace(src_addr) {
+            sh.roam_count.fetch_add(1, Ordering::Relaxed);
+            sh.bus.publish(TunnelEvent::Roamed { from: old_addr, to: src_addr });
+        }
+    }
+}
+
+fn local_loss_report(sh: &Shared) -> LossReport {
+    LossReport {
+        data_sent: sh.counters.data_sent.load(Ordering::Relaxed),
+        data_received: sh.counters.data_received.load(Ordering::Relaxed),
+    }
+}
 
-    let _ = tui_handle.join();
+// ----------------------------------------------------------------
+// LIVENESS TASK
+// Sends keep-alives and declares the peer lost after prolonged silence.
+// ----------------------------------------------------------------
+async fn liveness_loop(sh: Shared) {
+    let mut last_heartbeat = Instant::now();
+    loop {
+        sleep(Duration::from_secs(1)).await;
+
+        let target = *sh.peer.lock();
+        let Some(remote_addr) = target else { continue };
+
+        if last_heartbeat.elapsed() >= KEEPALIVE_INTERVAL {
+            let _ = send_heartbeat(&sh, remote_addr).await;
+            last_heartbeat = Instant::now();
+        }
+
+        let silent_for = sh.last_rx.lock().elapsed();
+        if silent_for > PEER_TIMEOUT && sh.peer_up.swap(false, Ordering::Relaxed) {
+            sh.bus.publish(TunnelEvent::PeerLost { peer: remote_addr });
+        }
+    }
+}
+
+async fn send_heartbeat(sh: &Shared, addr: SocketAddr) -> Result<()> {
+    let hb = WireFrame::new_heartbeat(sh.tx_seq.load(Ordering::Relaxed));
+    let format = *sh.wire_format.lock();
+    let bytes = format.encode(&hb, &sh.cipher)?;
+    sh.socket.send_to(&bytes, addr).await?;
+    metrics::Counters::inc(&sh.counters.heartbeats_sent);
+    metrics::Counters::add(&sh.counters.tx_bytes.control, bytes.len() as u64);
     Ok(())
 }
+
+// ----------------------------------------------------------------
+// CHAFF TASK
+// Cover traffic (`--chaff`): sealed filler frames on the schedule's clock, so
+// the start of real use doesn't stand out against silence. Steps aside for
+// data frames, and stops while forwarding is paused or the peer is down.
+// ----------------------------------------------------------------
+async fn chaff_loop(sh: Shared, mut schedule: chaff::Schedule) {
+    let mut last_data = sh.counters.data_sent.load(Ordering::Relaxed);
+    let mut last_at = Instant::now();
+    loop {
+        let gap = schedule.next_gap(&mut *sh.rng.lock());
+        sleep(gap).await;
+
+        let data = sh.counters.data_sent.load(Ordering::Relaxed);
+        let now = Instant::now();
+        let frames = schedule.due(data - last_data, now.duration_since(last_at));
+        (last_data, last_at) = (data, now);
+
+        let target = *sh.peer.lock();
+        let Some(remote_addr) = target else { continue };
+        if !sh.peer_up.load(Ordering::Relaxed) || !sh.peer_chaff.load(Ordering::Relaxed) || sh.paused.load(Ordering::Relaxed) {
+            continue;
+        }
+        for _ in 0..frames {
+            let filler = chaff::padding(&mut *sh.rng.lock(), sh.fitter.as_ref().map_or(sh.mtu, |fitter| fitter.lock().max_payload().min(sh.mtu)));
+            let format = *sh.wire_format.lock();
+            let Ok(payload) = seal_payload(&sh, format, &filler) else { break };
+            let Ok(bytes) = format.encode(&WireFrame::new_chaff(payload), &sh.cipher) else { break };
+            if sh.socket.send_to(&bytes, remote_addr).await.is_ok() {
+                metrics::Counters::inc(&sh.counters.chaff_sent);
+                metrics::Counters::add(&sh.counters.tx_bytes.padding, bytes.len() as u64);
+            }
+        }
+    }
+}
+
+/// Moves the socket to a new source port every `interval`, give or take a quarter so the
+/// moves keep no clock, and tells the peer from the new port. Its roaming follows on the
+/// first frame from there; what it had already sent to the old port is still read for
+/// `PORT_DRAIN`.
+async fn port_rotation(sh: Shared, interval: Duration) {
+    // Time already spent draining counts towards the next wait.
+    let mut drained = Duration::ZERO;
+    loop {
+        let wait = interval.mul_f64(sh.rng.lock().gen_range(0.75..=1.25));
+        sleep(wait.saturating_sub(drained)).await;
+        drained = Duration::ZERO;
+        match sh.socket.rotate() {
+            Ok((old, new)) => {
+                tlog!(sh.stats, Level::Info, "UDP: Source port {} -> {}", old, new);
+                // A peer that predates the notice follows a heartbeat just the same.
+                let notice = sh.agreement.lock().is_none_or(|agreement| agreement.port_change);
+                let target = *sh.peer.lock();
+                match target {
+                    Some(_) if notice => send_control(sh.clone(), PeerControl::PortChanged(new)).await,
+                    Some(peer) => {
+                        let _ = send_heartbeat(&sh, peer).await;
+                    }
+                    None => {}
+                }
+                sleep(PORT_DRAIN).await;
+                sh.socket.retire();
+                drained = PORT_DRAIN;
+            }
+            Err(e) => tlog!(sh.stats, Level::Warn, "UDP: Cannot change source port, keeping it: {:#}", e),
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// RETRANSMISSION TASK
+// Resends dropped packets if RTO is exceeded.
+// ----------------------------------------------------------------
+async fn retransmit_loop(sh: Shared) {
+    loop {
+        sleep(Duration::from_millis(10)).await; // Check every 10ms
+
+        let now = Instant::now();
+        if sh.socket.is_stream() {
+            // TCP already retransmits; doing it here too stacks two ARQs whose timers
+            // fight each other (TCP meltdown). ACKs still measure RTT and free the window.
+            sh.pending.lock().retain(|_, pending| now.duration_since(pending.sent_at) <= STREAM_ACK_TIMEOUT);
+            continue;
+        }
+        let mut retransmits = Vec::new();
+
+        // Scope for lock
+        {
+            let lock = sh.pending.lock();
+            for (seq, pending) in lock.iter() {
+                if now.duration_since(pending.sent_at) > RTO {
+                    retransmits.push((*seq, pending.data.clone()));
+                }
+            }
+        }
+
+        if !retransmits.is_empty() {
+            let target = *sh.peer.lock();
+            if let Some(remote_addr) = target {
+                for (seq, data) in retransmits {
+                    // A timed-out window would otherwise go out again as one burst
+                    let wait = sh.pacer.reserve(sh.monitor.lock().srtt());
+                    if !wait.is_zero() {
+                        sleep(wait).await;
+                    }
+                    // TODO: Implement exponential backoff for RTO
+                    if let Err(e) = sh.socket.send_to(&data, remote_addr).await {
+                         tlog!(sh.stats, Level::Debug, "RTX::Err: {}", e);
+                    } else {
+                         metrics::Counters::inc(&sh.counters.data_sent);
+                         metrics::Counters::inc(&sh.counters.retransmits);
+                         metrics::Counters::add(&sh.counters.tx_bytes.retransmit, data.len() as u64);
+                         tlog!(sh.stats, Level::Debug, "RTX: Seq {} to {}", seq, remote_addr);
+                         // Update timestamp (reset RTO)
+                         let mut lock = sh.pending.lock();
+                         if let Some(entry) = lock.get_mut(&seq) {
+                             entry.sent_at = Instant::now();
+                             entry.retransmits += 1;
+                         }
+                         drop(lock);
+                         sh.monitor.lock().on_retransmit(Instant::now().into_std());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// TX LOOP: TUN Interface -> UDP Socket
+// Reads IP packets, compresses, encrypts, and blasts them over UDP.
+// ----------------------------------------------------------------
+async fn tx_loop(sh: Shared, tun_queue: TunReader, iface: u8) {
+    let mut health = sh.tun_health[iface as usize].subscribe();
+    let mut generation = health.borrow().0;
+    let mut tun_reader = tun_queue.lock().await;
+    let mut frame_buffer = vec![0u8; packet::max_frame_len(sh.mtu).max(4096)]; // Oversized buffer for safety
+    // With `--compress-workers`, packets leave in the order they were read, through a
+    // queue as deep as the window: the pool's own queue fills first, and sends raw.
+    let pipeline = sh.compress_pool.clone().map(|pool| {
+        let (queue, ordered) = tokio::sync::mpsc::channel(WINDOW_SIZE);
+        tokio::spawn(send_in_order(sh.clone(), iface, ordered));
+        (pool, queue)
+    });
+    let mut loop_warned: Option<Instant> = None;
+    loop {
+        // The interface vanished: let go of the queue while it is recreated behind us.
+        if tunwatch::stale(&health, generation) {
+            drop(tun_reader);
+            match tunwatch::wait_up(&mut health, generation).await {
+                Some(current) => generation = current,
+                None => return std::future::pending().await,
+            }
+            tun_reader = tun_queue.lock().await;
+            continue;
+        }
+
+        // Flow Control: Don't read from TUN if window is full (or forwarding is paused)
+        let is_full = {
+             let lock = sh.pending.lock();
+             // Packets still on their way through the workers count against the window too.
+             let queued = pipeline.as_ref().map_or(0, |(_, queue)| queue.max_capacity() - queue.capacity());
+             lock.len() + queued >= sh.cwnd.limit()
+        };
+
+        if is_full || sh.paused.load(Ordering::Relaxed) {
+             let waited = Instant::now();
+             sleep(Duration::from_millis(1)).await;
+             if is_full {
+                 sh.window_stalls.fetch_add(waited.elapsed().as_micros() as u64, Ordering::Relaxed);
+             }
+             continue;
+        }
+
+        let read = tokio::select! {
+            read = tun_reader.read(&mut frame_buffer) => read,
+            _ = health.changed() => continue,
+        };
+        match read {
+            Ok(n) if n > 0 => {
+                let target = *sh.peer.lock();
+                if let Some(remote_addr) = target {
+                    // Our own datagram to the peer, routed back into TUN: sending it would
+                    // wrap it again, and again, each lap adding another copy.
+                    if packet::UdpHeader::parse(packet::inner_ip(&frame_buffer[..n])).is_some_and(|udp| udp.addressed_to(remote_addr)) {
+                        metrics::Counters::inc(&sh.counters.drop_loop);
+                        let level = if loop_warned.is_none_or(|at| at.elapsed() >= LOOP_WARN_INTERVAL) {
+                            loop_warned = Some(Instant::now());
+                            Level::Error
+                        } else {
+                            Level::Debug
+                        };
+                        tlog!(sh.stats, level, "LOOP: Tunnel traffic to {} came back in through TUN and was dropped; route the peer outside the tunnel", remote_addr);
+                        continue;
+                    }
+                    if let Some(dns) = &sh.dns {
+                        if let Some(server) = dns.outbound(packet::inner_ip_mut(&mut frame_buffer[..n])) {
+                            metrics::Counters::inc(&sh.counters.dns_redirected);
+                            tlog!(sh.stats, Level::Debug, "DNS: Query for {} redirected to {}", server, dns.resolver());
+                        }
+                    }
+                    let ip_packet = &frame_buffer[..n];
+                    match sh.quota.outbound(n) {
+                        None => {
+                            metrics::Counters::inc(&sh.counters.drop_quota);
+                            continue;
+                        }
+                        Some(wait) if !wait.is_zero() => sleep(wait).await,
+                        Some(_) => {}
+                    }
+                    if let Some(tap) = &sh.pcap {
+                        tap.capture(packet::inner_ip(ip_packet));
+                    }
+                    
+                    // Introduce jitter to mitigate timing analysis correlation
+                    if let Some(delay) = sh.obfuscator.pacing_delay() {
+                        sleep(delay).await;
+                    }
+
+                    // Pacing: wait for this frame's slot in the schedule shared by all senders
+                    let srtt = sh.monitor.lock().srtt();
+                    let wait = sh.pacer.reserve(srtt);
+                    if !wait.is_zero() {
+                        sleep(wait).await;
+                    }
+
+                    // Pipeline: Compress -> Encrypt -> Wrap
+                    let outgoing = Outgoing { remote_addr, frame_len: n, inner_len: packet::inner_ip(ip_packet).len(), released: Instant::now() };
+                    let compress = sh.compression.load(Ordering::Relaxed);
+                    match &pipeline {
+                        Some((pool, queue)) => {
+                            let payload = match compress.then(|| pool.submit(ip_packet)) {
+                                Some(Some(result)) => TxPayload::Queued(result),
+                                // The workers are behind: better raw now than compressed late.
+                                Some(None) => TxPayload::Ready((compression::uncompressed(ip_packet), compression::Outcome::QueueFull)),
+                                None => TxPayload::Ready((compression::uncompressed(ip_packet), compression::Outcome::Disabled)),
+                            };
+                            if queue.send((outgoing, payload)).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => {
+                            let (processed, outcome) = if compress {
+                                sh.codec.compress(ip_packet)
+                            } else {
+                                (compression::uncompressed(ip_packet), compression::Outcome::Disabled)
+                            };
+                            send_data(&sh, iface, outgoing, processed, outcome).await;
+                        }
+                    }
+                }
+            }
+            Ok(_) => break, // EOF from TUN usually means interface went down
+            Err(e) if tunwatch::vanished(&e) => {
+                if sh.tun_health[iface as usize].report_lost(generation) {
+                    tlog!(sh.stats, Level::Error, "TUN::ReadErr: {} (interface gone)", e);
+                }
+            }
+            Err(e) => {
+                tlog!(sh.stats, Level::Error, "TUN::ReadErr: {}", e);
+                // Cool-down to prevent CPU spin loop on device errors
+                sleep(Duration::from_millis(10)).await;
+                break;
+            }
+        }
+    }
+}
+
+/// A data packet read from the TUN device, on its way to the socket.
+struct Outgoing {
+    remote_addr: SocketAddr,
+    /// As read from the device, TUN framing included.
+    frame_len: usize,
+    inner_len: usize,
+    /// When pacing let it go.
+    released: Instant,
+}
+
+/// An `Outgoing` packet's payload: compressed already, or still with the pool.
+enum TxPayload {
+    Ready(compress_pool::Compressed),
+    Queued(tokio::sync::oneshot::Receiver<compress_pool::Compressed>),
+}
+
+/// `--compress-workers`: sends one TX task's packets in the order it read them, each once its compression is done.
+async fn send_in_order(sh: Shared, iface: u8, mut ordered: tokio::sync::mpsc::Receiver<(Outgoing, TxPayload)>) {
+    while let Some((outgoing, payload)) = ordered.recv().await {
+        let (processed, outcome) = match payload {
+            TxPayload::Ready(done) => done,
+            TxPayload::Queued(result) => match result.await {
+                Ok(done) => done,
+                Err(_) => continue, // Its worker is gone: nothing left to send.
+            },
+        };
+        send_data(&sh, iface, outgoing, processed, outcome).await;
+    }
+}
+
+/// Encrypts and wraps one compressed payload, buffers it for retransmission and sends it.
+async fn send_data(sh: &Shared, iface: u8, outgoing: Outgoing, processed: Vec<u8>, outcome: compression::Outcome) {
+    match outcome {
+        compression::Outcome::Compressed => metrics::Counters::inc(&sh.counters.compressed),
+        compression::Outcome::TooSmall => metrics::Counters::inc(&sh.counters.compress_too_small),
+        compression::Outcome::HighEntropy => metrics::Counters::inc(&sh.counters.compress_entropy),
+        compression::Outcome::NoGain => metrics::Counters::inc(&sh.counters.compress_no_gain),
+        compression::Outcome::QueueFull => metrics::Counters::inc(&sh.counters.compress_queue_full),
+        compression::Outcome::Disabled | compression::Outcome::Suspended => {}
+    }
+    // `--morph`: the payload goes out as one or more padded fragments, sized to the profile.
+    // `--mimic dns`: as fragments only if one datagram can't carry it.
+    let morpher = sh.morph.as_ref().filter(|_| sh.agreement.lock().is_some_and(|agreed| agreed.morphing));
+    let split = match (morpher, &sh.fitter) {
+        (Some(morpher), _) => Some(morpher.lock().split(&processed, &mut *sh.rng.lock())),
+        (None, Some(fitter)) => fitter.lock().split(&processed),
+        (None, None) => None,
+    };
+    let envelopes = match split {
+        Some(envelopes) => {
+            if envelopes.len() > 1 {
+                metrics::Counters::inc(&sh.counters.morph_split);
+            }
+            envelopes.into_iter().map(|envelope| (envelope.bytes, Some(envelope.chunk))).collect()
+        }
+        None => vec![(processed, None)],
+    };
+
+    // Serialization in whichever format was negotiated with the peer
+    let format = *sh.wire_format.lock();
+    let bytes = &sh.counters.tx_bytes;
+    let mut sent = false;
+    for (plain, chunk) in envelopes {
+        let encrypted = seal_data(sh, format, iface, &plain).unwrap();
+        let crypto_len = if format.seals_frame() { crypto::SEAL_OVERHEAD } else { encrypted.len() - plain.len() };
+
+        let seq = sh.tx_seq.fetch_add(1, Ordering::Relaxed);
+        let frame = match chunk {
+            Some(_) => WireFrame::new_morphed(seq, iface, encrypted),
+            None => WireFrame::new_data(seq, iface, encrypted),
+        };
+        let encoded = format.encode(&frame, &sh.cipher).unwrap();
+        if let Some(fitter) = &sh.fitter {
+            fitter.lock().encoded(plain.len(), encoded.len());
+        }
+
+        // Buffer for reliability
+        {
+            let mut lock = sh.pending.lock();
+            lock.insert(seq, PendingFrame {
+                sent_at: Instant::now(),
+                retransmits: 0,
+                data: encoded.clone(),
+            });
+        }
+        sh.monitor.lock().on_sent(Instant::now().into_std());
+
+        if let Err(e) = sh.socket.send_to(&encoded, outgoing.remote_addr).await {
+             tlog!(sh.stats, Level::Debug, "UDP::SendErr: {}", e);
+             continue;
+        }
+        sent = true;
+        metrics::Counters::inc(&sh.counters.data_sent);
+        let payload_len = chunk.unwrap_or(plain.len());
+        metrics::Counters::add(&bytes.payload, payload_len as u64);
+        metrics::Counters::add(&bytes.padding, (plain.len() - payload_len) as u64);
+        metrics::Counters::add(&bytes.crypto, crypto_len as u64);
+        metrics::Counters::add(&bytes.framing, (encoded.len() - plain.len() - crypto_len) as u64);
+        if let Some(morpher) = morpher {
+            let datagram_len = sh.obfuscator.wrapped_len(encoded.len());
+            morpher.lock().sent(plain.len(), datagram_len);
+        }
+    }
+
+    let n = outgoing.frame_len;
+    if sent {
+         sh.tx_delay.record(outgoing.released.elapsed());
+         metrics::Counters::inc(&sh.counters.packets_sent);
+         sh.counters.tx_sizes.record(n);
+         metrics::Counters::add(&bytes.inner, outgoing.inner_len as u64);
+         sh.quota.on_sent(n);
+    }
+}
+
+/// Sends the obfuscation's answer to a decoy handshake, counted as padding.
+async fn send_decoy_reply(sh: &Shared, reply: &[u8], to: SocketAddr) {
+    if sh.socket.send_raw(reply, to).await.is_ok() {
+        metrics::Counters::add(&sh.counters.tx_bytes.padding, reply.len() as u64);
+    }
+}
+
+// ----------------------------------------------------------------
+// RX LOOP: UDP Socket -> TUN Interface
+// Listens for encrypted frames, validates, decrypts, queues for the TUN writer.
+// ----------------------------------------------------------------
+async fn rx_loop(sh: Shared, deliveries: Arc<Vec<Arc<delivery::DeliveryQueue>>>) {
+    let mut udp_buffer = [0u8; 65535]; // Max UDP size
+    let mut unreachable_warned: Option<Instant> = None;
+    loop {
+        match sh.socket.recv_from(&mut udp_buffer).await {
+            Ok((size, src_addr, ecn)) => {
+                // Cheapest check first: strangers never get as far as decoding, let alone roaming.
+                if sh.allowlist.as_ref().is_some_and(|acl| !acl.allows(src_addr.ip())) {
+                    metrics::Counters::inc(&sh.counters.drop_acl);
+                    tlog!(sh.stats, Level::Debug, "DROP: {} is not in the allowlist", src_addr);
+                    continue;
+                }
+
+                sh.quota.on_datagram(size);
+
+                // Too short to be a frame in any format (e.g. a NAT's empty keepalive): not worth decoding.
+                if size < protocol::MIN_FRAME_LEN {
+                    metrics::Counters::inc(&sh.counters.drop_runt);
+                    tlog!(sh.stats, Level::Debug, "DROP: Runt datagram ({} bytes) from {}", size, src_addr);
+                    continue;
+                }
+
+                // Undress the datagram. A decoy handshake ends here, ahead of roaming, as
+                // does anything not dressed the way we dress ours.
+                let size = match sh.obfuscator.unwrap_incoming(&mut udp_buffer[..size], src_addr) {
+                    obfuscation::Unwrapped::Frame(len) => len,
+                    obfuscation::Unwrapped::Decoy { reply } => {
+                        metrics::Counters::add(&sh.counters.rx_bytes.padding, size as u64);
+                        if let Some(reply) = reply {
+                            match sh.obfuscator.reply_delay() {
+                                // Answered from a task of its own, so receiving carries on meanwhile.
+                                Some(delay) => {
+                                    let sh = sh.clone();
+                                    tokio::spawn(async move {
+                                        sleep(delay).await;
+                                        send_decoy_reply(&sh, &reply, src_addr).await;
+                                    });
+                                }
+                                None => send_decoy_reply(&sh, &reply, src_addr).await,
+                            }
+                        }
+                        continue;
+                    }
+                    obfuscation::Unwrapped::Foreign(why) => {
+                        metrics::Counters::inc(&sh.counters.drop_malformed);
+                        tlog!(sh.stats, Level::Debug, "DROP: Datagram ({} bytes) from {} is {}", size, src_addr, why);
+                        continue;
+                    }
+                };
+
+                // Deserialize & Unwrap
+                let expected = *sh.wire_format.lock();
+                let Some((frame, format)) = WireFrame::decode(&udp_buffer[..size], expected, &sh.cipher) else {
+                    if sh.mimic.is_none() && mimicry::looks_wrapped(&udp_buffer[..size]) {
+                        metrics::Counters::inc(&sh.counters.drop_mimic);
+                        tlog!(sh.stats, Level::Debug, "DROP: TLS-wrapped datagram ({} bytes) from {} (peer has --mimic tls?)", size, src_addr);
+                    } else if sh.mimic != Some(mimicry::Mimicry::Wireguard) && wireguard_mimicry::looks_dressed(&udp_buffer[..size]) {
+                        metrics::Counters::inc(&sh.counters.drop_mimic);
+                        tlog!(sh.stats, Level::Debug, "DROP: WireGuard-dressed datagram ({} bytes) from {} (peer has --mimic wireguard?)", size, src_addr);
+                    } else {
+                        metrics::Counters::inc(&sh.counters.drop_malformed);
+                        tlog!(sh.stats, Level::Debug, "DROP: Malformed frame ({} bytes) from {}", size, src_addr);
+                    }
+                    continue;
+                };
+                *sh.last_rx.lock() = Instant::now();
+                if !sh.peer_up.swap(true, Ordering::Relaxed) {
+                    sh.bus.publish(TunnelEvent::PeerEstablished { peer: src_addr });
+                }
+                match frame.header.frame_type {
+                    FrameType::Transport | FrameType::Morphed => {}
+                    FrameType::Chaff => metrics::Counters::add(&sh.counters.rx_bytes.padding, size as u64),
+                    _ => metrics::Counters::add(&sh.counters.rx_bytes.control, size as u64),
+                }
+
+                match frame.header.frame_type {
+                    FrameType::Transport | FrameType::Morphed => {
+                        metrics::Counters::inc(&sh.counters.data_received);
+
+                        // Paused: withhold the ACK too, so the sender's window fills and it backs off.
+                        if sh.paused.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        // Over quota: same, as the way to slow (or stop) the sender.
+                        if !sh.quota.inbound(frame.payload.len()) {
+                            metrics::Counters::inc(&sh.counters.drop_quota);
+                            continue;
+                        }
+
+                        // Note: Silently drop decryption failures (prevent oracle attacks)
+                        let Ok(decrypted) = open_data(&sh, format, &frame) else {
+                            metrics::Counters::inc(&sh.counters.drop_decrypt);
+                            tlog!(sh.stats, Level::Debug, "DROP: Seq {} failed authentication", frame.header.seq);
+                            continue;
+                        };
+                        // If decryption passes, we trust the logic (Authenticated Encryption)
+                        *sh.last_auth.lock() = Some(Instant::now());
+                        roam(&sh, src_addr);
+
+                        // ACK only what authenticated: a forged frame must not earn a genuine ACK.
+                        // In the frame's own format: mid-switch it need not be the one we expect.
+                        if let Ok(ack_bytes) = format.encode_ack(frame.header.seq, sh.acks_signed.load(Ordering::Relaxed), &sh.cipher) {
+                            if sh.socket.send_to(&ack_bytes, src_addr).await.is_ok() {
+                                metrics::Counters::inc(&sh.counters.acks_sent);
+                                metrics::Counters::add(&sh.counters.tx_bytes.control, ack_bytes.len() as u64);
+                            }
+                        }
+
+                        // A retransmission whose original already arrived (lost ACK): re-ACKed above, never re-delivered.
+                        if !sh.replay.lock().accept(frame.header.seq) {
+                            metrics::Counters::inc(&sh.counters.drop_replay);
+                            metrics::Counters::add(&sh.counters.rx_bytes.retransmit, size as u64);
+                            tlog!(sh.stats, Level::Debug, "DROP: Duplicate seq {}", frame.header.seq);
+                            continue;
+                        }
+
+                        // Only the peer marks ECT, and only once both ends read ECN, so a CE mark is the path's.
+                        let congested = ecn == ecn::Ecn::Ce;
+                        if congested {
+                            on_congestion_mark(&sh);
+                        }
+
+                        // Authenticated, so the id is the sender's: it just has an interface we lack.
+                        let Some(backlog) = deliveries.get(frame.header.iface as usize) else {
+                            metrics::Counters::inc(&sh.counters.drop_iface);
+                            tlog!(sh.stats, Level::Debug, "DROP: Seq {} is for interface {}, which we don't have", frame.header.seq, frame.header.iface);
+                            continue;
+                        };
+
+                        let bytes = &sh.counters.rx_bytes;
+                        let crypto_len = if format.seals_frame() { crypto::SEAL_OVERHEAD } else { frame.payload.len() - decrypted.len() };
+                        metrics::Counters::add(&bytes.crypto, crypto_len as u64);
+                        metrics::Counters::add(&bytes.framing, (size - decrypted.len() - crypto_len) as u64);
+                        // `--morph` on the peer: a fragment of a payload, delivered once the last one is in.
+                        let decrypted = if frame.header.frame_type == FrameType::Morphed {
+                            let fragment = match morph::Fragment::open(&decrypted) {
+                                Ok(fragment) => fragment,
+                                Err(e) => {
+                                    metrics::Counters::inc(&sh.counters.drop_reassembly);
+                                    tlog!(sh.stats, Level::Debug, "DROP: Seq {} has a malformed morph envelope: {}", frame.header.seq, e);
+                                    continue;
+                                }
+                            };
+                            metrics::Counters::add(&bytes.payload, fragment.chunk.len() as u64);
+                            metrics::Counters::add(&bytes.padding, (decrypted.len() - fragment.chunk.len()) as u64);
+                            let now = Instant::now().into_std();
+                            let mut reassembly = sh.reassembly.lock();
+                            let abandoned = reassembly.expire(now);
+                            if abandoned > 0 {
+                                metrics::Counters::add(&sh.counters.drop_reassembly, abandoned as u64);
+                                tlog!(sh.stats, Level::Debug, "DROP: Gave up reassembling {} morphed payload(s)", abandoned);
+                            }
+                            match reassembly.accept(frame.header.iface, fragment, now) {
+                                Some(payload) => payload,
+                                None => continue,
+                            }
+                        } else {
+                            metrics::Counters::add(&bytes.payload, decrypted.len() as u64);
+                            decrypted
+                        };
+                        if !sh.codec.accepts(&decrypted) {
+                            metrics::Counters::inc(&sh.counters.drop_codec);
+                            tlog!(sh.stats, Level::Debug, "DROP: Payload compressed with an algorithm we did not advertise");
+                            continue;
+                        }
+                        let mut decompressed = match sh.codec.decompress(&decrypted) {
+                            Ok(decompressed) => decompressed,
+                            Err(e) if e.is::<compression::Oversized>() => {
+                                metrics::Counters::inc(&sh.counters.drop_oversize);
+                                tlog!(sh.stats, Level::Debug, "DROP: Seq {}: {}", frame.header.seq, e);
+                                continue;
+                            }
+                            Err(_) => {
+                                metrics::Counters::inc(&sh.counters.drop_decompress);
+                                continue;
+                            }
+                        };
+                        // Authenticated is not the same as sane: never hand the kernel an empty or oversized packet.
+                        if !packet::plausible_frame(&decompressed, sh.mtu) {
+                            metrics::Counters::inc(&sh.counters.drop_decompress);
+                            continue;
+                        }
+                        if congested {
+                            ecn::mark_ce(packet::inner_ip_mut(&mut decompressed));
+                        }
+                        if let Some(dns) = &sh.dns {
+                            dns.inbound(packet::inner_ip_mut(&mut decompressed));
+                        }
+                        if let Some(tap) = &sh.pcap {
+                            tap.capture(packet::inner_ip(&decompressed));
+                        }
+                        if backlog.push(delivery::Delivery { packet: decompressed }).is_some() {
+                            metrics::Counters::inc(&sh.counters.drop_tun_backlog);
+                            tlog!(sh.stats, Level::Debug, "DROP: TUN backlog full, discarded oldest packet");
+                        }
+                    },
+                    FrameType::Ack => {
+                        // Process ACK: Remove from buffer
+                        metrics::Counters::inc(&sh.counters.acks_received);
+                        // A sealed frame is authenticated whole; otherwise the header is not, and the tag has to be.
+                        if sh.acks_signed.load(Ordering::Relaxed) && !format.seals_frame() && !frame.ack_authentic(&sh.cipher) {
+                            metrics::Counters::inc(&sh.counters.drop_ack_auth);
+                            tlog!(sh.stats, Level::Debug, "DROP: ACK for seq {} failed authentication", frame.header.ack_num);
+                            continue;
+                        }
+                        if format.seals_frame() || sh.acks_signed.load(Ordering::Relaxed) {
+                            roam(&sh, src_addr);
+                        }
+                        let acked = sh.pending.lock().remove(&frame.header.ack_num);
+                        if let Some(pending) = acked {
+                            sh.cwnd.on_ack();
+                            if pending.retransmits == 0 {
+                                let rtt = pending.sent_at.elapsed();
+                                sh.rtt.record(rtt);
+                                sh.monitor.lock().on_rtt_sample(rtt);
+                            }
+                        }
+                    },
+                    FrameType::Handshake => {
+                        metrics::Counters::inc(&sh.counters.handshakes_received);
+                        let hello = open_payload(&sh, format, &frame.payload).ok()
+                            .and_then(|plain| Hello::decode(&plain));
+                        let Some(hello) = hello else {
+                            metrics::Counters::inc(&sh.counters.drop_decrypt);
+                            continue;
+                        };
+                        *sh.last_auth.lock() = Some(Instant::now());
+                        roam(&sh, src_addr);
+                        if sh.quota.begin(hello.session, sh.socket.bytes_sent()) {
+                            tlog!(sh.stats, Level::Debug, "QUOTA: New session {:016x}, usage starts from zero", hello.session);
+                        }
+
+                        let agreement = protocol::Agreement::negotiate(&local_hello(&sh, false), &hello);
+                        if sh.agreement.lock().replace(agreement).is_none_or(|previous| previous.version != agreement.version) {
+                            let missing: Vec<_> = agreement.missing().iter().map(|f| f.name()).collect();
+                            if missing.is_empty() {
+                                tlog!(sh.stats, Level::Info, "PROTO: Protocol v{} (peer speaks v{})", agreement.version, hello.version);
+                            } else {
+                                tlog!(sh.stats, Level::Warn, "PROTO: Peer speaks protocol v{}, older than ours (v{}); going without {}",
+                                    hello.version, protocol::PROTOCOL_VERSION, missing.join(", "));
+                            }
+                            if sh.morph.is_some() && !agreement.morphing {
+                                tlog!(sh.stats, Level::Warn, "OBSF: Peer does not understand morphed frames (it predates them); sending data unshaped");
+                            }
+                        }
+
+                        match WireFormat::negotiate(&sh.supported_formats, &hello.wire_formats) {
+                            Some(agreed) => {
+                                let previous = std::mem::replace(&mut *sh.wire_format.lock(), agreed);
+                                if previous != agreed {
+                                    tlog!(sh.stats, Level::Info, "PROTO: Wire format {:?} -> {:?}", previous, agreed);
+                                }
+                            }
+                            None => {
+                                tlog!(sh.stats, Level::Warn, "PROTO: No common wire format (peer speaks {:?})", hello.wire_formats);
+                            }
+                        }
+                        match crypto::CipherSuite::negotiate(&sh.ciphers, &hello.ciphers) {
+                            Some(agreed) => {
+                                let previous = sh.cipher.set_active(agreed);
+                                if previous != agreed {
+                                    tlog!(sh.stats, Level::Info, "CRYPTO: Data cipher {} -> {}", previous.name(), agreed.name());
+                                }
+                            }
+                            None => {
+                                let names: Vec<_> = hello.ciphers.iter().map(|c| c.name()).collect();
+                                tlog!(sh.stats, Level::Warn, "CRYPTO: No common cipher (peer accepts {}), still sealing with {}", names.join(", "), sh.cipher.active().name());
+                            }
+                        }
+                        if hello.interfaces != sh.interface_count {
+                            tlog!(sh.stats, Level::Warn, "TUN: Peer has {} interface(s), we have {}; packets for an interface one side lacks are dropped",
+                                hello.interfaces, sh.interface_count);
+                        }
+                        let (previous, agreed) = sh.codec.negotiate(&hello.compression);
+                        if agreed != sh.codec.algorithm() {
+                            let names: Vec<_> = hello.compression.iter().map(|a| a.name()).collect();
+                            tlog!(sh.stats, Level::Warn, "COMPRESS: Peer does not decode {} (accepts {}), sending uncompressed",
+                                sh.codec.algorithm().name(), if names.is_empty() { "raw only".to_string() } else { names.join(", ") });
+                        } else if previous != agreed {
+                            tlog!(sh.stats, Level::Info, "COMPRESS: Outbound {} -> {}", previous.name(), agreed.name());
+                        }
+                        *sh.peer_compression.lock() = hello.compressing;
+                        *sh.peer_codec.lock() = hello.compress_tx;
+                        let ect = agreement.ecn;
+                        match sh.socket.set_ect(ect) {
+                            Ok(true) if ect => tlog!(sh.stats, Level::Info, "ECN: Peer reads ECN marks, sending ECN-capable packets"),
+                            Ok(true) => tlog!(sh.stats, Level::Info, "ECN: Peer does not read ECN marks, no longer marking packets"),
+                            Ok(false) if sh.socket.reads_ecn() && !ect => {
+                                tlog!(sh.stats, Level::Warn, "ECN: Peer does not read ECN marks (it needs --ecn too); packets stay unmarked");
+                            }
+                            Ok(false) => {}
+                            Err(e) => tlog!(sh.stats, Level::Warn, "ECN: Failed to mark packets ECN-capable: {}", e),
+                        }
+                        let chaff_ok = agreement.chaff;
+                        if !sh.peer_chaff.swap(chaff_ok, Ordering::Relaxed) && sh.chaff.is_some() {
+                            if !chaff_ok {
+                                tlog!(sh.stats, Level::Warn, "OBSF: Peer does not understand chaff frames (it predates them); sending none");
+                            } else if !sh.wire_format.lock().seals_frame() {
+                                tlog!(sh.stats, Level::Warn, "OBSF: Chaff frames show their type outside --wire-format sealed");
+                            }
+                        }
+                        let signed = agreement.auth_acks;
+                        let was_signed = sh.acks_signed.swap(signed, Ordering::Relaxed);
+                        if signed && !was_signed {
+                            tlog!(sh.stats, Level::Info, "PROTO: ACKs tagged and checked both ways");
+                        } else if sh.auth_acks && !signed {
+                            tlog!(sh.stats, Level::Warn, "PROTO: Peer does not tag ACKs (it needs --auth-acks too); accepting them untagged");
+                        }
+                        let had_dictionary = sh.codec.dictionary_agreed();
+                        match (sh.codec.negotiate_dictionary(hello.dictionary), sh.codec.dictionary_id()) {
+                            (Some(false), Some(ours)) => {
+                                let theirs = hello.dictionary.map_or("none".to_string(), |id| format!("{:08x}", id));
+                                tlog!(sh.stats, Level::Warn, "COMPRESS: Peer's dictionary ({}) differs from ours ({:08x}); small packets compressed without one", theirs, ours);
+                            }
+                            (Some(true), Some(id)) if !had_dictionary => {
+                                tlog!(sh.stats, Level::Info, "COMPRESS: Dictionary {:08x} in use for small packets", id);
+                            }
+                            _ => {}
+                        }
+
+                        if !hello.is_reply {
+                            // A new session restarts the peer's sequence numbers. The same session
+                            // again means our reply was lost: answer it, but keep the replay state.
+                            let known = sh.peer_session.lock().replace(hello.session) == Some(hello.session);
+                            if !known {
+                                sh.replay.lock().reset();
+                            }
+                            sh.handshake.lock().answered();
+                            if let Ok(reply) = build_hello(&sh, true) {
+                                if sh.socket.send_to(&reply, src_addr).await.is_ok() {
+                                    metrics::Counters::inc(&sh.counters.handshakes_sent);
+                                    metrics::Counters::add(&sh.counters.tx_bytes.control, reply.len() as u64);
+                                }
+                            }
+                        }
+                    },
+                    FrameType::Stats => {
+                        metrics::Counters::inc(&sh.counters.stats_received);
+                        let report = open_payload(&sh, format, &frame.payload).ok()
+                            .and_then(|plain| bincode::deserialize::<LossReport>(&plain).ok());
+                        let Some(report) = report else { continue };
+                        roam(&sh, src_addr);
+
+                        let ours = local_loss_report(&sh);
+                        let measured = sh.echo_loss.lock().on_report(report, ours);
+                        if let Some(loss) = measured {
+                            let _ = sh.stats.send(TelemetryUpdate::EchoLoss(loss));
+                        }
+                    },
+                    FrameType::Control => {
+                        let message = open_payload(&sh, format, &frame.payload).ok()
+                            .and_then(|plain| bincode::deserialize::<PeerControl>(&plain).ok());
+                        let Some(message) = message else {
+                            metrics::Counters::inc(&sh.counters.drop_decrypt);
+                            continue;
+                        };
+                        roam(&sh, src_addr);
+                        on_peer_control(&sh, message);
+                    }
+                    FrameType::Heartbeat => {
+                        metrics::Counters::inc(&sh.counters.heartbeats_received);
+                        // Only sealed is a heartbeat authenticated: otherwise anyone can forge one.
+                        if format.seals_frame() {
+                            roam(&sh, src_addr);
+                        }
+                    }
+                    FrameType::Chaff => {
+                        if open_payload(&sh, format, &frame.payload).is_err() {
+                            metrics::Counters::inc(&sh.counters.drop_decrypt);
+                            continue;
+                        }
+                        roam(&sh, src_addr);
+                        metrics::Counters::inc(&sh.counters.chaff_received);
+                    }
+                }
+            },
+            // Only the connected socket (`--connect-peer`) hears ICMP port unreachable.
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                let peer = sh.peer.lock().map_or("-".to_string(), |p| p.to_string());
+                let level = if unreachable_warned.is_none_or(|at| at.elapsed() >= UNREACHABLE_WARN_INTERVAL) {
+                    unreachable_warned = Some(Instant::now());
+                    Level::Warn
+                } else {
+                    Level::Debug
+                };
+                tlog!(sh.stats, level, "UDP: {} is unreachable (ICMP port unreachable)", peer);
+            }
+            Err(e) => {
+                tlog!(sh.stats, Level::Warn, "UDP::RecvErr: {}", e);
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+// TUN WRITER: Delivery Queue -> TUN Interface
+// Drains the delivery queue into the TUN device. Only this task ever
+// waits on the kernel, so the RX loop keeps receiving and ACKing.
+// ----------------------------------------------------------------
+async fn tun_write_loop(sh: Shared, deliveries: Arc<delivery::DeliveryQueue>, tun_writer: TunWriter, iface: usize) {
+    let health = &sh.tun_health[iface];
+    loop {
+        let delivery = deliveries.pop().await;
+        // Locked per packet, so a recreated interface can be swapped in between writes.
+        let mut writer = tun_writer.lock().await;
+        let generation = health.subscribe().borrow().0;
+        match writer.write_all(&delivery.packet).await {
+            Ok(()) => {
+                metrics::Counters::inc(&sh.counters.packets_received);
+                sh.counters.rx_sizes.record(delivery.packet.len());
+                metrics::Counters::add(&sh.counters.rx_bytes.inner, packet::inner_ip(&delivery.packet).len() as u64);
+                sh.quota.on_delivered(delivery.packet.len());
+            }
+            Err(e) if tunwatch::vanished(&e) => {
+                if health.report_lost(generation) {
+                    tlog!(sh.stats, Level::Error, "TUN::WriteErr: {} (interface gone)", e);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Builds an encrypted `Handshake` frame advertising our capabilities.
+/// Bincode-encoded and sealed with the baseline cipher: the one encoding and AEAD every
+/// peer can open. With `--wire-format sealed` the peer must seal too, so Hellos are
+/// sealed whole like every other frame rather than showing their type.
+fn build_hello(sh: &Shared, is_reply: bool) -> Result<Vec<u8>> {
+    let plain = bincode::serialize(&local_hello(sh, is_reply))?;
+    if sh.supported_formats.as_slice() == [WireFormat::Sealed] {
+        return WireFormat::Sealed.encode(&WireFrame::new_handshake(0, plain), &sh.cipher);
+    }
+    let payload = sh.cipher.encrypt_with(crypto::CipherSuite::BASELINE, &plain)?;
+    WireFormat::Bincode.encode(&WireFrame::new_handshake(0, payload), &sh.cipher)
+}
+
+/// Our capabilities and settings as they stand.
+fn local_hello(sh: &Shared, is_reply: bool) -> Hello {
+    Hello {
+        is_reply,
+        session: sh.session_id,
+        wire_formats: sh.supported_formats.to_vec(),
+        ciphers: sh.ciphers.to_vec(),
+        compression: sh.codec.accepted(),
+        interfaces: sh.interface_count,
+        dictionary: sh.codec.dictionary_id(),
+        compressing: Some(sh.compression.load(Ordering::Relaxed)),
+        ecn: Some(sh.socket.reads_ecn()),
+        auth_acks: Some(sh.auth_acks),
+        compress_tx: Some((sh.codec.algorithm(), sh.codec.level())),
+        chaff: Some(true),
+        version: protocol::PROTOCOL_VERSION,
+    }
+}
+
+/// Applies a `Control` frame from the peer. A request is answered with our resulting
+/// state, so the peer learns it even when nothing changed.
+fn on_peer_control(sh: &Shared, message: PeerControl) {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    match message {
+        PeerControl::SetCompression(enabled) => {
+            if sh.compression.swap(enabled, Ordering::Relaxed) != enabled {
+                tlog!(sh.stats, Level::Info, "CTRL: Peer switched our compression {}", on_off(enabled));
+            }
+            tokio::spawn(send_control(sh.clone(), PeerControl::Compression(enabled)));
+        }
+        PeerControl::Compression(enabled) => {
+            if sh.peer_compression.lock().replace(enabled) != Some(enabled) {
+                tlog!(sh.stats, Level::Info, "CTRL: Peer compression now {}", on_off(enabled));
+            }
+        }
+        PeerControl::KeyInstalled { fingerprint, in_use } => {
+            if switch_key(sh, fingerprint).is_none() && sh.cipher.key_status().current != fingerprint {
+                tlog!(sh.stats, Level::Debug, "CRYPTO: Peer has key {:08x}, which is not installed here", fingerprint);
+                return;
+            }
+            // The peer is still waiting for us: tell it we have the key and seal with it.
+            if !in_use {
+                tokio::spawn(send_control(sh.clone(), PeerControl::KeyInstalled { fingerprint, in_use: true }));
+            }
+        }
+        PeerControl::PortChanged(port) => {
+            tlog!(sh.stats, Level::Debug, "CTRL: Peer moved to source port {}", port);
+        }
+        PeerControl::CongestionMarks(marks) => {
+            let srtt = sh.monitor.lock().srtt();
+            if sh.cwnd.on_marks(marks, srtt, RTO, std::time::Instant::now()) {
+                metrics::Counters::inc(&sh.counters.ecn_cuts);
+                tlog!(sh.stats, Level::Debug, "ECN: Peer reports {} marked frames, window cut to {}", marks, sh.cwnd.limit());
+            }
+        }
+    }
+}
+
+/// Announces the key installed by `rekey` every `KEY_ANNOUNCE_INTERVAL` until it is in
+/// use, or replaced by another before the peer had it.
+async fn announce_key(sh: Shared, fingerprint: u32) {
+    while sh.cipher.key_status().next == Some(fingerprint) {
+        send_control(sh.clone(), PeerControl::KeyInstalled { fingerprint, in_use: false }).await;
+        sleep(KEY_ANNOUNCE_INTERVAL).await;
+    }
+}
+
+/// Starts sealing with the installed key `fingerprint`, now that the peer has it too.
+/// Returns the key it replaced, or `None` if that key isn't the one installed.
+fn switch_key(sh: &Shared, fingerprint: u32) -> Option<u32> {
+    let replaced = sh.cipher.promote(fingerprint, KEY_GRACE)?;
+    tlog!(sh.stats, Level::Info, "CRYPTO: Switched to key {:08x}; {:08x} accepted for {}s more", fingerprint, replaced, KEY_GRACE.as_secs());
+    sh.bus.publish(TunnelEvent::Rekeyed);
+    Some(replaced)
+}
+
+/// Counts a data frame that arrived marked CE and reports the running count to the
+/// peer, at most every `ECN_ECHO_INTERVAL` (the peer only cuts once per RTT anyway).
+fn on_congestion_mark(sh: &Shared) {
+    let marks = sh.counters.ecn_ce.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut echoed = sh.ecn_echoed.lock();
+    if echoed.is_some_and(|at| at.elapsed() < ECN_ECHO_INTERVAL) {
+        return;
+    }
+    *echoed = Some(Instant::now());
+    tokio::spawn(send_control(sh.clone(), PeerControl::CongestionMarks(marks)));
+}
+
+/// Sends one `Control` frame to the current peer. Not retransmitted: each message
+/// carries a whole state, so a lost one is repaired by the next (or by toggling again).
+async fn send_control(sh: Shared, message: PeerControl) {
+    let target = *sh.peer.lock();
+    let Some(remote_addr) = target else { return };
+    let Ok(plain) = bincode::serialize(&message) else { return };
+    let format = *sh.wire_format.lock();
+    let Ok(payload) = seal_payload(&sh, format, &plain) else { return };
+    if let Ok(bytes) = format.encode(&WireFrame::new_control(payload), &sh.cipher) {
+        if sh.socket.send_to(&bytes, remote_addr).await.is_ok() {
+            metrics::Counters::add(&sh.counters.tx_bytes.control, bytes.len() as u64);
+        }
+    }
+}
+
+/// Seals a frame payload, unless `format` seals the whole frame anyway.
+fn seal_payload(sh: &Shared, format: WireFormat, plain: &[u8]) -> Result<Vec<u8>> {
+    if format.seals_frame() { Ok(plain.to_vec()) } else { sh.cipher.encrypt(plain) }
+}
+
+/// Opens a frame payload from a frame that arrived in `format`.
+fn open_payload(sh: &Shared, format: WireFormat, payload: &[u8]) -> Result<Vec<u8>> {
+    if format.seals_frame() { Ok(payload.to_vec()) } else { sh.cipher.decrypt(payload) }
+}
+
+/// `seal_payload` for a data frame of interface `iface`, with the id bound into the tag.
+fn seal_data(sh: &Shared, format: WireFormat, iface: u8, plain: &[u8]) -> Result<Vec<u8>> {
+    if format.seals_frame() { Ok(plain.to_vec()) } else { sh.cipher.encrypt_bound(plain, &interfaces::interface_aad(iface)) }
+}
+
+/// `open_payload` for a data frame, checking the interface id in its header.
+fn open_data(sh: &Shared, format: WireFormat, frame: &WireFrame) -> Result<Vec<u8>> {
+    if format.seals_frame() {
+        Ok(frame.payload.clone())
+    } else {
+        sh.cipher.decrypt_bound(&frame.payload, &interfaces::interface_aad(frame.header.iface))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// A `Shared` as a listening end with no `--tun`, `--mimic` or compression, its
+    /// socket bound on loopback with `--connect-peer`.
+    async fn listening_end() -> Shared {
+        let obfuscator: Arc<dyn obfuscation::Obfuscator> = Arc::new(obfuscation::Null);
+        let socket = transport::Transport::bind(transport::TransportKind::Udp, "127.0.0.1:0", false, None, true, false, obfuscator.clone()).await.unwrap();
+        let (stats, _) = telemetry::channel();
+        let codec = compression::Codec::new(
+            compression::Algorithm::Off,
+            0,
+            compression::Algorithm::Off,
+            compression::Policy { min_size: 0, min_savings: 0 },
+            None,
+            packet::max_frame_len(MTU),
+        ).unwrap();
+        Shared {
+            socket: Arc::new(socket),
+            peer: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            cipher: Arc::new(crypto::SessionGuard::new(&KEY)),
+            tx_seq: Arc::new(AtomicU64::new(1)),
+            supported_formats: Arc::new(vec![WireFormat::Bincode, WireFormat::Compact]),
+            ciphers: Arc::new(vec![crypto::CipherSuite::BASELINE]),
+            wire_format: Arc::new(Mutex::new(WireFormat::Compact)),
+            auth_acks: false,
+            acks_signed: Arc::new(AtomicBool::new(false)),
+            monitor: Arc::new(Mutex::new(stats::LinkMonitor::new())),
+            rtt: Arc::new(stats::RttHistogram::new()),
+            tx_delay: Arc::new(stats::RttHistogram::new()),
+            last_rx: Arc::new(Mutex::new(Instant::now())),
+            peer_up: Arc::new(AtomicBool::new(false)),
+            last_auth: Arc::new(Mutex::new(None)),
+            roam_count: Arc::new(AtomicU64::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            compression: Arc::new(AtomicBool::new(false)),
+            peer_compression: Arc::new(Mutex::new(None)),
+            peer_codec: Arc::new(Mutex::new(None)),
+            codec: Arc::new(codec),
+            compress_auto: Arc::new(Mutex::new(None)),
+            compress_adapt: Arc::new(Mutex::new(None)),
+            window_stalls: Arc::new(AtomicU64::new(0)),
+            compress_pool: None,
+            jitter: Arc::new(AtomicBool::new(false)),
+            jitter_range: Arc::new(Mutex::new(obfuscation::JitterRange::DEFAULT)),
+            jitter_gate: None,
+            chaff: None,
+            peer_chaff: Arc::new(AtomicBool::new(false)),
+            mimic: None,
+            obfuscator,
+            fitter: None,
+            morph: None,
+            reassembly: Arc::new(Mutex::new(morph::Reassembly::new())),
+            pacer: Arc::new(pacing::Pacer::new(false, WINDOW_SIZE)),
+            cwnd: Arc::new(ecn::CongestionWindow::new(WINDOW_SIZE)),
+            ecn_echoed: Arc::new(Mutex::new(None)),
+            rng: Arc::new(Mutex::new(obfuscation::traffic_rng(Some(1)))),
+            obfs: Arc::new(AtomicU8::new(0)),
+            obfs_adaptive: false,
+            pcap: None,
+            allowlist: None,
+            dns: None,
+            quota: Arc::new(quota::Session::new(None)),
+            counters: Arc::new(metrics::Counters::default()),
+            replay: Arc::new(Mutex::new(replay::ReplayWindow::new())),
+            echo_loss: Arc::new(Mutex::new(stats::EchoLossTracker::new())),
+            alerts: Arc::new(Mutex::new(Vec::new())),
+            handshake: Arc::new(Mutex::new(handshake::Tracker::default())),
+            handshake_policy: handshake::RetryPolicy {
+                initial: PUNCH_INITIAL_INTERVAL,
+                max_interval: PUNCH_MAX_INTERVAL,
+                max_attempts: None,
+            },
+            mtu: MTU,
+            interface_count: 1,
+            tun_health: Arc::new(vec![tunwatch::TunWatch::new()]),
+            connection: Arc::new(Mutex::new(connection::Tracker::new(STALE_AFTER))),
+            session_id: 1,
+            peer_session: Arc::new(Mutex::new(None)),
+            agreement: Arc::new(Mutex::new(None)),
+            bus: EventBus::new(),
+            shutdown: shutdown::Shutdown::new(stats.clone()),
+            stats,
+        }
+    }
+
+    /// Waits (briefly) for the RX loop to have counted one more of `counter`.
+    async fn counted(counter: &AtomicU64, before: u64) {
+        for _ in 0..200 {
+            if counter.load(Ordering::Relaxed) > before {
+                return;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        panic!("the RX loop never got to the datagram");
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_datagrams_do_not_roam() {
+        let sh = listening_end().await;
+        let local = sh.socket.local_addr().unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let stranger_addr = stranger.local_addr().unwrap();
+        *sh.peer.lock() = Some(peer_addr);
+        follow_peer(&sh, peer_addr);
+        tokio::spawn(rx_loop(sh.clone(), Arc::new(Vec::new())));
+
+        // A data frame whose payload was never sealed under our key.
+        let forged = WireFormat::Compact.encode(&WireFrame::new_data(5, 0, vec![0xAA; 64]), &sh.cipher).unwrap();
+        let before = sh.counters.drop_decrypt.load(Ordering::Relaxed);
+        stranger.send_to(&forged, local).unwrap();
+        counted(&sh.counters.drop_decrypt, before).await;
+
+        // Compact heartbeats and ACKs carry no tag at all.
+        let heartbeat = WireFormat::Compact.encode(&WireFrame::new_heartbeat(6), &sh.cipher).unwrap();
+        let before = sh.counters.heartbeats_received.load(Ordering::Relaxed);
+        stranger.send_to(&heartbeat, local).unwrap();
+        counted(&sh.counters.heartbeats_received, before).await;
+
+        let ack = WireFormat::Compact.encode(&WireFrame::new_ack(0, 1), &sh.cipher).unwrap();
+        let before = sh.counters.acks_received.load(Ordering::Relaxed);
+        stranger.send_to(&ack, local).unwrap();
+        counted(&sh.counters.acks_received, before).await;
+
+        assert_eq!(sh.socket.connected_peer(), Some(peer_addr));
+        assert_eq!(*sh.peer.lock(), Some(peer_addr));
+        assert_eq!(sh.roam_count.load(Ordering::Relaxed), 0);
+
+        // A Hello sealed under the key does move the tunnel: the peer really roamed.
+        let hello = build_hello(&sh, false).unwrap();
+        let before = sh.counters.handshakes_received.load(Ordering::Relaxed);
+        stranger.send_to(&hello, local).unwrap();
+        counted(&sh.counters.handshakes_received, before).await;
+        assert_eq!(sh.socket.connected_peer(), Some(stranger_addr));
+        assert_eq!(*sh.peer.lock(), Some(stranger_addr));
+        assert_eq!(sh.roam_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn acks_go_back_in_the_format_the_data_came_in() {
+        let sh = listening_end().await;
+        let local = sh.socket.local_addr().unwrap();
+        sh.acks_signed.store(true, Ordering::Relaxed);
+        tokio::spawn(rx_loop(sh.clone(), Arc::new(Vec::new())));
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(std::time::Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 2048];
+
+        // Expecting sealed frames, then a compact one from before the switch: its ACK
+        // has to be compact and tagged, or the peer would drop it.
+        for (expected, sent) in [(WireFormat::Sealed, WireFormat::Compact), (WireFormat::Compact, WireFormat::Sealed), (WireFormat::Compact, WireFormat::Compact)] {
+            *sh.wire_format.lock() = expected;
+            let seq = 10 + sent as u64;
+            let payload = seal_data(&sh, sent, 0, &[0x45; 60]).unwrap();
+            peer.send_to(&sent.encode(&WireFrame::new_data(seq, 0, payload), &sh.cipher).unwrap(), local).unwrap();
+            let (len, _) = peer.recv_from(&mut buf).unwrap();
+            let (ack, format) = WireFrame::decode(&buf[..len], expected, &sh.cipher).unwrap();
+            assert_eq!((&ack.header.frame_type, ack.header.ack_num, format), (&FrameType::Ack, seq, sent));
+            assert!(format.seals_frame() || ack.ack_authentic(&sh.cipher), "{:?} while expecting {:?}", sent, expected);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn early_bincode_frames_are_not_taken_for_wireguard() {
+        let sh = listening_end().await;
+        let local = sh.socket.local_addr().unwrap();
+        *sh.wire_format.lock() = WireFormat::Bincode;
+        tokio::spawn(rx_loop(sh.clone(), Arc::new(Vec::new())));
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(std::time::Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 2048];
+
+        // The first data frame, `[1, 0, 0, 0, ...]`, as long as a WireGuard initiation.
+        let frame = (0..148).find_map(|len| {
+            let payload = seal_data(&sh, WireFormat::Bincode, 0, &vec![0x45; len]).unwrap();
+            let frame = WireFormat::Bincode.encode(&WireFrame::new_data(1, 0, payload), &sh.cipher).unwrap();
+            (frame.len() == 148).then_some(frame)
+        }).unwrap();
+        let before = sh.counters.data_received.load(Ordering::Relaxed);
+        peer.send_to(&frame, local).unwrap();
+        let (len, _) = peer.recv_from(&mut buf).unwrap();
+        let (ack, _) = WireFrame::decode(&buf[..len], WireFormat::Bincode, &sh.cipher).unwrap();
+        assert_eq!((&ack.header.frame_type, ack.header.ack_num), (&FrameType::Ack, 1));
+        assert_eq!(sh.counters.data_received.load(Ordering::Relaxed), before + 1);
+        assert_eq!(sh.counters.drop_mimic.load(Ordering::Relaxed), 0);
+
+        // A peer that does dress as WireGuard is counted as such.
+        let dressed = obfuscation::Obfuscator::preamble(&wireguard_mimicry::WireGuardMimic::new(sh.rng.clone())).remove(0);
+        let before = sh.counters.drop_mimic.load(Ordering::Relaxed);
+        peer.send_to(&dressed, local).unwrap();
+        counted(&sh.counters.drop_mimic, before).await;
+    }
+}
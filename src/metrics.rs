@@ -0,0 +1,377 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Pipeline counters maintained by the networking core.
+///
+/// Plain relaxed atomics: they are bumped on the hot path and only ever read as a
+/// loosely-consistent snapshot, so no ordering guarantees are needed.
+#[derive(Default)]
+pub struct Counters {
+    pub data_sent: AtomicU64,
+    pub data_received: AtomicU64,
+    pub acks_sent: AtomicU64,
+    pub acks_received: AtomicU64,
+    pub heartbeats_sent: AtomicU64,
+    pub heartbeats_received: AtomicU64,
+    pub handshakes_sent: AtomicU64,
+    pub handshakes_received: AtomicU64,
+    /// `--loss-echo` counter reports.
+    pub stats_sent: AtomicU64,
+    pub stats_received: AtomicU64,
+    /// `--chaff` cover frames.
+    pub chaff_sent: AtomicU64,
+    pub chaff_received: AtomicU64,
+    /// `--morph`: payloads split across several frames.
+    pub morph_split: AtomicU64,
+    pub retransmits: AtomicU64,
+    /// DNS queries sent to the `--dns-through-tunnel` resolver instead of their own server.
+    pub dns_redirected: AtomicU64,
+    pub drop_decrypt: AtomicU64,
+    /// Decompression failed, or produced an empty / larger-than-MTU packet.
+    pub drop_decompress: AtomicU64,
+    /// Payloads compressed with an algorithm this end did not advertise, discarded undecoded.
+    pub drop_codec: AtomicU64,
+    /// Payloads that would decompress past the largest packet (`compression::Oversized`), cut off undecoded.
+    pub drop_oversize: AtomicU64,
+    /// Data frames that arrived marked Congestion Experienced (`--ecn`).
+    pub ecn_ce: AtomicU64,
+    /// Send window halvings after the peer reported CE marks on our frames.
+    pub ecn_cuts: AtomicU64,
+    pub drop_malformed: AtomicU64,
+    /// Datagrams dressed by a `--mimic` mode this end doesn't run, discarded undecoded.
+    pub drop_mimic: AtomicU64,
+    /// Datagrams too short to be any frame (`protocol::MIN_FRAME_LEN`), discarded unread.
+    pub drop_runt: AtomicU64,
+    pub drop_replay: AtomicU64,
+    /// ACKs without a valid tag over their sequence number, once both ends `--auth-acks`.
+    pub drop_ack_auth: AtomicU64,
+    /// Datagrams from a source outside `--allow`, discarded unread.
+    pub drop_acl: AtomicU64,
+    /// Data frames refused either way because the session is over its quota.
+    pub drop_quota: AtomicU64,
+    /// Delivered packets discarded because the TUN device fell behind.
+    pub drop_tun_backlog: AtomicU64,
+    /// Data frames for an interface id (`--tun` position) this end doesn't have.
+    pub drop_iface: AtomicU64,
+    /// Packets read from TUN that were our own outer datagrams to the peer (a routing loop), not sent.
+    pub drop_loop: AtomicU64,
+    /// Morphed payloads given up on: a malformed envelope, or fragments whose rest never came.
+    pub drop_reassembly: AtomicU64,
+    /// Outgoing data payloads by compression decision (`compression::Outcome`): sent
+    /// compressed, or raw because too short, high-entropy, not shrunk enough, or
+    /// turned away by a full `--compress-workers` queue.
+    pub compressed: AtomicU64,
+    pub compress_too_small: AtomicU64,
+    pub compress_entropy: AtomicU64,
+    pub compress_no_gain: AtomicU64,
+    pub compress_queue_full: AtomicU64,
+    /// Packet totals behind the dashboard's throughput graphs (inner packets sent,
+    /// and delivered); the bytes are `tx_bytes.inner` / `rx_bytes.inner`. Reported as
+    /// `Throughput` deltas, not as panel rows.
+    pub packets_sent: AtomicU64,
+    pub packets_received: AtomicU64,
+    /// Inner packet sizes, counted alongside `packets_sent` / `packets_received`.
+    pub tx_sizes: SizeHistogram,
+    pub rx_sizes: SizeHistogram,
+    /// What the bytes on the wire carried, each way (goodput vs overhead).
+    pub tx_bytes: ByteCategories,
+    pub rx_bytes: ByteCategories,
+}
+
+impl Counters {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CounterSnapshot {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        CounterSnapshot {
+            data_sent: get(&self.data_sent),
+            data_received: get(&self.data_received),
+            acks_sent: get(&self.acks_sent),
+            acks_received: get(&self.acks_received),
+            heartbeats_sent: get(&self.heartbeats_sent),
+            heartbeats_received: get(&self.heartbeats_received),
+            handshakes_sent: get(&self.handshakes_sent),
+            handshakes_received: get(&self.handshakes_received),
+            stats_sent: get(&self.stats_sent),
+            stats_received: get(&self.stats_received),
+            chaff_sent: get(&self.chaff_sent),
+            chaff_received: get(&self.chaff_received),
+            morph_split: get(&self.morph_split),
+            retransmits: get(&self.retransmits),
+            dns_redirected: get(&self.dns_redirected),
+            drop_decrypt: get(&self.drop_decrypt),
+            drop_decompress: get(&self.drop_decompress),
+            drop_codec: get(&self.drop_codec),
+            drop_oversize: get(&self.drop_oversize),
+            ecn_ce: get(&self.ecn_ce),
+            ecn_cuts: get(&self.ecn_cuts),
+            drop_malformed: get(&self.drop_malformed),
+            drop_mimic: get(&self.drop_mimic),
+            drop_runt: get(&self.drop_runt),
+            drop_replay: get(&self.drop_replay),
+            drop_ack_auth: get(&self.drop_ack_auth),
+            drop_acl: get(&self.drop_acl),
+            drop_quota: get(&self.drop_quota),
+            drop_tun_backlog: get(&self.drop_tun_backlog),
+            drop_iface: get(&self.drop_iface),
+            drop_loop: get(&self.drop_loop),
+            drop_reassembly: get(&self.drop_reassembly),
+            compressed: get(&self.compressed),
+            compress_too_small: get(&self.compress_too_small),
+            compress_entropy: get(&self.compress_entropy),
+            compress_no_gain: get(&self.compress_no_gain),
+            compress_queue_full: get(&self.compress_queue_full),
+        }
+    }
+
+    pub fn packet_sizes(&self) -> PacketSizes {
+        PacketSizes { tx: self.tx_sizes.snapshot(), rx: self.rx_sizes.snapshot() }
+    }
+
+    /// The byte categories against the transport's totals of UDP payload bytes.
+    pub fn overhead(&self, wire_tx: u64, wire_rx: u64) -> Overhead {
+        Overhead { tx: self.tx_bytes.snapshot(wire_tx), rx: self.rx_bytes.snapshot(wire_rx) }
+    }
+}
+
+/// Bytes of one direction by what they carried. A datagram is counted where the
+/// pipeline knows what it is; whatever no category claims (drops, junk) is `other`.
+#[derive(Default)]
+pub struct ByteCategories {
+    /// Inner IP packets: sent for the first time, or delivered to TUN.
+    pub inner: AtomicU64,
+    /// Data frame plaintext (the inner packet after compression).
+    pub payload: AtomicU64,
+    /// Nonces and authentication tags of data frames.
+    pub crypto: AtomicU64,
+    /// Data frame headers and encoding.
+    pub framing: AtomicU64,
+    /// Data frames sent again, or received again after they were delivered.
+    pub retransmit: AtomicU64,
+    /// ACKs, heartbeats, Hellos and loss reports.
+    pub control: AtomicU64,
+    /// Decoys: the mimicked TLS ClientHellos sent ahead of each Hello, and chaff frames.
+    pub padding: AtomicU64,
+}
+
+impl ByteCategories {
+    fn snapshot(&self, wire: u64) -> WireBytes {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        WireBytes {
+            wire,
+            inner: get(&self.inner),
+            payload: get(&self.payload),
+            crypto: get(&self.crypto),
+            framing: get(&self.framing),
+            retransmit: get(&self.retransmit),
+            control: get(&self.control),
+            padding: get(&self.padding),
+        }
+    }
+}
+
+/// Point-in-time copy of one direction's `ByteCategories`, with the wire total.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WireBytes {
+    /// UDP payload bytes sent or received (outer IP/UDP headers not included).
+    pub wire: u64,
+    pub inner: u64,
+    pub payload: u64,
+    pub crypto: u64,
+    pub framing: u64,
+    pub retransmit: u64,
+    pub control: u64,
+    pub padding: u64,
+}
+
+impl WireBytes {
+    /// Wire bytes no category accounts for.
+    pub fn other(&self) -> u64 {
+        self.wire.saturating_sub(self.payload + self.crypto + self.framing + self.retransmit + self.control + self.padding)
+    }
+
+    /// Inner bytes per wire byte. Compression can take it above 1.
+    pub fn efficiency(&self) -> Option<f64> {
+        (self.wire > 0).then(|| self.inner as f64 / self.wire as f64)
+    }
+
+    /// (category, bytes) rows that add up to `wire`, in display order.
+    pub fn rows(&self) -> [(&'static str, u64); 7] {
+        [
+            ("payload", self.payload),
+            ("crypto", self.crypto),
+            ("framing", self.framing),
+            ("retransmit", self.retransmit),
+            ("control", self.control),
+            ("padding", self.padding),
+            ("other", self.other()),
+        ]
+    }
+}
+
+/// Both directions' `WireBytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Overhead {
+    /// Bytes sent.
+    pub tx: WireBytes,
+    /// Bytes received.
+    pub rx: WireBytes,
+}
+
+/// Upper bounds of the packet size buckets, in bytes. Anything larger lands in one
+/// final bucket, which the MTU caps.
+pub const SIZE_BUCKET_BOUNDS: [usize; 5] = [64, 128, 256, 512, 1024];
+
+/// Buckets in a size histogram: one per bound plus the final one.
+pub const SIZE_BUCKETS: usize = SIZE_BUCKET_BOUNDS.len() + 1;
+
+/// Display names of the buckets, in order.
+pub const SIZE_BUCKET_LABELS: [&str; SIZE_BUCKETS] = ["<=64", "<=128", "<=256", "<=512", "<=1024", "<=MTU"];
+
+/// Packet counts per size bucket (log-scaled: each bound doubles the previous).
+#[derive(Default)]
+pub struct SizeHistogram {
+    buckets: [AtomicU64; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    pub fn record(&self, len: usize) {
+        let bucket = SIZE_BUCKET_BOUNDS.iter().position(|&bound| len <= bound).unwrap_or(SIZE_BUCKET_BOUNDS.len());
+        Counters::inc(&self.buckets[bucket]);
+    }
+
+    pub fn snapshot(&self) -> [u64; SIZE_BUCKETS] {
+        self.buckets.each_ref().map(|c| c.load(Ordering::Relaxed))
+    }
+}
+
+/// Point-in-time copy of both directions' size histograms.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PacketSizes {
+    /// Packets read from TUN and sent.
+    pub tx: [u64; SIZE_BUCKETS],
+    /// Packets delivered to TUN.
+    pub rx: [u64; SIZE_BUCKETS],
+}
+
+impl PacketSizes {
+    /// Packets counted between `earlier` and `self`.
+    pub fn since(&self, earlier: &PacketSizes) -> PacketSizes {
+        PacketSizes {
+            tx: std::array::from_fn(|i| self.tx[i] - earlier.tx[i]),
+            rx: std::array::from_fn(|i| self.rx[i] - earlier.rx[i]),
+        }
+    }
+
+    /// (bucket label, tx, rx) rows, smallest bucket first.
+    pub fn rows(&self) -> [(&'static str, u64, u64); SIZE_BUCKETS] {
+        std::array::from_fn(|i| (SIZE_BUCKET_LABELS[i], self.tx[i], self.rx[i]))
+    }
+}
+
+/// Point-in-time copy of `Counters`.
+///
+/// This is the one representation every view renders (dashboard panel, control
+/// socket `status`), so they always agree on names and values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CounterSnapshot {
+    pub data_sent: u64,
+    pub data_received: u64,
+    pub acks_sent: u64,
+    pub acks_received: u64,
+    pub heartbeats_sent: u64,
+    pub heartbeats_received: u64,
+    pub handshakes_sent: u64,
+    pub handshakes_received: u64,
+    pub stats_sent: u64,
+    pub stats_received: u64,
+    pub chaff_sent: u64,
+    pub chaff_received: u64,
+    pub morph_split: u64,
+    pub retransmits: u64,
+    pub dns_redirected: u64,
+    pub drop_decrypt: u64,
+    pub drop_decompress: u64,
+    pub drop_codec: u64,
+    pub drop_oversize: u64,
+    pub ecn_ce: u64,
+    pub ecn_cuts: u64,
+    pub drop_malformed: u64,
+    pub drop_mimic: u64,
+    pub drop_runt: u64,
+    pub drop_replay: u64,
+    pub drop_ack_auth: u64,
+    pub drop_acl: u64,
+    pub drop_quota: u64,
+    pub drop_tun_backlog: u64,
+    pub drop_iface: u64,
+    pub drop_loop: u64,
+    pub drop_reassembly: u64,
+    pub compressed: u64,
+    pub compress_too_small: u64,
+    pub compress_entropy: u64,
+    pub compress_no_gain: u64,
+    pub compress_queue_full: u64,
+}
+
+impl CounterSnapshot {
+    /// Stable (name, value) rows, in display order.
+    pub fn rows(&self) -> [(&'static str, u64); 37] {
+        [
+            ("data_sent", self.data_sent),
+            ("data_received", self.data_received),
+            ("acks_sent", self.acks_sent),
+            ("acks_received", self.acks_received),
+            ("heartbeats_sent", self.heartbeats_sent),
+            ("heartbeats_received", self.heartbeats_received),
+            ("handshakes_sent", self.handshakes_sent),
+            ("handshakes_received", self.handshakes_received),
+            ("stats_sent", self.stats_sent),
+            ("stats_received", self.stats_received),
+            ("chaff_sent", self.chaff_sent),
+            ("chaff_received", self.chaff_received),
+            ("morph_split", self.morph_split),
+            ("retransmits", self.retransmits),
+            ("dns_redirected", self.dns_redirected),
+            ("drop_decrypt", self.drop_decrypt),
+            ("drop_decompress", self.drop_decompress),
+            ("drop_codec", self.drop_codec),
+            ("drop_oversize", self.drop_oversize),
+            ("ecn_ce", self.ecn_ce),
+            ("ecn_cuts", self.ecn_cuts),
+            ("drop_malformed", self.drop_malformed),
+            ("drop_mimic", self.drop_mimic),
+            ("drop_runt", self.drop_runt),
+            ("drop_replay", self.drop_replay),
+            ("drop_ack_auth", self.drop_ack_auth),
+            ("drop_acl", self.drop_acl),
+            ("drop_quota", self.drop_quota),
+            ("drop_tun_backlog", self.drop_tun_backlog),
+            ("drop_iface", self.drop_iface),
+            ("drop_loop", self.drop_loop),
+            ("drop_reassembly", self.drop_reassembly),
+            ("compressed", self.compressed),
+            ("compress_too_small", self.compress_too_small),
+            ("compress_entropy", self.compress_entropy),
+            ("compress_no_gain", self.compress_no_gain),
+            ("compress_queue_full", self.compress_queue_full),
+        ]
+    }
+
+    /// (frame type, sent, received) for every frame type, in wire-protocol order.
+    pub fn frame_mix(&self) -> [(&'static str, u64, u64); 6] {
+        [
+            ("data", self.data_sent, self.data_received),
+            ("ack", self.acks_sent, self.acks_received),
+            ("heartbeat", self.heartbeats_sent, self.heartbeats_received),
+            ("handshake", self.handshakes_sent, self.handshakes_received),
+            ("stats", self.stats_sent, self.stats_received),
+            ("chaff", self.chaff_sent, self.chaff_received),
+        ]
+    }
+}
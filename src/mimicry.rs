@@ -0,0 +1,388 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::obfuscation::{self, Obfuscator, Unwrapped};
+
+/// TLS record header: content type, version, length.
+pub const RECORD_HEADER: usize = 5;
+
+/// Longest record body sent: the TLS plaintext limit (2^14), which real stacks fill
+/// on bulk transfers. Longer frames are split across records.
+const MAX_RECORD: usize = 16_384;
+
+const HANDSHAKE: u8 = 0x16;
+const CHANGE_CIPHER_SPEC: u8 = 0x14;
+const APPLICATION_DATA: u8 = 0x17;
+/// Record version of everything after the ClientHello (TLS 1.2, which TLS 1.3 keeps).
+const TLS12: [u8; 2] = [0x03, 0x03];
+/// Record version of the decoy ClientHello (`obfuscation::mimic_tls_client_hello`).
+const TLS10: [u8; 2] = [0x03, 0x01];
+
+/// A source is sent the full server flight at most this often; other ClientHellos from
+/// it get the ServerHello alone.
+const FLIGHT_INTERVAL: Duration = Duration::from_secs(10);
+/// Sources remembered for `FLIGHT_INTERVAL`. Beyond this, expired ones are swept, and if
+/// none has expired (a flood from spoofed addresses) the newcomer gets the ServerHello alone.
+const FLIGHT_SOURCES: usize = 4096;
+/// How long the decoy answer takes, in milliseconds: a server's key exchange and signature.
+const REPLY_DELAY_MS: RangeInclusive<u64> = 2..=20;
+
+/// `--mimic`: what every datagram is dressed as.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Mimicry {
+    /// TLS: the decoy ClientHello is answered with a ServerHello, ChangeCipherSpec and
+    /// the encrypted rest of the server's flight, and every frame after it travels in
+    /// ApplicationData records.
+    Tls,
+    /// DNS: frames travel as TXT queries and responses about names under `--mimic-domain`
+    /// (see `dns_mimicry`).
+    Dns,
+    /// QUIC: a decoy Initial each way, then every frame in short-header packets (see
+    /// `quic_mimicry`).
+    Quic,
+    /// WireGuard: a handshake initiation and response, then every frame as transport
+    /// data (see `wireguard_mimicry`).
+    Wireguard,
+}
+
+impl Mimicry {
+    pub fn label(self) -> &'static str {
+        match self {
+            Mimicry::Tls => "tls",
+            Mimicry::Dns => "dns",
+            Mimicry::Quic => "quic",
+            Mimicry::Wireguard => "wireguard",
+        }
+    }
+}
+
+/// The TLS layer of the obfuscation stack. Sends the decoy ClientHello ahead of each
+/// Hello; with `--mimic tls` (`records`) it also carries every frame in ApplicationData
+/// records and answers the peer's decoy, as a TLS 1.3 server would, a few milliseconds
+/// later.
+pub struct TlsMimic {
+    records: bool,
+    rng: Arc<Mutex<StdRng>>,
+    /// When each source was last sent the full server flight (`server_flight`).
+    flights: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl TlsMimic {
+    pub fn new(records: bool, rng: Arc<Mutex<StdRng>>) -> Self {
+        Self { records, rng, flights: Mutex::new(HashMap::new()) }
+    }
+
+    /// The answer to a ClientHello from `from`: the full server flight, unless `from`
+    /// had one in the last `FLIGHT_INTERVAL`. The flight is several times the size of a
+    /// ClientHello, so a spoofed source could otherwise turn it on a victim.
+    fn answer(&self, from: SocketAddr) -> Vec<u8> {
+        let source = from.ip().to_canonical();
+        let now = Instant::now();
+        let due = {
+            let mut flights = self.flights.lock();
+            if flights.len() >= FLIGHT_SOURCES {
+                flights.retain(|_, at| now.duration_since(*at) < FLIGHT_INTERVAL);
+            }
+            let due = flights.get(&source).is_none_or(|at| now.duration_since(*at) >= FLIGHT_INTERVAL)
+                && (flights.len() < FLIGHT_SOURCES || flights.contains_key(&source));
+            if due {
+                flights.insert(source, now);
+            }
+            due
+        };
+        let mut rng = self.rng.lock();
+        if due { server_flight(&mut *rng) } else { server_hello(&mut *rng) }
+    }
+}
+
+impl Obfuscator for TlsMimic {
+    fn preamble(&self) -> Vec<Vec<u8>> {
+        vec![obfuscation::mimic_tls_client_hello(&mut *self.rng.lock())]
+    }
+
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.records { Cow::Owned(wrap(frame)) } else { Cow::Borrowed(frame) }
+    }
+
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        if self.records { wrapped_len(frame_len) } else { frame_len }
+    }
+
+    /// With `records`, only ApplicationData gets any further. The decoy handshake ends
+    /// here, as does anything that isn't TLS records.
+    fn unwrap_incoming(&self, datagram: &mut [u8], from: SocketAddr) -> Unwrapped {
+        if !self.records {
+            return Unwrapped::Frame(datagram.len());
+        }
+        match classify(datagram) {
+            Record::Data(len) => Unwrapped::Frame(len),
+            Record::ClientHello => Unwrapped::Decoy { reply: Some(self.answer(from)) },
+            Record::ServerHello => Unwrapped::Decoy { reply: None },
+            Record::Other => Unwrapped::Foreign("not TLS records (peer lacks --mimic tls?)"),
+        }
+    }
+
+    fn reply_delay(&self) -> Option<Duration> {
+        self.records.then(|| Duration::from_millis(self.rng.lock().gen_range(REPLY_DELAY_MS)))
+    }
+
+    fn needs_datagrams(&self) -> bool {
+        self.records
+    }
+}
+
+/// What a received datagram is, under `--mimic tls`.
+enum Record {
+    /// ApplicationData records, their bodies now joined at the front: a frame this long.
+    Data(usize),
+    /// The initiator's decoy ClientHello, to be answered with `server_hello`.
+    ClientHello,
+    /// The responder's decoy answer, with or without the rest of its flight.
+    ServerHello,
+    /// Not TLS records at all: an unwrapped frame, from a peer without `--mimic tls`.
+    Other,
+}
+
+/// Wraps one encoded frame in ApplicationData records.
+fn wrap(frame: &[u8]) -> Vec<u8> {
+    let records = frame.len().div_ceil(MAX_RECORD).max(1);
+    let mut datagram = Vec::with_capacity(frame.len() + records * RECORD_HEADER);
+    for chunk in frame.chunks(MAX_RECORD) {
+        push_record(&mut datagram, APPLICATION_DATA, TLS12, chunk);
+    }
+    datagram
+}
+
+/// Length of `wrap`'s output for a frame of `frame_len` bytes.
+fn wrapped_len(frame_len: usize) -> usize {
+    frame_len + frame_len.div_ceil(MAX_RECORD).max(1) * RECORD_HEADER
+}
+
+/// Tells a received datagram's records apart, unwrapping ApplicationData in place.
+///
+/// **Strict parsing**: records must cover the datagram exactly, so a frame from a peer
+/// without mimicry (or one cut short) is never mistaken for a wrapped one.
+fn classify(datagram: &mut [u8]) -> Record {
+    let Some(found) = records(datagram) else { return Record::Other };
+    match found.as_slice() {
+        [hello] if hello.is(HANDSHAKE, TLS10) => Record::ClientHello,
+        [hello, ccs, flight @ ..]
+            if hello.is(HANDSHAKE, TLS12)
+                && ccs.is(CHANGE_CIPHER_SPEC, TLS12)
+                && flight.iter().all(|record| record.is(APPLICATION_DATA, TLS12)) =>
+        {
+            Record::ServerHello
+        }
+        all if all.iter().all(|record| record.is(APPLICATION_DATA, TLS12)) => {
+            let mut len = 0;
+            for record in all {
+                datagram.copy_within(record.body.clone(), len);
+                len += record.body.len();
+            }
+            Record::Data(len)
+        }
+        _ => Record::Other,
+    }
+}
+
+/// Whether a datagram that failed to decode looks like `wrap`'s output: a sign the
+/// peer has `--mimic tls` and we don't.
+pub fn looks_wrapped(datagram: &[u8]) -> bool {
+    datagram.len() > RECORD_HEADER && datagram[0] == APPLICATION_DATA && datagram[1..3] == TLS12
+}
+
+/// The responder's decoy answer to a ClientHello: a ServerHello record with random
+/// contents, then ChangeCipherSpec, as a TLS 1.3 server sends in middlebox
+/// compatibility mode. Never longer than the shortest decoy ClientHello, so a spoofed
+/// one can't be used to amplify traffic.
+fn server_hello(rng: &mut impl Rng) -> Vec<u8> {
+    let body_len = rng.gen_range(64..=79);
+    let mut body = vec![0u8; body_len];
+    rng.fill(&mut body[..]);
+    // Handshake type ServerHello, its 24-bit length, and the legacy version.
+    body[0] = 0x02;
+    body[1..4].copy_from_slice(&((body_len - 4) as u32).to_be_bytes()[1..]);
+    body[4..6].copy_from_slice(&TLS12);
+    let mut datagram = Vec::with_capacity(2 * RECORD_HEADER + body_len + 1);
+    push_record(&mut datagram, HANDSHAKE, TLS12, &body);
+    push_record(&mut datagram, CHANGE_CIPHER_SPEC, TLS12, &[0x01]);
+    datagram
+}
+
+/// `server_hello` followed by the rest of a TLS 1.3 server's first flight, each message
+/// in its own ApplicationData record as TLS 1.3 encrypts them: EncryptedExtensions, a
+/// single ECDSA certificate, CertificateVerify and Finished, each with its 16-byte tag.
+/// Random contents of plausible lengths, the whole kept to one unfragmented datagram.
+fn server_flight(rng: &mut impl Rng) -> Vec<u8> {
+    let mut datagram = server_hello(rng);
+    let lengths = [rng.gen_range(25..=60), rng.gen_range(700..=1100), rng.gen_range(90..=100), 53];
+    for len in lengths {
+        let mut body = vec![0u8; len];
+        rng.fill(&mut body[..]);
+        push_record(&mut datagram, APPLICATION_DATA, TLS12, &body);
+    }
+    datagram
+}
+
+fn push_record(datagram: &mut Vec<u8>, kind: u8, version: [u8; 2], body: &[u8]) {
+    datagram.push(kind);
+    datagram.extend_from_slice(&version);
+    datagram.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    datagram.extend_from_slice(body);
+}
+
+/// One record found in a datagram.
+struct Span {
+    kind: u8,
+    version: [u8; 2],
+    body: Range<usize>,
+}
+
+impl Span {
+    fn is(&self, kind: u8, version: [u8; 2]) -> bool {
+        self.kind == kind && self.version == version
+    }
+}
+
+/// The records making up `datagram`, if they cover it exactly and none is empty.
+fn records(datagram: &[u8]) -> Option<Vec<Span>> {
+    let mut found = Vec::new();
+    let mut at = 0;
+    while at < datagram.len() {
+        let header = datagram.get(at..at + RECORD_HEADER)?;
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let start = at + RECORD_HEADER;
+        if len == 0 || start + len > datagram.len() {
+            return None;
+        }
+        found.push(Span { kind: header[0], version: [header[1], header[2]], body: start..start + len });
+        at = start + len;
+    }
+    (!found.is_empty()).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn mimic(records: bool) -> TlsMimic {
+        TlsMimic::new(records, Arc::new(Mutex::new(StdRng::seed_from_u64(5))))
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 0xC1) as u8).collect()
+    }
+
+    /// Kinds of the records in a datagram, in order.
+    fn kinds(datagram: &[u8]) -> Vec<u8> {
+        records(datagram).unwrap().iter().map(|record| record.kind).collect()
+    }
+
+    #[test]
+    fn frames_round_trip_through_records() {
+        let tls = mimic(true);
+        for len in [1, 60, 1400, MAX_RECORD, MAX_RECORD + 1, 40_000] {
+            let frame = frame(len);
+            let mut datagram = tls.wrap_outgoing(&frame).into_owned();
+            assert_eq!(datagram.len(), tls.wrapped_len(len), "{} bytes", len);
+            let found = records(&datagram).unwrap();
+            assert_eq!(found.len(), len.div_ceil(MAX_RECORD), "{} bytes", len);
+            assert!(found.iter().all(|record| record.is(APPLICATION_DATA, TLS12) && record.body.len() <= MAX_RECORD));
+            assert!(looks_wrapped(&datagram));
+            match tls.unwrap_incoming(&mut datagram, addr("192.0.2.1:443")) {
+                Unwrapped::Frame(n) => assert_eq!(&datagram[..n], &frame[..], "{} bytes", len),
+                _ => panic!("{}-byte frame not unwrapped", len),
+            }
+        }
+    }
+
+    #[test]
+    fn without_records_frames_pass_untouched() {
+        let tls = mimic(false);
+        let frame = frame(100);
+        assert!(matches!(tls.wrap_outgoing(&frame), Cow::Borrowed(_)));
+        assert_eq!(tls.wrapped_len(100), 100);
+        assert!(matches!(tls.unwrap_incoming(&mut frame.clone(), addr("192.0.2.1:443")), Unwrapped::Frame(100)));
+        assert_eq!(tls.reply_delay(), None);
+        assert!(!tls.needs_datagrams());
+    }
+
+    #[test]
+    fn anything_but_exact_records_is_foreign() {
+        let tls = mimic(true);
+        let wrapped = wrap(&frame(300));
+        let mut cut = wrapped[..wrapped.len() - 1].to_vec();
+        let mut trailing = [&wrapped[..], &[0x17]].concat();
+        let mut empty_record = [&wrapped[..], &[APPLICATION_DATA, 0x03, 0x03, 0, 0]].concat();
+        let mut old_version = wrapped.clone();
+        old_version[2] = 0x01;
+        let mut mixed = wrapped.clone();
+        push_record(&mut mixed, HANDSHAKE, TLS12, &[1, 2, 3]);
+        // A frame from a peer without `--mimic tls`: compact, then bincode.
+        let mut bare = frame(300);
+        let mut bincode = vec![0; 40];
+        for datagram in [&mut cut, &mut trailing, &mut empty_record, &mut old_version, &mut mixed, &mut bare, &mut bincode] {
+            assert!(matches!(tls.unwrap_incoming(datagram, addr("192.0.2.1:443")), Unwrapped::Foreign(_)), "{:02x?}", &datagram[..8]);
+        }
+        assert!(!looks_wrapped(&frame(300)));
+    }
+
+    #[test]
+    fn client_hello_is_answered_with_the_server_flight() {
+        let tls = mimic(true);
+        let mut rng = StdRng::seed_from_u64(9);
+        let client_hello = obfuscation::mimic_tls_client_hello(&mut rng);
+        let Unwrapped::Decoy { reply: Some(mut flight) } = tls.unwrap_incoming(&mut client_hello.clone(), addr("198.51.100.7:50000")) else {
+            panic!("ClientHello not answered");
+        };
+        assert_eq!(kinds(&flight), [HANDSHAKE, CHANGE_CIPHER_SPEC, APPLICATION_DATA, APPLICATION_DATA, APPLICATION_DATA, APPLICATION_DATA]);
+        assert!((1000..1400).contains(&flight.len()), "{} bytes", flight.len());
+        // The initiator takes it for the decoy answer it is, and sends nothing back.
+        assert!(matches!(mimic(true).unwrap_incoming(&mut flight, addr("203.0.113.1:443")), Unwrapped::Decoy { reply: None }));
+        let delay = tls.reply_delay().unwrap();
+        assert!(delay >= Duration::from_millis(*REPLY_DELAY_MS.start()) && delay <= Duration::from_millis(*REPLY_DELAY_MS.end()));
+    }
+
+    #[test]
+    fn repeat_client_hellos_get_the_server_hello_alone() {
+        let tls = mimic(true);
+        let mut rng = StdRng::seed_from_u64(9);
+        assert_eq!(records(&tls.answer(addr("198.51.100.7:50000"))).unwrap().len(), 6);
+        // Same source, another port or as v4-mapped IPv6: no second flight.
+        for from in ["198.51.100.7:50001", "[::ffff:198.51.100.7]:50000"] {
+            let reply = tls.answer(addr(from));
+            assert_eq!(kinds(&reply), [HANDSHAKE, CHANGE_CIPHER_SPEC], "{}", from);
+            // Never more than the shortest ClientHello it answers.
+            let shortest = (0..200).map(|_| obfuscation::mimic_tls_client_hello(&mut rng).len()).min().unwrap();
+            assert!(reply.len() <= shortest, "{} > {}", reply.len(), shortest);
+        }
+        assert_eq!(records(&tls.answer(addr("198.51.100.8:50000"))).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn a_flood_of_sources_gets_no_flights() {
+        let tls = mimic(true);
+        for i in 0..FLIGHT_SOURCES as u32 {
+            let from = SocketAddr::new(IpAddr::from((0x0a00_0000 + i).to_be_bytes()), 443);
+            assert_eq!(records(&tls.answer(from)).unwrap().len(), 6);
+        }
+        assert_eq!(kinds(&tls.answer(addr("198.51.100.7:443"))), [HANDSHAKE, CHANGE_CIPHER_SPEC]);
+        // Once a remembered source has expired, a newcomer takes its place.
+        let first = IpAddr::from([10, 0, 0, 0]);
+        *tls.flights.lock().get_mut(&first).unwrap() -= FLIGHT_INTERVAL;
+        assert_eq!(records(&tls.answer(addr("198.51.100.7:443"))).unwrap().len(), 6);
+        assert!(!tls.flights.lock().contains_key(&first));
+    }
+}
@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use serde::Deserialize;
+
+/// In front of every morphed frame's chunk: packet id (4B), fragment index and count
+/// (1B each), chunk length (2B). The padding follows the chunk.
+pub const ENVELOPE_HEADER: usize = 8;
+
+/// Default `--morph-overhead`, in percent of what the frames would cost unmorphed.
+pub const DEFAULT_OVERHEAD: u8 = 30;
+
+/// Most frames one payload is split across.
+const MAX_FRAGMENTS: usize = 8;
+
+/// Most frames `Fitter` splits one payload across, and the most a received fragment
+/// may say its payload has.
+const MAX_FIT_FRAGMENTS: usize = 16;
+
+/// Smallest chunk a split leaves in a frame: below it, the frame costs more than it carries.
+const MIN_CHUNK: usize = 32;
+
+/// Overhead allowance banked while the traffic needs none, so a burst of small packets
+/// after a bulk transfer is not padded at the whole transfer's expense.
+const MAX_CREDIT: f64 = 64.0 * 1024.0;
+
+/// Framing, seal and record bytes assumed per frame until the first one has been sent.
+const FRAME_OVERHEAD_GUESS: usize = 40;
+
+/// `Fitter`'s overhead until a frame has been encoded: above any format's framing and seal.
+const FIT_OVERHEAD_GUESS: usize = 64;
+
+/// Bytes `Fitter` keeps spare in each frame: a varint sequence number can grow between
+/// the frame it measured and the next.
+const FIT_SLACK: usize = 4;
+
+/// How long a partly reassembled payload waits for its other fragments. Each fragment
+/// is retransmitted on its own, so one missing for this long has been given up on.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Payloads being reassembled at once; a new one beyond it pushes out the oldest.
+const MAX_PARTIAL: usize = 64;
+
+/// Most bins a `--morph-histogram` file may have.
+const MAX_BINS: usize = 64;
+
+/// `--morph`: the shipped target distributions, of UDP payload sizes.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum MorphProfile {
+    /// HTTPS browsing: mostly ACK-sized and full-size packets, little in between.
+    Https,
+    /// A video call: audio-sized packets and video frames just short of the MTU.
+    Video,
+}
+
+impl MorphProfile {
+    pub fn label(self) -> &'static str {
+        match self {
+            MorphProfile::Https => "https",
+            MorphProfile::Video => "video",
+        }
+    }
+
+    fn bins(self) -> &'static [(usize, f64)] {
+        match self {
+            MorphProfile::Https => &[(100, 35.0), (200, 10.0), (400, 6.0), (700, 5.0), (1000, 4.0), (1300, 5.0), (1420, 35.0)],
+            MorphProfile::Video => &[(150, 15.0), (300, 10.0), (700, 10.0), (1000, 25.0), (1200, 40.0)],
+        }
+    }
+}
+
+/// One bin of a target distribution: sizes above the previous bin's `up_to`, up to this one.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Bin {
+    pub up_to: usize,
+    pub weight: f64,
+}
+
+/// A target distribution of datagram sizes.
+pub struct Histogram {
+    /// The profile's name, or the file it came from.
+    label: String,
+    bins: Vec<Bin>,
+    total: f64,
+}
+
+impl Histogram {
+    pub fn builtin(profile: MorphProfile) -> Self {
+        let bins = profile.bins().iter().map(|&(up_to, weight)| Bin { up_to, weight }).collect();
+        Self::new(profile.label().to_string(), bins).expect("shipped profiles are valid")
+    }
+
+    /// `--morph-histogram`: a JSON array of bins, `[{"up_to": 120, "weight": 35}, ...]`,
+    /// in increasing `up_to` order. Weights are relative; they need not sum to 100.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read histogram {}", path.display()))?;
+        let bins: Vec<Bin> = serde_json::from_str(&text).with_context(|| format!("Failed to parse histogram {}", path.display()))?;
+        Self::new(path.display().to_string(), bins).with_context(|| format!("Bad histogram {}", path.display()))
+    }
+
+    fn new(label: String, bins: Vec<Bin>) -> Result<Self> {
+        if bins.is_empty() || bins.len() > MAX_BINS {
+            bail!("needs 1 to {} bins, has {}", MAX_BINS, bins.len());
+        }
+        let mut floor = 0;
+        for bin in &bins {
+            if bin.up_to <= floor || bin.up_to > u16::MAX as usize {
+                bail!("bin up_to {} is out of order or outside 1..=65535", bin.up_to);
+            }
+            if !bin.weight.is_finite() || bin.weight < 0.0 {
+                bail!("bin up_to {} has weight {}", bin.up_to, bin.weight);
+            }
+            floor = bin.up_to;
+        }
+        let total = bins.iter().map(|bin| bin.weight).sum::<f64>();
+        if total <= 0.0 {
+            bail!("weights sum to zero");
+        }
+        Ok(Self { label, bins, total })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// A size drawn from the distribution: a bin, then uniformly within it.
+    ///
+    /// **Steering**: bins are weighted by how far they are behind their share of the
+    /// frames counted in `achieved`, not by the target alone. When the budget keeps
+    /// some sizes out of reach, the draws make up with the others, and the histogram
+    /// as a whole stays as close to the target as the budget allows.
+    fn sample(&self, rng: &mut impl Rng, achieved: &[u64]) -> usize {
+        let next = achieved.iter().sum::<u64>() as f64 + 1.0;
+        // Sums to at least 1: the next frame's share, plus whatever bins are ahead of theirs.
+        let deficit = |i: usize| (self.bins[i].weight / self.total * next - achieved[i] as f64).max(0.0);
+        let total = (0..self.bins.len()).map(deficit).sum::<f64>();
+        let mut pick = rng.gen::<f64>() * total;
+        let mut floor = 0;
+        for (i, bin) in self.bins.iter().enumerate() {
+            let weight = deficit(i);
+            if pick < weight {
+                return rng.gen_range(floor + 1..=bin.up_to);
+            }
+            pick -= weight;
+            floor = bin.up_to;
+        }
+        floor
+    }
+
+    /// Which bin a size falls in; sizes past the last bin count towards it.
+    fn bin_of(&self, size: usize) -> usize {
+        self.bins.iter().position(|bin| size <= bin.up_to).unwrap_or(self.bins.len() - 1)
+    }
+}
+
+/// One morphed frame's plaintext, before sealing.
+pub struct Envelope {
+    pub bytes: Vec<u8>,
+    /// Payload bytes in it; the rest is header and padding.
+    pub chunk: usize,
+}
+
+/// Shapes outgoing payloads into frames whose sizes follow the target distribution.
+///
+/// **Budget**: every payload earns `--morph-overhead` of what it would cost unmorphed,
+/// and padding and extra frame headers are paid out of what has been earned. A payload
+/// the budget can't stretch to its drawn sizes goes out in one frame, padded as far as
+/// the budget allows, so the overhead stays under the cap at the cost of a looser fit
+/// (which `report` shows).
+pub struct Morpher {
+    target: Histogram,
+    /// `--morph-overhead`, as a fraction.
+    cap: f64,
+    /// Largest chunk a frame carries: a full-size packet's payload, so padding never
+    /// makes a datagram bigger than the tunnel already sends.
+    max_chunk: usize,
+    /// Wire bytes a frame costs beyond its envelope (framing, seal, TLS record), as last seen.
+    frame_overhead: usize,
+    /// Overhead allowance, in bytes. Goes negative when even the envelope headers overrun it.
+    credit: f64,
+    next_id: u32,
+    /// Frames sent per target bin.
+    achieved: Vec<u64>,
+    /// What the morphed payloads would have cost unmorphed, and what they did cost.
+    plain_bytes: u64,
+    wire_bytes: u64,
+}
+
+impl Morpher {
+    pub fn new(target: Histogram, overhead_percent: u8, max_chunk: usize) -> Self {
+        let bins = target.bins.len();
+        Self {
+            target,
+            cap: overhead_percent as f64 / 100.0,
+            max_chunk,
+            frame_overhead: FRAME_OVERHEAD_GUESS,
+            credit: 0.0,
+            next_id: 0,
+            achieved: vec![0; bins],
+            plain_bytes: 0,
+            wire_bytes: 0,
+        }
+    }
+
+    pub fn target(&self) -> &Histogram {
+        &self.target
+    }
+
+    /// Splits and pads one payload into the envelopes of the frames that carry it.
+    pub fn split(&mut self, payload: &[u8], rng: &mut impl Rng) -> Vec<Envelope> {
+        let plain = payload.len() + self.frame_overhead;
+        self.credit = (self.credit + self.cap * plain as f64).min(MAX_CREDIT);
+        let mut frames = self.plan(payload.len(), rng);
+        if self.cost(&frames) > plain as f64 + self.credit {
+            frames = self.within_budget(payload.len(), plain, rng);
+        }
+        self.credit = (self.credit - (self.cost(&frames) - plain as f64)).max(-MAX_CREDIT);
+        self.plain_bytes += plain as u64;
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        envelopes(id, payload, &frames)
+    }
+
+    /// Records a frame as sent: its envelope's length and the datagram it became.
+    pub fn sent(&mut self, envelope_len: usize, datagram_len: usize) {
+        self.frame_overhead = datagram_len.saturating_sub(envelope_len);
+        self.achieved[self.target.bin_of(datagram_len)] += 1;
+        self.wire_bytes += datagram_len as u64;
+    }
+
+    /// (chunk, padding) per frame, each frame sized by a fresh draw.
+    fn plan(&self, len: usize, rng: &mut impl Rng) -> Vec<(usize, usize)> {
+        let mut frames = Vec::new();
+        let mut left = len;
+        loop {
+            let room = self.room(self.target.sample(rng, &self.achieved));
+            if left <= room || frames.len() + 1 == MAX_FRAGMENTS {
+                frames.push((left, room.saturating_sub(left)));
+                return frames;
+            }
+            frames.push((room, 0));
+            left -= room;
+        }
+    }
+
+    /// The fallback plan: as few frames as the payload fits in, the last padded towards
+    /// a drawn size with what the budget has left.
+    fn within_budget(&self, len: usize, plain: usize, rng: &mut impl Rng) -> Vec<(usize, usize)> {
+        let mut frames: Vec<(usize, usize)> = Vec::new();
+        let mut left = len;
+        while left > self.max_chunk {
+            frames.push((self.max_chunk, 0));
+            left -= self.max_chunk;
+        }
+        frames.push((left, 0));
+        let spare = (plain as f64 + self.credit - self.cost(&frames)).max(0.0) as usize;
+        let wanted = self.room(self.target.sample(rng, &self.achieved)).saturating_sub(left);
+        if let Some(last) = frames.last_mut() {
+            last.1 = wanted.min(spare);
+        }
+        frames
+    }
+
+    /// Payload room in a datagram of `size` bytes.
+    fn room(&self, size: usize) -> usize {
+        size.saturating_sub(self.frame_overhead + ENVELOPE_HEADER).clamp(MIN_CHUNK, self.max_chunk)
+    }
+
+    /// Wire bytes a plan costs.
+    fn cost(&self, frames: &[(usize, usize)]) -> f64 {
+        frames.iter().map(|&(chunk, padding)| chunk + padding + ENVELOPE_HEADER + self.frame_overhead).sum::<usize>() as f64
+    }
+
+    /// How close the frames sent so far come to the target.
+    pub fn report(&self) -> Report {
+        let frames = self.achieved.iter().sum::<u64>();
+        let bins: Vec<_> = self.target.bins.iter().zip(&self.achieved).map(|(bin, &sent)| ReportBin {
+            up_to: bin.up_to,
+            target: bin.weight / self.target.total,
+            achieved: if frames == 0 { 0.0 } else { sent as f64 / frames as f64 },
+        }).collect();
+        Report {
+            frames,
+            overhead: if self.plain_bytes == 0 { 0.0 } else { self.wire_bytes as f64 / self.plain_bytes as f64 - 1.0 },
+            cap: self.cap,
+            // Total variation distance: the share of frames that would have to change bins.
+            distance: (frames > 0).then(|| bins.iter().map(|bin| (bin.target - bin.achieved).abs()).sum::<f64>() / 2.0),
+            bins,
+        }
+    }
+}
+
+/// Splits payloads too large for one datagram of a size-capped dressing (`--mimic dns`)
+/// into unpadded fragments, which the peer reassembles like morphed ones. A payload
+/// that fits goes out whole, in a plain data frame.
+pub struct Fitter {
+    /// Largest frame one datagram carries (`Obfuscator::max_frame`).
+    max_frame: usize,
+    /// Bytes a frame adds to its plaintext (framing, seal), as last seen.
+    frame_overhead: usize,
+    next_id: u32,
+}
+
+impl Fitter {
+    pub fn new(max_frame: usize) -> Self {
+        Self { max_frame, frame_overhead: FIT_OVERHEAD_GUESS, next_id: 0 }
+    }
+
+    /// Largest payload one data frame carries.
+    pub fn max_payload(&self) -> usize {
+        self.max_frame.saturating_sub(self.frame_overhead + FIT_SLACK)
+    }
+
+    /// The envelopes of the frames that carry `payload`, or `None` if it fits in one
+    /// data frame as it is. Chunks are evened out across the fewest frames that hold it.
+    pub fn split(&mut self, payload: &[u8]) -> Option<Vec<Envelope>> {
+        if payload.len() <= self.max_payload() {
+            return None;
+        }
+        let room = self.max_payload().saturating_sub(ENVELOPE_HEADER).max(1);
+        let count = payload.len().div_ceil(room).min(MAX_FIT_FRAGMENTS);
+        let chunk = payload.len().div_ceil(count);
+        let mut frames = vec![(chunk, 0); count];
+        frames[count - 1].0 = payload.len() - chunk * (count - 1);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        Some(envelopes(id, payload, &frames))
+    }
+
+    /// Records a frame as encoded: its plaintext's length and the frame it became.
+    pub fn encoded(&mut self, plain_len: usize, frame_len: usize) {
+        self.frame_overhead = frame_len.saturating_sub(plain_len);
+    }
+}
+
+/// `payload` cut into envelopes, one per (chunk, padding) in `frames`.
+fn envelopes(id: u32, payload: &[u8], frames: &[(usize, usize)]) -> Vec<Envelope> {
+    let count = frames.len() as u8;
+    let mut at = 0;
+    frames.iter().enumerate().map(|(index, &(chunk, padding))| {
+        let mut bytes = Vec::with_capacity(ENVELOPE_HEADER + chunk + padding);
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.push(index as u8);
+        bytes.push(count);
+        bytes.extend_from_slice(&(chunk as u16).to_be_bytes());
+        bytes.extend_from_slice(&payload[at..at + chunk]);
+        bytes.resize(ENVELOPE_HEADER + chunk + padding, 0);
+        at += chunk;
+        Envelope { bytes, chunk }
+    }).collect()
+}
+
+/// What the dashboard shows of morphing: `Report`'s headline figures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MorphStatus {
+    pub distance: Option<f64>,
+    pub overhead: f64,
+    /// The peer understands morphed frames; until it does, data goes out unshaped.
+    pub agreed: bool,
+}
+
+/// Achieved against target, bin by bin.
+pub struct Report {
+    pub frames: u64,
+    /// Bytes on the wire beyond what the same payloads cost unmorphed, as a fraction of it.
+    pub overhead: f64,
+    pub cap: f64,
+    /// From 0 (the target exactly) to 1 (no frame in a bin the target uses); `None`
+    /// until a frame has been sent.
+    pub distance: Option<f64>,
+    pub bins: Vec<ReportBin>,
+}
+
+/// Shares of all frames, from 0 to 1.
+pub struct ReportBin {
+    pub up_to: usize,
+    pub target: f64,
+    pub achieved: f64,
+}
+
+/// A received morphed frame, its envelope read.
+pub struct Fragment<'a> {
+    id: u32,
+    index: u8,
+    count: u8,
+    pub chunk: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    pub fn open(envelope: &'a [u8]) -> Result<Self> {
+        if envelope.len() < ENVELOPE_HEADER {
+            bail!("envelope of {} bytes", envelope.len());
+        }
+        let id = u32::from_be_bytes([envelope[0], envelope[1], envelope[2], envelope[3]]);
+        let (index, count) = (envelope[4], envelope[5]);
+        let len = u16::from_be_bytes([envelope[6], envelope[7]]) as usize;
+        if count == 0 || count as usize > MAX_FIT_FRAGMENTS || index >= count {
+            bail!("fragment {} of {}", index, count);
+        }
+        let chunk = envelope.get(ENVELOPE_HEADER..ENVELOPE_HEADER + len).context("chunk runs past the envelope")?;
+        Ok(Self { id, index, count, chunk })
+    }
+}
+
+/// Payloads split across frames, put back together.
+///
+/// Fragments are separate frames with sequence numbers of their own, so they are ACKed,
+/// retransmitted and deduplicated like any other; this only waits for the set, in
+/// whatever order it arrives.
+pub struct Reassembly {
+    partial: HashMap<(u8, u32), Partial>,
+}
+
+struct Partial {
+    parts: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    started: Instant,
+}
+
+impl Reassembly {
+    pub fn new() -> Self {
+        Self { partial: HashMap::new() }
+    }
+
+    /// Takes in one fragment, for interface `iface`. The whole payload once it completes one.
+    pub fn accept(&mut self, iface: u8, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        if fragment.count == 1 {
+            return Some(fragment.chunk.to_vec());
+        }
+        let count = fragment.count as usize;
+        let key = (iface, fragment.id);
+        let partial = self.partial.entry(key).or_insert_with(|| Partial { parts: vec![None; count], missing: count, started: now });
+        // The id came round again: whatever was left of the old payload is lost.
+        if partial.parts.len() != count {
+            *partial = Partial { parts: vec![None; count], missing: count, started: now };
+        }
+        let slot = &mut partial.parts[fragment.index as usize];
+        if slot.is_none() {
+            *slot = Some(fragment.chunk.to_vec());
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        let parts = self.partial.remove(&key)?.parts;
+        Some(parts.into_iter().flatten().flatten().collect())
+    }
+
+    /// Gives up on payloads that waited too long for a fragment, and on the oldest when
+    /// there is no room for another. How many were given up on.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partial.len();
+        self.partial.retain(|_, partial| now.duration_since(partial.started) < REASSEMBLY_TIMEOUT);
+        while self.partial.len() >= MAX_PARTIAL {
+            let oldest = self.partial.iter().min_by_key(|(_, partial)| partial.started).map(|(&key, _)| key);
+            if let Some(key) = oldest {
+                self.partial.remove(&key);
+            }
+        }
+        before - self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// What a 1400-byte MTU gives `Morpher::new` in the tunnel.
+    const MAX_CHUNK: usize = 1405;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + len) as u8).collect()
+    }
+
+    fn envelope(id: u32, index: u8, count: u8, chunk: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[index, count]);
+        bytes.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(chunk);
+        bytes
+    }
+
+    /// Feeds envelopes to a fresh reassembly in order; the payload they complete, if any.
+    fn reassemble(envelopes: &[Envelope]) -> Option<Vec<u8>> {
+        let mut reassembly = Reassembly::new();
+        let now = Instant::now();
+        envelopes.iter().filter_map(|envelope| reassembly.accept(0, Fragment::open(&envelope.bytes).unwrap(), now)).last()
+    }
+
+    #[test]
+    fn morphed_payloads_stay_in_bounds_and_reassemble() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for profile in [MorphProfile::Https, MorphProfile::Video] {
+            let mut morpher = Morpher::new(Histogram::builtin(profile), DEFAULT_OVERHEAD, MAX_CHUNK);
+            for _ in 0..2000 {
+                let payload = payload(rng.gen_range(1..=MAX_CHUNK));
+                let envelopes = morpher.split(&payload, &mut rng);
+                assert!((1..=MAX_FRAGMENTS).contains(&envelopes.len()), "{} frames", envelopes.len());
+                for envelope in &envelopes {
+                    // Padding never takes a frame past a full-size packet's.
+                    assert!(envelope.bytes.len() <= ENVELOPE_HEADER + MAX_CHUNK, "{} bytes", envelope.bytes.len());
+                    assert_eq!(Fragment::open(&envelope.bytes).unwrap().chunk.len(), envelope.chunk);
+                    morpher.sent(envelope.bytes.len(), envelope.bytes.len() + FRAME_OVERHEAD_GUESS);
+                }
+                assert_eq!(reassemble(&envelopes).unwrap(), payload);
+            }
+            let report = morpher.report();
+            assert!(report.overhead <= report.cap, "{}: {:.3} over a cap of {}", profile.label(), report.overhead, report.cap);
+            assert!(report.distance.unwrap() < 0.5, "{}: {:?}", profile.label(), report.distance);
+        }
+    }
+
+    #[test]
+    fn fitted_payloads_fit_the_dressing() {
+        let mut fitter = Fitter::new(512);
+        assert!(fitter.split(&payload(fitter.max_payload())).is_none());
+        for len in [fitter.max_payload() + 1, 1405, 4000] {
+            let payload = payload(len);
+            let envelopes = fitter.split(&payload).unwrap();
+            assert!(envelopes.iter().all(|envelope| envelope.bytes.len() <= fitter.max_payload()), "{} bytes", len);
+            // Evened out: equal chunks, and the remainder in the last.
+            let chunks: Vec<usize> = envelopes.iter().map(|envelope| envelope.chunk).collect();
+            let (last, rest) = chunks.split_last().unwrap();
+            assert!(rest.iter().all(|&chunk| chunk == chunks[0]) && *last <= chunks[0], "{:?}", chunks);
+            assert_eq!(reassemble(&envelopes).unwrap(), payload);
+        }
+        // Once a frame's real overhead is known, the room follows it.
+        let before = fitter.max_payload();
+        fitter.encoded(100, 130);
+        assert_eq!(fitter.max_payload(), before + FIT_OVERHEAD_GUESS - 30);
+    }
+
+    #[test]
+    fn malformed_envelopes_are_refused() {
+        let chunk = payload(20);
+        let good = envelope(7, 1, 3, &chunk);
+        let mut zero_count = good.clone();
+        zero_count[5] = 0;
+        let mut too_many = good.clone();
+        too_many[5] = MAX_FIT_FRAGMENTS as u8 + 1;
+        let mut index_past_count = good.clone();
+        index_past_count[4] = 3;
+        let mut long_chunk = good.clone();
+        long_chunk[7] = 21;
+        for bad in [&good[..ENVELOPE_HEADER - 1], &zero_count, &too_many, &index_past_count, &long_chunk] {
+            assert!(Fragment::open(bad).is_err(), "{:02x?}", &bad[..bad.len().min(ENVELOPE_HEADER)]);
+        }
+        // Padding after the chunk is not part of it.
+        let padded = [&good[..], &[0; 100]].concat();
+        let fragment = Fragment::open(&padded).unwrap();
+        assert_eq!((fragment.id, fragment.index, fragment.count, fragment.chunk), (7, 1, 3, &chunk[..]));
+    }
+
+    #[test]
+    fn fragments_reassemble_in_any_order_once_each() {
+        let mut reassembly = Reassembly::new();
+        let now = Instant::now();
+        let parts = [payload(10), payload(20), payload(30)];
+        let envelopes: Vec<_> = (0..3).map(|i| envelope(1, i as u8, 3, &parts[i])).collect();
+        let mut accept = |iface: u8, bytes: &[u8]| reassembly.accept(iface, Fragment::open(bytes).unwrap(), now);
+        assert_eq!(accept(0, &envelopes[2]), None);
+        // A duplicate, and the same id on another interface, don't fill the gap.
+        assert_eq!(accept(0, &envelopes[2]), None);
+        assert_eq!(accept(1, &envelopes[0]), None);
+        assert_eq!(accept(0, &envelopes[0]), None);
+        assert_eq!(accept(0, &envelopes[1]), Some(parts.concat()));
+        // A lone fragment is its payload.
+        assert_eq!(accept(0, &envelope(2, 0, 1, &parts[0])), Some(parts[0].clone()));
+        // The id coming round with another count starts over.
+        assert_eq!(accept(1, &envelope(1, 0, 2, &parts[1])), None);
+        assert_eq!(accept(1, &envelope(1, 1, 2, &parts[2])), Some([&parts[1][..], &parts[2]].concat()));
+    }
+
+    #[test]
+    fn incomplete_payloads_expire_and_are_bounded() {
+        let mut reassembly = Reassembly::new();
+        let start = Instant::now();
+        let first = envelope(0, 0, 2, &payload(10));
+        reassembly.accept(0, Fragment::open(&first).unwrap(), start);
+        assert_eq!(reassembly.expire(start + REASSEMBLY_TIMEOUT - Duration::from_millis(1)), 0);
+        assert_eq!(reassembly.expire(start + REASSEMBLY_TIMEOUT), 1);
+        // Its other half, arriving late, starts a new wait rather than completing it.
+        let second = envelope(0, 1, 2, &payload(10));
+        assert_eq!(reassembly.accept(0, Fragment::open(&second).unwrap(), start + REASSEMBLY_TIMEOUT), None);
+
+        let mut reassembly = Reassembly::new();
+        for id in 0..MAX_PARTIAL as u32 + 10 {
+            let bytes = envelope(id, 0, 2, &payload(10));
+            reassembly.accept(0, Fragment::open(&bytes).unwrap(), start + Duration::from_millis(id.into()));
+        }
+        assert_eq!(reassembly.expire(start + Duration::from_secs(1)), 11);
+        assert_eq!(reassembly.partial.len(), MAX_PARTIAL - 1);
+        // The oldest went first.
+        assert!((0..11).all(|id| !reassembly.partial.contains_key(&(0, id))));
+    }
+}
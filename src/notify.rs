@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+/// Reports to systemd over `$NOTIFY_SOCKET` (`Type=notify` units): `READY=1` once the
+/// tunnel is up, then a `STATUS=` line per connection state change, which
+/// `systemctl status` shows. Without the variable (not under systemd) it does nothing.
+pub struct Notifier {
+    socket: Option<PathBuf>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        Self { socket: std::env::var_os("NOTIFY_SOCKET").filter(|s| !s.is_empty()).map(PathBuf::from) }
+    }
+
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    /// Best effort: a service manager that stopped listening is no reason to fail.
+    #[cfg(unix)]
+    fn send(&self, message: &str) {
+        use std::os::unix::net::UnixDatagram;
+        let Some(path) = &self.socket else { return };
+        let Ok(sock) = UnixDatagram::unbound() else { return };
+        // A leading '@' names a socket in Linux's abstract namespace.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|p| p.strip_prefix('@')) {
+            use std::os::linux::net::SocketAddrExt;
+            if let Ok(addr) = std::os::unix::net::SocketAddr::from_abstract_name(name) {
+                let _ = sock.send_to_addr(message.as_bytes(), &addr);
+            }
+            return;
+        }
+        let _ = sock.send_to(message.as_bytes(), path);
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _message: &str) {}
+}
@@ -1,22 +1,429 @@
-use tokio::time::{sleep, Duration};
-use rand::Rng;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Instant;
+use parking_lot::Mutex;
+use tokio::time::Duration;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-/// Introduces stochastic timing delays (jitter) to packet transmission.
+/// How much timing obfuscation the TX path applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Intensity {
+    /// No added delay.
+    Off,
+    /// Up to 3ms: blurs IAT fingerprints at a fraction of the latency cost.
+    Light,
+    /// Up to 15ms.
+    Full,
+}
+
+impl Intensity {
+    /// One step less obfuscation (saturating at `Off`).
+    fn relaxed(self) -> Self {
+        match self {
+            Intensity::Full => Intensity::Light,
+            _ => Intensity::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Intensity::Off => "off",
+            Intensity::Light => "light",
+            Intensity::Full => "full",
+        }
+    }
+
+    pub fn load(cell: &AtomicU8) -> Self {
+        match cell.load(Ordering::Relaxed) {
+            0 => Intensity::Off,
+            1 => Intensity::Light,
+            _ => Intensity::Full,
+        }
+    }
+
+    pub fn store(self, cell: &AtomicU8) {
+        cell.store(self as u8, Ordering::Relaxed);
+    }
+}
+
+/// The delays jitter draws from at `Full` intensity (`--jitter <min>-<max>ms`, 0-15ms
+/// unless set). `Light` draws from a fifth of it, `Off` adds nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterRange {
+    min_micros: u64,
+    max_micros: u64,
+}
+
+impl JitterRange {
+    pub const DEFAULT: Self = Self { min_micros: 0, max_micros: 15_000 };
+
+    /// Bounds in microseconds at `intensity`.
+    fn at(self, intensity: Intensity) -> (u64, u64) {
+        match intensity {
+            Intensity::Off => (0, 0),
+            Intensity::Light => (self.min_micros / 5, self.max_micros / 5),
+            Intensity::Full => (self.min_micros, self.max_micros),
+        }
+    }
+
+    /// The range at `intensity`, e.g. `0-15ms jitter`.
+    pub fn label(self, intensity: Intensity) -> String {
+        match self.at(intensity) {
+            (_, 0) => "no jitter".to_string(),
+            (min, max) => format!("{}-{}ms jitter", min as f64 / 1000.0, max as f64 / 1000.0),
+        }
+    }
+
+    /// The range as `--jitter` takes it, e.g. `2-8ms`.
+    pub fn spec(self) -> String {
+        format!("{}-{}ms", self.min_micros / 1000, self.max_micros / 1000)
+    }
+
+    pub fn min_ms(self, intensity: Intensity) -> u64 {
+        self.at(intensity).0 / 1000
+    }
+
+    pub fn max_ms(self, intensity: Intensity) -> u64 {
+        self.at(intensity).1 / 1000
+    }
+}
+
+/// `--jitter` and `set-jitter`: `off`, or the range to draw from, e.g. `2-8ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitterSetting {
+    Off,
+    Range(JitterRange),
+}
+
+impl JitterSetting {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s == "off" {
+            return Ok(JitterSetting::Off);
+        }
+        let (min, max) = s
+            .strip_suffix("ms")
+            .and_then(|r| r.split_once('-'))
+            .ok_or_else(|| format!("'{}' is neither 'off' nor a range like 2-8ms", s))?;
+        let ms = |v: &str| v.trim().parse::<u64>().map_err(|_| format!("'{}' is not a whole number of milliseconds", v));
+        let (min, max) = (ms(min)?, ms(max)?);
+        if min > max {
+            return Err(format!("jitter range {}-{}ms runs backwards", min, max));
+        }
+        if max > 1000 {
+            return Err("jitter above 1000ms would stall the tunnel".to_string());
+        }
+        Ok(JitterSetting::Range(JitterRange { min_micros: min * 1000, max_micros: max * 1000 }))
+    }
+}
+
+/// `--jitter-below`: jitter only while the packet rate stays under a threshold.
+///
+/// Jitter's value is in hiding the timing of sparse, beacon-like traffic; on a bulk
+/// transfer it mostly costs throughput. The rate is counted over one-second windows
+/// and each window decides for the next, so a burst switches jitter off a second late
+/// and an idle link gets it back with its first packet.
+pub struct BulkGate {
+    threshold: u32,
+    /// Start of the current window and the packets counted in it.
+    window: Mutex<(Instant, u32)>,
+    bulk: AtomicBool,
+}
+
+impl BulkGate {
+    pub fn new(threshold: u32) -> Self {
+        Self { threshold, window: Mutex::new((Instant::now(), 0)), bulk: AtomicBool::new(false) }
+    }
+
+    /// Counts one outgoing packet. True while the last full window ran above the threshold.
+    pub fn bulk(&self, now: Instant) -> bool {
+        let mut window = self.window.lock();
+        window.1 += 1;
+        let elapsed = now.duration_since(window.0).as_secs_f64();
+        if elapsed >= 1.0 {
+            self.bulk.store(window.1 as f64 / elapsed > self.threshold as f64, Ordering::Relaxed);
+            *window = (now, 0);
+        }
+        self.bulk.load(Ordering::Relaxed)
+    }
+
+    /// What the last window decided, without counting a packet.
+    pub fn is_bulk(&self) -> bool {
+        self.bulk.load(Ordering::Relaxed)
+    }
+}
+
+/// `--obfs`: a fixed intensity, or `adaptive` to let link quality drive it.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ObfsMode {
+    Off,
+    Light,
+    Full,
+    /// Start at full; relax while the link is clean, snap back to full if it goes dark.
+    Adaptive,
+}
+
+impl ObfsMode {
+    /// Intensity at startup.
+    pub fn initial(self) -> Intensity {
+        match self {
+            ObfsMode::Off => Intensity::Off,
+            ObfsMode::Light => Intensity::Light,
+            ObfsMode::Full | ObfsMode::Adaptive => Intensity::Full,
+        }
+    }
+}
+
+/// Tunables of the adaptive policy.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptivePolicy {
+    /// Clean traffic needed before each one-step relaxation.
+    pub relax_after: Duration,
+    /// Highest loss rate that still counts as clean.
+    pub max_loss: f64,
+    /// Sending with no reply (ACK or authenticated frame) for this long, after the link
+    /// had worked, reads as blocking rather than congestion.
+    pub blackout: Duration,
+}
+
+/// What the adapter sees of the link on each tick.
+#[derive(Debug, Clone, Copy)]
+pub struct LinkSample {
+    /// Data frames sent since the previous sample.
+    pub sent: u64,
+    /// ACKs received since the previous sample.
+    pub acked: u64,
+    pub loss_rate: Option<f64>,
+    /// Time since the last frame that passed AEAD verification.
+    pub auth_age: Option<Duration>,
+}
+
+/// Adaptive obfuscation policy.
+///
+/// Relaxation is slow and stepwise: each step needs `relax_after` of uninterrupted
+/// traffic with loss at or below `max_loss`. Ordinary loss only restarts that clock,
+/// since congestion is no reason to add delay. Escalation is immediate and straight to
+/// `Full`, and only on the DPI signature: a link that carried traffic, then went
+/// completely silent while we kept sending.
+pub struct Adapter {
+    policy: AdaptivePolicy,
+    level: Intensity,
+    clean_since: Option<Instant>,
+    /// Last sign of life from the peer.
+    last_heard: Option<Instant>,
+    /// The peer has acknowledged traffic since the last escalation.
+    worked: bool,
+}
+
+impl Adapter {
+    pub fn new(policy: AdaptivePolicy, level: Intensity) -> Self {
+        Self { policy, level, clean_since: None, last_heard: None, worked: false }
+    }
+
+    /// Feeds one sample. Returns the new intensity and why, if it changed.
+    pub fn observe(&mut self, sample: LinkSample, now: Instant) -> Option<(Intensity, &'static str)> {
+        let authed_at = sample.auth_age.and_then(|age| now.checked_sub(age));
+        let acked_at = (sample.acked > 0).then_some(now);
+        self.last_heard = self.last_heard.max(authed_at).max(acked_at);
+
+        let silent = self.last_heard.is_none_or(|t| now.duration_since(t) >= self.policy.blackout);
+        if self.worked && sample.sent > 0 && silent {
+            self.worked = false;
+            self.clean_since = None;
+            return self.change(Intensity::Full, "link went silent after working");
+        }
+        if sample.acked > 0 {
+            self.worked = true;
+        }
+
+        let clean = sample.sent > 0 && sample.acked > 0 && sample.loss_rate.is_some_and(|l| l <= self.policy.max_loss);
+        if !clean {
+            self.clean_since = None;
+            return None;
+        }
+        let since = *self.clean_since.get_or_insert(now);
+        if now.duration_since(since) >= self.policy.relax_after {
+            self.clean_since = Some(now);
+            return self.change(self.level.relaxed(), "link clean");
+        }
+        None
+    }
+
+    fn change(&mut self, level: Intensity, reason: &'static str) -> Option<(Intensity, &'static str)> {
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some((level, reason))
+    }
+}
+
+/// What the receive path is left with once the obfuscation has seen a datagram.
+pub enum Unwrapped {
+    /// A frame, moved to the front of the datagram: this long.
+    Frame(usize),
+    /// The obfuscation's own traffic (a decoy handshake), ending here. `reply` goes
+    /// back to the sender as it is, not wrapped, after `reply_delay`.
+    Decoy { reply: Option<Vec<u8>> },
+    /// Not dressed the way this end dresses its datagrams: dropped, with why.
+    Foreign(&'static str),
+}
+
+/// One layer of obfuscation between the frame codec and the socket.
+///
+/// The send path asks it for a delay before each data frame (`pacing_delay`), dresses
+/// every frame as the datagram that goes out (`wrap_outgoing`), and sends `preamble`
+/// ahead of each Hello. The receive path undresses every datagram before decoding
+/// (`unwrap_incoming`), and answers a decoy handshake after `reply_delay`. Each hook defaults to doing nothing, so a layer only
+/// implements what it changes. Layers are combined with `Stack`.
+pub trait Obfuscator: Send + Sync {
+    /// Datagrams sent, unwrapped, ahead of each Hello.
+    fn preamble(&self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+
+    /// The datagram that carries `frame`.
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        Cow::Borrowed(frame)
+    }
+
+    /// Length of `wrap_outgoing`'s output for a frame of `frame_len` bytes.
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        frame_len
+    }
+
+    /// Undoes `wrap_outgoing` in place, on a datagram that came `from` there.
+    fn unwrap_incoming(&self, datagram: &mut [u8], _from: SocketAddr) -> Unwrapped {
+        Unwrapped::Frame(datagram.len())
+    }
+
+    /// How long to hold the next data frame back, if at all.
+    fn pacing_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// How long to wait before answering a decoy handshake, as a real server takes a
+    /// moment to produce its reply.
+    fn reply_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Largest frame one datagram can carry, if the dressing caps it. Larger data
+    /// payloads are split to fit (`morph::Fitter`); other frames must fit as they are.
+    fn max_frame(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the wrapping relies on datagram boundaries, which a stream transport
+    /// doesn't keep.
+    fn needs_datagrams(&self) -> bool {
+        false
+    }
+}
+
+/// No obfuscation: frames go out as they are, without delay.
+pub struct Null;
+
+impl Obfuscator for Null {}
+
+/// Timing jitter (`--obfs`, `--jitter`): a random delay before each data frame.
+///
+/// Shares its state with the control paths: `toggle-jitter` and `set-jitter` switch it
+/// and set its range, the adapter moves its intensity, and `--jitter-below` has it
+/// stand aside on bulk flows.
+pub struct Jitter {
+    pub on: Arc<AtomicBool>,
+    pub range: Arc<Mutex<JitterRange>>,
+    /// `Intensity`, as `Intensity::store` leaves it.
+    pub intensity: Arc<AtomicU8>,
+    pub gate: Option<Arc<BulkGate>>,
+    pub rng: Arc<Mutex<StdRng>>,
+}
+
+impl Obfuscator for Jitter {
+    fn pacing_delay(&self) -> Option<Duration> {
+        // Only counted towards the gate while jitter is on.
+        if !self.on.load(Ordering::Relaxed) || self.gate.as_ref().is_some_and(|gate| gate.bulk(Instant::now())) {
+            return None;
+        }
+        let range = *self.range.lock();
+        let delay = jitter_delay(Intensity::load(&self.intensity), range, &mut *self.rng.lock());
+        (!delay.is_zero()).then_some(delay)
+    }
+}
+
+/// Layers applied in turn: the first wraps innermost and unwraps last, and delays add up.
+pub struct Stack(pub Vec<Box<dyn Obfuscator>>);
+
+impl Obfuscator for Stack {
+    fn preamble(&self) -> Vec<Vec<u8>> {
+        self.0.iter().flat_map(|layer| layer.preamble()).collect()
+    }
+
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        self.0.iter().fold(Cow::Borrowed(frame), |datagram, layer| match datagram {
+            Cow::Borrowed(datagram) => layer.wrap_outgoing(datagram),
+            Cow::Owned(datagram) => Cow::Owned(layer.wrap_outgoing(&datagram).into_owned()),
+        })
+    }
+
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        self.0.iter().fold(frame_len, |len, layer| layer.wrapped_len(len))
+    }
+
+    fn unwrap_incoming(&self, datagram: &mut [u8], from: SocketAddr) -> Unwrapped {
+        let mut len = datagram.len();
+        for layer in self.0.iter().rev() {
+            match layer.unwrap_incoming(&mut datagram[..len], from) {
+                Unwrapped::Frame(inner) => len = inner,
+                other => return other,
+            }
+        }
+        Unwrapped::Frame(len)
+    }
+
+    fn pacing_delay(&self) -> Option<Duration> {
+        self.0.iter().filter_map(|layer| layer.pacing_delay()).reduce(|total, delay| total + delay)
+    }
+
+    fn reply_delay(&self) -> Option<Duration> {
+        self.0.iter().filter_map(|layer| layer.reply_delay()).reduce(|total, delay| total + delay)
+    }
+
+    fn max_frame(&self) -> Option<usize> {
+        self.0.iter().filter_map(|layer| layer.max_frame()).min()
+    }
+
+    fn needs_datagrams(&self) -> bool {
+        self.0.iter().any(|layer| layer.needs_datagrams())
+    }
+}
+
+/// RNG behind every non-cryptographic random choice (jitter, ClientHello mimicry, punch
+/// timing). `--seed` makes it deterministic so runs can be replayed; otherwise it is
+/// seeded from the OS. Key material and nonces never come from here.
+pub fn traffic_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Picks a stochastic timing delay (jitter) for the next packet.
 /// 
 /// **Mitigating Traffic Analysis**:
 /// Statistical analysis of Inter-Arrival Times (IAT) can distinguish between automated beacons and human traffic.
 /// We introduce random variation to flatten the IAT distribution, reducing the confidence of classifier models.
-pub async fn jitter_sleep() {
-    let micros = {
-        let mut rng = rand::thread_rng();
-        // 0-15ms represents a trade-off between obfuscation effectiveness and latency overhead.
-        // This is within the standard variation of cellular networks.
-        rng.gen_range(0..15_000)
-    };
-    
-    if micros > 0 {
-        sleep(Duration::from_micros(micros)).await;
+pub fn jitter_delay(intensity: Intensity, range: JitterRange, rng: &mut impl Rng) -> Duration {
+    let (min, max) = range.at(intensity);
+    if max == 0 {
+        return Duration::ZERO;
     }
+    // 0-15ms (full) represents a trade-off between obfuscation effectiveness and latency overhead.
+    // This is within the standard variation of cellular networks.
+    Duration::from_micros(rng.gen_range(min..=max))
 }
 
 /// Generates a synthetic payload resembling the start of a TLS handshake.
@@ -25,8 +432,7 @@ pub async fn jitter_sleep() {
 /// State-managed firewalls and DPI systems often drop unidentified UDP datagrams.
 /// By emitting a sequence matching the TLS 1.0 ClientHello header structure (0x16, 0x03, 0x01),
 /// we exploit "Fast-Path/Slow-Path" processing where inspection logic approves the flow based on the initial signature.
-pub fn mimic_tls_client_hello() -> Vec<u8> {
-    let mut rng = rand::thread_rng();
+pub fn mimic_tls_client_hello(rng: &mut impl Rng) -> Vec<u8> {
     let mut packet = vec![
         0x16,       // ContentType: Handshake
         0x03, 0x01  // Version: TLS 1.0 (Widely permitted for backward compatibility)
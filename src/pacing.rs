@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+/// Waits shorter than this are not slept: the timer can't resolve them. Packets then
+/// go out back to back until the schedule is this far ahead, so bursts stay within
+/// about a millisecond of sending.
+const TIMER_GRANULARITY: Duration = Duration::from_millis(1);
+
+/// Spreads data frames across the round trip (`--pacing`).
+///
+/// A full window sent back to back arrives at the bottleneck as one burst, which a
+/// shallow buffer drops. Paced, the window goes out one frame every `srtt / window`,
+/// so the offered rate is what the window allows per RTT but without the burst. One
+/// schedule is shared by every TX task and by retransmissions, so the rate is for the
+/// whole tunnel.
+pub struct Pacer {
+    enabled: AtomicBool,
+    /// Frames the ARQ window holds.
+    window: usize,
+    /// Earliest time the next frame may go out.
+    next_send: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn new(enabled: bool, window: usize) -> Self {
+        Self { enabled: AtomicBool::new(enabled), window: window.max(1), next_send: Mutex::new(Instant::now()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Switches pacing on or off, returning the new state.
+    pub fn toggle(&self) -> bool {
+        !self.enabled.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Gap between frames at this RTT. `None` while pacing is off or no RTT has been measured.
+    pub fn interval(&self, srtt: Option<Duration>) -> Option<Duration> {
+        if !self.enabled() {
+            return None;
+        }
+        srtt.filter(|s| !s.is_zero()).map(|s| s / self.window as u32)
+    }
+
+    /// Frames per second the schedule allows, for telemetry.
+    pub fn rate(&self, srtt: Option<Duration>) -> Option<f64> {
+        self.interval(srtt).map(|gap| 1.0 / gap.as_secs_f64().max(f64::EPSILON))
+    }
+
+    /// Books the next send slot and returns how long to wait for it (zero: send now).
+    /// Idle time earns no credit, so a quiet sender can't burst when traffic resumes.
+    pub fn reserve(&self, srtt: Option<Duration>) -> Duration {
+        let Some(gap) = self.interval(srtt) else { return Duration::ZERO };
+        let now = Instant::now();
+        let mut next_send = self.next_send.lock();
+        let slot = (*next_send).max(now);
+        *next_send = slot + gap;
+        let wait = slot - now;
+        if wait < TIMER_GRANULARITY { Duration::ZERO } else { wait }
+    }
+}
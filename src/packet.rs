@@ -0,0 +1,181 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Length of the packet-information prefix the kernel adds to TUN frames
+/// (`flags: u16, proto: u16`) when `packet_information(true)` is configured.
+#[cfg(target_os = "linux")]
+const PI_LEN: usize = 4;
+
+/// Returns the raw IP packet inside a TUN frame.
+///
+/// On Linux the device is opened with packet information enabled, so every frame
+/// carries a 4-byte header ahead of the IP packet; elsewhere frames are passed through.
+pub fn inner_ip(frame: &[u8]) -> &[u8] {
+    #[cfg(target_os = "linux")]
+    {
+        frame.get(PI_LEN..).unwrap_or(&[])
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        frame
+    }
+}
+
+/// Largest TUN frame an interface with this MTU hands us (the packet plus any prefix).
+pub fn max_frame_len(mtu: usize) -> usize {
+    #[cfg(target_os = "linux")]
+    {
+        mtu + PI_LEN
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        mtu
+    }
+}
+
+/// Mutable counterpart of `inner_ip`.
+pub fn inner_ip_mut(frame: &mut [u8]) -> &mut [u8] {
+    #[cfg(target_os = "linux")]
+    {
+        frame.get_mut(PI_LEN..).unwrap_or(&mut [])
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        frame
+    }
+}
+
+/// Whether a decoded TUN frame is plausible enough to hand to the kernel:
+/// a non-empty IP packet no larger than the interface MTU.
+pub fn plausible_frame(frame: &[u8], mtu: usize) -> bool {
+    let ip = inner_ip(frame);
+    !ip.is_empty() && ip.len() <= mtu
+}
+
+const PROTO_UDP: u8 = 17;
+const IPV4_MIN_HEADER: usize = 20;
+const IPV6_HEADER: usize = 40;
+const UDP_HEADER: usize = 8;
+
+/// Addressing of an inner UDP datagram, read straight from its IP and UDP headers.
+///
+/// Deliberately minimal: IPv6 extension headers and IPv4 fragments (other than a
+/// complete, unfragmented datagram) are not followed, so such packets don't parse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UdpHeader {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// Where the UDP header starts within the IP packet.
+    offset: usize,
+}
+
+impl UdpHeader {
+    pub fn parse(ip: &[u8]) -> Option<Self> {
+        let (src, dst, offset) = match ip.first()? >> 4 {
+            4 => {
+                let header_len = usize::from(ip[0] & 0x0f) * 4;
+                let fragmented = u16::from_be_bytes([ip.get(6)? & 0x3f, *ip.get(7)?]) != 0;
+                if header_len < IPV4_MIN_HEADER || ip.len() < header_len || ip[9] != PROTO_UDP || fragmented {
+                    return None;
+                }
+                let addr = |at: usize| IpAddr::V4(Ipv4Addr::new(ip[at], ip[at + 1], ip[at + 2], ip[at + 3]));
+                (addr(12), addr(16), header_len)
+            }
+            6 => {
+                if ip.len() < IPV6_HEADER || ip[6] != PROTO_UDP {
+                    return None;
+                }
+                let addr = |at: usize| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&ip[at..at + 16]).unwrap()));
+                (addr(8), addr(24), IPV6_HEADER)
+            }
+            _ => return None,
+        };
+        let udp = ip.get(offset..offset + UDP_HEADER)?;
+        let header = Self {
+            src,
+            dst,
+            src_port: u16::from_be_bytes([udp[0], udp[1]]),
+            dst_port: u16::from_be_bytes([udp[2], udp[3]]),
+            offset,
+        };
+        // The UDP length must fit what we have, or the checksum can't be redone.
+        let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+        (udp_len >= UDP_HEADER && offset + udp_len <= ip.len()).then_some(header)
+    }
+
+    /// Whether the datagram is addressed to `peer`: read from TUN, that is one of the
+    /// tunnel's own outer datagrams routed back into it.
+    pub fn addressed_to(&self, peer: SocketAddr) -> bool {
+        self.dst == peer.ip().to_canonical() && self.dst_port == peer.port()
+    }
+
+    /// Rewrites the source address of `ip` (the packet this was parsed from) and
+    /// fixes both checksums. False, leaving the packet alone, if `addr` is of the
+    /// other IP family.
+    pub fn set_src(&self, ip: &mut [u8], addr: IpAddr) -> bool {
+        self.set_addr(ip, addr, true)
+    }
+
+    /// Like `set_src`, for the destination address.
+    pub fn set_dst(&self, ip: &mut [u8], addr: IpAddr) -> bool {
+        self.set_addr(ip, addr, false)
+    }
+
+    fn set_addr(&self, ip: &mut [u8], addr: IpAddr, source: bool) -> bool {
+        match addr {
+            IpAddr::V4(v4) if self.src.is_ipv4() => {
+                let at = if source { 12 } else { 16 };
+                ip[at..at + 4].copy_from_slice(&v4.octets());
+                ip[10..12].fill(0);
+                let sum = checksum(0, &ip[..self.offset]);
+                ip[10..12].copy_from_slice(&sum.to_be_bytes());
+            }
+            IpAddr::V6(v6) if self.src.is_ipv6() => {
+                let at = if source { 8 } else { 24 };
+                ip[at..at + 16].copy_from_slice(&v6.octets());
+            }
+            _ => return false,
+        }
+        self.fix_udp_checksum(ip);
+        true
+    }
+
+    fn fix_udp_checksum(&self, ip: &mut [u8]) {
+        let at = self.offset;
+        let udp_len = usize::from(u16::from_be_bytes([ip[at + 4], ip[at + 5]]));
+        // Zero means "no checksum", which only IPv4 allows; keep it that way.
+        if self.src.is_ipv4() && ip[at + 6..at + 8] == [0, 0] {
+            return;
+        }
+        ip[at + 6..at + 8].fill(0);
+        let addrs = if self.src.is_ipv4() { &ip[12..20] } else { &ip[8..40] };
+        let pseudo = sum_words(sum_words(0, addrs), &[0, PROTO_UDP]) + udp_len as u32;
+        let sum = match checksum(pseudo, &ip[at..at + udp_len]) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        ip[at + 6..at + 8].copy_from_slice(&sum.to_be_bytes());
+    }
+}
+
+/// One's-complement sum of big-endian 16-bit words (odd length padded with zero).
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u32::from(u16::from_be_bytes([word[0], word[1]]));
+    }
+    if let [last] = words.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+/// Internet checksum (RFC 1071) of `data`, starting from a partial `sum`.
+fn checksum(sum: u32, data: &[u8]) -> u16 {
+    let mut sum = sum_words(sum, data);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
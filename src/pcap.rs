@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+
+use crate::logging::{tlog, Level};
+use crate::telemetry::TelemetrySender;
+
+/// Classic libpcap file header constants.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const SNAPLEN: u32 = 65_535;
+/// LINKTYPE_RAW: packets begin directly with an IPv4/IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// Packets queued for the writer thread before the hot path starts dropping them.
+const QUEUE_DEPTH: usize = 1024;
+/// The writer flushes at least this often so the file is readable while running.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum CaptureMsg {
+    Packet { ts: SystemTime, data: Vec<u8> },
+    Close,
+}
+
+/// Handle for capturing inner packets to a pcap file (`--pcap`).
+///
+/// **Hot Path Safety**: `capture` only copies the packet into a bounded queue; all file
+/// I/O happens on a dedicated thread. If the disk can't keep up, packets are dropped
+/// from the capture (never from the tunnel).
+#[derive(Clone)]
+pub struct PcapTap {
+    tx: mpsc::SyncSender<CaptureMsg>,
+}
+
+impl PcapTap {
+    /// Creates the file and starts the writer thread. Writing stops once the file
+    /// reaches `max_bytes`.
+    pub fn create(path: &Path, max_bytes: u64, stats: TelemetrySender) -> Result<(Self, thread::JoinHandle<()>)> {
+        let file = File::create(path).with_context(|| format!("Failed to create pcap file {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        // Global header: magic, version 2.4, thiszone, sigfigs, snaplen, network
+        out.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        out.flush()?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        let display = path.display().to_string();
+        let handle = thread::spawn(move || writer_loop(out, rx, max_bytes, &display, &stats));
+        Ok((Self { tx }, handle))
+    }
+
+    /// Queues a raw IP packet for capture. Never blocks.
+    pub fn capture(&self, ip_packet: &[u8]) {
+        if ip_packet.is_empty() {
+            return;
+        }
+        let _ = self.tx.try_send(CaptureMsg::Packet { ts: SystemTime::now(), data: ip_packet.to_vec() });
+    }
+
+    /// Asks the writer to flush and close the file. Queued packets are written first.
+    pub fn close(&self) {
+        let _ = self.tx.send(CaptureMsg::Close);
+    }
+}
+
+fn writer_loop(
+    mut out: BufWriter<File>,
+    rx: mpsc::Receiver<CaptureMsg>,
+    max_bytes: u64,
+    path: &str,
+    stats: &TelemetrySender,
+) {
+    let mut written: u64 = 24; // Global header
+    let mut capped = false;
+
+    loop {
+        let msg = match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let _ = out.flush();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        let (ts, data) = match msg {
+            CaptureMsg::Packet { ts, data } => (ts, data),
+            CaptureMsg::Close => break,
+        };
+
+        let record_len = 16 + data.len() as u64;
+        if capped {
+            continue;
+        }
+        if written + record_len > max_bytes {
+            capped = true;
+            tlog!(stats, Level::Warn, "PCAP: Size cap reached, capture to {} stopped", path);
+            continue;
+        }
+
+        let since_epoch = ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let caplen = data.len().min(SNAPLEN as usize);
+        let record = out.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())
+            .and_then(|_| out.write_all(&since_epoch.subsec_micros().to_le_bytes()))
+            .and_then(|_| out.write_all(&(caplen as u32).to_le_bytes()))
+            .and_then(|_| out.write_all(&(data.len() as u32).to_le_bytes()))
+            .and_then(|_| out.write_all(&data[..caplen]));
+
+        if let Err(e) = record {
+            tlog!(stats, Level::Error, "PCAP::WriteErr: {}", e);
+            capped = true;
+            continue;
+        }
+        written += record_len;
+    }
+
+    let _ = out.flush();
+}
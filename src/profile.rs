@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::bench::{BenchOptions, BenchReport, Payload};
+use crate::crypto::CipherSuite;
+use crate::units::{format_rate, RateUnit};
+
+/// Options for `profile` mode: measure the link to `--peer` and suggest settings.
+///
+/// The far end runs `bench` (the receiving side) for at least as long as the profile,
+/// so the synthetic traffic lands in its sink rather than a real TUN device.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ProfileOptions {
+    /// Seconds of full-rate traffic for the throughput, RTT and loss measurement
+    #[arg(long, default_value_t = 10)] pub duration: u64,
+
+    /// Seconds of traffic with full timing obfuscation, to measure what it costs (0 skips it)
+    #[arg(long, default_value_t = 5)] pub obfs_duration: u64,
+
+    /// Seconds to wait for the handshake before giving up
+    #[arg(long, default_value_t = 30)] pub handshake_timeout: u64,
+}
+
+impl ProfileOptions {
+    /// The bench pipeline the measurements run on: full-size packets, as fast as the window allows.
+    pub fn bench(&self) -> BenchOptions {
+        BenchOptions { send: true, duration: self.duration, size: 1200, jitter: false, payload: Payload::Random }
+    }
+}
+
+/// Loss above which per-direction loss echo is worth its extra frames.
+const ECHO_LOSS_ABOVE: f64 = 0.01;
+/// Alert thresholds sit this far above the measured RTT and loss.
+const ALERT_HEADROOM: f64 = 3.0;
+/// Lowest suggested `--alert-rtt` (ms) and `--alert-loss` (%), so a quiet LAN doesn't alert on noise.
+const ALERT_RTT_FLOOR_MS: f64 = 50.0;
+const ALERT_LOSS_FLOOR_PCT: f64 = 1.0;
+/// Full jitter keeping less than this share of the clear throughput is worth relaxing.
+const OBFS_COSTLY_BELOW: f64 = 0.8;
+/// Default `--handshake-attempts`; a link that needed close to it gets more.
+const DEFAULT_HANDSHAKE_ATTEMPTS: u64 = 10;
+
+/// Everything measured by one profile run.
+pub struct Findings {
+    pub peer: SocketAddr,
+    /// Time from the first Hello to an authenticated reply (`None`: never answered).
+    pub established_after: Option<Duration>,
+    pub hellos: u64,
+    /// Why the handshake gave up, if it did.
+    pub failure: Option<String>,
+    pub cipher: Option<CipherSuite>,
+    /// The kernel's path MTU towards the peer: the route MTU, or less if ICMP taught it otherwise.
+    pub path_mtu: Option<usize>,
+    /// Largest datagram the tunnel sends (inner MTU plus framing, AEAD and IP/UDP headers).
+    pub max_datagram: usize,
+    /// Full-rate traffic without timing jitter.
+    pub clear: Option<BenchReport>,
+    /// The same with `--obfs full` jitter.
+    pub obfuscated: Option<BenchReport>,
+}
+
+/// One suggested flag and the measurement behind it.
+struct Suggestion {
+    flag: String,
+    why: String,
+}
+
+impl Findings {
+    /// Measurements, then the suggested flags as a snippet to paste into the command line.
+    pub fn report(&self, unit: RateUnit) -> String {
+        let mut out = format!("RESILINET PROFILE {}\n", self.peer);
+        for (name, value) in self.rows(unit) {
+            out.push_str(&format!("  {:<22}{}\n", name, value));
+        }
+        out.push('\n');
+        for note in self.notes() {
+            out.push_str(&format!("note: {}\n", note));
+        }
+
+        let suggestions = self.suggestions();
+        if self.established_after.is_none() {
+            return out;
+        }
+        out.push_str("# Suggested flags for this end (defaults are kept where they fit):\n");
+        if suggestions.is_empty() {
+            out.push_str("# none: the defaults suit this link\n");
+        }
+        let width = suggestions.iter().map(|s| s.flag.len()).max().unwrap_or(0);
+        for s in suggestions {
+            out.push_str(&format!("{:<width$}  # {}\n", s.flag, s.why, width = width));
+        }
+        out
+    }
+
+    fn rows(&self, unit: RateUnit) -> Vec<(&'static str, String)> {
+        let opt = |v: Option<f64>, unit: &str| v.map_or("-".to_string(), |v| format!("{:.2}{}", v, unit));
+        let mut rows = vec![
+            ("handshake", match self.established_after {
+                Some(d) => format!("{:.2}s ({} Hello{})", d.as_secs_f64(), self.hellos, if self.hellos == 1 { "" } else { "s" }),
+                None => format!("no reply after {} Hellos", self.hellos),
+            }),
+            ("cipher", self.cipher.map_or("-", |c| c.name()).to_string()),
+            ("path mtu", match self.path_mtu {
+                Some(mtu) => format!("{} (tunnel datagrams up to {})", mtu, self.max_datagram),
+                None => "unknown".to_string(),
+            }),
+        ];
+        if let Some(clear) = &self.clear {
+            rows.push(("throughput", format_rate(throughput(clear), unit)));
+            rows.push(("srtt", opt(clear.quality.srtt_ms, "ms")));
+            rows.push(("jitter", opt(clear.quality.jitter_ms, "ms")));
+            rows.push(("loss (arq)", opt(clear.quality.loss_rate.map(|l| l * 100.0), "%")));
+            rows.push(("retransmits", clear.counters.retransmits.to_string()));
+        }
+        if let (Some(clear), Some(obfs)) = (&self.clear, &self.obfuscated) {
+            rows.push(("throughput, obfs full", format!("{} ({:.0}% of clear)", format_rate(throughput(obfs), unit), obfs_share(clear, obfs) * 100.0)));
+            rows.push(("srtt, obfs full", opt(obfs.quality.srtt_ms, "ms")));
+        }
+        rows
+    }
+
+    /// Findings there is no flag for.
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if self.established_after.is_none() {
+            notes.push(match &self.failure {
+                Some(reason) => format!("the handshake failed ({}).", reason),
+                None => "the handshake did not complete in time.".to_string(),
+            });
+            notes.push("check that the far end runs `bench` on that address with the same --key, and that UDP to it is not filtered.".to_string());
+        }
+        if let Some(mtu) = self.path_mtu.filter(|mtu| *mtu < self.max_datagram) {
+            notes.push(format!(
+                "the path MTU ({}) is below the largest tunnel datagram ({}): full-size packets will be fragmented. The inner MTU is fixed in this build.",
+                mtu, self.max_datagram
+            ));
+        }
+        if self.blackholed() {
+            notes.push("data frames went unanswered after a successful handshake, the pattern DPI blocking produces.".to_string());
+        }
+        notes
+    }
+
+    fn suggestions(&self) -> Vec<Suggestion> {
+        let mut out = Vec::new();
+        if self.hellos * 2 > DEFAULT_HANDSHAKE_ATTEMPTS {
+            out.push(Suggestion {
+                flag: format!("--handshake-attempts {}", self.hellos * 3),
+                why: format!("the handshake took {} Hellos", self.hellos),
+            });
+        }
+        let Some(clear) = &self.clear else { return out };
+
+        if self.blackholed() {
+            out.push(Suggestion { flag: "--obfs full".to_string(), why: "keep full timing obfuscation: the link looks filtered".to_string() });
+            return out;
+        }
+        if let Some(obfs) = self.obfuscated.as_ref().filter(|obfs| obfs_share(clear, obfs) < OBFS_COSTLY_BELOW) {
+            out.push(Suggestion {
+                flag: "--obfs adaptive".to_string(),
+                why: format!(
+                    "full jitter keeps {:.0}% of the throughput; adaptive relaxes it on a clean link and returns to full if the link goes silent",
+                    obfs_share(clear, obfs) * 100.0
+                ),
+            });
+        }
+
+        let loss = clear.quality.loss_rate.unwrap_or(0.0);
+        if loss > ECHO_LOSS_ABOVE {
+            out.push(Suggestion {
+                flag: "--loss-echo".to_string(),
+                why: format!("{:.1}% loss: measure it per direction", loss * 100.0),
+            });
+        }
+        let loss_pct = loss * 100.0;
+        let why = if loss_pct * ALERT_HEADROOM > ALERT_LOSS_FLOOR_PCT {
+            format!("{:.0}x the measured loss of {:.2}%", ALERT_HEADROOM, loss_pct)
+        } else {
+            format!("the lowest useful threshold; measured loss was {:.2}%", loss_pct)
+        };
+        out.push(Suggestion { flag: format!("--alert-loss {:.0}", (loss_pct * ALERT_HEADROOM).max(ALERT_LOSS_FLOOR_PCT).ceil()), why });
+        if let Some(srtt) = clear.quality.srtt_ms {
+            let why = if srtt * ALERT_HEADROOM > ALERT_RTT_FLOOR_MS {
+                format!("{:.0}x the measured RTT of {:.1}ms", ALERT_HEADROOM, srtt)
+            } else {
+                format!("the lowest useful threshold; measured RTT was {:.1}ms", srtt)
+            };
+            out.push(Suggestion { flag: format!("--alert-rtt {:.0}", (srtt * ALERT_HEADROOM).max(ALERT_RTT_FLOOR_MS).ceil()), why });
+        }
+        out
+    }
+
+    /// Data went out during the clear phase and not a single ACK came back.
+    fn blackholed(&self) -> bool {
+        self.clear.as_ref().is_some_and(|c| c.counters.data_sent > 0 && c.counters.acks_received == 0)
+    }
+}
+
+/// Bytes per second the window let through.
+fn throughput(report: &BenchReport) -> f64 {
+    report.generated_bytes as f64 / report.elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Throughput with full jitter as a fraction of the clear run.
+fn obfs_share(clear: &BenchReport, obfs: &BenchReport) -> f64 {
+    let base = throughput(clear);
+    if base > 0.0 { (throughput(obfs) / base).min(1.0) } else { 0.0 }
+}
+
+/// The kernel's current path MTU estimate towards `peer` (Linux only).
+#[cfg(target_os = "linux")]
+pub fn path_mtu(peer: SocketAddr) -> Option<usize> {
+    use std::os::fd::AsRawFd;
+    let local: SocketAddr = if peer.is_ipv4() { "0.0.0.0:0".parse().ok()? } else { "[::]:0".parse().ok()? };
+    let socket = std::net::UdpSocket::bind(local).ok()?;
+    socket.connect(peer).ok()?;
+    let (level, name) = if peer.is_ipv4() { (libc::IPPROTO_IP, libc::IP_MTU) } else { (libc::IPPROTO_IPV6, libc::IPV6_MTU) };
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `mtu` and `len` are valid for writes and `len` holds the size of `mtu`.
+    let rc = unsafe { libc::getsockopt(socket.as_raw_fd(), level, name, (&mut mtu as *mut libc::c_int).cast(), &mut len) };
+    (rc == 0 && mtu > 0).then_some(mtu as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn path_mtu(_peer: SocketAddr) -> Option<usize> {
+    None
+}
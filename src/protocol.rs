@@ -1,4 +1,19 @@
 use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Result};
+
+use crate::compression::Algorithm;
+use crate::crypto::{CipherSuite, SessionGuard};
+
+/// Leading byte of every compact-encoded frame (high nibble: marker, low nibble: version).
+const COMPACT_MAGIC: u8 = 0xC1;
+
+/// Set in the type byte of compact and sealed frames when an interface id byte follows.
+const IFACE_FLAG: u8 = 0x80;
+
+/// Shortest datagram any wire format decodes: a compact frame with one-byte sequence
+/// and ACK numbers and no payload. Anything shorter is a runt, such as the empty or
+/// one-byte keepalives some NATs send.
+pub const MIN_FRAME_LEN: usize = 4;
 
 /// The type of frame traveling through the tunnel.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -11,6 +26,17 @@ pub enum FrameType {
     Handshake,
     /// Reliability Acknowledgment.
     Ack,
+    /// Periodic (encrypted) frame counters for end-to-end loss verification.
+    Stats,
+    /// A runtime request or notice between the ends (encrypted `PeerControl`). Only
+    /// sent to peers whose Hello shows they understand it (`Hello::compressing`).
+    Control,
+    /// Cover traffic (`--chaff`): sealed random filler, discarded once it authenticates.
+    /// Only sent to peers whose Hello shows they understand it (`Hello::chaff`).
+    Chaff,
+    /// A data frame under `--morph`: one fragment of a payload, padded (`morph::Envelope`).
+    /// Only sent to peers whose protocol version has `Feature::Morphing`.
+    Morphed,
 }
 
 /// The headers for our Ghost Protocol (Wire Format).
@@ -28,6 +54,11 @@ pub struct FrameHeader {
     pub ack_num: u64,
     /// The type of payload.
     pub frame_type: FrameType,
+    /// Local interface a Transport frame belongs to: its position among `--tun`, 0 with
+    /// a single interface. Compact and sealed framing carry it; bincode has no room for
+    /// it, never serializes it, and always reads 0.
+    #[serde(skip)]
+    pub iface: u8,
 }
 
 /// The Atomic Unit of the Ghost Protocol.
@@ -40,18 +71,26 @@ pub struct WireFrame {
 }
 
 impl WireFrame {
-    /// Create a new data frame ready for the wire.
-    pub fn new_data(seq: u64, payload: Vec<u8>) -> Self {
+    /// Create a new data frame ready for the wire, for interface `iface`.
+    pub fn new_data(seq: u64, iface: u8, payload: Vec<u8>) -> Self {
         Self {
             header: FrameHeader {
                 seq,
                 ack_num: 0, // Piggybacking not implemented yet
                 frame_type: FrameType::Transport,
+                iface,
             },
             payload,
         }
     }
 
+    /// Create a morphed data frame, carrying an (encrypted) envelope.
+    pub fn new_morphed(seq: u64, iface: u8, payload: Vec<u8>) -> Self {
+        let mut frame = Self::new_data(seq, iface, payload);
+        frame.header.frame_type = FrameType::Morphed;
+        frame
+    }
+
     /// Create an ACK frame.
     pub fn new_ack(seq: u64, ack_num: u64) -> Self {
         Self {
@@ -59,11 +98,77 @@ impl WireFrame {
                 seq,
                 ack_num,
                 frame_type: FrameType::Ack,
+                iface: 0,
             },
             payload: vec![],
         }
     }
 
+    /// Create an ACK frame whose payload authenticates `ack_num` (`--auth-acks`): an empty
+    /// plaintext sealed with the number as associated data, so only a key holder can ACK.
+    pub fn new_signed_ack(seq: u64, ack_num: u64, guard: &SessionGuard) -> Result<Self> {
+        let mut frame = Self::new_ack(seq, ack_num);
+        frame.payload = guard.encrypt_bound(&[], &ack_aad(ack_num))?;
+        Ok(frame)
+    }
+
+    /// Whether this ACK's payload is a valid `new_signed_ack` tag over its `ack_num`.
+    pub fn ack_authentic(&self, guard: &SessionGuard) -> bool {
+        guard.decrypt_bound(&self.payload, &ack_aad(self.header.ack_num)).is_ok()
+    }
+
+    /// Create a handshake frame carrying an (encrypted) `Hello`.
+    pub fn new_handshake(seq: u64, payload: Vec<u8>) -> Self {
+        Self {
+            header: FrameHeader {
+                seq,
+                ack_num: 0,
+                frame_type: FrameType::Handshake,
+                iface: 0,
+            },
+            payload,
+        }
+    }
+
+    /// Create a stats frame carrying an (encrypted) `LossReport`.
+    pub fn new_stats(payload: Vec<u8>) -> Self {
+        Self {
+            header: FrameHeader {
+                seq: 0,
+                ack_num: 0,
+                frame_type: FrameType::Stats,
+                iface: 0,
+            },
+            payload,
+        }
+    }
+
+    /// Create a control frame carrying an (encrypted) `PeerControl`.
+    pub fn new_control(payload: Vec<u8>) -> Self {
+        Self {
+            header: FrameHeader {
+                seq: 0,
+                ack_num: 0,
+                frame_type: FrameType::Control,
+                iface: 0,
+            },
+            payload,
+        }
+    }
+
+    /// Create a chaff frame carrying (encrypted) filler.
+    pub fn new_chaff(payload: Vec<u8>) -> Self {
+        Self {
+            header: FrameHeader {
+                seq: 0,
+                ack_num: 0,
+                frame_type: FrameType::Chaff,
+                iface: 0,
+            },
+            payload,
+        }
+    }
+
     /// Create a heartbeat frame to keep middleboxes happy.
     pub fn new_heartbeat(seq: u64) -> Self {
         Self {
@@ -71,8 +176,653 @@ impl WireFrame {
                 seq,
                 ack_num: 0,
                 frame_type: FrameType::Heartbeat,
+                iface: 0,
             },
             payload: vec![],
         }
     }
 }
+
+/// On-the-wire encoding of a `WireFrame`.
+///
+/// Ordered by preference: later variants are preferred during negotiation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WireFormat {
+    /// Legacy serde/bincode framing (28-byte header). Understood by every release.
+    Bincode,
+    /// Hand-rolled framing: `[MAGIC (1B) | TYPE (1B) | SEQ (varint) | ACK (varint) | PAYLOAD]`,
+    /// with `IFACE (1B)` after the type byte for frames of interfaces other than 0.
+    Compact,
+    /// Compact framing sealed whole under the session key:
+    /// `[NONCE (12B) | AEAD(TYPE | SEQ | ACK | PAYLOAD) | TAG (16B)]`.
+    ///
+    /// Only the random nonce is in the clear, so an observer can't tell data from ACKs,
+    /// heartbeats or Hellos. The payload inside is not sealed a second time (see `seals_frame`).
+    Sealed,
+}
+
+impl WireFormat {
+    pub fn encode(self, frame: &WireFrame, guard: &SessionGuard) -> Result<Vec<u8>> {
+        match self {
+            WireFormat::Bincode if frame.header.iface != 0 => bail!("Protocol::BincodeHasNoInterface: {}", frame.header.iface),
+            WireFormat::Bincode => Ok(bincode::serialize(frame)?),
+            WireFormat::Compact => Ok(encode_compact(frame)),
+            WireFormat::Sealed => {
+                let mut body = Vec::with_capacity(20 + frame.payload.len());
+                put_body(&mut body, frame);
+                guard.encrypt(&body)
+            }
+        }
+    }
+
+    /// An ACK for `ack_num` in this format, the one the frame it acknowledges came in.
+    /// `signed` (`--auth-acks` on both ends) tags it, unless sealing covers it already.
+    pub fn encode_ack(self, ack_num: u64, signed: bool, guard: &SessionGuard) -> Result<Vec<u8>> {
+        let ack = if signed && !self.seals_frame() { WireFrame::new_signed_ack(0, ack_num, guard)? } else { WireFrame::new_ack(0, ack_num) };
+        self.encode(&ack, guard)
+    }
+
+    /// Whether the whole frame is sealed, in which case payloads go in as plaintext.
+    pub fn seals_frame(self) -> bool {
+        self == WireFormat::Sealed
+    }
+
+    /// Picks the most preferred format both sides can decode.
+    pub fn negotiate(ours: &[WireFormat], theirs: &[WireFormat]) -> Option<WireFormat> {
+        ours.iter().filter(|f| theirs.contains(f)).max().copied()
+    }
+}
+
+impl WireFrame {
+    /// Decodes a datagram, trying the `expected` format first.
+    ///
+    /// **Migration**: The other formats are tried as a fallback so frames already in flight
+    /// (or retransmitted) from before a format switch are still accepted. Sealed goes first
+    /// among them: it is the only one that can't mistake another format's frame for its own.
+    pub fn decode(bytes: &[u8], expected: WireFormat, guard: &SessionGuard) -> Option<(WireFrame, WireFormat)> {
+        if bytes.len() < MIN_FRAME_LEN {
+            return None;
+        }
+        let fallbacks = [WireFormat::Sealed, WireFormat::Compact, WireFormat::Bincode];
+        std::iter::once(expected).chain(fallbacks.into_iter().filter(|f| *f != expected)).find_map(|fmt| {
+            let frame = match fmt {
+                WireFormat::Bincode => bincode::deserialize::<WireFrame>(bytes).ok(),
+                WireFormat::Compact => decode_compact(bytes).ok(),
+                WireFormat::Sealed => guard.decrypt(bytes).ok().and_then(|body| decode_body(&body).ok()),
+            };
+            frame.map(|f| (f, fmt))
+        })
+    }
+}
+
+/// Associated data of an ACK tag. The leading byte keeps it distinct from a data
+/// frame's (empty, or the interface id), so one tag can never pass for the other.
+fn ack_aad(ack_num: u64) -> [u8; 9] {
+    let mut aad = [b'A'; 9];
+    aad[1..].copy_from_slice(&ack_num.to_le_bytes());
+    aad
+}
+
+/// Cumulative data-frame counters, echoed to the peer in `Stats` frames.
+///
+/// Comparing the peer's counters with our own over the same interval gives the
+/// real loss in each direction, rather than an inference from retransmissions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct LossReport {
+    /// Transport frames the reporter put on the wire (retransmissions included).
+    pub data_sent: u64,
+    /// Transport frames the reporter received (duplicates included).
+    pub data_received: u64,
+}
+
+/// Runtime settings one end asks of, or reports to, the other in `Control` frames.
+/// Each carries the whole state rather than a toggle, so a duplicate changes nothing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PeerControl {
+    /// Switch your outbound compression on or off. Answered with `Compression`.
+    SetCompression(bool),
+    /// Whether the sender now compresses what it sends. Sent after every change.
+    Compression(bool),
+    /// How many of your frames so far arrived marked Congestion Experienced (`--ecn`).
+    /// A running count, so a lost report is made up by the next.
+    CongestionMarks(u64),
+    /// The sender has the pre-shared key with this fingerprint (`rekey`), and whether
+    /// it seals with it yet. Repeated until the other end has the key as well; an end
+    /// that has it switches, and answers one that hasn't switched yet.
+    KeyInstalled { fingerprint: u32, in_use: bool },
+    /// The sender now sends from this port (`--rotate-port`), and stops reading its old
+    /// one shortly. Sent from the new port, where roaming has already followed it.
+    PortChanged(u16),
+}
+
+/// Capability advertisement, carried encrypted in `Handshake` frames.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    /// Set when answering a peer's Hello. Replies are never answered (no ping-pong).
+    pub is_reply: bool,
+    /// Random per process, i.e. per run of the sender's sequence counter. A Hello that
+    /// repeats a known session is a retry after our reply was lost, not a restart.
+    pub session: u64,
+    /// Wire formats this node can decode.
+    pub wire_formats: Vec<WireFormat>,
+    /// AEADs this node accepts for data, most preferred first. Appended later: older
+    /// peers ignore the trailing bytes, and their own Hellos lack it (see `decode`).
+    pub ciphers: Vec<CipherSuite>,
+    /// Compression this node decodes besides raw. Appended after `ciphers`, likewise.
+    pub compression: Vec<Algorithm>,
+    /// Local interfaces (`--tun`) this node multiplexes. Appended after `compression`.
+    pub interfaces: u8,
+    /// Id of the zstd dictionary this node has for small packets. Appended after `interfaces`.
+    pub dictionary: Option<u32>,
+    /// Whether this node's outbound compression is switched on. Appended after
+    /// `dictionary`; `None` from peers that predate `Control` frames.
+    pub compressing: Option<bool>,
+    /// Whether this node reads ECN marks and reports them (`--ecn`). Appended after
+    /// `compressing`; `None` from peers that predate it.
+    pub ecn: Option<bool>,
+    /// Whether this node tags its ACKs and wants the peer's tagged (`--auth-acks`).
+    /// Appended after `ecn`; `None` from peers that predate it.
+    pub auth_acks: Option<bool>,
+    /// The algorithm and level this node compresses with (`--compress-tx`), whether or
+    /// not we decode it. Appended after `auth_acks`; `None` from peers that predate it.
+    pub compress_tx: Option<(Algorithm, i32)>,
+    /// Whether this node understands `Chaff` frames. Appended after `compress_tx`; `None`
+    /// from peers that predate them, which would count chaff as malformed.
+    pub chaff: Option<bool>,
+    /// The wire protocol version this node speaks (`PROTOCOL_VERSION`). Appended after
+    /// `chaff`; for peers that predate it, `decode` infers it from the fields they sent.
+    pub version: u16,
+}
+
+impl Hello {
+    /// Fields are read in order and each one missing at the end falls back to what
+    /// a peer from before it had: no cipher choice, zstd only, one interface, no dictionary,
+    /// no control frames, no ECN, untagged ACKs, an unannounced algorithm, no chaff, and
+    /// the version that sent that many fields.
+    pub fn decode(plain: &[u8]) -> Option<Hello> {
+        let mut fields = Fields { cursor: plain, read: 0 };
+        let mut hello = Hello {
+            is_reply: fields.next()?,
+            session: fields.next()?,
+            wire_formats: fields.next()?,
+            ciphers: fields.next().unwrap_or_else(|| vec![CipherSuite::BASELINE]),
+            compression: fields.next().unwrap_or_else(|| vec![Algorithm::Zstd]),
+            interfaces: fields.next().unwrap_or(1),
+            dictionary: fields.next().unwrap_or(None),
+            compressing: fields.next().unwrap_or(None),
+            ecn: fields.next().unwrap_or(None),
+            auth_acks: fields.next().unwrap_or(None),
+            compress_tx: fields.next().unwrap_or(None),
+            chaff: fields.next().unwrap_or(None),
+            version: 0,
+        };
+        hello.version = fields.next().unwrap_or(fields.read - VERSION_1_FIELDS + 1);
+        Some(hello)
+    }
+}
+
+/// The fields of a Hello, read off the front one at a time, counting those that were there.
+struct Fields<'a> {
+    cursor: &'a [u8],
+    read: u16,
+}
+
+impl Fields<'_> {
+    /// Reads one bincode-encoded value.
+    fn next<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        let value = bincode::deserialize_from(&mut self.cursor).ok()?;
+        self.read += 1;
+        Some(value)
+    }
+}
+
+/// Version of the wire protocol this build speaks. A release that changes what goes on
+/// the wire bumps it, and gives the change a row in `Feature::since`.
+pub const PROTOCOL_VERSION: u16 = 12;
+
+/// Hello fields every version sends: `is_reply`, `session` and `wire_formats`. Versions 2
+/// to 10 each appended one more, so a Hello without `version` is dated by its length.
+const VERSION_1_FIELDS: u16 = 3;
+
+/// What the wire protocol gained after version 1, each with the version that brought
+/// it (`since`): the compatibility matrix, in one place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// A choice of AEAD (`Hello::ciphers`). Before it, ChaCha20-Poly1305 only.
+    CipherChoice,
+    /// A choice of compression (`Hello::compression`). Before it, zstd only.
+    CompressionChoice,
+    /// Several interfaces multiplexed by id (`Hello::interfaces`).
+    Interfaces,
+    /// A zstd dictionary for small packets (`Hello::dictionary`).
+    Dictionary,
+    /// `Control` frames: remote compression toggles, rekeying, ECN echo.
+    ControlFrames,
+    /// ECN marks read and echoed (`--ecn`).
+    Ecn,
+    /// ACKs tagged with the key (`--auth-acks`).
+    AuthAcks,
+    /// The outbound algorithm and level announced (`Hello::compress_tx`).
+    CompressTx,
+    /// `Chaff` frames (`--chaff`).
+    Chaff,
+    /// `PeerControl::PortChanged` (`--rotate-port`), and `Hello::version` itself.
+    PortChange,
+    /// `Morphed` frames (`--morph`).
+    Morphing,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 11] = [
+        Feature::CipherChoice,
+        Feature::CompressionChoice,
+        Feature::Interfaces,
+        Feature::Dictionary,
+        Feature::ControlFrames,
+        Feature::Ecn,
+        Feature::AuthAcks,
+        Feature::CompressTx,
+        Feature::Chaff,
+        Feature::PortChange,
+        Feature::Morphing,
+    ];
+
+    /// The first protocol version with it.
+    pub fn since(self) -> u16 {
+        match self {
+            Feature::CipherChoice => 2,
+            Feature::CompressionChoice => 3,
+            Feature::Interfaces => 4,
+            Feature::Dictionary => 5,
+            Feature::ControlFrames => 6,
+            Feature::Ecn => 7,
+            Feature::AuthAcks => 8,
+            Feature::CompressTx => 9,
+            Feature::Chaff => 10,
+            Feature::PortChange => 11,
+            Feature::Morphing => 12,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::CipherChoice => "cipher-choice",
+            Feature::CompressionChoice => "compression-choice",
+            Feature::Interfaces => "interfaces",
+            Feature::Dictionary => "dictionary",
+            Feature::ControlFrames => "control-frames",
+            Feature::Ecn => "ecn",
+            Feature::AuthAcks => "auth-acks",
+            Feature::CompressTx => "compress-tx",
+            Feature::Chaff => "chaff",
+            Feature::PortChange => "port-change",
+            Feature::Morphing => "morphing",
+        }
+    }
+}
+
+/// What the two ends use together, settled from their Hellos.
+///
+/// **Graceful Degradation**: the version is the lower of the two, and a feature is on
+/// only if that version has it and, where it is an option (`--ecn`, `--auth-acks`),
+/// both ends turned it on. Whatever the older end lacks is simply not used: it is never
+/// sent anything it would misread. Wire format, cipher, compression and dictionary are
+/// lists and ids rather than flags, and are negotiated on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Agreement {
+    pub version: u16,
+    pub control_frames: bool,
+    pub ecn: bool,
+    pub auth_acks: bool,
+    pub chaff: bool,
+    pub port_change: bool,
+    pub morphing: bool,
+}
+
+impl Agreement {
+    pub fn negotiate(ours: &Hello, theirs: &Hello) -> Self {
+        let version = ours.version.min(theirs.version);
+        let has = |feature: Feature| version >= feature.since();
+        let both = |a: Option<bool>, b: Option<bool>| a == Some(true) && b == Some(true);
+        Self {
+            version,
+            control_frames: has(Feature::ControlFrames),
+            ecn: has(Feature::Ecn) && both(ours.ecn, theirs.ecn),
+            auth_acks: has(Feature::AuthAcks) && both(ours.auth_acks, theirs.auth_acks),
+            chaff: has(Feature::Chaff) && both(ours.chaff, theirs.chaff),
+            port_change: has(Feature::PortChange),
+            morphing: has(Feature::Morphing),
+        }
+    }
+
+    /// Features of this build that the agreed version lacks.
+    pub fn missing(&self) -> Vec<Feature> {
+        Feature::ALL.into_iter().filter(|f| f.since() > self.version).collect()
+    }
+}
+
+impl FrameType {
+    fn to_byte(&self) -> u8 {
+        match self {
+            FrameType::Transport => 0,
+            FrameType::Heartbeat => 1,
+            FrameType::Handshake => 2,
+            FrameType::Ack => 3,
+            FrameType::Stats => 4,
+            FrameType::Control => 5,
+            FrameType::Chaff => 6,
+            FrameType::Morphed => 7,
+        }
+    }
+
+    fn from_byte(b: u8) -> Result<Self> {
+        Ok(match b {
+            0 => FrameType::Transport,
+            1 => FrameType::Heartbeat,
+            2 => FrameType::Handshake,
+            3 => FrameType::Ack,
+            4 => FrameType::Stats,
+            5 => FrameType::Control,
+            6 => FrameType::Chaff,
+            7 => FrameType::Morphed,
+            _ => bail!("Protocol::UnknownFrameType: {}", b),
+        })
+    }
+}
+
+fn encode_compact(frame: &WireFrame) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 + 20 + frame.payload.len());
+    out.push(COMPACT_MAGIC);
+    put_body(&mut out, frame);
+    out
+}
+
+fn decode_compact(bytes: &[u8]) -> Result<WireFrame> {
+    if bytes.len() < MIN_FRAME_LEN || bytes[0] != COMPACT_MAGIC {
+        bail!("Protocol::NotCompact");
+    }
+    decode_body(&bytes[1..])
+}
+
+/// `[TYPE (1B) | IFACE (1B, if flagged) | SEQ (varint) | ACK (varint) | PAYLOAD]`: a
+/// compact frame after its magic byte. Interface 0 is left out, so single-interface
+/// frames look exactly as they did before interfaces existed.
+fn put_body(out: &mut Vec<u8>, frame: &WireFrame) {
+    match frame.header.iface {
+        0 => out.push(frame.header.frame_type.to_byte()),
+        iface => out.extend_from_slice(&[frame.header.frame_type.to_byte() | IFACE_FLAG, iface]),
+    }
+    put_varint(out, frame.header.seq);
+    put_varint(out, frame.header.ack_num);
+    out.extend_from_slice(&frame.payload);
+}
+
+fn decode_body(bytes: &[u8]) -> Result<WireFrame> {
+    let Some((&frame_type, mut cursor)) = bytes.split_first() else {
+        bail!("Protocol::EmptyFrame");
+    };
+    let iface = if frame_type & IFACE_FLAG != 0 {
+        let Some((&iface, rest)) = cursor.split_first() else {
+            bail!("Protocol::TruncatedInterface");
+        };
+        cursor = rest;
+        iface
+    } else {
+        0
+    };
+    let frame_type = FrameType::from_byte(frame_type & !IFACE_FLAG)?;
+    let seq = get_varint(&mut cursor)?;
+    let ack_num = get_varint(&mut cursor)?;
+
+    Ok(WireFrame {
+        header: FrameHeader { seq, ack_num, frame_type, iface },
+        payload: cursor.to_vec(),
+    })
+}
+
+/// LEB128 encoding: small sequence numbers (the common case) cost 1-3 bytes instead of 8.
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn get_varint(cursor: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in cursor.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *cursor = &cursor[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Protocol::BadVarint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> SessionGuard {
+        SessionGuard::new(&[3; 32])
+    }
+
+    #[test]
+    fn runts_decode_to_nothing() {
+        let guard = guard();
+        for format in [WireFormat::Bincode, WireFormat::Compact, WireFormat::Sealed] {
+            for len in [0, 1, MIN_FRAME_LEN - 1] {
+                assert!(WireFrame::decode(&[COMPACT_MAGIC; MIN_FRAME_LEN][..len], format, &guard).is_none(), "{} bytes as {:?}", len, format);
+            }
+        }
+        // The shortest compact frame is exactly `MIN_FRAME_LEN`.
+        let heartbeat = WireFormat::Compact.encode(&WireFrame::new_heartbeat(0), &guard).unwrap();
+        assert_eq!(heartbeat.len(), MIN_FRAME_LEN);
+        assert!(WireFrame::decode(&heartbeat, WireFormat::Compact, &guard).is_some());
+    }
+
+    #[test]
+    fn compact_frames_cut_inside_the_header_are_rejected() {
+        let guard = guard();
+        // Ten-byte varints either side, after an interface byte.
+        let mut frame = WireFrame::new_data(u64::MAX - 1, 2, vec![0x45; 40]);
+        frame.header.ack_num = u64::MAX;
+        let bytes = WireFormat::Compact.encode(&frame, &guard).unwrap();
+        let header_len = 1 + 2 + 10 + 10;
+        let (decoded, format) = WireFrame::decode(&bytes, WireFormat::Compact, &guard).unwrap();
+        assert_eq!((decoded.header.seq, decoded.header.ack_num, decoded.header.iface, format), (u64::MAX - 1, u64::MAX, 2, WireFormat::Compact));
+        for len in 0..header_len {
+            assert!(WireFrame::decode(&bytes[..len], WireFormat::Compact, &guard).is_none(), "cut to {} bytes", len);
+        }
+        // Past the header, a cut only shortens the payload, which the AEAD catches later.
+        assert_eq!(WireFrame::decode(&bytes[..header_len], WireFormat::Compact, &guard).unwrap().0.payload, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn sealed_frames_cut_anywhere_are_rejected() {
+        let guard = guard();
+        let frame = WireFrame::new_data(7, 0, vec![0x45; 40]);
+        // A nonce that happens to start with the compact magic could pass for a compact frame.
+        let bytes = std::iter::repeat_with(|| WireFormat::Sealed.encode(&frame, &guard).unwrap())
+            .find(|bytes| bytes[0] != COMPACT_MAGIC)
+            .unwrap();
+        assert!(WireFrame::decode(&bytes, WireFormat::Sealed, &guard).is_some());
+        for len in 0..bytes.len() {
+            assert!(WireFrame::decode(&bytes[..len], WireFormat::Sealed, &guard).is_none(), "cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn signed_acks_authenticate_their_number() {
+        let guard = guard();
+        let bytes = WireFormat::Compact.encode(&WireFrame::new_signed_ack(0, 4242, &guard).unwrap(), &guard).unwrap();
+        let (ack, _) = WireFrame::decode(&bytes, WireFormat::Compact, &guard).unwrap();
+        assert_eq!((&ack.header.frame_type, ack.header.ack_num), (&FrameType::Ack, 4242));
+        assert!(ack.ack_authentic(&guard));
+        // Only the key it was tagged with.
+        assert!(!ack.ack_authentic(&SessionGuard::new(&[4; 32])));
+
+        let mut tampered = ack.clone();
+        *tampered.payload.last_mut().unwrap() ^= 1;
+        assert!(!tampered.ack_authentic(&guard));
+        // The tag of one ACK moved onto another number.
+        let mut moved = ack.clone();
+        moved.header.ack_num = 4243;
+        assert!(!moved.ack_authentic(&guard));
+    }
+
+    #[test]
+    fn unsigned_acks_are_not_authentic() {
+        let guard = guard();
+        assert!(!WireFrame::new_ack(0, 4242).ack_authentic(&guard));
+        // Nor is a data frame's tag, sealed over the same empty plaintext.
+        let mut forged = WireFrame::new_ack(0, 0);
+        forged.payload = guard.encrypt(&[]).unwrap();
+        assert!(!forged.ack_authentic(&guard));
+    }
+
+    #[test]
+    fn acks_follow_the_format_of_the_acknowledged_frame() {
+        let guard = guard();
+        // Tagged in the clear formats, where nothing else covers the number...
+        for format in [WireFormat::Bincode, WireFormat::Compact] {
+            let bytes = format.encode_ack(9, true, &guard).unwrap();
+            let (ack, decoded) = WireFrame::decode(&bytes, WireFormat::Sealed, &guard).unwrap();
+            assert_eq!(decoded, format);
+            assert!(ack.ack_authentic(&guard), "{:?}", format);
+            let (ack, _) = WireFrame::decode(&format.encode_ack(9, false, &guard).unwrap(), format, &guard).unwrap();
+            assert!(ack.payload.is_empty());
+        }
+        // ...and sealed whole, not tagged twice, even while the clear format is expected.
+        let bytes = WireFormat::Sealed.encode_ack(9, true, &guard).unwrap();
+        let (ack, decoded) = WireFrame::decode(&bytes, WireFormat::Compact, &guard).unwrap();
+        assert_eq!((decoded, ack.header.ack_num), (WireFormat::Sealed, 9));
+        assert!(ack.payload.is_empty());
+    }
+
+    /// A current Hello with every field away from what its absence decodes to.
+    fn hello(version: u16) -> Hello {
+        Hello {
+            is_reply: true,
+            session: 0x5e55,
+            wire_formats: vec![WireFormat::Bincode, WireFormat::Compact, WireFormat::Sealed],
+            ciphers: vec![CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305],
+            compression: vec![Algorithm::Lz4],
+            interfaces: 3,
+            dictionary: Some(0xd1c7),
+            compressing: Some(true),
+            ecn: Some(true),
+            auth_acks: Some(true),
+            compress_tx: Some((Algorithm::Lz4, 1)),
+            chaff: Some(true),
+            version,
+        }
+    }
+
+    /// The first `count` fields of `hello` as a peer that only knew that many sends them.
+    fn first_fields(hello: &Hello, count: usize) -> Vec<u8> {
+        let fields = [
+            bincode::serialize(&hello.is_reply),
+            bincode::serialize(&hello.session),
+            bincode::serialize(&hello.wire_formats),
+            bincode::serialize(&hello.ciphers),
+            bincode::serialize(&hello.compression),
+            bincode::serialize(&hello.interfaces),
+            bincode::serialize(&hello.dictionary),
+            bincode::serialize(&hello.compressing),
+            bincode::serialize(&hello.ecn),
+            bincode::serialize(&hello.auth_acks),
+            bincode::serialize(&hello.compress_tx),
+            bincode::serialize(&hello.chaff),
+            bincode::serialize(&hello.version),
+        ];
+        fields[..count].iter().flat_map(|field| field.as_ref().unwrap().clone()).collect()
+    }
+
+    #[test]
+    fn hellos_round_trip() {
+        let sent = hello(PROTOCOL_VERSION);
+        let plain = bincode::serialize(&sent).unwrap();
+        assert_eq!(plain, first_fields(&sent, 13));
+        assert_eq!(format!("{:?}", Hello::decode(&plain).unwrap()), format!("{:?}", sent));
+        // Fields a later version appends are skipped.
+        let longer = [&plain[..], &[1, 2, 3]].concat();
+        assert_eq!(format!("{:?}", Hello::decode(&longer).unwrap()), format!("{:?}", sent));
+    }
+
+    #[test]
+    fn older_hellos_are_dated_by_their_length_and_fall_back_field_by_field() {
+        let sent = hello(PROTOCOL_VERSION);
+        for count in 0..VERSION_1_FIELDS as usize {
+            assert!(Hello::decode(&first_fields(&sent, count)).is_none(), "{} fields", count);
+        }
+        for version in 1..=10u16 {
+            let count = (VERSION_1_FIELDS + version - 1) as usize;
+            let got = Hello::decode(&first_fields(&sent, count)).unwrap();
+            assert_eq!(got.version, version, "{} fields", count);
+            assert_eq!((got.is_reply, got.session, &got.wire_formats), (true, 0x5e55, &sent.wire_formats));
+            // What each version brought is there from that version on, and its fallback before.
+            let has = |feature: Feature| version >= feature.since();
+            let fallback = |feature| !has(feature);
+            assert_eq!(got.ciphers == [CipherSuite::BASELINE], fallback(Feature::CipherChoice), "v{}", version);
+            assert_eq!(got.compression == [Algorithm::Zstd], fallback(Feature::CompressionChoice), "v{}", version);
+            assert_eq!(got.interfaces == 1, fallback(Feature::Interfaces), "v{}", version);
+            assert_eq!(got.dictionary.is_none(), fallback(Feature::Dictionary), "v{}", version);
+            assert_eq!(got.compressing.is_none(), fallback(Feature::ControlFrames), "v{}", version);
+            assert_eq!(got.ecn.is_none(), fallback(Feature::Ecn), "v{}", version);
+            assert_eq!(got.auth_acks.is_none(), fallback(Feature::AuthAcks), "v{}", version);
+            assert_eq!(got.compress_tx.is_none(), fallback(Feature::CompressTx), "v{}", version);
+            assert_eq!(got.chaff.is_none(), fallback(Feature::Chaff), "v{}", version);
+        }
+        // From version 11 on it is sent, and believed over the field count.
+        for version in [11, 12, 40] {
+            assert_eq!(Hello::decode(&first_fields(&hello(version), 13)).unwrap().version, version);
+        }
+    }
+
+    #[test]
+    fn agreement_degrades_to_the_older_end() {
+        let ours = hello(PROTOCOL_VERSION);
+        for version in 1..=PROTOCOL_VERSION {
+            let theirs = hello(version);
+            let agreed = Agreement::negotiate(&ours, &theirs);
+            assert_eq!(agreed, Agreement::negotiate(&theirs, &ours));
+            let has = |feature: Feature| version >= feature.since();
+            assert_eq!(agreed, Agreement {
+                version,
+                control_frames: has(Feature::ControlFrames),
+                ecn: has(Feature::Ecn),
+                auth_acks: has(Feature::AuthAcks),
+                chaff: has(Feature::Chaff),
+                port_change: has(Feature::PortChange),
+                morphing: has(Feature::Morphing),
+            });
+            let missing = agreed.missing();
+            assert_eq!(missing.len(), (PROTOCOL_VERSION - version) as usize, "v{}", version);
+            assert!(missing.iter().all(|feature| !has(*feature)), "v{}: {:?}", version, missing);
+        }
+        // Every version after the first brought exactly one feature.
+        let mut since: Vec<u16> = Feature::ALL.iter().map(|feature| feature.since()).collect();
+        since.sort_unstable();
+        assert_eq!(since, (2..=PROTOCOL_VERSION).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn optional_features_need_both_ends() {
+        let ours = hello(PROTOCOL_VERSION);
+        for answer in [None, Some(false)] {
+            let theirs = Hello { ecn: answer, auth_acks: answer, chaff: answer, ..hello(PROTOCOL_VERSION) };
+            for agreed in [Agreement::negotiate(&ours, &theirs), Agreement::negotiate(&theirs, &ours)] {
+                assert_eq!((agreed.ecn, agreed.auth_acks, agreed.chaff), (false, false, false), "{:?}", answer);
+                // The rest is not an option, and stays on.
+                assert!(agreed.control_frames && agreed.port_change && agreed.morphing);
+                assert!(agreed.missing().is_empty());
+            }
+        }
+        let only_ecn = Hello { auth_acks: Some(false), chaff: None, ..hello(PROTOCOL_VERSION) };
+        let agreed = Agreement::negotiate(&ours, &only_ecn);
+        assert_eq!((agreed.ecn, agreed.auth_acks, agreed.chaff), (true, false, false));
+    }
+}
@@ -0,0 +1,288 @@
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::obfuscation::{Obfuscator, Unwrapped};
+
+/// QUIC version 1 (RFC 9000).
+const VERSION: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+/// Length of the connection IDs both ends pick, as common stacks do.
+const CID_LEN: usize = 8;
+
+/// Longest connection ID a version 1 long header may carry.
+const MAX_CID: usize = 20;
+
+/// Header form (long) and fixed bit: every long header has both.
+const LONG_HEADER: u8 = 0xc0;
+/// Fixed bit alone: a short header.
+const SHORT_HEADER: u8 = 0x40;
+const FORM_MASK: u8 = 0xc0;
+/// Long packet type bits; Initial is 0.
+const TYPE_MASK: u8 = 0x30;
+
+/// A client must pad datagrams carrying an Initial to at least this (RFC 9000 14.1).
+const MIN_INITIAL: usize = 1200;
+/// Largest decoy Initial sent: browsers pad theirs a little past the minimum.
+const MAX_INITIAL: usize = 1252;
+
+/// A short header: first byte and the destination connection ID. The packet number
+/// and payload that follow are header-protected and encrypted in real QUIC, so the
+/// frame passes for them.
+const SHORT_OVERHEAD: usize = 1 + CID_LEN;
+
+/// `--mimic quic`: every datagram dressed as QUIC version 1, for paths that let
+/// UDP/443 through when it looks like QUIC and throttle other UDP.
+///
+/// The end with `--peer` sends a decoy client Initial ahead of each Hello: a long
+/// header with a random original destination connection ID, its own source ID and an
+/// empty token, padded past 1200 bytes. The other end answers with a server Initial
+/// of the same size (so a spoofed one can't be used to amplify traffic). Each end
+/// learns the other's connection ID from its Initial, and every frame then travels
+/// in a short-header packet addressed to it.
+///
+/// The Initials' payloads are random, not sealed under the version 1 initial keys:
+/// an observer that parses headers sees QUIC, one that decrypts Initials finds no
+/// ClientHello.
+pub struct QuicMimic {
+    /// This end opens the connection (it has `--peer`); otherwise it answers.
+    client: bool,
+    /// The source ID of our Initials, which the peer's short headers carry.
+    local_cid: [u8; CID_LEN],
+    /// The source ID of the peer's last Initial (the client's original destination ID
+    /// until then).
+    remote_cid: Mutex<[u8; CID_LEN]>,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl QuicMimic {
+    pub fn new(client: bool, rng: Arc<Mutex<StdRng>>) -> Self {
+        let (local_cid, remote_cid) = {
+            let mut rng = rng.lock();
+            (rng.gen(), rng.gen())
+        };
+        Self { client, local_cid, remote_cid: Mutex::new(remote_cid), rng }
+    }
+
+    /// A long-header Initial `len` bytes long from `local_cid` to `dcid`, its protected
+    /// bits and payload random.
+    fn initial(&self, dcid: &[u8], len: usize) -> Vec<u8> {
+        let mut rng = self.rng.lock();
+        let mut packet = Vec::with_capacity(len);
+        // Reserved bits and packet number length are header-protected: random on the wire.
+        packet.push(LONG_HEADER | rng.gen_range(0..0x10));
+        packet.extend_from_slice(&VERSION);
+        packet.push(dcid.len() as u8);
+        packet.extend_from_slice(dcid);
+        packet.push(CID_LEN as u8);
+        packet.extend_from_slice(&self.local_cid);
+        // Token length 0, then the 2-byte Length varint over packet number and payload.
+        packet.push(0);
+        let remaining = len - packet.len() - 2;
+        packet.extend_from_slice(&(0x4000 | remaining as u16).to_be_bytes());
+        let start = packet.len();
+        packet.resize(len, 0);
+        rng.fill(&mut packet[start..]);
+        packet
+    }
+
+    /// Reads a long header as an Initial: the source connection ID it carries, or `None`
+    /// if it isn't one of ours.
+    ///
+    /// **Strict parsing**: version 1, type Initial, an empty token, a Length that covers
+    /// the rest of the datagram exactly, and padding to the client minimum.
+    fn parse_initial(datagram: &[u8]) -> Option<[u8; CID_LEN]> {
+        if datagram.len() < MIN_INITIAL || datagram[0] & TYPE_MASK != 0 || datagram.get(1..5)? != VERSION {
+            return None;
+        }
+        let mut at = 5;
+        let dcid_len = *datagram.get(at)? as usize;
+        if dcid_len > MAX_CID {
+            return None;
+        }
+        at += 1 + dcid_len;
+        if *datagram.get(at)? as usize != CID_LEN {
+            return None;
+        }
+        let scid: [u8; CID_LEN] = datagram.get(at + 1..at + 1 + CID_LEN)?.try_into().ok()?;
+        at += 1 + CID_LEN;
+        if *datagram.get(at)? != 0 {
+            return None;
+        }
+        let length = datagram.get(at + 1..at + 3)?;
+        let length = u16::from_be_bytes([length[0], length[1]]);
+        (length & 0xc000 == 0x4000 && (length & 0x3fff) as usize == datagram.len() - at - 3).then_some(scid)
+    }
+}
+
+impl Obfuscator for QuicMimic {
+    /// The client's decoy Initial, to a fresh original destination ID. Short headers go
+    /// to that ID too until the server's Initial names its own.
+    fn preamble(&self) -> Vec<Vec<u8>> {
+        if !self.client {
+            return Vec::new();
+        }
+        let (odcid, len): ([u8; CID_LEN], usize) = {
+            let mut rng = self.rng.lock();
+            (rng.gen(), rng.gen_range(MIN_INITIAL..=MAX_INITIAL))
+        };
+        *self.remote_cid.lock() = odcid;
+        vec![self.initial(&odcid, len)]
+    }
+
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut packet = Vec::with_capacity(SHORT_OVERHEAD + frame.len());
+        // Spin bit, reserved bits, key phase and packet number length, as header
+        // protection leaves them: random.
+        packet.push(SHORT_HEADER | self.rng.lock().gen_range(0..0x40));
+        packet.extend_from_slice(&*self.remote_cid.lock());
+        packet.extend_from_slice(frame);
+        Cow::Owned(packet)
+    }
+
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        SHORT_OVERHEAD + frame_len
+    }
+
+    /// Short headers carry frames; Initials end here, the peer's connection ID noted and,
+    /// at the answering end, a server Initial sent back.
+    fn unwrap_incoming(&self, datagram: &mut [u8], _from: SocketAddr) -> Unwrapped {
+        match datagram.first().map(|b| b & FORM_MASK) {
+            Some(LONG_HEADER) => match Self::parse_initial(datagram) {
+                Some(scid) => {
+                    *self.remote_cid.lock() = scid;
+                    let reply = (!self.client).then(|| self.initial(&scid, datagram.len()));
+                    Unwrapped::Decoy { reply }
+                }
+                None => Unwrapped::Foreign("a QUIC long header other than our Initial"),
+            },
+            Some(SHORT_HEADER) if datagram.len() > SHORT_OVERHEAD => {
+                let len = datagram.len() - SHORT_OVERHEAD;
+                datagram.copy_within(SHORT_OVERHEAD.., 0);
+                Unwrapped::Frame(len)
+            }
+            _ => Unwrapped::Foreign("not a QUIC packet (peer lacks --mimic quic?)"),
+        }
+    }
+
+    fn needs_datagrams(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obfuscation;
+
+    fn mimic(client: bool) -> QuicMimic {
+        QuicMimic::new(client, Arc::new(Mutex::new(obfuscation::traffic_rng(Some(u64::from(client))))))
+    }
+
+    fn from() -> SocketAddr {
+        "192.0.2.1:443".parse().unwrap()
+    }
+
+    /// Destination and source IDs of a long header, checked field by field on the way.
+    fn long_header(packet: &[u8]) -> (&[u8], &[u8]) {
+        assert_eq!(packet[0] & FORM_MASK, LONG_HEADER);
+        assert_eq!(packet[0] & TYPE_MASK, 0, "not an Initial");
+        assert_eq!(packet[1..5], VERSION);
+        let dcid_len = packet[5] as usize;
+        let scid_at = 6 + dcid_len;
+        assert_eq!((dcid_len, packet[scid_at] as usize), (CID_LEN, CID_LEN));
+        let token_at = scid_at + 1 + CID_LEN;
+        // An empty token, then a two-byte varint Length over everything after it.
+        assert_eq!(packet[token_at], 0);
+        let length = u16::from_be_bytes([packet[token_at + 1], packet[token_at + 2]]);
+        assert_eq!(length >> 14, 1);
+        assert_eq!((length & 0x3fff) as usize, packet.len() - token_at - 3);
+        (&packet[6..scid_at], &packet[scid_at + 1..token_at])
+    }
+
+    fn unwrap(mimic: &QuicMimic, mut datagram: Vec<u8>) -> Option<Vec<u8>> {
+        match mimic.unwrap_incoming(&mut datagram, from()) {
+            Unwrapped::Frame(len) => Some(datagram[..len].to_vec()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn initials_have_the_long_header_shape() {
+        let (client, server) = (mimic(true), mimic(false));
+        assert!(server.preamble().is_empty());
+        let initial = client.preamble().remove(0);
+        assert!((MIN_INITIAL..=MAX_INITIAL).contains(&initial.len()), "{} bytes", initial.len());
+        let (odcid, scid) = long_header(&initial);
+        assert_eq!((odcid, scid), (&client.remote_cid.lock()[..], &client.local_cid[..]));
+
+        let Unwrapped::Decoy { reply: Some(answer) } = server.unwrap_incoming(&mut initial.clone(), from()) else {
+            panic!("client Initial not answered");
+        };
+        // As large as what it answers, to the client's ID from the server's.
+        assert_eq!(answer.len(), initial.len());
+        assert_eq!(long_header(&answer), (&client.local_cid[..], &server.local_cid[..]));
+        assert!(matches!(client.unwrap_incoming(&mut answer.clone(), from()), Unwrapped::Decoy { reply: None }));
+        assert_eq!(*client.remote_cid.lock(), server.local_cid);
+    }
+
+    #[test]
+    fn frames_round_trip_in_short_headers() {
+        let (client, server) = (mimic(true), mimic(false));
+        // Until the server's Initial, short headers go to the original destination ID.
+        let odcid = long_header(&client.preamble()[0]).0.to_vec();
+        assert_eq!(client.wrap_outgoing(&[0xC1; 40])[1..1 + CID_LEN], odcid);
+
+        let Unwrapped::Decoy { reply: Some(answer) } = server.unwrap_incoming(&mut client.preamble().remove(0), from()) else {
+            panic!("client Initial not answered");
+        };
+        client.unwrap_incoming(&mut answer.clone(), from());
+        for len in [1, 60, 1400] {
+            let frame: Vec<u8> = (0..len).map(|i| (i * 11 + 0xC1) as u8).collect();
+            for (sender, receiver) in [(&client, &server), (&server, &client)] {
+                let packet = sender.wrap_outgoing(&frame).into_owned();
+                assert_eq!(packet.len(), sender.wrapped_len(len));
+                assert_eq!(packet[0] & FORM_MASK, SHORT_HEADER);
+                assert_eq!(packet[1..1 + CID_LEN], receiver.local_cid);
+                assert_eq!(unwrap(receiver, packet).unwrap(), frame, "{} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn anything_else_is_foreign() {
+        let (client, server) = (mimic(true), mimic(false));
+        let initial = client.preamble().remove(0);
+        let edit = |at: usize, value: u8| {
+            let mut packet = initial.clone();
+            packet[at] = value;
+            packet
+        };
+        let token_at = 6 + CID_LEN + 1 + CID_LEN;
+        let rejected = [
+            // Not QUIC at all: the fixed bit clear, or nothing after a short header's ID.
+            vec![0x00; 100],
+            vec![0x80; 100],
+            vec![0x45; SHORT_OVERHEAD],
+            Vec::new(),
+            // Long headers that aren't our Initial: Handshake type, version 2, an oversized
+            // or unexpected connection ID, a token, a Length that doesn't cover the rest.
+            edit(0, initial[0] | 0x20),
+            edit(4, 0x02),
+            edit(5, MAX_CID as u8 + 1),
+            edit(6 + CID_LEN, 4),
+            edit(token_at, 1),
+            edit(token_at + 2, initial[token_at + 2].wrapping_add(1)),
+            initial[..initial.len() - 1].to_vec(),
+            [&initial[..], &[0]].concat(),
+            // Well formed, but shorter than a client may send an Initial.
+            client.initial(&[7; CID_LEN], MIN_INITIAL - 1),
+        ];
+        for (i, mut datagram) in rejected.into_iter().enumerate() {
+            assert!(matches!(server.unwrap_incoming(&mut datagram, from()), Unwrapped::Foreign(_)), "case {}", i);
+        }
+    }
+}
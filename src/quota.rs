@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+
+use crate::metrics::Counters;
+
+/// What happens once a session goes over its quota (`--quota-action`).
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum QuotaAction {
+    /// Keep forwarding, limited to `--quota-throttle` bytes per second (both directions together).
+    Throttle,
+    /// Stop forwarding data either way until a new session or `reset-quota`.
+    Disconnect,
+}
+
+/// Configured caps. Both count the two directions together, goodput only.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub bytes: Option<u64>,
+    pub packets: Option<u64>,
+    pub action: QuotaAction,
+    /// Bytes per second allowed while throttled.
+    pub throttle_rate: u64,
+}
+
+impl Limits {
+    /// Why `usage` is over a cap, if it is.
+    fn exceeded(&self, usage: &UsageSnapshot) -> Option<String> {
+        if let Some(cap) = self.bytes.filter(|cap| usage.goodput_bytes() > *cap) {
+            return Some(format!("byte quota of {} exceeded ({} used)", cap, usage.goodput_bytes()));
+        }
+        if let Some(cap) = self.packets.filter(|cap| usage.packets() > *cap) {
+            return Some(format!("packet quota of {} exceeded ({} used)", cap, usage.packets()));
+        }
+        None
+    }
+}
+
+/// Where enforcement of the current session stands.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum QuotaState {
+    #[default]
+    WithinQuota,
+    Throttled { reason: String },
+    Disconnected { reason: String },
+}
+
+impl QuotaState {
+    /// Short name used in control replies and the JSON stream.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuotaState::WithinQuota => "ok",
+            QuotaState::Throttled { .. } => "throttled",
+            QuotaState::Disconnected { .. } => "disconnected",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            QuotaState::WithinQuota => None,
+            QuotaState::Throttled { reason } | QuotaState::Disconnected { reason } => Some(reason),
+        }
+    }
+}
+
+// Enforcement modes, as stored in `Session::mode` for the data path.
+const MODE_OPEN: u8 = 0;
+const MODE_THROTTLED: u8 = 1;
+const MODE_BLOCKED: u8 = 2;
+
+/// Point-in-time usage of one session.
+///
+/// Goodput is inner packets: sent for the first time, or delivered to TUN. Wire is
+/// every UDP byte of the session, so retransmissions, ACKs and control frames show
+/// up there and only there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsageSnapshot {
+    pub goodput_tx: u64,
+    pub goodput_rx: u64,
+    pub packets_tx: u64,
+    pub packets_rx: u64,
+    pub wire_tx: u64,
+    pub wire_rx: u64,
+}
+
+impl UsageSnapshot {
+    pub fn goodput_bytes(&self) -> u64 {
+        self.goodput_tx + self.goodput_rx
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets_tx + self.packets_rx
+    }
+}
+
+/// Usage, limits and state, as published to telemetry and the control socket.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaStatus {
+    pub usage: UsageSnapshot,
+    pub bytes_limit: Option<u64>,
+    pub packets_limit: Option<u64>,
+    pub state: QuotaState,
+}
+
+impl QuotaStatus {
+    pub fn bytes_remaining(&self) -> Option<u64> {
+        self.bytes_limit.map(|cap| cap.saturating_sub(self.usage.goodput_bytes()))
+    }
+
+    pub fn packets_remaining(&self) -> Option<u64> {
+        self.packets_limit.map(|cap| cap.saturating_sub(self.usage.packets()))
+    }
+}
+
+/// Traffic accounting and quota enforcement for the current peer session.
+///
+/// The data path only bumps counters and reads the enforcement mode; limits are
+/// compared by `check`, called periodically, so a session can overshoot its cap by
+/// whatever it moves in one check interval.
+pub struct Session {
+    limits: Option<Limits>,
+    /// Peer session id the usage belongs to (from its Hello).
+    id: Mutex<Option<u64>>,
+    goodput_tx: AtomicU64,
+    goodput_rx: AtomicU64,
+    packets_tx: AtomicU64,
+    packets_rx: AtomicU64,
+    wire_rx: AtomicU64,
+    /// Wire TX is counted by the socket for the whole process; usage is the
+    /// difference from this value, taken when the session began.
+    wire_tx_base: AtomicU64,
+    mode: AtomicU8,
+    state: Mutex<QuotaState>,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl Session {
+    /// `limits` is `None` when no quota is configured: usage is still accounted.
+    pub fn new(limits: Option<Limits>) -> Self {
+        let rate = limits.map_or(0, |l| l.throttle_rate);
+        Self {
+            limits,
+            id: Mutex::new(None),
+            goodput_tx: AtomicU64::new(0),
+            goodput_rx: AtomicU64::new(0),
+            packets_tx: AtomicU64::new(0),
+            packets_rx: AtomicU64::new(0),
+            wire_rx: AtomicU64::new(0),
+            wire_tx_base: AtomicU64::new(0),
+            mode: AtomicU8::new(MODE_OPEN),
+            state: Mutex::new(QuotaState::WithinQuota),
+            bucket: Mutex::new(TokenBucket::new(rate)),
+        }
+    }
+
+    /// The peer announced session `id`. A different session than before starts
+    /// from zero; returns true if that happened.
+    pub fn begin(&self, id: u64, wire_tx_total: u64) -> bool {
+        let fresh = self.id.lock().replace(id) != Some(id);
+        if fresh {
+            self.reset(wire_tx_total);
+        }
+        fresh
+    }
+
+    /// Zeroes usage and lifts any enforcement.
+    pub fn reset(&self, wire_tx_total: u64) {
+        for counter in [&self.goodput_tx, &self.goodput_rx, &self.packets_tx, &self.packets_rx, &self.wire_rx] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.wire_tx_base.store(wire_tx_total, Ordering::Relaxed);
+        self.mode.store(MODE_OPEN, Ordering::Relaxed);
+        *self.state.lock() = QuotaState::WithinQuota;
+    }
+
+    pub fn on_sent(&self, inner_len: usize) {
+        Counters::add(&self.goodput_tx, inner_len as u64);
+        Counters::inc(&self.packets_tx);
+    }
+
+    pub fn on_delivered(&self, inner_len: usize) {
+        Counters::add(&self.goodput_rx, inner_len as u64);
+        Counters::inc(&self.packets_rx);
+    }
+
+    pub fn on_datagram(&self, wire_len: usize) {
+        Counters::add(&self.wire_rx, wire_len as u64);
+    }
+
+    /// Outbound data of `len` bytes: `None` to drop it, else how long to wait first.
+    pub fn outbound(&self, len: usize) -> Option<Duration> {
+        match self.mode.load(Ordering::Relaxed) {
+            MODE_OPEN => Some(Duration::ZERO),
+            MODE_THROTTLED => Some(self.bucket.lock().reserve(len)),
+            _ => None,
+        }
+    }
+
+    /// Whether to accept inbound data of `len` bytes. Rejected frames go unACKed,
+    /// so the sender's window stalls and it backs off.
+    pub fn inbound(&self, len: usize) -> bool {
+        match self.mode.load(Ordering::Relaxed) {
+            MODE_OPEN => true,
+            MODE_THROTTLED => self.bucket.lock().try_take(len),
+            _ => false,
+        }
+    }
+
+    /// Compares usage with the limits. Returns the new state when it just went over.
+    pub fn check(&self, wire_tx_total: u64) -> Option<QuotaState> {
+        let limits = self.limits?;
+        if self.mode.load(Ordering::Relaxed) != MODE_OPEN {
+            return None;
+        }
+        let reason = limits.exceeded(&self.usage(wire_tx_total))?;
+        let (mode, state) = match limits.action {
+            QuotaAction::Throttle => (MODE_THROTTLED, QuotaState::Throttled { reason }),
+            QuotaAction::Disconnect => (MODE_BLOCKED, QuotaState::Disconnected { reason }),
+        };
+        *self.bucket.lock() = TokenBucket::new(limits.throttle_rate);
+        *self.state.lock() = state.clone();
+        self.mode.store(mode, Ordering::Relaxed);
+        Some(state)
+    }
+
+    pub fn usage(&self, wire_tx_total: u64) -> UsageSnapshot {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        UsageSnapshot {
+            goodput_tx: get(&self.goodput_tx),
+            goodput_rx: get(&self.goodput_rx),
+            packets_tx: get(&self.packets_tx),
+            packets_rx: get(&self.packets_rx),
+            wire_tx: wire_tx_total.saturating_sub(get(&self.wire_tx_base)),
+            wire_rx: get(&self.wire_rx),
+        }
+    }
+
+    pub fn status(&self, wire_tx_total: u64) -> QuotaStatus {
+        QuotaStatus {
+            usage: self.usage(wire_tx_total),
+            bytes_limit: self.limits.and_then(|l| l.bytes),
+            packets_limit: self.limits.and_then(|l| l.packets),
+            state: self.state.lock().clone(),
+        }
+    }
+}
+
+/// Byte budget refilled at `rate` per second, holding at most one second's worth.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self { rate: rate.max(1) as f64, tokens: rate as f64, refilled: Instant::now() }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + earned).min(self.rate);
+        self.refilled = now;
+    }
+
+    /// Takes `len` if available.
+    fn try_take(&mut self, len: usize) -> bool {
+        self.refill();
+        if self.tokens < len as f64 {
+            return false;
+        }
+        self.tokens -= len as f64;
+        true
+    }
+
+    /// Takes `len` regardless, returning how long until the budget is back in credit.
+    fn reserve(&mut self, len: usize) -> Duration {
+        self.refill();
+        self.tokens -= len as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::serial;
+
+/// Number of sequence numbers tracked behind the highest one seen.
+const WINDOW_BITS: u64 = 1024;
+const WORDS: usize = (WINDOW_BITS / 64) as usize;
+
+/// Sliding-window duplicate detector for received data sequence numbers.
+///
+/// Modeled on the IPsec/WireGuard anti-replay window: a bitmap of the last
+/// `WINDOW_BITS` sequence numbers relative to the highest accepted one.
+/// Duplicates are expected in normal operation (an ACK got lost and the sender
+/// retransmitted); they must be ACKed again but never delivered to TUN twice.
+///
+/// "Highest" is in serial-number order (see `serial`), so the window slides across
+/// a wrap of the counter. It covers one epoch: `reset` when the sender restarts.
+pub struct ReplayWindow {
+    /// Highest accepted sequence number (`None`: nothing accepted this epoch).
+    top: Option<u64>,
+    /// Bit `i` set => `top - i` has been accepted.
+    bitmap: [u64; WORDS],
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self { top: None, bitmap: [0; WORDS] }
+    }
+
+    /// Forget all history (new session: the peer's counter restarted).
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Records `seq`; returns `false` if it was already seen or is too old to tell.
+    pub fn accept(&mut self, seq: u64) -> bool {
+        let Some(top) = self.top else {
+            // First frame of the epoch defines the window, whatever its value.
+            self.top = Some(seq);
+            self.set_bit(0);
+            return true;
+        };
+
+        let ahead = serial::distance(top, seq);
+        if ahead > 0 {
+            self.shift(ahead as u64);
+            self.top = Some(seq);
+            self.set_bit(0);
+            return true;
+        }
+
+        let offset = ahead.unsigned_abs();
+        if offset >= WINDOW_BITS {
+            return false;
+        }
+        if self.bit(offset) {
+            return false;
+        }
+        self.set_bit(offset);
+        true
+    }
+
+    fn bit(&self, offset: u64) -> bool {
+        self.bitmap[(offset / 64) as usize] & (1 << (offset % 64)) != 0
+    }
+
+    fn set_bit(&mut self, offset: u64) {
+        self.bitmap[(offset / 64) as usize] |= 1 << (offset % 64);
+    }
+
+    /// Ages every tracked entry by `n` positions.
+    fn shift(&mut self, n: u64) {
+        if n >= WINDOW_BITS {
+            self.bitmap = [0; WORDS];
+            return;
+        }
+        let words = (n / 64) as usize;
+        let bits = (n % 64) as u32;
+        for i in (0..WORDS).rev() {
+            let src = i.checked_sub(words);
+            let hi = src.map_or(0, |s| self.bitmap[s] << bits);
+            let lo = match src.and_then(|s| s.checked_sub(1)) {
+                Some(s) if bits > 0 => self.bitmap[s] >> (64 - bits),
+                _ => 0,
+            };
+            self.bitmap[i] = hi | lo;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_defines_the_window_whatever_its_value() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(u64::MAX - 5));
+        assert!(!window.accept(u64::MAX - 5));
+        assert!(window.accept(u64::MAX - 6));
+    }
+
+    #[test]
+    fn duplicates_are_refused_in_and_out_of_order() {
+        let mut window = ReplayWindow::new();
+        for seq in [10, 12, 11, 15] {
+            assert!(window.accept(seq), "{}", seq);
+        }
+        for seq in [10, 11, 12, 15] {
+            assert!(!window.accept(seq), "{}", seq);
+        }
+        assert!(window.accept(13));
+        assert!(window.accept(14));
+    }
+
+    #[test]
+    fn window_slides_across_the_wrap() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(u64::MAX - 1));
+        assert!(window.accept(1));
+        // Still inside the window behind the new top, on the far side of the wrap.
+        assert!(window.accept(u64::MAX));
+        assert!(window.accept(0));
+        assert!(!window.accept(u64::MAX - 1));
+        assert!(!window.accept(u64::MAX));
+        assert!(!window.accept(1));
+    }
+
+    #[test]
+    fn edge_of_the_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(5000));
+        assert!(window.accept(5000 - (WINDOW_BITS - 1)));
+        assert!(!window.accept(5000 - WINDOW_BITS));
+    }
+
+    #[test]
+    fn a_long_jump_forgets_everything_behind_it() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(100));
+        assert!(window.accept(100 + WINDOW_BITS));
+        // 100 is now exactly a window behind: too old to tell.
+        assert!(!window.accept(100));
+        assert!(window.accept(100 + WINDOW_BITS - 1));
+    }
+
+    #[test]
+    fn shifts_keep_bits_across_word_boundaries() {
+        let mut window = ReplayWindow::new();
+        for seq in 0..200 {
+            assert!(window.accept(seq));
+        }
+        // Shift by a non-multiple of 64 so bits cross words.
+        assert!(window.accept(270));
+        for seq in 0..200 {
+            assert!(!window.accept(seq), "{}", seq);
+        }
+        for seq in 200..270 {
+            assert!(window.accept(seq), "{}", seq);
+        }
+    }
+
+    #[test]
+    fn half_the_space_ahead_is_not_new() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(0));
+        assert!(!window.accept(1 << 63));
+    }
+
+    #[test]
+    fn reset_starts_a_new_epoch() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(5000));
+        assert!(!window.accept(1));
+        window.reset();
+        assert!(window.accept(1));
+        assert!(window.accept(2));
+    }
+}
@@ -0,0 +1,71 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+
+/// A resolver that does not answer within this is treated as a failed lookup.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `--peer` as given: a literal address, or a DNS name to (re-)resolve.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerSpec {
+    Addr(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl PeerSpec {
+    /// Accepts `1.2.3.4:8000`, `[::1]:8000` or `vpn.example.com:8000`.
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Ok(addr) = s.parse() {
+            return Ok(PeerSpec::Addr(addr));
+        }
+        let (host, port) = s.rsplit_once(':').ok_or_else(|| anyhow!("peer '{}' needs a port (host:port)", s))?;
+        let port = port.parse().with_context(|| format!("invalid port in peer '{}'", s))?;
+        if host.is_empty() {
+            return Err(anyhow!("peer '{}' has an empty host", s));
+        }
+        Ok(PeerSpec::Host { host: host.to_string(), port })
+    }
+}
+
+/// Looks up `host` and picks the address the tunnel socket can reach.
+pub async fn resolve(host: &str, port: u16, socket_v6: bool) -> Result<SocketAddr> {
+    let candidates = lookup(host, port).await?;
+    pick(&candidates, socket_v6).ok_or_else(|| {
+        anyhow!("{} has no {} address", host, if socket_v6 { "usable" } else { "IPv4" })
+    })
+}
+
+/// The family to bind for `host` when `--bind` is left out: IPv4 if it has an IPv4
+/// address, IPv6 if it has only IPv6. `None` if it does not resolve.
+pub async fn needs_v6(host: &str, port: u16) -> Option<bool> {
+    let candidates = lookup(host, port).await.ok()?;
+    (!candidates.is_empty()).then(|| !candidates.iter().any(SocketAddr::is_ipv4))
+}
+
+async fn lookup(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let lookup = tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host((host, port))).await
+        .map_err(|_| anyhow!("Timed out resolving {}", host))?;
+    Ok(lookup.with_context(|| format!("Failed to resolve {}", host))?.collect())
+}
+
+/// Prefers the socket's own family. An IPv6 socket can still fall back to IPv4
+/// (sent v4-mapped); an IPv4 socket cannot reach IPv6 at all.
+fn pick(candidates: &[SocketAddr], socket_v6: bool) -> Option<SocketAddr> {
+    candidates.iter().find(|a| a.is_ipv6() == socket_v6)
+        .or_else(|| candidates.iter().find(|a| socket_v6 && a.is_ipv4()))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn an_unbound_end_takes_the_peers_family() {
+        assert_eq!(needs_v6("127.0.0.1", 8000).await, Some(false));
+        assert_eq!(needs_v6("::1", 8000).await, Some(true));
+        // A name with both gets an IPv4 socket, and re-resolving keeps to IPv4 for it.
+        let both = ["[2001:db8::1]:8000".parse().unwrap(), "192.0.2.1:8000".parse().unwrap()];
+        assert_eq!(pick(&both, false), Some(both[1]));
+    }
+}
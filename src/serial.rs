@@ -0,0 +1,51 @@
+//! Sequence number comparison (RFC 1982 serial-number arithmetic, `SERIAL_BITS = 64`).
+//!
+//! Data sequence numbers are compared by their wrapping difference, not numerically:
+//! `b` is after `a` when `b - a (mod 2^64)` lies in `1..2^63`. This keeps ordering
+//! correct across a wrap of the counter. A distance of exactly `2^63` has no defined
+//! order; it is treated as "before" so such a frame is never taken as new.
+//!
+//! Ordering only means something within one epoch, i.e. one run of the sender's
+//! counter. When the counter restarts (new session, and rekey once it lands) every
+//! receiver-side structure keyed by sequence number must be reset, not compared
+//! across the boundary: `1` in the new epoch is not "before" `5000` in the old one.
+
+/// Signed distance from `from` to `to`: positive when `to` is after `from`.
+pub fn distance(from: u64, to: u64) -> i64 {
+    to.wrapping_sub(from) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF: u64 = 1 << 63;
+
+    #[test]
+    fn plain_order() {
+        assert_eq!(distance(5, 8), 3);
+        assert_eq!(distance(8, 5), -3);
+        assert_eq!(distance(7, 7), 0);
+    }
+
+    #[test]
+    fn order_holds_across_the_wrap() {
+        assert_eq!(distance(u64::MAX, 0), 1);
+        assert_eq!(distance(u64::MAX - 2, 4), 7);
+        assert_eq!(distance(4, u64::MAX - 2), -7);
+    }
+
+    #[test]
+    fn farthest_defined_distances() {
+        assert_eq!(distance(0, HALF - 1), i64::MAX);
+        assert_eq!(distance(HALF - 1, 0), -i64::MAX);
+        assert_eq!(distance(10, 10 + HALF + 1), -i64::MAX);
+    }
+
+    #[test]
+    fn half_the_space_away_is_never_after() {
+        assert!(distance(0, HALF) < 0);
+        assert!(distance(HALF, 0) < 0);
+        assert!(distance(123, 123u64.wrapping_add(HALF)) < 0);
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use crate::logging::{tlog, Level};
+use crate::telemetry::TelemetrySender;
+use crate::tui::TelemetryUpdate;
+
+/// Why the process is stopping.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// The operator closed the dashboard.
+    Quit,
+    /// SIGINT or SIGTERM.
+    Signal(&'static str),
+    /// A `bench` or `profile` run completed.
+    Finished,
+    /// The core asked to stop: `shutdown` over the control interface, or a task that
+    /// keeps dying. Also sent as a `Shutdown` telemetry update.
+    Core(String),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Quit => write!(f, "dashboard closed"),
+            StopReason::Signal(name) => write!(f, "{} received", name),
+            StopReason::Finished => write!(f, "run finished"),
+            StopReason::Core(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// The shutdown broadcast: anything can request a stop, and the networking tasks,
+/// the dashboard (or headless telemetry thread) and `main` all observe it. The first
+/// request wins; later ones are ignored.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<Option<StopReason>>>,
+    stats: TelemetrySender,
+}
+
+impl Shutdown {
+    pub fn new(stats: TelemetrySender) -> Self {
+        Self { tx: Arc::new(watch::Sender::new(None)), stats }
+    }
+
+    pub fn request(&self, reason: StopReason) {
+        self.tx.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            // Telemetry first: consumers that stop on the broadcast drain their queue, record included.
+            if let StopReason::Core(msg) = &reason {
+                let _ = self.stats.send(TelemetryUpdate::Shutdown { reason: msg.clone() });
+            }
+            *current = Some(reason);
+            true
+        });
+    }
+
+    /// The stop requested so far, if any. Never blocks, for the UI threads to poll.
+    pub fn reason(&self) -> Option<StopReason> {
+        self.tx.borrow().clone()
+    }
+
+    /// Resolves once a stop has been requested.
+    pub async fn wait(&self) -> StopReason {
+        let mut rx = self.tx.subscribe();
+        loop {
+            if let Some(reason) = rx.borrow_and_update().clone() {
+                return reason;
+            }
+            // Cannot fail: `self` keeps the sender alive.
+            let _ = rx.changed().await;
+        }
+    }
+
+    /// Turns SIGINT and SIGTERM into a stop request, so they get the same orderly
+    /// teardown as quitting the dashboard (hooks, control socket, CSV and pcap files).
+    pub fn on_signals(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let (mut interrupt, mut terminate) = match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+                (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+                (Err(e), _) | (_, Err(e)) => {
+                    tlog!(this.stats, Level::Warn, "SIGNAL: Handlers unavailable, signals stop the process abruptly: {}", e);
+                    return;
+                }
+            };
+            let name = tokio::select! {
+                _ = interrupt.recv() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+            tlog!(this.stats, Level::Warn, "SIGNAL: {} received, shutting down", name);
+            this.request(StopReason::Signal(name));
+        });
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::protocol::LossReport;
+
+/// Horizon over which loss is estimated. Long enough to smooth single drops,
+/// short enough that a recovered link reads clean again within seconds.
+const LOSS_WINDOW: Duration = Duration::from_secs(10);
+
+/// Gain for the smoothed RTT (RFC 6298: alpha = 1/8).
+const RTT_ALPHA: f64 = 1.0 / 8.0;
+/// Gain for the jitter estimator (RFC 3550: 1/16).
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+
+/// RTT histogram resolution: each power of two (in microseconds) is split into this
+/// many buckets, so a reported percentile is within 1/32 (about 3%) of the true one.
+const RTT_SUB_BITS: u32 = 4;
+const RTT_SUB_BUCKETS: usize = 1 << RTT_SUB_BITS;
+/// Octaves covered: up to 2^27 us (134s). Longer RTTs land in the last bucket.
+const RTT_OCTAVES: usize = 24;
+const RTT_BUCKETS: usize = RTT_SUB_BUCKETS * RTT_OCTAVES;
+
+/// Span of the sliding percentiles, and how often its baseline moves.
+pub const RTT_WINDOW: Duration = Duration::from_secs(60);
+const RTT_WINDOW_STEP: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Sent,
+    Retransmitted,
+}
+
+/// Point-in-time link quality. `None` means "no data yet", not zero.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkQuality {
+    /// Fraction of frames needing retransmission in the window (0.0..=1.0).
+    pub loss_rate: Option<f64>,
+    /// Mean deviation between consecutive RTT samples.
+    pub jitter_ms: Option<f64>,
+    /// Smoothed round-trip time.
+    pub srtt_ms: Option<f64>,
+}
+
+/// Link quality estimator fed by the ARQ layer.
+///
+/// **Loss**: ratio of retransmissions to fresh sends over a sliding time window.
+/// A retransmission means the original (or its ACK) never made it back within RTO,
+/// which is the loss the application actually experiences.
+///
+/// **Jitter**: RFC 3550-style smoothed absolute difference between consecutive RTT
+/// samples. RTT samples must follow Karn's rule (never from retransmitted frames),
+/// otherwise the ACK cannot be matched to a specific transmission.
+pub struct LinkMonitor {
+    outcomes: VecDeque<(Instant, Outcome)>,
+    last_rtt: Option<Duration>,
+    srtt_ms: Option<f64>,
+    jitter_ms: Option<f64>,
+}
+
+impl LinkMonitor {
+    pub fn new() -> Self {
+        Self {
+            outcomes: VecDeque::new(),
+            last_rtt: None,
+            srtt_ms: None,
+            jitter_ms: None,
+        }
+    }
+
+    /// A fresh data frame went out.
+    pub fn on_sent(&mut self, now: Instant) {
+        self.record(now, Outcome::Sent);
+    }
+
+    /// A data frame hit RTO and was sent again.
+    pub fn on_retransmit(&mut self, now: Instant) {
+        self.record(now, Outcome::Retransmitted);
+    }
+
+    /// An ACK matched a frame that was transmitted exactly once.
+    pub fn on_rtt_sample(&mut self, rtt: Duration) {
+        let rtt_ms = rtt.as_secs_f64() * 1000.0;
+
+        self.srtt_ms = Some(match self.srtt_ms {
+            Some(srtt) => srtt + RTT_ALPHA * (rtt_ms - srtt),
+            None => rtt_ms,
+        });
+
+        if let Some(prev) = self.last_rtt {
+            let delta = (rtt_ms - prev.as_secs_f64() * 1000.0).abs();
+            let j = self.jitter_ms.unwrap_or(0.0);
+            self.jitter_ms = Some(j + JITTER_GAIN * (delta - j));
+        }
+        self.last_rtt = Some(rtt);
+    }
+
+    /// The smoothed RTT, once a sample has been taken.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt_ms.map(|ms| Duration::from_secs_f64(ms / 1000.0))
+    }
+
+    pub fn snapshot(&mut self, now: Instant) -> LinkQuality {
+        self.expire(now);
+
+        let sent = self.outcomes.iter().filter(|(_, o)| *o == Outcome::Sent).count();
+        let lost = self.outcomes.len() - sent;
+        let loss_rate = if sent > 0 {
+            Some((lost as f64 / sent as f64).min(1.0))
+        } else {
+            None
+        };
+
+        LinkQuality {
+            loss_rate,
+            jitter_ms: self.jitter_ms,
+            srtt_ms: self.srtt_ms,
+        }
+    }
+
+    fn record(&mut self, now: Instant, outcome: Outcome) {
+        self.outcomes.push_back((now, outcome));
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((t, _)) = self.outcomes.front() {
+            if now.duration_since(*t) > LOSS_WINDOW {
+                self.outcomes.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Loss measured from counters echoed by the peer (`--loss-echo`).
+#[derive(Debug, Clone, Copy)]
+pub struct EchoLoss {
+    /// Our frames that never reached the peer (local -> remote).
+    pub forward: Option<f64>,
+    /// Peer frames that never reached us (remote -> local).
+    pub reverse: Option<f64>,
+}
+
+/// Turns successive `LossReport`s into per-interval loss figures.
+///
+/// Each report is paired with a snapshot of our own counters taken when it arrived.
+/// Loss over the interval between two reports is `1 - delivered / sent` per direction.
+/// Frames still in flight at a report boundary skew a single interval slightly, which
+/// is why the reporting interval is seconds rather than milliseconds.
+pub struct EchoLossTracker {
+    previous: Option<(LossReport, LossReport)>,
+}
+
+impl EchoLossTracker {
+    pub fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// `theirs`: the peer's counters; `ours`: our counters at the time of receipt.
+    pub fn on_report(&mut self, theirs: LossReport, ours: LossReport) -> Option<EchoLoss> {
+        let previous = self.previous.replace((theirs, ours));
+        let (prev_theirs, prev_ours) = previous?;
+
+        // A counter going backwards means the peer restarted: start a fresh baseline.
+        if theirs.data_sent < prev_theirs.data_sent || theirs.data_received < prev_theirs.data_received {
+            return None;
+        }
+
+        Some(EchoLoss {
+            forward: interval_loss(ours.data_sent - prev_ours.data_sent, theirs.data_received - prev_theirs.data_received),
+            reverse: interval_loss(theirs.data_sent - prev_theirs.data_sent, ours.data_received - prev_ours.data_received),
+        })
+    }
+}
+
+fn interval_loss(sent: u64, delivered: u64) -> Option<f64> {
+    if sent == 0 {
+        return None;
+    }
+    Some((1.0 - delivered as f64 / sent as f64).clamp(0.0, 1.0))
+}
+
+/// RTT samples of the whole session, for percentiles.
+///
+/// Log-linear buckets (HDR-style): exact below 16us, then 16 buckets per power of
+/// two. Recording is one relaxed atomic increment, so every ACK can feed it without
+/// a lock; readers take a snapshot and work on that.
+pub struct RttHistogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl RttHistogram {
+    pub fn new() -> Self {
+        Self { buckets: (0..RTT_BUCKETS).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    pub fn record(&self, rtt: Duration) {
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[rtt_bucket(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> RttSnapshot {
+        RttSnapshot { counts: self.buckets.iter().map(|c| c.load(Ordering::Relaxed)).collect() }
+    }
+}
+
+fn rtt_bucket(micros: u64) -> usize {
+    if micros < RTT_SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    // The top RTT_SUB_BITS + 1 bits: a leading one and the position within the octave.
+    let octave = (63 - micros.leading_zeros() - RTT_SUB_BITS + 1) as usize;
+    let sub = (micros >> (octave - 1)) as usize - RTT_SUB_BUCKETS;
+    (octave * RTT_SUB_BUCKETS + sub).min(RTT_BUCKETS - 1)
+}
+
+/// Midpoint of a bucket, in milliseconds.
+fn rtt_bucket_value_ms(bucket: usize) -> f64 {
+    let (octave, sub) = (bucket / RTT_SUB_BUCKETS, bucket % RTT_SUB_BUCKETS);
+    let micros = if octave == 0 {
+        sub as f64
+    } else {
+        let width = (1u64 << (octave - 1)) as f64;
+        (sub + RTT_SUB_BUCKETS) as f64 * width + width / 2.0
+    };
+    micros / 1000.0
+}
+
+/// Point-in-time copy of an `RttHistogram`.
+#[derive(Debug, Clone, Default)]
+pub struct RttSnapshot {
+    counts: Vec<u64>,
+}
+
+impl RttSnapshot {
+    /// Samples recorded between `earlier` and `self`.
+    pub fn since(&self, earlier: &RttSnapshot) -> RttSnapshot {
+        let before = |i: usize| earlier.counts.get(i).copied().unwrap_or(0);
+        RttSnapshot { counts: self.counts.iter().enumerate().map(|(i, c)| c.saturating_sub(before(i))).collect() }
+    }
+
+    pub fn percentiles(&self) -> Percentiles {
+        let samples = self.counts.iter().sum();
+        Percentiles {
+            p50_ms: self.quantile(0.50, samples),
+            p95_ms: self.quantile(0.95, samples),
+            p99_ms: self.quantile(0.99, samples),
+            samples,
+        }
+    }
+
+    /// The smallest bucket holding at least `q` of the samples.
+    fn quantile(&self, q: f64, samples: u64) -> Option<f64> {
+        if samples == 0 {
+            return None;
+        }
+        let rank = ((q * samples as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.counts.iter().position(|&c| {
+            seen += c;
+            seen >= rank
+        })?;
+        Some(rtt_bucket_value_ms(bucket))
+    }
+}
+
+/// RTT percentiles in milliseconds; `None` without samples.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub samples: u64,
+}
+
+impl std::fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ms = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.1}ms", v));
+        let plural = if self.samples == 1 { "" } else { "s" };
+        write!(f, "p50 {} / p95 {} / p99 {} ({} sample{})", ms(self.p50_ms), ms(self.p95_ms), ms(self.p99_ms), self.samples, plural)
+    }
+}
+
+/// Session-wide percentiles, and the same over the last `RTT_WINDOW`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latency {
+    pub session: Percentiles,
+    pub window: Percentiles,
+}
+
+/// Derives the sliding percentiles from successive session snapshots: the window is
+/// the difference from a snapshot about `RTT_WINDOW` old, so nothing is ever removed
+/// from the shared histogram.
+pub struct LatencyTracker {
+    /// Baselines, oldest first, `RTT_WINDOW_STEP` apart.
+    baselines: VecDeque<(Instant, RttSnapshot)>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self { baselines: VecDeque::new() }
+    }
+
+    pub fn update(&mut self, now: Instant, current: RttSnapshot) -> Latency {
+        if self.baselines.back().is_none_or(|(at, _)| now.duration_since(*at) >= RTT_WINDOW_STEP) {
+            self.baselines.push_back((now, current.clone()));
+        }
+        // Keep the newest baseline at least a window old, so the window is never short.
+        while self.baselines.len() > 1 && now.duration_since(self.baselines[1].0) >= RTT_WINDOW {
+            self.baselines.pop_front();
+        }
+        let window = match self.baselines.front() {
+            Some((at, base)) if now.duration_since(*at) >= RTT_WINDOW => current.since(base),
+            // Younger than a window: everything so far.
+            _ => current.clone(),
+        };
+        Latency { session: current.percentiles(), window: window.percentiles() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn no_data_reads_as_none() {
+        let quality = LinkMonitor::new().snapshot(Instant::now());
+        assert!(quality.loss_rate.is_none());
+        assert!(quality.jitter_ms.is_none());
+        assert!(quality.srtt_ms.is_none());
+    }
+
+    #[test]
+    fn loss_is_retransmissions_over_fresh_sends() {
+        let mut monitor = LinkMonitor::new();
+        let now = Instant::now();
+        for _ in 0..10 {
+            monitor.on_sent(now);
+        }
+        monitor.on_retransmit(now);
+        monitor.on_retransmit(now);
+        assert_eq!(monitor.snapshot(now).loss_rate, Some(0.2));
+    }
+
+    #[test]
+    fn loss_is_capped_at_one() {
+        let mut monitor = LinkMonitor::new();
+        let now = Instant::now();
+        monitor.on_sent(now);
+        for _ in 0..5 {
+            monitor.on_retransmit(now);
+        }
+        assert_eq!(monitor.snapshot(now).loss_rate, Some(1.0));
+    }
+
+    #[test]
+    fn loss_forgets_outcomes_older_than_the_window() {
+        let mut monitor = LinkMonitor::new();
+        let start = Instant::now();
+        monitor.on_sent(start);
+        monitor.on_retransmit(start);
+        let later = start + LOSS_WINDOW + ms(1);
+        assert_eq!(monitor.snapshot(later).loss_rate, None);
+        monitor.on_sent(later);
+        assert_eq!(monitor.snapshot(later).loss_rate, Some(0.0));
+    }
+
+    #[test]
+    fn srtt_starts_at_the_first_sample_and_moves_by_an_eighth() {
+        let mut monitor = LinkMonitor::new();
+        monitor.on_rtt_sample(ms(10));
+        assert_eq!(monitor.snapshot(Instant::now()).srtt_ms, Some(10.0));
+        monitor.on_rtt_sample(ms(90));
+        assert_eq!(monitor.snapshot(Instant::now()).srtt_ms, Some(20.0));
+        assert_eq!(monitor.srtt(), Some(ms(20)));
+    }
+
+    #[test]
+    fn jitter_needs_two_samples_and_smooths_by_a_sixteenth() {
+        let mut monitor = LinkMonitor::new();
+        monitor.on_rtt_sample(ms(10));
+        assert_eq!(monitor.snapshot(Instant::now()).jitter_ms, None);
+        monitor.on_rtt_sample(ms(26));
+        assert_eq!(monitor.snapshot(Instant::now()).jitter_ms, Some(1.0));
+        monitor.on_rtt_sample(ms(26));
+        assert_eq!(monitor.snapshot(Instant::now()).jitter_ms, Some(0.9375));
+    }
+
+    #[test]
+    fn steady_rtt_has_no_jitter() {
+        let mut monitor = LinkMonitor::new();
+        for _ in 0..100 {
+            monitor.on_rtt_sample(ms(40));
+        }
+        assert_eq!(monitor.snapshot(Instant::now()).jitter_ms, Some(0.0));
+    }
+
+    /// A reported percentile is a bucket midpoint: within 1/32 of the true value, plus
+    /// a little for the discreteness of the samples.
+    const PERCENTILE_ERROR: f64 = 1.0 / 32.0 + 0.005;
+
+    fn assert_near(reported: Option<f64>, expected_ms: f64) {
+        let reported = reported.expect("percentile with samples");
+        let error = (reported - expected_ms).abs() / expected_ms;
+        assert!(error <= PERCENTILE_ERROR, "reported {}ms, expected {}ms ({:.2}% off)", reported, expected_ms, error * 100.0);
+    }
+
+    /// 10us to 100ms in even steps.
+    fn record_uniform(histogram: &RttHistogram) {
+        for i in 1..=10_000 {
+            histogram.record(Duration::from_micros(i * 10));
+        }
+    }
+
+    /// Exponential with a 20ms mean, drawn at evenly spaced probabilities so the
+    /// true quantiles are known exactly: -mean * ln(1 - q).
+    fn record_exponential(histogram: &RttHistogram) {
+        const N: usize = 20_000;
+        for i in 0..N {
+            let u = (i as f64 + 0.5) / N as f64;
+            histogram.record(Duration::from_secs_f64(-0.020 * (1.0 - u).ln()));
+        }
+    }
+
+    fn exponential_quantile_ms(q: f64) -> f64 {
+        -20.0 * (1.0 - q).ln()
+    }
+
+    #[test]
+    fn no_samples_no_percentiles() {
+        let percentiles = RttHistogram::new().snapshot().percentiles();
+        assert_eq!(percentiles.samples, 0);
+        assert!(percentiles.p50_ms.is_none() && percentiles.p95_ms.is_none() && percentiles.p99_ms.is_none());
+    }
+
+    #[test]
+    fn uniform_percentiles_within_bounds() {
+        let histogram = RttHistogram::new();
+        record_uniform(&histogram);
+        let percentiles = histogram.snapshot().percentiles();
+        assert_eq!(percentiles.samples, 10_000);
+        assert_near(percentiles.p50_ms, 50.0);
+        assert_near(percentiles.p95_ms, 95.0);
+        assert_near(percentiles.p99_ms, 99.0);
+    }
+
+    #[test]
+    fn exponential_percentiles_within_bounds() {
+        let histogram = RttHistogram::new();
+        record_exponential(&histogram);
+        let percentiles = histogram.snapshot().percentiles();
+        assert_near(percentiles.p50_ms, exponential_quantile_ms(0.50));
+        assert_near(percentiles.p95_ms, exponential_quantile_ms(0.95));
+        assert_near(percentiles.p99_ms, exponential_quantile_ms(0.99));
+    }
+
+    #[test]
+    fn sub_bucket_rtts_are_exact() {
+        let histogram = RttHistogram::new();
+        histogram.record(Duration::from_micros(5));
+        assert_eq!(histogram.snapshot().percentiles().p99_ms, Some(0.005));
+    }
+
+    #[test]
+    fn every_bucket_holds_its_own_midpoint() {
+        for bucket in 0..RTT_BUCKETS {
+            let micros = (rtt_bucket_value_ms(bucket) * 1000.0) as u64;
+            assert_eq!(rtt_bucket(micros), bucket, "{}us", micros);
+        }
+    }
+
+    #[test]
+    fn huge_rtts_land_in_the_last_bucket() {
+        assert_eq!(rtt_bucket(u64::MAX), RTT_BUCKETS - 1);
+        let histogram = RttHistogram::new();
+        histogram.record(Duration::from_secs(3600));
+        assert!(histogram.snapshot().percentiles().p50_ms.is_some());
+    }
+
+    #[test]
+    fn window_is_the_whole_session_until_a_minute_has_passed() {
+        let histogram = RttHistogram::new();
+        let mut tracker = LatencyTracker::new();
+        let start = Instant::now();
+        tracker.update(start, histogram.snapshot());
+        record_uniform(&histogram);
+        let latency = tracker.update(start + Duration::from_secs(30), histogram.snapshot());
+        assert_eq!(latency.window.samples, latency.session.samples);
+        assert_eq!(latency.window.p95_ms, latency.session.p95_ms);
+    }
+
+    #[test]
+    fn window_covers_only_the_last_minute() {
+        let histogram = RttHistogram::new();
+        let mut tracker = LatencyTracker::new();
+        let start = Instant::now();
+        // A slow first minute, then an exponential one.
+        for _ in 0..10_000 {
+            histogram.record(Duration::from_millis(500));
+        }
+        let mut latency = tracker.update(start, histogram.snapshot());
+        for step in 1..=12 {
+            if step == 6 {
+                record_exponential(&histogram);
+            }
+            latency = tracker.update(start + RTT_WINDOW_STEP * step, histogram.snapshot());
+        }
+        assert_eq!(latency.session.samples, 30_000);
+        assert_near(latency.session.p50_ms, exponential_quantile_ms(0.75));
+        assert_eq!(latency.window.samples, 20_000);
+        assert_near(latency.window.p50_ms, exponential_quantile_ms(0.50));
+        assert_near(latency.window.p95_ms, exponential_quantile_ms(0.95));
+        assert_near(latency.window.p99_ms, exponential_quantile_ms(0.99));
+
+        // A minute later, with nothing new, the window is empty again.
+        for step in 13..=24 {
+            latency = tracker.update(start + RTT_WINDOW_STEP * step, histogram.snapshot());
+        }
+        assert_eq!(latency.window.samples, 0);
+        assert!(latency.window.p50_ms.is_none());
+        assert_eq!(latency.session.samples, 30_000);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::logging::{tlog, Level};
+use crate::shutdown::{Shutdown, StopReason};
+use crate::telemetry::TelemetrySender;
+
+/// Restarts allowed within `RESTART_WINDOW` before the supervisor gives up.
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+/// Pause before respawning, so a task failing instantly doesn't spin the CPU.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Watchdog for a long-running networking task.
+///
+/// **Failure Model**: The tunnel's tasks are meant to run forever, so *any* exit —
+/// clean return (e.g. TUN EOF) or panic — is a failure. A dead TX or RX loop leaves
+/// the tunnel half-functional while the dashboard keeps rendering, which is worse
+/// than crashing outright.
+///
+/// The supervisor respawns the task via `spawn` after a short backoff. If it keeps
+/// dying (more than `MAX_RESTARTS` in `RESTART_WINDOW`), restarting is clearly not
+/// helping, so it requests a full shutdown instead.
+///
+/// Once a shutdown is requested (by anyone) it aborts the task and returns, so the
+/// handle resolves when the task is gone.
+pub fn supervise<F>(name: impl Into<String>, spawn: F, stats: TelemetrySender, shutdown: Shutdown) -> JoinHandle<()>
+where
+    F: Fn() -> JoinHandle<()> + Send + 'static,
+{
+    let name = name.into();
+    tokio::spawn(async move {
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+
+        loop {
+            let mut task = spawn();
+            let result = tokio::select! {
+                result = &mut task => result,
+                _ = shutdown.wait() => {
+                    task.abort();
+                    let _ = task.await;
+                    return;
+                }
+            };
+            let outcome = describe_exit(result);
+
+            let now = Instant::now();
+            while restarts.front().is_some_and(|t| now.duration_since(*t) > RESTART_WINDOW) {
+                restarts.pop_front();
+            }
+
+            if restarts.len() >= MAX_RESTARTS {
+                let reason = format!("{} task {} ({} restarts in {:?})", name, outcome, restarts.len(), RESTART_WINDOW);
+                tlog!(stats, Level::Error, "WDOG: Giving up: {}", reason);
+                shutdown.request(StopReason::Core(reason));
+                return;
+            }
+
+            restarts.push_back(now);
+            tlog!(stats, Level::Warn, "WDOG: {} task {}; restarting ({}/{})", name, outcome, restarts.len(), MAX_RESTARTS);
+            sleep(RESTART_BACKOFF).await;
+        }
+    })
+}
+
+fn describe_exit(result: Result<(), JoinError>) -> String {
+    match result {
+        Ok(()) => "exited".to_string(),
+        Err(e) if e.is_panic() => {
+            let payload = e.into_panic();
+            let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "<non-string panic>".to_string());
+            format!("panicked: {}", msg)
+        }
+        Err(_) => "was cancelled".to_string(),
+    }
+}
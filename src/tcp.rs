@@ -0,0 +1,185 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use socket2::SockRef;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// Bytes in front of every frame on the stream: its length, big-endian.
+pub const LEN_PREFIX: usize = 2;
+/// Largest frame the length prefix can carry.
+const MAX_FRAME: usize = u16::MAX as usize;
+
+/// How long a dial may take, and how long after a failed one sends to the same
+/// address fail straight away instead of dialling again.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+const DIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Frames read from the stream but not yet taken by `recv`. When full, reading the
+/// stream stops and TCP flow control pushes back on the peer.
+const INBOUND_QUEUE: usize = 1024;
+
+/// The tunnel's frames over TCP (`--transport tcp`), for networks that block or
+/// throttle UDP.
+///
+/// Each frame goes out as a big-endian `u16` length and the frame, in one write.
+/// Both ends listen on `--bind`; a send to an address with no stream dials one. A
+/// new stream, dialled or accepted, replaces the old, so a peer that reconnects
+/// (or both ends dialling at once) converges on one stream. The address a frame
+/// came from is the stream's remote end, so peer tracking and roaming work as on
+/// UDP: the listening side answers on the stream the dialler opened.
+pub struct TcpLink {
+    streams: Arc<Streams>,
+    inbound: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+    /// The last failed dial; held across a dial so concurrent sends don't each open a stream.
+    dial: tokio::sync::Mutex<Option<(SocketAddr, Instant)>>,
+}
+
+struct Streams {
+    active: Mutex<Option<Stream>>,
+    inbound: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+    next_id: AtomicU64,
+    tos: Option<u32>,
+}
+
+#[derive(Clone)]
+struct Stream {
+    id: u64,
+    peer: SocketAddr,
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+}
+
+impl TcpLink {
+    /// Listens on `bind`. `tos` (the DSCP already shifted into place) marks every stream.
+    pub async fn bind(bind: &str, tos: Option<u32>) -> Result<Self> {
+        let listener = TcpListener::bind(bind).await.context("Failed to bind TCP listener")?;
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_QUEUE);
+        let streams = Arc::new(Streams { active: Mutex::new(None), inbound: inbound_tx, next_id: AtomicU64::new(0), tos });
+        tokio::spawn(accept_loop(listener, streams.clone()));
+        Ok(Self { streams, inbound: tokio::sync::Mutex::new(inbound_rx), dial: tokio::sync::Mutex::new(None) })
+    }
+
+    /// Sends one frame to `target`, dialling it first if no stream goes there.
+    /// Waits while the stream's send buffer is full. Returns the frame's length; the
+    /// stream also carried `LEN_PREFIX` more.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        if buf.len() > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large for the TCP transport"));
+        }
+        let stream = match self.stream_to(target) {
+            Some(stream) => stream,
+            None => self.dial(target).await?,
+        };
+        let mut framed = Vec::with_capacity(LEN_PREFIX + buf.len());
+        framed.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        framed.extend_from_slice(buf);
+        let written = stream.writer.lock().await.write_all(&framed).await;
+        if let Err(e) = written {
+            // Broken: the next send dials again.
+            self.streams.close(stream.id);
+            return Err(e);
+        }
+        Ok(buf.len())
+    }
+
+    /// Receives the next frame from any stream. Frames longer than `buf` are cut
+    /// short, as a datagram would be.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (frame, peer) = self.inbound.lock().await.recv().await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "TCP listener stopped"))?;
+        let len = frame.len().min(buf.len());
+        buf[..len].copy_from_slice(&frame[..len]);
+        Ok((len, peer))
+    }
+
+    fn stream_to(&self, target: SocketAddr) -> Option<Stream> {
+        self.streams.active.lock().as_ref().filter(|s| s.peer == target).cloned()
+    }
+
+    async fn dial(&self, target: SocketAddr) -> io::Result<Stream> {
+        let mut last_failure = self.dial.lock().await;
+        // Another send may have connected while this one waited for the lock.
+        if let Some(stream) = self.stream_to(target) {
+            return Ok(stream);
+        }
+        if let Some((addr, at)) = *last_failure {
+            if addr == target && at.elapsed() < DIAL_BACKOFF {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "no TCP stream to the peer (retrying shortly)"));
+            }
+        }
+        let connected = match tokio::time::timeout(DIAL_TIMEOUT, TcpStream::connect(target)).await {
+            Ok(connected) => connected,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "TCP connect timed out")),
+        };
+        match connected {
+            Ok(tcp) => {
+                *last_failure = None;
+                Ok(self.streams.adopt(tcp, target))
+            }
+            Err(e) => {
+                *last_failure = Some((target, Instant::now()));
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Streams {
+    /// Makes `tcp` the active stream and starts reading it.
+    fn adopt(self: &Arc<Self>, tcp: TcpStream, peer: SocketAddr) -> Stream {
+        let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+        // Frames are small and latency-bound; don't hold them back for coalescing.
+        let _ = tcp.set_nodelay(true);
+        if let Some(tos) = self.tos {
+            let sock = SockRef::from(&tcp);
+            let _ = if peer.is_ipv6() { sock.set_tclass_v6(tos) } else { sock.set_tos_v4(tos) };
+        }
+        let (reader, writer) = tcp.into_split();
+        let stream = Stream { id: self.next_id.fetch_add(1, Ordering::Relaxed), peer, writer: Arc::new(tokio::sync::Mutex::new(writer)) };
+        tokio::spawn(read_loop(reader, stream.id, peer, self.clone()));
+        // Dropping the old stream's writer closes our side of it.
+        *self.active.lock() = Some(stream.clone());
+        stream
+    }
+
+    /// Forgets stream `id` if it is still the active one.
+    fn close(&self, id: u64) {
+        let mut active = self.active.lock();
+        if active.as_ref().is_some_and(|s| s.id == id) {
+            *active = None;
+        }
+    }
+}
+
+async fn accept_loop(listener: TcpListener, streams: Arc<Streams>) {
+    loop {
+        match listener.accept().await {
+            Ok((tcp, peer)) => {
+                streams.adopt(tcp, peer);
+            }
+            // Out of descriptors and the like: pause rather than spin.
+            Err(_) => tokio::time::sleep(DIAL_BACKOFF).await,
+        }
+    }
+}
+
+/// Splits the stream back into frames until it ends or fails.
+async fn read_loop(mut reader: OwnedReadHalf, id: u64, peer: SocketAddr, streams: Arc<Streams>) {
+    let mut len = [0u8; LEN_PREFIX];
+    loop {
+        if reader.read_exact(&mut len).await.is_err() {
+            break;
+        }
+        let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+        if reader.read_exact(&mut frame).await.is_err() || streams.inbound.send((frame, peer)).await.is_err() {
+            break;
+        }
+    }
+    streams.close(id);
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::tui::TelemetryUpdate;
+
+/// Queued periodic samples (throughput, link quality, peer/window state, counters) beyond
+/// which new ones are dropped. Each is superseded by the next report, so losing one under
+/// pressure only costs a stale frame.
+const SAMPLE_LIMIT: usize = 1024;
+
+/// Queued events of any kind beyond which even log lines and alert changes are dropped.
+/// The headroom above `SAMPLE_LIMIT` is reserved for them; the hard cap keeps a stalled
+/// consumer from growing the queue without bound.
+const ESSENTIAL_LIMIT: usize = 8192;
+
+/// Counters shared by every hop of one telemetry pipeline.
+#[derive(Default)]
+struct Accounting {
+    dropped: AtomicU64,
+}
+
+/// Creates the telemetry channel from the networking core to its consumer.
+pub fn channel() -> (TelemetrySender, TelemetryReceiver) {
+    channel_with(Arc::new(Accounting::default()))
+}
+
+fn channel_with(accounting: Arc<Accounting>) -> (TelemetrySender, TelemetryReceiver) {
+    let (tx, rx) = mpsc::channel();
+    let queued = Arc::new(AtomicUsize::new(0));
+    (
+        TelemetrySender { tx, queued: queued.clone(), accounting: accounting.clone() },
+        TelemetryReceiver { rx, queued, accounting },
+    )
+}
+
+/// Never blocks: an update that does not fit is counted and discarded.
+#[derive(Clone)]
+pub struct TelemetrySender {
+    tx: mpsc::Sender<TelemetryUpdate>,
+    queued: Arc<AtomicUsize>,
+    accounting: Arc<Accounting>,
+}
+
+/// The update was not queued: the consumer is gone or too far behind.
+#[derive(Debug)]
+pub struct Dropped;
+
+impl TelemetrySender {
+    pub fn send(&self, update: TelemetryUpdate) -> Result<(), Dropped> {
+        let limit = match update {
+            // The consumer's exit depends on it; one event cannot grow the queue.
+            TelemetryUpdate::Shutdown { .. } => usize::MAX,
+            // Not repeated later (a cleared alert set, a state change or a reset is sent once).
+            TelemetryUpdate::Log { .. } | TelemetryUpdate::Alerts(_) | TelemetryUpdate::Connection(_) | TelemetryUpdate::StatsReset => ESSENTIAL_LIMIT,
+            _ => SAMPLE_LIMIT,
+        };
+        if self.queued.fetch_add(1, Ordering::Relaxed) >= limit {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            self.accounting.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(Dropped);
+        }
+        self.tx.send(update).map_err(|_| {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            Dropped
+        })
+    }
+}
+
+pub struct TelemetryReceiver {
+    rx: mpsc::Receiver<TelemetryUpdate>,
+    queued: Arc<AtomicUsize>,
+    accounting: Arc<Accounting>,
+}
+
+impl TelemetryReceiver {
+    pub fn try_recv(&self) -> Result<TelemetryUpdate, mpsc::TryRecvError> {
+        self.rx.try_recv().inspect(|_| self.dequeued())
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<TelemetryUpdate, mpsc::RecvTimeoutError> {
+        self.rx.recv_timeout(timeout).inspect(|_| self.dequeued())
+    }
+
+    /// Updates dropped anywhere in this pipeline so far. Non-zero means totals and
+    /// graphs downstream may undercount.
+    pub fn dropped(&self) -> u64 {
+        self.accounting.dropped.load(Ordering::Relaxed)
+    }
+
+    /// A further hop (e.g. a tap that forwards what it reads). It is bounded the same
+    /// way and its drops count towards the same total.
+    pub fn relay(&self) -> (TelemetrySender, TelemetryReceiver) {
+        channel_with(self.accounting.clone())
+    }
+
+    fn dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
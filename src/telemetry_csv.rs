@@ -0,0 +1,201 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+
+use crate::logging::{tlog, Level};
+use crate::telemetry::{TelemetryReceiver, TelemetrySender};
+use crate::telemetry_json::Snapshot;
+
+const HEADER: &str = "timestamp_utc,uptime_s,tx_bytes,rx_bytes,tx_packets,rx_packets,tx_bytes_per_sec,rx_bytes_per_sec,\
+srtt_ms,jitter_ms,loss_rate,retransmits,retransmits_per_sec,window_in_flight,window_capacity,in_flight_bytes,\
+tx_wire_bytes,rx_wire_bytes,tx_wire_bytes_per_sec,rx_wire_bytes_per_sec";
+
+/// How often the writer looks for `finish` while waiting for telemetry.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Periodic stats rows in a CSV file (`--stats-csv`), for soak tests and spreadsheets.
+///
+/// Like the JSON tap, it interposes on the telemetry channel and aggregates the same
+/// updates the dashboard sees, on its own thread: a slow disk delays rows, never the
+/// data path. An existing file is appended to. Once it reaches the size cap it is
+/// renamed to `<path>.1` (replacing any older one) and a fresh file is started.
+pub struct CsvExport {
+    finish: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CsvExport {
+    /// Opens `path` and starts the tap; updates continue to the returned receiver.
+    pub fn spawn(
+        rx: TelemetryReceiver,
+        path: &Path,
+        interval: Duration,
+        max_bytes: u64,
+        stats: TelemetrySender,
+    ) -> Result<(Self, TelemetryReceiver)> {
+        let out = CsvFile::open(path.to_path_buf(), max_bytes)?;
+        let (forward_tx, forward_rx) = rx.relay();
+        let finish = Arc::new(AtomicBool::new(false));
+        let stop = finish.clone();
+        let handle = thread::Builder::new()
+            .name("telemetry-csv".to_string())
+            .spawn(move || writer_loop(rx, forward_tx, out, interval, &stop, &stats))
+            .context("Failed to spawn CSV writer thread")?;
+        Ok((Self { finish, handle }, forward_rx))
+    }
+
+    /// Writes a last row for the time since the previous one and closes the file.
+    pub fn finish(self) {
+        self.finish.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+fn writer_loop(
+    rx: TelemetryReceiver,
+    forward_tx: TelemetrySender,
+    mut out: CsvFile,
+    interval: Duration,
+    finish: &AtomicBool,
+    stats: &TelemetrySender,
+) {
+    let started = Instant::now();
+    let mut snapshot = Snapshot::default();
+    let mut rates = RateTracker::new(started);
+    let mut next_row = started + interval;
+    let mut failed = false;
+
+    loop {
+        let finishing = finish.load(Ordering::Relaxed);
+        if !finishing {
+            let timeout = next_row.saturating_duration_since(Instant::now()).min(POLL_INTERVAL);
+            match rx.recv_timeout(timeout) {
+                Ok(update) => {
+                    snapshot.absorb(&update);
+                    let _ = forward_tx.send(update);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if finishing || Instant::now() >= next_row {
+            let row = rates.row(&snapshot, started);
+            // Report the first failure only; later rows keep trying (the disk may recover).
+            match out.append(&row) {
+                Ok(()) => failed = false,
+                Err(e) if !failed => {
+                    failed = true;
+                    tlog!(stats, Level::Error, "CSV::WriteErr: {:#}", e);
+                }
+                Err(_) => {}
+            }
+            next_row += interval;
+        }
+        if finishing {
+            break;
+        }
+    }
+}
+
+/// Turns running totals into per-second rates over the time since the last row.
+struct RateTracker {
+    at: Instant,
+    tx_bytes: u64,
+    rx_bytes: u64,
+    tx_wire: u64,
+    rx_wire: u64,
+}
+
+impl RateTracker {
+    fn new(now: Instant) -> Self {
+        Self { at: now, tx_bytes: 0, rx_bytes: 0, tx_wire: 0, rx_wire: 0 }
+    }
+
+    fn row(&mut self, s: &Snapshot, started: Instant) -> String {
+        let now = Instant::now();
+        let secs = now.duration_since(self.at).as_secs_f64().max(f64::EPSILON);
+        let tx_rate = s.tx_bytes_total.saturating_sub(self.tx_bytes) as f64 / secs;
+        let rx_rate = s.rx_bytes_total.saturating_sub(self.rx_bytes) as f64 / secs;
+        let tx_wire_rate = s.tx_wire_bytes_total.saturating_sub(self.tx_wire) as f64 / secs;
+        let rx_wire_rate = s.rx_wire_bytes_total.saturating_sub(self.rx_wire) as f64 / secs;
+        *self = Self { at: now, tx_bytes: s.tx_bytes_total, rx_bytes: s.rx_bytes_total, tx_wire: s.tx_wire_bytes_total, rx_wire: s.rx_wire_bytes_total };
+
+        // Absent measurements are empty cells, which spreadsheets treat as blanks.
+        let opt = |v: Option<f64>| v.map_or(String::new(), |v| format!("{:.3}", v));
+        let retransmits = s.counters.get("retransmits").copied().unwrap_or(0);
+        format!(
+            "{},{:.1},{},{},{},{},{:.0},{:.0},{},{},{},{},{:.2},{},{},{},{},{},{:.0},{:.0}",
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            now.duration_since(started).as_secs_f64(),
+            s.tx_bytes_total,
+            s.rx_bytes_total,
+            s.tx_packets_total,
+            s.rx_packets_total,
+            tx_rate,
+            rx_rate,
+            opt(s.quality.srtt_ms),
+            opt(s.quality.jitter_ms),
+            opt(s.quality.loss_rate),
+            retransmits,
+            s.retransmits_per_sec,
+            s.in_flight,
+            s.capacity,
+            s.in_flight_bytes,
+            s.tx_wire_bytes_total,
+            s.rx_wire_bytes_total,
+            tx_wire_rate,
+            rx_wire_rate,
+        )
+    }
+}
+
+/// The output file, rotated at `max_bytes`.
+struct CsvFile {
+    path: PathBuf,
+    max_bytes: u64,
+    out: BufWriter<File>,
+    written: u64,
+}
+
+impl CsvFile {
+    fn open(path: PathBuf, max_bytes: u64) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .with_context(|| format!("Failed to open stats CSV {}", path.display()))?;
+        let written = file.metadata()?.len();
+        let mut csv = Self { path, max_bytes, out: BufWriter::new(file), written };
+        if written == 0 {
+            csv.write_line(HEADER)?;
+        }
+        Ok(csv)
+    }
+
+    /// Appends a row and flushes it, rotating first if the file is full.
+    fn append(&mut self, row: &str) -> Result<()> {
+        if self.written + row.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.write_line(row)
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.out.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated).with_context(|| format!("Failed to rotate {}", self.path.display()))?;
+        *self = Self::open(self.path.clone(), self.max_bytes)?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::alerts::Alert;
+use crate::compression::{self, Algorithm, CompressionReport, DictionaryStats};
+use crate::handshake::HandshakeState;
+use crate::metrics::{self, CounterSnapshot, PacketSizes, WireBytes, SIZE_BUCKET_BOUNDS};
+use crate::mimicry::Mimicry;
+use crate::quota::QuotaStatus;
+use crate::stats::{self, EchoLoss, LinkQuality, Percentiles};
+use crate::telemetry::TelemetryReceiver;
+use crate::tui::{self, PeerInfo, TelemetryUpdate};
+
+/// Bumped on any incompatible change to the records below (renamed/removed fields,
+/// changed units). Adding fields or event types is compatible.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Interval between aggregated `snapshot` records.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One line of output: `{"schema":1,"ts":"...","event":"<type>", ...fields}`.
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub schema: u32,
+    /// RFC 3339 UTC timestamp with millisecond precision.
+    pub ts: String,
+    #[serde(flatten)]
+    pub event: Event<'a>,
+}
+
+/// Event payloads, one per `TelemetryUpdate` variant plus the periodic `snapshot`.
+/// Durations are milliseconds, ratios are 0.0..=1.0, absent measurements are `null`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Throughput { tx_bytes: u64, rx_bytes: u64, tx_packets: u64, rx_packets: u64, tx_wire_bytes: u64, rx_wire_bytes: u64 },
+    LinkQuality(Quality),
+    EchoLoss { forward: Option<f64>, reverse: Option<f64> },
+    Latency(Latency),
+    PeerState(Peer),
+    Settings(Settings),
+    Window { in_flight: usize, capacity: usize, in_flight_bytes: usize, retransmits_per_sec: f64, pacing_frames_per_sec: Option<f64>, ecn_marked: Option<f64> },
+    Counters { counters: BTreeMap<&'static str, u64> },
+    PacketSizes { buckets: Vec<SizeBucket> },
+    Overhead(Overhead),
+    Dictionary(Dictionary),
+    Compression(Compression),
+    Quota(Quota),
+    Log { level: &'static str, msg: &'a str },
+    Alerts { active: &'a [Alert] },
+    ConnectionState { state: &'static str },
+    /// The dashboard's totals were zeroed; the `snapshot` totals are not.
+    StatsReset,
+    Shutdown { reason: &'a str },
+    Snapshot(Box<Snapshot>),
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Quality {
+    pub loss_rate: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    pub srtt_ms: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct Peer {
+    pub addr: Option<String>,
+    pub established: bool,
+    pub last_auth_age_ms: Option<u64>,
+    pub wire_format: Option<String>,
+    /// Protocol version agreed with the peer: the lower of the two ends'.
+    pub protocol_version: Option<u16>,
+    /// AEAD data is sealed with: `chacha20-poly1305` or `aes-256-gcm`.
+    pub cipher: Option<&'static str>,
+    /// Fingerprint (8 hex digits) of the pre-shared key frames are sealed with, and of
+    /// one installed by `rekey` that waits for the peer to have it too.
+    pub key_fingerprint: Option<String>,
+    pub next_key_fingerprint: Option<String>,
+    pub roam_count: u64,
+    pub handshake: &'static str,
+    /// Why the handshake gave up (`handshake` is `failed`).
+    pub handshake_failure: Option<String>,
+}
+
+/// The knobs in effect, runtime toggles included. `obfs` is `off`, `light` or `full`;
+/// `min_jitter_ms` and `max_jitter_ms` are 0 while jitter is toggled off, and
+/// `jitter_bulk` is true while `--jitter-below` has it standing aside (`null` without it).
+/// `chaff` is the `--chaff` profile and `chaff_rate` its rate (`null` without it);
+/// `chaff_agreed` is whether the peer understands chaff, so it is actually sent.
+/// `tls_records` is whether every datagram goes out as TLS records (`--mimic tls`),
+/// `dns_mimicry` whether as DNS messages (`--mimic dns`), `quic_mimicry` whether as
+/// QUIC packets (`--mimic quic`) and `wireguard_mimicry` whether as WireGuard messages
+/// (`--mimic wireguard`).
+/// `morph_distance` is how far `--morph` frame sizes are from the target (0 to 1; `null`
+/// before the first frame) and `morph_overhead` the bytes it added, as a fraction (both
+/// `null` without it, or while the peer doesn't understand morphed frames). `compression` is true while
+/// outgoing packets are compressed: switched on, and `compression_algorithm`
+/// (`off`, `lz4` or `zstd`) accepted by the peer. `compression_dictionary` is true
+/// while small packets go out against the dictionary. `peer_compression` is whether the
+/// peer compresses what it sends (`null` if it can't say). `compression_rx_algorithm` is
+/// what this end decodes, and `peer_compression_algorithm` and `peer_compression_level`
+/// what the peer says it sends with (`null` if it didn't). `compression_auto` is
+/// whether `--compress-auto` has compression on (`false`: suspended; `null` without
+/// it). `compression_adapt` is whether `--compress-adapt` has compression on (`false`:
+/// stepped down to raw; `null` without it), with `compression_level` following its
+/// steps. `pacing` is `--pacing` as toggled.
+#[derive(Serialize, Clone, Default)]
+pub struct Settings {
+    pub jitter: bool,
+    pub obfs: &'static str,
+    pub min_jitter_ms: u64,
+    pub max_jitter_ms: u64,
+    pub jitter_bulk: Option<bool>,
+    pub chaff: Option<&'static str>,
+    pub chaff_rate: Option<u32>,
+    pub chaff_agreed: bool,
+    pub tls_records: bool,
+    pub dns_mimicry: bool,
+    pub quic_mimicry: bool,
+    pub wireguard_mimicry: bool,
+    pub morph_distance: Option<f64>,
+    pub morph_overhead: Option<f64>,
+    pub adaptive: bool,
+    pub compression: bool,
+    pub compression_algorithm: &'static str,
+    /// Zstd only; `null` otherwise.
+    pub compression_level: Option<i32>,
+    pub compression_dictionary: bool,
+    pub peer_compression: Option<bool>,
+    pub compression_rx_algorithm: &'static str,
+    pub peer_compression_algorithm: Option<&'static str>,
+    /// Zstd only; `null` otherwise.
+    pub peer_compression_level: Option<i32>,
+    pub compression_auto: Option<bool>,
+    pub compression_adapt: Option<bool>,
+    pub pacing: bool,
+    pub connected_socket: bool,
+}
+
+impl From<&tui::Settings> for Settings {
+    fn from(s: &tui::Settings) -> Self {
+        Self {
+            jitter: s.jitter,
+            obfs: s.obfs.label(),
+            min_jitter_ms: if s.jitter { s.jitter_range.min_ms(s.obfs) } else { 0 },
+            max_jitter_ms: if s.jitter { s.jitter_range.max_ms(s.obfs) } else { 0 },
+            jitter_bulk: s.jitter_bulk,
+            chaff: s.chaff.map(|(profile, _)| profile.label()),
+            chaff_rate: s.chaff.map(|(_, rate)| rate),
+            chaff_agreed: s.chaff.is_some() && s.peer_chaff,
+            tls_records: s.mimic == Some(Mimicry::Tls),
+            dns_mimicry: s.mimic == Some(Mimicry::Dns),
+            quic_mimicry: s.mimic == Some(Mimicry::Quic),
+            wireguard_mimicry: s.mimic == Some(Mimicry::Wireguard),
+            morph_distance: s.morph.filter(|morph| morph.agreed).and_then(|morph| morph.distance),
+            morph_overhead: s.morph.filter(|morph| morph.agreed).map(|morph| morph.overhead),
+            adaptive: s.adaptive,
+            compression: s.compression && s.outbound != Algorithm::Off && s.auto.is_none_or(|a| a.active) && s.adapt.is_none_or(|a| a.level.is_some()),
+            compression_algorithm: s.algorithm.name(),
+            compression_level: (s.algorithm == Algorithm::Zstd).then_some(s.level),
+            compression_dictionary: s.compression && s.dictionary,
+            peer_compression: s.peer_compression,
+            compression_rx_algorithm: s.inbound.name(),
+            peer_compression_algorithm: s.peer_algorithm.map(|(a, _)| a.name()),
+            peer_compression_level: s.peer_algorithm.and_then(|(a, level)| (a == Algorithm::Zstd).then_some(level)),
+            compression_auto: s.auto.map(|a| a.active),
+            compression_adapt: s.adapt.map(|a| a.level.is_some()),
+            pacing: s.pacing,
+            connected_socket: s.connected_socket,
+        }
+    }
+}
+
+/// Usage of the current session. `bytes` and `packets` are goodput, both directions
+/// together, as the limits count them; limits and remainders are `null` when unset.
+#[derive(Serialize, Clone, Default)]
+pub struct Quota {
+    pub state: &'static str,
+    /// Why the session is throttled or disconnected.
+    pub reason: Option<String>,
+    pub goodput_tx_bytes: u64,
+    pub goodput_rx_bytes: u64,
+    pub wire_tx_bytes: u64,
+    pub wire_rx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_packets: u64,
+    pub bytes_limit: Option<u64>,
+    pub bytes_remaining: Option<u64>,
+    pub packets_limit: Option<u64>,
+    pub packets_remaining: Option<u64>,
+}
+
+/// One bucket of the packet size histograms (cumulative packet counts).
+#[derive(Serialize, Clone)]
+pub struct SizeBucket {
+    /// Largest packet size in the bucket; `null` for the last one (up to the MTU).
+    pub max_bytes: Option<usize>,
+    pub tx: u64,
+    pub rx: u64,
+}
+
+/// Small packets compressed against the zstd dictionary (cumulative). `id` is hex;
+/// `ratio` is raw over sent bytes; `sampled_ratio_without` and `sampled_ratio_with`
+/// compare plain zstd and the dictionary on every 16th of those packets.
+#[derive(Serialize, Clone, Default)]
+pub struct Dictionary {
+    pub id: String,
+    pub agreed: bool,
+    pub packets: u64,
+    pub ratio: Option<f64>,
+    pub sampled_ratio_without: Option<f64>,
+    pub sampled_ratio_with: Option<f64>,
+}
+
+impl From<DictionaryStats> for Dictionary {
+    fn from(d: DictionaryStats) -> Self {
+        let sampled = d.sampled_ratios();
+        Self {
+            id: format!("{:08x}", d.id),
+            agreed: d.agreed,
+            packets: d.packets,
+            ratio: d.ratio(),
+            sampled_ratio_without: sampled.map(|(without, _)| without),
+            sampled_ratio_with: sampled.map(|(_, with)| with),
+        }
+    }
+}
+
+/// What went through the compressor (`tx`) and decompressor (`rx`). Totals are for the
+/// session; `window_*` fields cover the last 10s. Byte counts are inner packets (`raw`)
+/// and payloads with their marker byte (`wire`); `ratio` is raw over wire. Outgoing,
+/// `uncompressed_packets` were sent raw by the heuristics (too small, high entropy,
+/// no gain); incoming, they arrived raw.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Compression {
+    pub tx: CodecDirection,
+    pub rx: CodecDirection,
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct CodecDirection {
+    pub packets: u64,
+    pub raw_bytes: u64,
+    pub wire_bytes: u64,
+    pub uncompressed_packets: u64,
+    pub ratio: Option<f64>,
+    pub us_per_packet: Option<f64>,
+    pub window_packets: u64,
+    pub window_ratio: Option<f64>,
+    pub window_us_per_packet: Option<f64>,
+}
+
+impl From<CompressionReport> for Compression {
+    fn from(r: CompressionReport) -> Self {
+        let direction = |session: compression::DirectionTotals, window: compression::DirectionTotals| CodecDirection {
+            packets: session.packets,
+            raw_bytes: session.raw_bytes,
+            wire_bytes: session.wire_bytes,
+            uncompressed_packets: session.uncompressed,
+            ratio: session.ratio(),
+            us_per_packet: session.micros_per_packet(),
+            window_packets: window.packets,
+            window_ratio: window.ratio(),
+            window_us_per_packet: window.micros_per_packet(),
+        };
+        Self { tx: direction(r.session.tx, r.window.tx), rx: direction(r.session.rx, r.window.rx) }
+    }
+}
+
+/// Cumulative bytes each way: UDP payload on the wire, inner packets, and what the
+/// wire bytes carried (the categories add up to `wire`).
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Overhead {
+    pub tx: Bytes,
+    pub rx: Bytes,
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Bytes {
+    pub wire: u64,
+    pub inner: u64,
+    pub payload: u64,
+    pub crypto: u64,
+    pub framing: u64,
+    pub retransmit: u64,
+    pub control: u64,
+    pub padding: u64,
+    pub other: u64,
+    /// `inner / wire`; `null` before anything moved.
+    pub efficiency: Option<f64>,
+}
+
+impl From<metrics::Overhead> for Overhead {
+    fn from(o: metrics::Overhead) -> Self {
+        Self { tx: o.tx.into(), rx: o.rx.into() }
+    }
+}
+
+impl From<WireBytes> for Bytes {
+    fn from(b: WireBytes) -> Self {
+        Self {
+            wire: b.wire,
+            inner: b.inner,
+            payload: b.payload,
+            crypto: b.crypto,
+            framing: b.framing,
+            retransmit: b.retransmit,
+            control: b.control,
+            padding: b.padding,
+            other: b.other(),
+            efficiency: b.efficiency(),
+        }
+    }
+}
+
+/// Running totals and latest values, emitted every `SNAPSHOT_INTERVAL`.
+#[derive(Serialize, Clone, Default)]
+pub struct Snapshot {
+    pub uptime_ms: u64,
+    pub tx_bytes_total: u64,
+    pub rx_bytes_total: u64,
+    pub tx_packets_total: u64,
+    pub rx_packets_total: u64,
+    /// UDP payload bytes each way; the `*_bytes_total` above are goodput.
+    pub tx_wire_bytes_total: u64,
+    pub rx_wire_bytes_total: u64,
+    pub quality: Quality,
+    pub latency: Latency,
+    pub peer: Peer,
+    pub settings: Settings,
+    pub in_flight: usize,
+    pub capacity: usize,
+    pub in_flight_bytes: usize,
+    pub retransmits_per_sec: f64,
+    /// Frames per second pacing allows; `null` while pacing is off or before the first RTT sample.
+    pub pacing_frames_per_sec: Option<f64>,
+    /// Share of received data frames marked CE since the previous snapshot; `null` without ECN.
+    pub ecn_marked: Option<f64>,
+    pub counters: BTreeMap<&'static str, u64>,
+    pub packet_sizes: Vec<SizeBucket>,
+    pub overhead: Overhead,
+    /// `null` without a dictionary.
+    pub dictionary: Option<Dictionary>,
+    /// `null` with `--compress off`.
+    pub compression: Option<Compression>,
+    pub quota: Quota,
+    pub alerts: Vec<Alert>,
+    /// As in the `connection_state` event: `LISTENING`, `HANDSHAKING`, `ESTABLISHED`, ...
+    pub connection_state: &'static str,
+    /// Telemetry updates dropped because a consumer fell behind; non-zero means the
+    /// totals above may undercount.
+    pub telemetry_dropped: u64,
+}
+
+/// RTT percentiles over the whole session and the last minute.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Latency {
+    pub session: Percentiles,
+    pub last_minute: Percentiles,
+}
+
+impl From<stats::Latency> for Latency {
+    fn from(l: stats::Latency) -> Self {
+        Self { session: l.session, last_minute: l.window }
+    }
+}
+
+impl From<LinkQuality> for Quality {
+    fn from(q: LinkQuality) -> Self {
+        Self { loss_rate: q.loss_rate, jitter_ms: q.jitter_ms, srtt_ms: q.srtt_ms }
+    }
+}
+
+impl From<&PeerInfo> for Peer {
+    fn from(p: &PeerInfo) -> Self {
+        Self {
+            addr: p.addr.map(|a| a.to_string()),
+            established: p.established,
+            last_auth_age_ms: p.last_auth_age.map(|d| d.as_millis() as u64),
+            wire_format: p.wire_format.map(|f| format!("{:?}", f).to_lowercase()),
+            protocol_version: p.protocol,
+            cipher: p.cipher.map(|c| c.name()),
+            key_fingerprint: p.key.map(|k| format!("{:08x}", k.current)),
+            next_key_fingerprint: p.key.and_then(|k| k.next).map(|k| format!("{:08x}", k)),
+            roam_count: p.roam_count,
+            handshake: p.handshake.name(),
+            handshake_failure: match &p.handshake {
+                HandshakeState::Failed { reason } => Some(reason.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<&QuotaStatus> for Quota {
+    fn from(q: &QuotaStatus) -> Self {
+        Self {
+            state: q.state.name(),
+            reason: q.state.reason().map(str::to_string),
+            goodput_tx_bytes: q.usage.goodput_tx,
+            goodput_rx_bytes: q.usage.goodput_rx,
+            wire_tx_bytes: q.usage.wire_tx,
+            wire_rx_bytes: q.usage.wire_rx,
+            tx_packets: q.usage.packets_tx,
+            rx_packets: q.usage.packets_rx,
+            bytes_limit: q.bytes_limit,
+            bytes_remaining: q.bytes_remaining(),
+            packets_limit: q.packets_limit,
+            packets_remaining: q.packets_remaining(),
+        }
+    }
+}
+
+fn counter_map(snapshot: &CounterSnapshot) -> BTreeMap<&'static str, u64> {
+    snapshot.rows().into_iter().collect()
+}
+
+fn size_buckets(sizes: &PacketSizes) -> Vec<SizeBucket> {
+    sizes.tx.iter().zip(&sizes.rx).enumerate()
+        .map(|(i, (&tx, &rx))| SizeBucket { max_bytes: SIZE_BUCKET_BOUNDS.get(i).copied(), tx, rx })
+        .collect()
+}
+
+/// Maps a telemetry update onto its record payload.
+pub fn event_for(update: &TelemetryUpdate) -> Event<'_> {
+    match update {
+        TelemetryUpdate::Throughput { tx_bytes, rx_bytes, tx_packets, rx_packets, tx_wire, rx_wire } => Event::Throughput {
+            tx_bytes: *tx_bytes,
+            rx_bytes: *rx_bytes,
+            tx_packets: *tx_packets,
+            rx_packets: *rx_packets,
+            tx_wire_bytes: *tx_wire,
+            rx_wire_bytes: *rx_wire,
+        },
+        TelemetryUpdate::LinkQuality(q) => Event::LinkQuality((*q).into()),
+        TelemetryUpdate::EchoLoss(EchoLoss { forward, reverse }) => Event::EchoLoss { forward: *forward, reverse: *reverse },
+        TelemetryUpdate::Latency(l) => Event::Latency((*l).into()),
+        TelemetryUpdate::PeerState(info) => Event::PeerState(info.into()),
+        TelemetryUpdate::Settings(s) => Event::Settings(s.into()),
+        TelemetryUpdate::Window(w) => Event::Window {
+            in_flight: w.in_flight,
+            capacity: w.capacity,
+            in_flight_bytes: w.bytes,
+            retransmits_per_sec: w.retransmit_rate,
+            pacing_frames_per_sec: w.pacing_rate,
+            ecn_marked: w.ecn_mark_rate,
+        },
+        TelemetryUpdate::Counters(snapshot) => Event::Counters { counters: counter_map(snapshot) },
+        TelemetryUpdate::PacketSizes(sizes) => Event::PacketSizes { buckets: size_buckets(sizes) },
+        TelemetryUpdate::Overhead(o) => Event::Overhead((*o).into()),
+        TelemetryUpdate::Dictionary(d) => Event::Dictionary((*d).into()),
+        TelemetryUpdate::Compression(r) => Event::Compression((*r).into()),
+        TelemetryUpdate::Quota(status) => Event::Quota(status.into()),
+        TelemetryUpdate::Log { level, msg } => Event::Log { level: level.name(), msg },
+        TelemetryUpdate::Alerts(active) => Event::Alerts { active },
+        TelemetryUpdate::Connection(state) => Event::ConnectionState { state: state.name() },
+        TelemetryUpdate::StatsReset => Event::StatsReset,
+        TelemetryUpdate::Shutdown { reason } => Event::Shutdown { reason },
+    }
+}
+
+/// Serializes one record as a JSON line (without the trailing newline).
+pub fn to_line(event: Event<'_>) -> String {
+    let record = Record {
+        schema: SCHEMA_VERSION,
+        ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        event,
+    };
+    // Every field is a plain number, bool, string or map with string keys: cannot fail.
+    serde_json::to_string(&record).unwrap_or_default()
+}
+
+impl Snapshot {
+    /// Folds one update into the running totals and latest values.
+    pub fn absorb(&mut self, update: &TelemetryUpdate) {
+        match update {
+            TelemetryUpdate::Throughput { tx_bytes, rx_bytes, tx_packets, rx_packets, tx_wire, rx_wire } => {
+                self.tx_bytes_total += tx_bytes;
+                self.rx_bytes_total += rx_bytes;
+                self.tx_packets_total += tx_packets;
+                self.rx_packets_total += rx_packets;
+                self.tx_wire_bytes_total += tx_wire;
+                self.rx_wire_bytes_total += rx_wire;
+            }
+            TelemetryUpdate::LinkQuality(q) => self.quality = (*q).into(),
+            TelemetryUpdate::Latency(l) => self.latency = (*l).into(),
+            TelemetryUpdate::PeerState(info) => self.peer = info.into(),
+            TelemetryUpdate::Settings(s) => self.settings = s.into(),
+            TelemetryUpdate::Window(w) => {
+                self.in_flight = w.in_flight;
+                self.capacity = w.capacity;
+                self.in_flight_bytes = w.bytes;
+                self.retransmits_per_sec = w.retransmit_rate;
+                self.pacing_frames_per_sec = w.pacing_rate;
+                self.ecn_marked = w.ecn_mark_rate;
+            }
+            TelemetryUpdate::Counters(snapshot) => self.counters = counter_map(snapshot),
+            TelemetryUpdate::PacketSizes(sizes) => self.packet_sizes = size_buckets(sizes),
+            TelemetryUpdate::Overhead(o) => self.overhead = (*o).into(),
+            TelemetryUpdate::Dictionary(d) => self.dictionary = Some((*d).into()),
+            TelemetryUpdate::Compression(r) => self.compression = Some((*r).into()),
+            TelemetryUpdate::Quota(status) => self.quota = status.into(),
+            TelemetryUpdate::Alerts(active) => self.alerts = active.clone(),
+            TelemetryUpdate::Connection(state) => self.connection_state = state.name(),
+            _ => {}
+        }
+    }
+}
+
+/// Opens the JSON output: `-` is stdout, anything else a file (truncated).
+pub fn open_output(path: &Path) -> Result<Box<dyn Write + Send>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdout()));
+    }
+    let file = File::create(path).with_context(|| format!("Failed to create telemetry file {}", path.display()))?;
+    Ok(Box::new(file))
+}
+
+/// Interposes on the telemetry channel: every update is written as a JSON line and
+/// then forwarded unchanged to the returned receiver (the dashboard or headless sink).
+pub fn spawn_tap(rx: TelemetryReceiver, out: Box<dyn Write + Send>) -> TelemetryReceiver {
+    let (forward_tx, forward_rx) = rx.relay();
+    thread::Builder::new()
+        .name("telemetry-json".to_string())
+        .spawn(move || {
+            let mut out = BufWriter::new(out);
+            let started = Instant::now();
+            let mut snapshot = Snapshot::default();
+            let mut next_snapshot = started + SNAPSHOT_INTERVAL;
+
+            loop {
+                let timeout = next_snapshot.saturating_duration_since(Instant::now());
+                match rx.recv_timeout(timeout) {
+                    Ok(update) => {
+                        snapshot.absorb(&update);
+                        let _ = writeln!(out, "{}", to_line(event_for(&update)));
+                        let _ = forward_tx.send(update);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+
+                if Instant::now() >= next_snapshot {
+                    snapshot.uptime_ms = started.elapsed().as_millis() as u64;
+                    snapshot.telemetry_dropped = rx.dropped();
+                    let _ = writeln!(out, "{}", to_line(Event::Snapshot(Box::new(snapshot.clone()))));
+                    next_snapshot += SNAPSHOT_INTERVAL;
+                }
+                // Lines go out as they happen; a consumer tailing the stream never waits on a buffer.
+                let _ = out.flush();
+            }
+            let _ = out.flush();
+        })
+        .expect("failed to spawn telemetry thread");
+    forward_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::connection::ConnectionState;
+    use crate::logging::Level;
+
+    fn line_for(update: &TelemetryUpdate) -> Value {
+        serde_json::from_str(&to_line(event_for(update))).expect("every record is valid JSON")
+    }
+
+    fn throughput(bytes: u64) -> TelemetryUpdate {
+        TelemetryUpdate::Throughput { tx_bytes: bytes, rx_bytes: 2 * bytes, tx_packets: 1, rx_packets: 2, tx_wire: bytes + 40, rx_wire: 2 * bytes + 80 }
+    }
+
+    #[test]
+    fn every_record_carries_schema_timestamp_and_event() {
+        let record = line_for(&TelemetryUpdate::StatsReset);
+        assert_eq!(record["schema"], json!(SCHEMA_VERSION));
+        assert_eq!(record["event"], "stats_reset");
+        let ts = record["ts"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok(), "{}", ts);
+        assert!(ts.ends_with('Z'));
+    }
+
+    #[test]
+    fn throughput_fields_keep_their_names() {
+        let record = line_for(&throughput(100));
+        assert_eq!(record["event"], "throughput");
+        for (field, value) in [("tx_bytes", 100), ("rx_bytes", 200), ("tx_packets", 1), ("rx_packets", 2), ("tx_wire_bytes", 140), ("rx_wire_bytes", 280)] {
+            assert_eq!(record[field], json!(value), "{}", field);
+        }
+    }
+
+    #[test]
+    fn absent_measurements_are_null() {
+        let record = line_for(&TelemetryUpdate::LinkQuality(LinkQuality { loss_rate: Some(0.25), jitter_ms: None, srtt_ms: None }));
+        assert_eq!(record["event"], "link_quality");
+        assert_eq!(record["loss_rate"], json!(0.25));
+        assert_eq!(record["jitter_ms"], Value::Null);
+        assert_eq!(record["srtt_ms"], Value::Null);
+    }
+
+    #[test]
+    fn log_connection_and_shutdown_records() {
+        let log = line_for(&TelemetryUpdate::Log { level: Level::Warn, msg: "NET: \"quoted\"\nline".to_string() });
+        assert_eq!(log["event"], "log");
+        assert_eq!(log["level"], Level::Warn.name());
+        assert_eq!(log["msg"], "NET: \"quoted\"\nline");
+
+        let state = line_for(&TelemetryUpdate::Connection(ConnectionState::Established));
+        assert_eq!(state["event"], "connection_state");
+        assert_eq!(state["state"], "ESTABLISHED");
+
+        let shutdown = line_for(&TelemetryUpdate::Shutdown { reason: "SIGTERM".to_string() });
+        assert_eq!(shutdown["event"], "shutdown");
+        assert_eq!(shutdown["reason"], "SIGTERM");
+    }
+
+    #[test]
+    fn counters_are_a_map_of_every_row() {
+        let record = line_for(&TelemetryUpdate::Counters(CounterSnapshot::default()));
+        let counters = record["counters"].as_object().unwrap();
+        let rows = CounterSnapshot::default().rows();
+        assert_eq!(counters.len(), rows.len());
+        for (name, _) in rows {
+            assert_eq!(counters[name], json!(0), "{}", name);
+        }
+    }
+
+    #[test]
+    fn records_are_one_line() {
+        let line = to_line(event_for(&TelemetryUpdate::Log { level: Level::Info, msg: "a\nb".to_string() }));
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn snapshot_sums_throughput_and_keeps_latest_values() {
+        let mut snapshot = Snapshot::default();
+        snapshot.absorb(&throughput(100));
+        snapshot.absorb(&throughput(50));
+        snapshot.absorb(&TelemetryUpdate::Connection(ConnectionState::Stale));
+        snapshot.absorb(&TelemetryUpdate::Connection(ConnectionState::Established));
+        // Totals survive a dashboard reset.
+        snapshot.absorb(&TelemetryUpdate::StatsReset);
+        assert_eq!((snapshot.tx_bytes_total, snapshot.rx_bytes_total), (150, 300));
+        assert_eq!((snapshot.tx_wire_bytes_total, snapshot.rx_wire_bytes_total), (230, 460));
+        assert_eq!(snapshot.tx_packets_total, 2);
+        assert_eq!(snapshot.connection_state, "ESTABLISHED");
+    }
+
+    #[test]
+    fn snapshot_record_nests_its_sections() {
+        let record: Value = serde_json::from_str(&to_line(Event::Snapshot(Box::default()))).unwrap();
+        assert_eq!(record["event"], "snapshot");
+        for section in ["quality", "latency", "peer", "settings", "overhead", "quota"] {
+            assert!(record[section].is_object(), "{}", section);
+        }
+        assert!(record["latency"]["last_minute"].is_object());
+        assert_eq!(record["dictionary"], Value::Null);
+        assert_eq!(record["compression"], Value::Null);
+    }
+}
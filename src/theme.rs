@@ -0,0 +1,179 @@
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::connection::ConnectionState;
+use crate::logging::Level;
+
+/// Dashboard color scheme, picked with `--theme`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ThemeName {
+    /// Bright accents for dark terminal backgrounds.
+    Dark,
+    /// Darker accents that stay readable on light backgrounds.
+    Light,
+    /// No color: state is carried by symbols and bold/dim text.
+    Mono,
+}
+
+impl ThemeName {
+    /// `--theme` if given, else mono when `NO_COLOR` is set (https://no-color.org), else dark.
+    pub fn resolve(flag: Option<ThemeName>) -> ThemeName {
+        flag.unwrap_or_else(|| {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            if no_color { ThemeName::Mono } else { ThemeName::Dark }
+        })
+    }
+}
+
+/// Every style the dashboard uses. Widgets take their look from here, never from literals.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub border: Style,
+    pub tx: Style,
+    pub rx: Style,
+    pub rtt: Style,
+    /// ARQ window gauge, normally and while the window is stalled full.
+    pub gauge: Style,
+    pub stall: Style,
+    /// Peer session up / coming up / down / handshake given up.
+    pub good: Style,
+    pub pending: Style,
+    pub idle: Style,
+    pub failed: Style,
+    /// Peer session up but overdue for a heartbeat.
+    pub stale: Style,
+    /// Attention-worthy titles (paused log view).
+    pub alert: Style,
+    /// Status bar while an alert is firing.
+    pub alarm: Style,
+    /// The current tab in the tab bar, and the log search match last jumped to.
+    pub selected: Style,
+    /// Log lines matching the search.
+    pub search_match: Style,
+    debug: Style,
+    info: Style,
+    warn: Style,
+    error: Style,
+    /// Prefix log lines with a level symbol (themes without color).
+    level_markers: bool,
+    /// Text color of the connection badge, drawn on the state's color; `None` draws
+    /// the state style itself (themes without color).
+    badge_ink: Option<Color>,
+}
+
+impl Palette {
+    pub fn new(theme: ThemeName) -> Self {
+        let fg = |c: Color| Style::default().fg(c);
+        match theme {
+            ThemeName::Dark => Self {
+                border: Style::default(),
+                tx: fg(Color::LightGreen),
+                rx: fg(Color::LightCyan),
+                rtt: fg(Color::LightYellow),
+                gauge: fg(Color::LightBlue),
+                stall: fg(Color::Red),
+                good: fg(Color::LightGreen),
+                pending: fg(Color::Yellow),
+                idle: Style::default(),
+                failed: fg(Color::Red),
+                stale: fg(Color::Indexed(208)), // orange
+                alert: fg(Color::Yellow),
+                alarm: Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+                selected: fg(Color::LightCyan).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+                search_match: Style::default().fg(Color::Black).bg(Color::Yellow),
+                debug: fg(Color::DarkGray),
+                info: Style::default(),
+                warn: fg(Color::Yellow),
+                error: fg(Color::Red),
+                level_markers: false,
+                badge_ink: Some(Color::Black),
+            },
+            ThemeName::Light => Self {
+                border: fg(Color::DarkGray),
+                tx: fg(Color::Green),
+                rx: fg(Color::Blue),
+                rtt: fg(Color::Magenta),
+                gauge: fg(Color::Blue),
+                stall: fg(Color::Red),
+                good: fg(Color::Green),
+                pending: fg(Color::Indexed(130)), // dark orange; yellow vanishes on white
+                idle: Style::default(),
+                failed: fg(Color::Red),
+                stale: fg(Color::Indexed(166)),
+                alert: fg(Color::Indexed(130)),
+                alarm: Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+                selected: fg(Color::Blue).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+                search_match: Style::default().fg(Color::Black).bg(Color::LightYellow),
+                debug: fg(Color::Gray),
+                info: Style::default(),
+                warn: fg(Color::Indexed(130)),
+                error: fg(Color::Red),
+                level_markers: false,
+                badge_ink: Some(Color::Black),
+            },
+            ThemeName::Mono => {
+                let bold = Style::default().add_modifier(Modifier::BOLD);
+                Self {
+                    border: Style::default(),
+                    tx: Style::default(),
+                    rx: Style::default(),
+                    rtt: Style::default(),
+                    gauge: Style::default(),
+                    stall: bold,
+                    good: bold,
+                    pending: Style::default(),
+                    idle: Style::default().add_modifier(Modifier::DIM),
+                    failed: bold.add_modifier(Modifier::REVERSED),
+                    stale: bold.add_modifier(Modifier::UNDERLINED),
+                    alert: bold.add_modifier(Modifier::REVERSED),
+                    alarm: bold.add_modifier(Modifier::REVERSED),
+                    selected: bold.add_modifier(Modifier::REVERSED),
+                    search_match: bold.add_modifier(Modifier::UNDERLINED),
+                    debug: Style::default().add_modifier(Modifier::DIM),
+                    info: Style::default(),
+                    warn: bold,
+                    error: bold.add_modifier(Modifier::REVERSED),
+                    level_markers: true,
+                    badge_ink: None,
+                }
+            }
+        }
+    }
+
+    pub fn level(&self, level: Level) -> Style {
+        match level {
+            Level::Debug => self.debug,
+            Level::Info => self.info,
+            Level::Warn => self.warn,
+            Level::Error => self.error,
+        }
+    }
+
+    /// Symbol shown before a log line so severity survives without color; empty for color themes.
+    pub fn level_marker(&self, level: Level) -> &'static str {
+        if !self.level_markers {
+            return "";
+        }
+        match level {
+            Level::Debug => ". ",
+            Level::Info => "  ",
+            Level::Warn => "! ",
+            Level::Error => "!! ",
+        }
+    }
+
+    /// The status bar's connection badge: the state's color as a solid block.
+    pub fn connection(&self, state: ConnectionState) -> Style {
+        let style = match state {
+            ConnectionState::Listening => self.idle,
+            ConnectionState::Handshaking => self.pending,
+            ConnectionState::Established => self.good,
+            ConnectionState::Stale => self.stale,
+            ConnectionState::Reconnecting | ConnectionState::Dead | ConnectionState::TunDown => self.failed,
+        };
+        match self.badge_ink {
+            // Themes leave idle uncolored; the badge still needs a block to stand out.
+            Some(ink) => Style::default().fg(ink).bg(style.fg.unwrap_or(Color::Gray)).add_modifier(Modifier::BOLD),
+            None => style,
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use parking_lot::{Mutex, RwLock};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+
+use crate::ecn::Ecn;
+use crate::obfuscation::Obfuscator;
+use crate::tcp::{self, TcpLink};
+
+/// Retries of a send the kernel refused for lack of buffer space (`ENOBUFS`), and the
+/// pause before the first; each retry doubles it. About 30ms in all before giving up.
+const NOBUFS_RETRIES: u32 = 5;
+const NOBUFS_BACKOFF: Duration = Duration::from_millis(1);
+
+/// What carries the tunnel's frames (`--transport`).
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TransportKind {
+    /// Datagrams, one frame each (the default).
+    Udp,
+    /// One length-prefixed stream to the peer, for networks that block UDP. The tunnel's own retransmission is off.
+    Tcp,
+}
+
+/// The tunnel's UDP socket.
+///
+/// With `--dual-stack` this is a single IPv6 socket with `IPV6_V6ONLY` cleared, so
+/// IPv4 clients arrive as v4-mapped addresses (`::ffff:a.b.c.d`). Those are folded
+/// back to plain IPv4 on receive and re-mapped on send, so the rest of the tunnel
+/// (peer tracking, roaming, hooks, the dashboard) only ever sees canonical addresses
+/// and a peer moving between families is just another roam.
+///
+/// With `--connect-peer` (Linux) a second socket shares the port (`SO_REUSEPORT`) and
+/// is `connect()`ed to the active peer. The kernel only reports ICMP errors (port
+/// unreachable, fragmentation needed) on connected sockets, as the next send or
+/// receive failing. The kernel prefers the connected socket for the peer's datagrams
+/// and hands everything else to the unconnected one, so a new peer or a roamed
+/// address is still heard; the roaming paths then move the connection along.
+///
+/// With `--ecn` (Linux) each datagram's ECN bits are read on receive (`IP_RECVTOS`),
+/// and once the peer reads them too, every datagram is sent ECT(0).
+///
+/// Every datagram goes out dressed by the obfuscation stack (see `Obfuscator`), e.g. in
+/// TLS ApplicationData records with `--mimic tls`. A frame too large for the dressing
+/// (a DNS query, with `--mimic dns`) fails to send. Unwrapping is left to the receive
+/// path, which also has to tell the decoy handshake records apart.
+///
+/// With `--rotate-port` the unconnected socket is replaced now and then by one on a new
+/// port (see `rotate`).
+///
+/// With `--transport tcp` the frames go over a TCP stream instead (see `TcpLink`).
+pub struct Transport {
+    link: Link,
+    /// Bound to an IPv6 address: IPv4 destinations must be sent v4-mapped.
+    maps_v4: bool,
+    dscp: Option<u8>,
+    /// ECN bits are read on receive (`--ecn`).
+    reads_ecn: bool,
+    /// Outgoing datagrams are marked ECT(0).
+    ect: AtomicBool,
+    /// Dresses every outgoing frame.
+    obfuscator: Arc<dyn Obfuscator>,
+    /// Every byte sent, whatever the frame type (per-session wire accounting).
+    sent: AtomicU64,
+    /// Every byte received, from anyone (overhead accounting).
+    received: AtomicU64,
+}
+
+enum Link {
+    Udp { socket: Bound, connected: Option<Connected> },
+    Tcp(TcpLink),
+}
+
+/// The unconnected socket, and the one it replaced while that drains.
+struct Bound {
+    current: RwLock<Arc<UdpSocket>>,
+    /// The socket `rotate` replaced: still read, for datagrams the peer sent before it
+    /// saw the new port, until `retire`.
+    retiring: Mutex<Option<Arc<UdpSocket>>>,
+    /// Wakes receivers waiting on a socket that was just swapped out.
+    swapped: Notify,
+}
+
+impl Bound {
+    fn new(socket: UdpSocket) -> Self {
+        Self { current: RwLock::new(Arc::new(socket)), retiring: Mutex::new(None), swapped: Notify::new() }
+    }
+
+    fn current(&self) -> Arc<UdpSocket> {
+        self.current.read().clone()
+    }
+}
+
+struct Connected {
+    socket: UdpSocket,
+    /// Where `socket` is connected to (`None`: not yet).
+    peer: Mutex<Option<SocketAddr>>,
+}
+
+impl Transport {
+    /// `dscp` (0..=63) marks every outgoing datagram for QoS on the outer network.
+    pub async fn bind(kind: TransportKind, bind: &str, dual_stack: bool, dscp: Option<u8>, connect_peer: bool, ecn: bool, obfuscator: Arc<dyn Obfuscator>) -> Result<Self> {
+        if kind == TransportKind::Tcp {
+            if dual_stack || connect_peer || ecn || obfuscator.needs_datagrams() {
+                bail!("--dual-stack, --connect-peer, --ecn and --mimic apply to UDP only");
+            }
+            let link = TcpLink::bind(bind, dscp.map(|dscp| u32::from(dscp) << 2)).await?;
+            return Ok(Self {
+                link: Link::Tcp(link),
+                maps_v4: false,
+                dscp,
+                reads_ecn: false,
+                ect: AtomicBool::new(false),
+                obfuscator,
+                sent: AtomicU64::new(0),
+                received: AtomicU64::new(0),
+            });
+        }
+        if connect_peer && !cfg!(target_os = "linux") {
+            bail!("--connect-peer is only supported on Linux");
+        }
+        if ecn && !cfg!(target_os = "linux") {
+            bail!("--ecn is only supported on Linux");
+        }
+        let socket = if dual_stack {
+            let addr: SocketAddr = bind.parse().with_context(|| format!("--dual-stack needs a literal address, got '{}'", bind))?;
+            bind_socket(dual_stack_addr(addr)?, true, connect_peer)?
+        } else if connect_peer {
+            let addr = tokio::net::lookup_host(bind).await?.next()
+                .with_context(|| format!("Bind address '{}' did not resolve", bind))?;
+            bind_socket(addr, false, true)?
+        } else {
+            UdpSocket::bind(bind).await.context("Failed to bind UDP socket")?
+        };
+        let local = socket.local_addr()?;
+        let maps_v4 = local.is_ipv6();
+        let connected = if connect_peer {
+            // Bound before any peer is known: until then it simply shares the unconnected traffic.
+            let socket = bind_socket(local, dual_stack, true).context("Failed to bind the connected UDP socket")?;
+            Some(Connected { socket, peer: Mutex::new(None) })
+        } else {
+            None
+        };
+        if let Some(dscp) = dscp {
+            set_tos(&socket, tos(Some(dscp), false), maps_v4).with_context(|| format!("Failed to set DSCP {}", dscp))?;
+            if let Some(connected) = &connected {
+                set_tos(&connected.socket, tos(Some(dscp), false), maps_v4).with_context(|| format!("Failed to set DSCP {}", dscp))?;
+            }
+        }
+        if ecn {
+            recv_tos(&socket, maps_v4).context("Failed to enable IP_RECVTOS")?;
+            if let Some(connected) = &connected {
+                recv_tos(&connected.socket, maps_v4).context("Failed to enable IP_RECVTOS")?;
+            }
+        }
+        Ok(Self {
+            link: Link::Udp { socket: Bound::new(socket), connected },
+            maps_v4,
+            dscp,
+            reads_ecn: ecn,
+            ect: AtomicBool::new(false),
+            obfuscator,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+        })
+    }
+
+    /// ECN bits are read on receive (`--ecn`): what this end advertises.
+    pub fn reads_ecn(&self) -> bool {
+        self.reads_ecn
+    }
+
+    /// Outgoing datagrams are marked ECT(0).
+    pub fn is_ect(&self) -> bool {
+        self.ect.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops marking outgoing datagrams ECT(0), keeping the DSCP. Only
+    /// worth doing once the peer reads the marks: a CE mark nobody reports is a
+    /// congestion signal thrown away. Returns whether anything changed.
+    pub fn set_ect(&self, on: bool) -> io::Result<bool> {
+        let Link::Udp { socket, connected } = &self.link else { return Ok(false) };
+        let on = on && self.reads_ecn;
+        if self.ect.load(Ordering::Relaxed) == on {
+            return Ok(false);
+        }
+        set_tos(&socket.current(), tos(self.dscp, on), self.maps_v4).map_err(io::Error::other)?;
+        if let Some(connected) = connected {
+            set_tos(&connected.socket, tos(self.dscp, on), self.maps_v4).map_err(io::Error::other)?;
+        }
+        self.ect.store(on, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Points the connected socket at `peer` (with `--connect-peer`; otherwise a no-op).
+    /// Called whenever the active peer changes.
+    pub fn connect_peer(&self, peer: SocketAddr) -> io::Result<()> {
+        let Link::Udp { connected: Some(connected), .. } = &self.link else { return Ok(()) };
+        let mut current = connected.peer.lock();
+        if *current != Some(peer) {
+            SockRef::from(&connected.socket).connect(&self.wire_addr(peer).into())?;
+            *current = Some(peer);
+        }
+        Ok(())
+    }
+
+    /// Where the connected socket points (`None`: no peer yet, or no `--connect-peer`).
+    #[cfg(test)]
+    pub fn connected_peer(&self) -> Option<SocketAddr> {
+        let Link::Udp { connected: Some(connected), .. } = &self.link else { return None };
+        *connected.peer.lock()
+    }
+
+    /// Moves the unconnected socket to a new port on the same address, chosen by the
+    /// kernel (randomly, on Linux), with the same options (`--rotate-port`). Sends go
+    /// out from the new port at once; the old socket is still read until `retire`.
+    /// Returns the old and new ports.
+    pub fn rotate(&self) -> Result<(u16, u16)> {
+        let Link::Udp { socket, connected: None } = &self.link else {
+            bail!("only an unconnected UDP socket can change ports");
+        };
+        let old = socket.current();
+        let mut addr = old.local_addr()?;
+        let old_port = addr.port();
+        addr.set_port(0);
+        let dual_stack = addr.is_ipv6() && !SockRef::from(&*old).only_v6()?;
+        let fresh = bind_socket(addr, dual_stack, false)?;
+        if self.dscp.is_some() || self.is_ect() {
+            set_tos(&fresh, tos(self.dscp, self.is_ect()), self.maps_v4)?;
+        }
+        if self.reads_ecn {
+            recv_tos(&fresh, self.maps_v4)?;
+        }
+        let new_port = fresh.local_addr()?.port();
+        let replaced = std::mem::replace(&mut *socket.current.write(), Arc::new(fresh));
+        // A socket still draining from the rotation before is simply closed.
+        *socket.retiring.lock() = Some(replaced);
+        socket.swapped.notify_waiters();
+        Ok((old_port, new_port))
+    }
+
+    /// Closes the socket the last `rotate` replaced.
+    pub fn retire(&self) {
+        let Link::Udp { socket, .. } = &self.link else { return };
+        if socket.retiring.lock().take().is_some() {
+            socket.swapped.notify_waiters();
+        }
+    }
+
+    /// Whether peers get a connected socket (`--connect-peer`).
+    pub fn is_connected(&self) -> bool {
+        matches!(self.link, Link::Udp { connected: Some(_), .. })
+    }
+
+    /// Frames go over TCP, which retransmits on its own (`--transport tcp`).
+    pub fn is_stream(&self) -> bool {
+        matches!(self.link, Link::Tcp(_))
+    }
+
+    /// Address the UDP socket is bound to, the kernel's port choice included (`None` over TCP).
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        let Link::Udp { socket, .. } = &self.link else { return None };
+        socket.current().local_addr().ok()
+    }
+
+    /// Bound to IPv6 (IPv4 peers are reachable only through v4-mapping).
+    pub fn is_ipv6(&self) -> bool {
+        self.maps_v4
+    }
+
+    /// Sends one datagram, waiting out a full send buffer instead of failing.
+    ///
+    /// `WouldBlock` means the buffer filled after the socket last looked writable:
+    /// wait for writability again and retry, however long that takes. Some platforms
+    /// (BSDs, macOS) report a full interface queue as `ENOBUFS` instead and never
+    /// signal writability for it, so that gets a few short, backed-off retries. Only
+    /// other errors, or `ENOBUFS` that persists, reach the caller.
+    ///
+    /// Datagrams for the connected peer go out on the connected socket, so an ICMP
+    /// error about an earlier one fails this send.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        if let Some(max) = self.obfuscator.max_frame().filter(|&max| buf.len() > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}-byte frame does not fit the obfuscation's {} bytes", buf.len(), max)));
+        }
+        self.send_raw(&self.obfuscator.wrap_outgoing(buf), target).await
+    }
+
+    /// `send_to` without the obfuscation's wrapping, for its decoy handshake records.
+    pub async fn send_raw(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let (socket, connected) = match &self.link {
+            Link::Udp { socket, connected } => (socket, connected),
+            Link::Tcp(link) => {
+                let sent = link.send_to(buf, target).await?;
+                self.sent.fetch_add((tcp::LEN_PREFIX + sent) as u64, Ordering::Relaxed);
+                return Ok(sent);
+            }
+        };
+        let connected = connected.as_ref().filter(|c| *c.peer.lock() == Some(target));
+        let socket = socket.current();
+        let wire_target = self.wire_addr(target);
+        let mut nobufs_retries = 0;
+        loop {
+            let sent = match connected {
+                Some(c) => {
+                    c.socket.writable().await?;
+                    c.socket.try_send(buf)
+                }
+                None => {
+                    socket.writable().await?;
+                    socket.try_send_to(buf, wire_target)
+                }
+            };
+            match sent {
+                Ok(sent) => {
+                    self.sent.fetch_add(sent as u64, Ordering::Relaxed);
+                    return Ok(sent);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) && nobufs_retries < NOBUFS_RETRIES => {
+                    tokio::time::sleep(NOBUFS_BACKOFF * 2u32.pow(nobufs_retries)).await;
+                    nobufs_retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Receives from either socket (or the TCP stream), with the datagram's ECN bits
+    /// (`NotEct` unless `--ecn`). An ICMP error about the connected peer's traffic
+    /// surfaces here as an error (e.g. `ConnectionRefused`).
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Ecn)> {
+        let (socket, connected) = match &self.link {
+            Link::Udp { socket, connected } => (socket, connected),
+            Link::Tcp(link) => {
+                let (size, src) = link.recv_from(buf).await?;
+                self.received.fetch_add((tcp::LEN_PREFIX + size) as u64, Ordering::Relaxed);
+                return Ok((size, src, Ecn::NotEct));
+            }
+        };
+        let (size, src, ecn) = match connected {
+            Some(connected) => loop {
+                let socket = socket.current();
+                let received = tokio::select! {
+                    ready = socket.readable() => {
+                        ready?;
+                        self.try_recv(&socket, buf)
+                    }
+                    // A pending ICMP error only raises the error flag, not readability.
+                    ready = connected.socket.ready(Interest::READABLE | Interest::ERROR) => {
+                        if ready?.is_error() {
+                            // Reports the error once; the next pass finds none and clears the flag.
+                            connected.socket.try_io(Interest::ERROR, || {
+                                Err(connected.socket.take_error()?.unwrap_or_else(|| io::ErrorKind::WouldBlock.into()))
+                            })
+                        } else {
+                            self.try_recv(&connected.socket, buf)
+                        }
+                    }
+                };
+                match received {
+                    Ok(received) => break received,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+            // Waits on both sockets of a rotation, starting over whenever either is swapped out.
+            None => loop {
+                let swapped = socket.swapped.notified();
+                let (current, retiring) = (socket.current(), socket.retiring.lock().clone());
+                let received = tokio::select! {
+                    ready = current.readable() => {
+                        ready?;
+                        self.try_recv(&current, buf)
+                    }
+                    ready = readable(retiring.as_deref()) => self.try_recv(ready?, buf),
+                    () = swapped => continue,
+                };
+                match received {
+                    Ok(received) => break received,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+        };
+        self.received.fetch_add(size as u64, Ordering::Relaxed);
+        Ok((size, SocketAddr::new(src.ip().to_canonical(), src.port()), ecn))
+    }
+
+    /// One non-blocking receive; through `recvmsg` for the TOS byte with `--ecn`.
+    fn try_recv(&self, socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Ecn)> {
+        #[cfg(target_os = "linux")]
+        if self.reads_ecn {
+            return socket.try_io(Interest::READABLE, || recv_with_tos(socket, buf))
+                .map(|(size, src, tos)| (size, src, Ecn::from_tos(tos)));
+        }
+        let (size, src) = socket.try_recv_from(buf)?;
+        Ok((size, src, Ecn::NotEct))
+    }
+
+    /// IPv4 destinations are sent v4-mapped from an IPv6 socket.
+    fn wire_addr(&self, addr: SocketAddr) -> SocketAddr {
+        match addr {
+            SocketAddr::V4(v4) if self.maps_v4 => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+            other => other,
+        }
+    }
+}
+
+/// Waits for a socket that may not be there to be readable (forever, if it isn't).
+async fn readable(socket: Option<&UdpSocket>) -> io::Result<&UdpSocket> {
+    match socket {
+        Some(socket) => socket.readable().await.map(|()| socket),
+        None => std::future::pending().await,
+    }
+}
+
+/// `0.0.0.0:port` is promoted to `[::]:port`; any other IPv4 address cannot be dual-stack.
+fn dual_stack_addr(addr: SocketAddr) -> Result<SocketAddr> {
+    match addr.ip() {
+        IpAddr::V6(_) => Ok(addr),
+        IpAddr::V4(v4) if v4.is_unspecified() => Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), addr.port())),
+        IpAddr::V4(_) => bail!("--dual-stack needs an IPv6 or wildcard bind address, got {}", addr),
+    }
+}
+
+/// DSCP is the upper six bits of the TOS / traffic class byte, ECN the lower two.
+fn tos(dscp: Option<u8>, ect: bool) -> u32 {
+    let ecn = if ect { Ecn::Ect0 } else { Ecn::NotEct };
+    u32::from(dscp.unwrap_or(0)) << 2 | u32::from(ecn.bits())
+}
+
+fn set_tos(socket: &UdpSocket, tos: u32, ipv6: bool) -> Result<()> {
+    let sock = SockRef::from(socket);
+    if ipv6 {
+        sock.set_tclass_v6(tos)?;
+        // IPv4-mapped traffic on a dual-stack socket takes its TOS from the IPv4 option.
+        sock.set_tos_v4(tos)?;
+    } else {
+        sock.set_tos_v4(tos)?;
+    }
+    Ok(())
+}
+
+/// Asks for each datagram's TOS byte as a control message. IPv4-mapped traffic on
+/// a dual-stack socket reports it through the IPv4 option, like `set_tos`.
+fn recv_tos(socket: &UdpSocket, ipv6: bool) -> Result<()> {
+    let sock = SockRef::from(socket);
+    if ipv6 {
+        sock.set_recv_tclass_v6(true)?;
+    }
+    sock.set_recv_tos_v4(true)?;
+    Ok(())
+}
+
+/// `recvmsg`, returning the sender and the TOS / traffic class byte from the control
+/// messages (0 if none came).
+#[cfg(target_os = "linux")]
+fn recv_with_tos(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, u8)> {
+    use std::os::fd::AsRawFd;
+    // Room for both control messages, aligned for `cmsghdr`.
+    let mut control = [0u64; 8];
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+    // SAFETY: every pointer in `msg` is to a live local sized as declared, and the
+    // control messages are walked with the libc macros within `msg_controllen`.
+    let ((size, tos), src) = unsafe {
+        socket2::SockAddr::try_init(|storage, len| {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = storage.cast();
+            msg.msg_namelen = *len;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+            let size = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            *len = msg.msg_namelen;
+            let mut tos = 0u8;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_TOS) => tos = *libc::CMSG_DATA(cmsg),
+                    (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                        tos = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>()) as u8;
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            Ok((size as usize, tos))
+        })?
+    };
+    let src = src.as_socket().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "datagram from a non-IP address"))?;
+    Ok((size, src, tos))
+}
+
+/// Binds through socket2, for the options that must be set before `bind`.
+fn bind_socket(addr: SocketAddr, dual_stack: bool, reuse_port: bool) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP)).context("Failed to create UDP socket")?;
+    if dual_stack {
+        socket.set_only_v6(false).context("Failed to enable dual-stack (IPV6_V6ONLY)")?;
+    }
+    #[cfg(target_os = "linux")]
+    if reuse_port {
+        socket.set_reuse_port(true).context("Failed to set SO_REUSEPORT")?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = reuse_port;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into()).with_context(|| format!("Failed to bind UDP socket {}", addr))?;
+    UdpSocket::from_std(socket.into()).context("Failed to register UDP socket")
+}
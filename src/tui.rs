@@ -1,143 +1,995 @@
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Sparkline, List, ListItem},
-    Terminal,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::Style,
+    text::{Line, Span},
+    widgets::{block::Title, Bar, BarChart, BarGroup, Block, Borders, Clear, Gauge, Paragraph, Sparkline, List, ListItem, Tabs},
+    Frame, Terminal, TerminalOptions, Viewport,
 };
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    cursor::{MoveTo, Show},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent, MouseEventKind},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    style::Print,
+    terminal::{self, disable_raw_mode, enable_raw_mode, is_raw_mode_enabled, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::io;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::alerts::Alert;
+use crate::compression::{AdaptStatus, Algorithm, AutoStatus, CompressionReport, DictionaryStats, DirectionTotals};
+use crate::connection::ConnectionState;
+use crate::crypto::{CipherSuite, KeyStatus};
+use crate::control::{Command, ControlRequest, ControlSender};
+use crate::handshake::HandshakeState;
+use crate::log_file;
+use crate::logging::{self, Level};
+use crate::metrics::{self, CounterSnapshot, Overhead, PacketSizes};
+use crate::chaff::ChaffProfile;
+use crate::mimicry::Mimicry;
+use crate::morph::MorphStatus;
+use crate::obfuscation::{Intensity, JitterRange};
+use crate::protocol::WireFormat;
+use crate::quota::{QuotaState, QuotaStatus};
+use crate::shutdown::{Shutdown, StopReason};
+use crate::stats::{EchoLoss, Latency, LinkQuality};
+use crate::telemetry::TelemetryReceiver;
+use crate::theme::Palette;
+use crate::units::{format_bytes, format_rate, RateUnit};
+
+/// Terminals narrower than this get a one-line peer summary instead of the panel.
+const PEER_PANEL_MIN_WIDTH: u16 = 110;
+
+/// Log lines scrolled per mouse wheel notch.
+const WHEEL_LINES: i64 = 3;
+
+/// A window full for this long counts as stalled (gauge changes style).
+const WINDOW_STALL_AFTER: Duration = Duration::from_secs(3);
+
+/// Spans of the average rates shown beside the totals.
+const RATE_WINDOWS: [Duration; 2] = [Duration::from_secs(10), Duration::from_secs(60)];
+
+/// Graph windows reachable with +/-.
+const GRAPH_PRESETS: [Duration; 5] = [
+    Duration::from_secs(30),
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(60 * 60),
+];
+
 /// Telemetry events sent from the networking core to the UI.
 pub enum TelemetryUpdate {
-    Throughput { tx_bytes: u64, rx_bytes: u64 },
-    Log(String),
+    /// Goodput since the previous update: inner packets sent for the first time and
+    /// delivered (retransmissions and duplicates not counted again), with their bytes.
+    /// `tx_wire` / `rx_wire` are the UDP payload bytes over the same time, overhead,
+    /// retransmissions, control frames and decoys included.
+    Throughput { tx_bytes: u64, rx_bytes: u64, tx_packets: u64, rx_packets: u64, tx_wire: u64, rx_wire: u64 },
+    /// Periodic loss/jitter/RTT estimates from the ARQ layer.
+    LinkQuality(LinkQuality),
+    /// Periodic RTT percentiles, over the session and the last minute.
+    Latency(Latency),
+    /// Per-direction loss measured from the peer's echoed counters.
+    EchoLoss(EchoLoss),
+    /// Periodic view of who we're talking to.
+    PeerState(PeerInfo),
+    /// Periodic view of the knobs in effect, runtime toggles included.
+    Settings(Settings),
+    /// Periodic ARQ window occupancy and retransmission rate.
+    Window(WindowStats),
+    /// Periodic copy of the core's frame and drop counters.
+    Counters(CounterSnapshot),
+    /// Periodic copy of the inner packet size histograms (cumulative).
+    PacketSizes(PacketSizes),
+    /// Periodic goodput vs overhead byte totals (cumulative).
+    Overhead(Overhead),
+    /// Periodic small-packet dictionary measurements; only with a dictionary loaded.
+    Dictionary(DictionaryStats),
+    /// Periodic compression ratio and cost each way; only with `--compress` set.
+    Compression(CompressionReport),
+    /// Periodic usage of the current session against its quota.
+    Quota(QuotaStatus),
+    /// A log line; the core only sends levels at or above `logging::threshold()`.
+    Log { level: Level, msg: String },
+    /// Alerts now firing. Sent every tick while any is active, and once more when the last clears.
+    Alerts(Vec<Alert>),
+    /// The connection entered a new state (also sent once at startup).
+    Connection(ConnectionState),
+    /// The ingress/egress totals were zeroed (`reset-stats`); they count from here.
+    StatsReset,
+    /// The core cannot continue; the dashboard should exit.
+    Shutdown { reason: String },
 }
 
-use rand::Rng; // Import Rng for mock metrics
-
 struct TelemetryState {
-    tx_history: Vec<u64>,
-    rx_history: Vec<u64>,
-    logs: Vec<String>,
+    /// Tab on screen. Every tab's state updates regardless, so switching is instant.
+    tab: Tab,
+    /// Per-tick samples, oldest first. Enough is retained for the longest preset
+    /// (or the configured window, if longer); the graphs show the last `graph_window`.
+    tx_history: VecDeque<u64>,
+    rx_history: VecDeque<u64>,
+    tx_packet_history: VecDeque<u64>,
+    rx_packet_history: VecDeque<u64>,
+    /// UDP payload bytes per tick, to set the goodput above against.
+    tx_wire_history: VecDeque<u64>,
+    rx_wire_history: VecDeque<u64>,
+    /// What the traffic graphs plot ('u' toggles).
+    graph_unit: GraphUnit,
+    /// Bits or bytes for every rate shown (status bar, graph titles, snapshots).
+    rate_unit: RateUnit,
+    /// Smoothed RTT per tick (ms). Idle ticks carry the last estimate forward;
+    /// `None` only before the first sample.
+    rtt_history: VecDeque<Option<f64>>,
+    graph_window: Duration,
+    /// Sample period of every history above.
+    tick: Duration,
+    logs: LogBuffer,
+    log_view: LogView,
+    search: LogSearch,
     total_tx: u64,
     total_rx: u64,
-    // Quality Metrics
-    jitter_ms: f64,
-    loss_rate: f64,
+    /// When the totals were last zeroed (`z`, `reset-stats`); `None`: they count from startup.
+    totals_since: Option<Instant>,
+    // Quality Metrics (latest estimate from the core)
+    quality: LinkQuality,
+    echo_loss: Option<EchoLoss>,
+    dictionary: Option<DictionaryStats>,
+    compression: Option<CompressionReport>,
+    latency: Latency,
+    peer: PeerInfo,
+    /// `None` until the core's first report.
+    settings: Option<Settings>,
+    window: WindowStats,
+    /// When the window last became completely full (`None` while it has room).
+    window_full_since: Option<Instant>,
+    alerts: Vec<Alert>,
+    /// Latest state from the core's state machine, and when the dashboard saw it change.
+    connection: ConnectionState,
+    connection_since: Instant,
+    counters: CounterSnapshot,
+    /// Per-second change of each counter between the last two snapshots.
+    counter_rates: Vec<f64>,
+    counters_at: Option<Instant>,
+    packet_sizes: PacketSizes,
+    overhead: Overhead,
+    quota: QuotaStatus,
+    /// Direction the size histogram shows ('d' toggles).
+    size_view: SizeView,
     start_time: Instant,
+    /// Updates lost on the way here; the numbers on screen may undercount.
+    telemetry_dropped: u64,
+    /// Where the last frame put the clickable parts.
+    hitboxes: Hitboxes,
+}
+
+/// Screen areas of the last frame, for mouse hit testing.
+#[derive(Default)]
+struct Hitboxes {
+    tabs: Vec<(Rect, Tab)>,
+    /// The log pane, which the wheel scrolls.
+    logs: Option<Rect>,
+    /// Overview panes that open their full tab when clicked.
+    panes: Vec<(Rect, Tab)>,
+}
+
+impl Hitboxes {
+    /// The tab a click at (`column`, `row`) selects, if any.
+    fn tab_at(&self, column: u16, row: u16) -> Option<Tab> {
+        self.tabs.iter().chain(&self.panes).find(|(area, _)| contains(*area, column, row)).map(|(_, tab)| *tab)
+    }
+
+    fn over_logs(&self, column: u16, row: u16) -> bool {
+        self.logs.is_some_and(|area| contains(area, column, row))
+    }
+}
+
+/// ARQ window snapshot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowStats {
+    /// Frames sent but not yet acknowledged.
+    pub in_flight: usize,
+    pub capacity: usize,
+    /// Encoded size of the in-flight frames.
+    pub bytes: usize,
+    /// Retransmissions per second since the previous snapshot.
+    pub retransmit_rate: f64,
+    /// Frames per second pacing allows (`None`: pacing off, or no RTT yet).
+    pub pacing_rate: Option<f64>,
+    /// Share of the data frames received since the previous snapshot that the path
+    /// marked CE (`None`: ECN not in use).
+    pub ecn_mark_rate: Option<f64>,
+}
+
+/// The knobs this instance is running with right now: runtime toggles (`c`, `j`) and
+/// the `--obfs adaptive` adapter included. Cipher and wire format are negotiated with
+/// the peer and come with `PeerInfo`.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Timing jitter is on, at `obfs` intensity.
+    pub jitter: bool,
+    /// What jitter draws from at full intensity (`--jitter`).
+    pub jitter_range: JitterRange,
+    /// `--jitter-below` finds the traffic bulk and has jitter standing aside; `None` without it.
+    pub jitter_bulk: Option<bool>,
+    pub obfs: Intensity,
+    /// The intensity follows link quality (`--obfs adaptive`).
+    pub adaptive: bool,
+    /// `--chaff` profile and rate, and whether the peer understands chaff frames.
+    pub chaff: Option<(ChaffProfile, u32)>,
+    pub peer_chaff: bool,
+    /// `--mimic`: every datagram goes out as TLS records or DNS messages, not only the
+    /// decoy ClientHello (`None`).
+    pub mimic: Option<Mimicry>,
+    /// `--morph`: how far the frame sizes are from the target; `None` without it.
+    pub morph: Option<MorphStatus>,
+    /// Compression is switched on (`c`).
+    pub compression: bool,
+    /// The peer's compression is switched on (`C`); `None` if it can't say.
+    pub peer_compression: Option<bool>,
+    /// What this end sends with (`--compress`, `--compress-tx`), and its level (zstd only).
+    pub algorithm: Algorithm,
+    pub level: i32,
+    /// What this end decodes (`--compress-rx`).
+    pub inbound: Algorithm,
+    /// What the peer's Hello says it sends with, and at what level.
+    pub peer_algorithm: Option<(Algorithm, i32)>,
+    /// What outgoing packets actually use: `algorithm` once the peer accepts it.
+    pub outbound: Algorithm,
+    /// Small packets are compressed against the dictionary the peer also has.
+    pub dictionary: bool,
+    /// `--compress-auto`'s state and measurements; `None` without it.
+    pub auto: Option<AutoStatus>,
+    /// `--compress-adapt`'s level and what it judged by; `None` without it.
+    pub adapt: Option<AdaptStatus>,
+    /// Data frames are paced across the RTT (`--pacing`, `P`).
+    pub pacing: bool,
+    /// Sends to the peer go over a connected socket (`--connect-peer`).
+    pub connected_socket: bool,
+}
+
+impl Settings {
+    /// Jitter, TLS mimicry and chaff, e.g. `full (0-15ms jitter, adaptive) + TLS mimicry
+    /// + chaff poisson 5/s` (`not agreed`: the peer would not understand it, none is sent).
+    /// `TLS records` in place of `TLS mimicry` with `--mimic tls` (`DNS mimicry` with
+    /// `--mimic dns`, `QUIC mimicry` with `--mimic quic`, `WireGuard mimicry` with
+    /// `--mimic wireguard`), and
+    /// `+ morph 0.04 off target, 12.0% overhead` after it with `--morph`.
+    fn obfs_label(&self) -> String {
+        let jitter = if self.jitter {
+            format!(
+                "{} ({}{}{})",
+                self.obfs.label(),
+                self.jitter_range.label(self.obfs),
+                if self.adaptive { ", adaptive" } else { "" },
+                if self.jitter_bulk == Some(true) { ", paused for bulk" } else { "" },
+            )
+        } else {
+            "jitter off".to_string()
+        };
+        let mut tls = match self.mimic {
+            None => "TLS mimicry",
+            Some(Mimicry::Tls) => "TLS records",
+            Some(Mimicry::Dns) => "DNS mimicry",
+            Some(Mimicry::Quic) => "QUIC mimicry",
+            Some(Mimicry::Wireguard) => "WireGuard mimicry",
+        }.to_string();
+        match self.morph {
+            Some(MorphStatus { agreed: true, distance: Some(distance), overhead }) => {
+                tls.push_str(&format!(" + morph {:.2} off target, {:.1}% overhead", distance, overhead * 100.0));
+            }
+            Some(MorphStatus { agreed: true, distance: None, .. }) => tls.push_str(" + morph"),
+            Some(_) => tls.push_str(" + morph not agreed"),
+            None => {}
+        }
+        match self.chaff {
+            Some((profile, rate)) => format!(
+                "{} + {} + chaff {} {}/s{}",
+                jitter,
+                tls,
+                profile.label(),
+                rate,
+                if self.peer_chaff { "" } else { " not agreed" },
+            ),
+            None => format!("{} + {}", jitter, tls),
+        }
+    }
+
+    /// Outgoing, then incoming: e.g. `zstd-3 on, in zstd-3`, `zstd-3+dict on` (small packets
+    /// against the dictionary), `lz4 off` (toggled), `lz4 not agreed` (sent raw: the peer
+    /// doesn't decode it), `zstd-19 on, in raw` (we decode nothing), `off, in zstd-19`,
+    /// `zstd-3 on, in off` (the peer switched its compression off). With `--compress-auto`:
+    /// `zstd-3 auto on (saving 41.2%, 3.1 µs/pkt)`, or `zstd-3 auto off (samples save 0.4%)`
+    /// while suspended. With `--compress-adapt`: `zstd-9 adapt (compressing 12%, window
+    /// full 72%)`, or `zstd adapt raw (...)` once stepped down to raw.
+    fn compression_label(&self) -> String {
+        format!("{}, in {}", self.outbound_label(), self.inbound_label())
+    }
+
+    fn outbound_label(&self) -> String {
+        if self.algorithm == Algorithm::Off {
+            return "off".to_string();
+        }
+        let state = match (self.auto, self.adapt) {
+            _ if !self.compression => "off".to_string(),
+            _ if self.outbound != self.algorithm => "not agreed".to_string(),
+            (_, Some(adapt)) => format!(
+                "adapt{} (compressing {}, window full {:.0}%)",
+                if adapt.level.is_none() { " raw" } else { "" },
+                format_metric(adapt.cpu.map(|cpu| cpu * 100.0), "%", 0),
+                adapt.window_full * 100.0,
+            ),
+            (None, None) => "on".to_string(),
+            (Some(auto), None) => match (auto.active, auto.savings) {
+                (true, Some(savings)) => format!("auto on (saving {:.1}%, {})", savings, format_metric(auto.micros_per_packet, " µs/pkt", 1)),
+                (true, None) => "auto on".to_string(),
+                (false, Some(savings)) => format!("auto off (samples save {:.1}%)", savings),
+                (false, None) => "auto off".to_string(),
+            },
+        };
+        // Stepped down to raw, no level applies.
+        let algorithm = match self.adapt {
+            Some(AdaptStatus { level: None, .. }) => self.algorithm.name().to_string(),
+            _ => self.algorithm.label(self.level),
+        };
+        format!("{}{} {}", algorithm, if self.dictionary { "+dict" } else { "" }, state)
+    }
+
+    /// What the peer's packets arrive as: its algorithm if we decode it, raw otherwise.
+    fn inbound_label(&self) -> String {
+        match self.peer_algorithm {
+            _ if self.inbound == Algorithm::Off => "raw".to_string(),
+            _ if self.peer_compression == Some(false) => "off".to_string(),
+            Some((Algorithm::Off, _)) => "raw".to_string(),
+            Some((algorithm, level)) if algorithm == self.inbound => algorithm.label(level),
+            Some((algorithm, level)) => format!("raw (peer's {} not decoded)", algorithm.label(level)),
+            None => self.inbound.name().to_string(),
+        }
+    }
+
+    fn transport_label(&self, wire_format: Option<WireFormat>) -> String {
+        format!(
+            "UDP{}, {}",
+            if self.connected_socket { " connected" } else { "" },
+            wire_format.map_or("-".to_string(), |f| format!("{:?}", f).to_lowercase())
+        )
+    }
+}
+
+/// Snapshot of the current peer session.
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub addr: Option<SocketAddr>,
+    pub established: bool,
+    /// Age of the most recent frame that passed AEAD verification.
+    pub last_auth_age: Option<Duration>,
+    /// Negotiated wire encoding.
+    pub wire_format: Option<WireFormat>,
+    /// Negotiated protocol version: the lower of the two ends'.
+    pub protocol: Option<u16>,
+    /// AEAD data is sealed with.
+    pub cipher: Option<CipherSuite>,
+    /// Fingerprints of the pre-shared keys in use (`rekey`).
+    pub key: Option<KeyStatus>,
+    /// Number of source-address changes this session.
+    pub roam_count: u64,
+    pub handshake: HandshakeState,
+}
+
+impl PeerInfo {
+    fn key_label(&self) -> String {
+        self.key.map_or("-".to_string(), |k| k.label())
+    }
+
+    fn state_label(&self) -> &'static str {
+        if matches!(self.handshake, HandshakeState::Failed { .. }) {
+            return "HANDSHAKE FAILED";
+        }
+        match (self.addr, self.established) {
+            (None, _) => "LISTENING",
+            (Some(_), true) => "ESTABLISHED",
+            (Some(_), false) => "CONNECTING",
+        }
+    }
+
+    fn state_style(&self, palette: &Palette) -> Style {
+        if matches!(self.handshake, HandshakeState::Failed { .. }) {
+            return palette.failed;
+        }
+        match (self.addr, self.established) {
+            (None, _) => palette.idle,
+            (Some(_), true) => palette.good,
+            (Some(_), false) => palette.pending,
+        }
+    }
+
+    fn addr_label(&self) -> String {
+        self.addr.map_or("-".to_string(), |a| a.to_string())
+    }
+
+    fn last_auth_label(&self) -> String {
+        self.last_auth_age.map_or("never".to_string(), |d| format!("{:.1}s ago", d.as_secs_f64()))
+    }
+}
+
+/// Dashboard settings derived from the command line.
+pub struct DashboardConfig {
+    /// Where 's' snapshots are written.
+    pub dump_dir: PathBuf,
+    /// Commands bound to keys go to the core's control dispatcher.
+    pub control: ControlSender,
+    /// Log lines retained for scrollback; older lines are discarded.
+    pub log_history: usize,
+    /// Lines from `--log-file` left by earlier sessions, shown above this one's.
+    pub history: Option<log_file::History>,
+    /// Time span shown by the graphs at startup.
+    pub graph_window: Duration,
+    /// Ring the terminal bell when a new alert fires.
+    pub alert_bell: bool,
+    /// Styles for every widget.
+    pub palette: Palette,
+    /// Render in the normal screen buffer so the final frame survives exit.
+    pub inline: bool,
+    /// Initial unit for throughput rates.
+    pub units: RateUnit,
+    /// Graph sample period, which is also how often the screen is redrawn when idle.
+    pub tick: Duration,
+    /// Capture the mouse (wheel scrolls logs, clicks select tabs); off keeps terminal text selection.
+    pub mouse: bool,
+    /// Observed every frame; `q` requests it.
+    pub shutdown: Shutdown,
 }
 
 impl TelemetryState {
-    fn new() -> Self {
+    fn new(log_history: usize, graph_window: Duration, tick: Duration) -> Self {
+        let retained = ticks_in(graph_window.max(GRAPH_PRESETS[GRAPH_PRESETS.len() - 1]), tick);
         Self {
-            tx_history: vec![0; 100],
-            rx_history: vec![0; 100],
-            logs: vec![],
+            tab: Tab::Overview,
+            tx_history: VecDeque::from(vec![0; retained]),
+            rx_history: VecDeque::from(vec![0; retained]),
+            tx_packet_history: VecDeque::from(vec![0; retained]),
+            rx_packet_history: VecDeque::from(vec![0; retained]),
+            tx_wire_history: VecDeque::from(vec![0; retained]),
+            rx_wire_history: VecDeque::from(vec![0; retained]),
+            graph_unit: GraphUnit::Bytes,
+            rate_unit: RateUnit::Bytes,
+            rtt_history: VecDeque::from(vec![None; retained]),
+            graph_window,
+            tick,
+            logs: LogBuffer::with_capacity(log_history),
+            log_view: LogView { follow: true, anchor: 0, paused_at: 0, rows: 1 },
+            search: LogSearch::default(),
             total_tx: 0,
             total_rx: 0,
-            jitter_ms: 12.5,
-            loss_rate: 0.01,
+            totals_since: None,
+            quality: LinkQuality::default(),
+            echo_loss: None,
+            dictionary: None,
+            compression: None,
+            latency: Latency::default(),
+            peer: PeerInfo::default(),
+            settings: None,
+            window: WindowStats::default(),
+            window_full_since: None,
+            alerts: Vec::new(),
+            connection: ConnectionState::default(),
+            connection_since: Instant::now(),
+            counters: CounterSnapshot::default(),
+            counter_rates: Vec::new(),
+            counters_at: None,
+            packet_sizes: PacketSizes::default(),
+            overhead: Overhead::default(),
+            quota: QuotaStatus::default(),
+            size_view: SizeView::Ingress,
             start_time: Instant::now(),
+            telemetry_dropped: 0,
+            hitboxes: Hitboxes::default(),
         }
     }
 
     fn on_tick(&mut self) {
         // Shift history window
-        self.tx_history.remove(0);
-        self.tx_history.push(0);
-        self.rx_history.remove(0);
-        self.rx_history.push(0);
+        self.tx_history.pop_front();
+        self.tx_history.push_back(0);
+        self.rx_history.pop_front();
+        self.rx_history.push_back(0);
+        self.tx_packet_history.pop_front();
+        self.tx_packet_history.push_back(0);
+        self.rx_packet_history.pop_front();
+        self.rx_packet_history.push_back(0);
+        self.tx_wire_history.pop_front();
+        self.tx_wire_history.push_back(0);
+        self.rx_wire_history.pop_front();
+        self.rx_wire_history.push_back(0);
+        self.rtt_history.pop_front();
+        self.rtt_history.push_back(self.quality.srtt_ms);
+    }
+
+    /// Index of the oldest sample inside the graph window.
+    fn window_start(&self) -> usize {
+        self.tx_history.len().saturating_sub(ticks_in(self.graph_window, self.tick))
+    }
+
+    /// Traffic samples for a graph `width` columns wide; each column is the busiest tick it covers.
+    fn traffic_series(history: &VecDeque<u64>, start: usize, width: usize) -> Vec<u64> {
+        let visible: Vec<u64> = history.range(start..).copied().collect();
+        downsample(&visible, width, |bucket| bucket.iter().copied().max().unwrap_or(0))
+    }
+
+    /// Per-second rate over the last second of completed ticks (the last one, if ticks are longer).
+    fn recent_rate(&self, history: &VecDeque<u64>) -> u64 {
+        self.average_rate(history, Duration::from_secs(1))
+    }
+
+    /// Per-second rate over the completed ticks of the last `span`, or of the time since
+    /// startup while that is shorter (idle ticks before it would only drag the average down).
+    fn average_rate(&self, history: &VecDeque<u64>, span: Duration) -> u64 {
+        let completed = (self.start_time.elapsed().as_millis() / self.tick.as_millis().max(1)) as usize;
+        let ticks = ticks_in(span, self.tick).min(completed.max(1));
+        let sum: u64 = history.iter().rev().skip(1).take(ticks).sum();
+        (sum as u128 * 1000 / (ticks as u128 * self.tick.as_millis()).max(1)) as u64
+    }
+
+    /// The `RATE_WINDOWS` averages of a byte history: `10s 1.20 MB/s, 60s 1.13 MB/s`.
+    fn window_rates(&self, history: &VecDeque<u64>) -> String {
+        RATE_WINDOWS.iter()
+            .map(|span| format!("{}s {}", span.as_secs(), format_rate(self.average_rate(history, *span) as f64, self.rate_unit)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Zeroes the ingress/egress totals; the rate histories are left alone.
+    fn reset_totals(&mut self, now: Instant) {
+        self.total_tx = 0;
+        self.total_rx = 0;
+        self.totals_since = Some(now);
+    }
+
+    /// Where the totals count from: `since startup` or `since reset 4m 12s ago`.
+    fn totals_label(&self) -> String {
+        self.totals_since.map_or("since startup".to_string(), |t| format!("since reset {} ago", format_elapsed(t.elapsed())))
+    }
+
+    /// The wire rate behind a goodput history, and goodput's share of it: `1.41 MB/s wire, 85%`.
+    fn wire_rate(&self, goodput: &VecDeque<u64>, wire: &VecDeque<u64>) -> String {
+        let (goodput, wire) = (self.recent_rate(goodput), self.recent_rate(wire));
+        let share = if wire > 0 { format!("{:.0}%", goodput as f64 / wire as f64 * 100.0) } else { "-".to_string() };
+        format!("{} wire, {}", format_rate(wire as f64, self.rate_unit), share)
+    }
+
+    /// Histories the traffic graphs plot in the current unit: (ingress, egress).
+    fn traffic_histories(&self) -> (&VecDeque<u64>, &VecDeque<u64>) {
+        match self.graph_unit {
+            GraphUnit::Bytes => (&self.tx_history, &self.rx_history),
+            GraphUnit::Packets => (&self.tx_packet_history, &self.rx_packet_history),
+        }
+    }
+
+    /// RTT in microseconds (Sparkline wants integers), each column the mean of its ticks.
+    fn rtt_series(&self, width: usize) -> Vec<u64> {
+        let visible: Vec<Option<f64>> = self.rtt_history.range(self.window_start()..).copied().collect();
+        downsample(&visible, width, |bucket| {
+            let samples: Vec<f64> = bucket.iter().flatten().copied().collect();
+            if samples.is_empty() {
+                return 0;
+            }
+            (samples.iter().sum::<f64>() / samples.len() as f64 * 1000.0) as u64
+        })
+    }
+
+    /// Steps the graph window through the presets (`longer`: towards 1h).
+    fn zoom_graphs(&mut self, longer: bool) {
+        let next = if longer {
+            GRAPH_PRESETS.iter().find(|p| **p > self.graph_window)
+        } else {
+            GRAPH_PRESETS.iter().rev().find(|p| **p < self.graph_window)
+        };
+        if let Some(window) = next {
+            self.graph_window = *window;
+        }
+    }
+
+    fn on_window(&mut self, window: WindowStats, now: Instant) {
+        let full = window.capacity > 0 && window.in_flight >= window.capacity;
+        self.window_full_since = if full { self.window_full_since.or(Some(now)) } else { None };
+        self.window = window;
+    }
+
+    fn window_stalled(&self) -> bool {
+        self.window_full_since.is_some_and(|t| t.elapsed() >= WINDOW_STALL_AFTER)
+    }
+
+    /// e.g. ` | paced 4520/s | ECN 1.2%` on the window gauge; empty while pacing is off or has
+    /// no RTT yet, and without ECN.
+    fn pacing_label(&self) -> String {
+        let paced = self.window.pacing_rate.map_or(String::new(), |rate| format!(" | paced {:.0}/s", rate));
+        let ecn = self.window.ecn_mark_rate.map_or(String::new(), |rate| format!(" | ECN {:.1}%", rate * 100.0));
+        paced + &ecn
+    }
+
+    fn on_counters(&mut self, snapshot: CounterSnapshot, now: Instant) {
+        if let Some(prev_at) = self.counters_at {
+            let secs = now.duration_since(prev_at).as_secs_f64();
+            if secs > 0.0 {
+                self.counter_rates = snapshot.rows().iter().zip(self.counters.rows())
+                    .map(|((_, cur), (_, prev))| cur.saturating_sub(prev) as f64 / secs)
+                    .collect();
+            }
+        }
+        self.counters = snapshot;
+        self.counters_at = Some(now);
+    }
+
+    /// Aligned `name  total  +rate/s` lines for the counters panel.
+    fn counter_lines(&self) -> String {
+        self.counters.rows().iter().enumerate()
+            .map(|(i, (name, total))| match self.counter_rates.get(i) {
+                Some(rate) => format!("{:<19} {:>10} {:>+8.0}/s", name, total, rate),
+                None => format!("{:<19} {:>10}", name, total),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Per frame type: totals each way and each type's share of all frames, so an
+    /// unusual mix (ACKs swamping data, a link that only carries heartbeats) stands out.
+    fn frame_mix_lines(&self) -> String {
+        let mix = self.counters.frame_mix();
+        let total_sent: u64 = mix.iter().map(|(_, sent, _)| sent).sum();
+        let total_received: u64 = mix.iter().map(|(_, _, received)| received).sum();
+        let mut lines = vec![format!("{:<10} {:>10} {:>7} {:>10} {:>7}", "TYPE", "SENT", "SHARE", "RECV", "SHARE")];
+        lines.extend(mix.iter().map(|(name, sent, received)| {
+            format!("{:<10} {:>10} {:>7} {:>10} {:>7}", name, sent, share(*sent, total_sent), received, share(*received, total_received))
+        }));
+        lines.join("\n")
+    }
+
+    /// What the wire bytes each way carried, then the inner bytes against the wire total.
+    fn overhead_lines(&self) -> String {
+        let (tx, rx) = (&self.overhead.tx, &self.overhead.rx);
+        let row = |name: &str, sent: u64, received: u64| {
+            format!("{:<10} {:>10} {:>7} {:>10} {:>7}", name, format_bytes(sent), share(sent, tx.wire), format_bytes(received), share(received, rx.wire))
+        };
+        let mut lines = vec![format!("{:<10} {:>10} {:>7} {:>10} {:>7}", "BYTES", "SENT", "SHARE", "RECV", "SHARE")];
+        lines.extend(tx.rows().iter().zip(rx.rows()).map(|((name, sent), (_, received))| row(name, *sent, received)));
+        lines.push(row("wire", tx.wire, rx.wire));
+        lines.push(row("inner", tx.inner, rx.inner));
+        lines.join("\n")
+    }
+
+    /// "EFFICIENCY tx 91.2% / rx 90.8%": inner bytes per wire byte each way.
+    fn efficiency_title(&self) -> String {
+        let pct = |v: Option<f64>| format_metric(v.map(|e| e * 100.0), "%", 1);
+        format!("EFFICIENCY tx {} / rx {}", pct(self.overhead.tx.efficiency()), pct(self.overhead.rx.efficiency()))
+    }
 
-        // Simulate network fluctuations
-        let mut rng = rand::thread_rng();
-        // Jitter wanders between 5ms and 25ms
-        self.jitter_ms = (self.jitter_ms + rng.gen_range(-2.0..2.0)).max(5.0).min(25.0);
-        // Loss rate wanders between 0.00% and 0.50%
-        self.loss_rate = (self.loss_rate + rng.gen_range(-0.05..0.05)).max(0.0).min(0.5);
+    /// Per size bucket: packet counts each way and their share of that direction.
+    fn packet_size_lines(&self) -> String {
+        let total_tx: u64 = self.packet_sizes.tx.iter().sum();
+        let total_rx: u64 = self.packet_sizes.rx.iter().sum();
+        let mut lines = vec![format!("{:<8} {:>10} {:>7} {:>10} {:>7}", "SIZE", "INGRESS", "SHARE", "EGRESS", "SHARE")];
+        lines.extend(self.packet_sizes.rows().iter().map(|(label, tx, rx)| {
+            format!("{:<8} {:>10} {:>7} {:>10} {:>7}", label, tx, share(*tx, total_tx), rx, share(*rx, total_rx))
+        }));
+        lines.join("\n")
+    }
+
+    /// Fills the log with earlier sessions' lines, then a divider marking where this one starts.
+    fn replay_history(&mut self, history: &log_file::History) {
+        if history.entries.is_empty() {
+            return;
+        }
+        for entry in &history.entries {
+            self.logs.push_with(entry.level, |line| {
+                let _ = write!(line, "[{}] {}", entry.time, entry.msg);
+            });
+        }
+        let note = if history.ended_cleanly { "" } else { " (it did not shut down cleanly)" };
+        self.push_log(Level::Info, &format!("──── Replayed from the log file; previous session ends here{} ────", note));
+    }
+
+    fn push_log(&mut self, level: Level, msg: &str) {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        self.logs.push_with(level, |line| {
+            let _ = write!(line, "[{}] {}", timestamp, msg);
+        });
+    }
+
+    /// Session goodput against whichever quota is set (bytes first), plus any enforcement.
+    fn quota_label(&self) -> String {
+        let usage = &self.quota.usage;
+        let mut label = match (self.quota.bytes_limit, self.quota.packets_limit) {
+            (Some(cap), _) => format!("{} of {}", format_bytes(usage.goodput_bytes()), format_bytes(cap)),
+            (None, Some(cap)) => format!("{} of {} pkts", usage.packets(), cap),
+            (None, None) => format_bytes(usage.goodput_bytes()),
+        };
+        match self.quota.state {
+            QuotaState::WithinQuota => {}
+            QuotaState::Throttled { .. } => label.push_str(" THROTTLED"),
+            QuotaState::Disconnected { .. } => label.push_str(" CUT OFF"),
+        }
+        label
+    }
+
+    /// Plain-text stats block for snapshot dumps.
+    fn stats_report(&self) -> String {
+        let pct = |v: Option<f64>| format_metric(v.map(|l| l * 100.0), "%", 2);
+        let mut out = String::new();
+        out.push_str(&format!("uptime:          {:?}\n", self.start_time.elapsed()));
+        out.push_str(&format!("peer:            {}\n", self.peer.addr_label()));
+        out.push_str(&format!("peer state:      {}\n", self.peer.state_label()));
+        out.push_str(&format!("connection:      {} for {}\n", self.connection.name(), format_elapsed(self.connection_since.elapsed())));
+        out.push_str(&format!("last auth rx:    {}\n", self.peer.last_auth_label()));
+        out.push_str(&format!("wire format:     {}\n", self.peer.wire_format.map_or("-".to_string(), |f| format!("{:?}", f))));
+        out.push_str(&format!("protocol:        {}\n", self.peer.protocol.map_or("-".to_string(), |v| format!("v{}", v))));
+        out.push_str(&format!("cipher:          {}\n", self.peer.cipher.map_or("-", |c| c.name())));
+        out.push_str(&format!("key:             {}\n", self.peer.key_label()));
+        if let Some(settings) = &self.settings {
+            out.push_str(&format!("obfuscation:     {}\n", settings.obfs_label()));
+            out.push_str(&format!("compression:     {}\n", settings.compression_label()));
+            out.push_str(&format!("transport:       {}\n", settings.transport_label(self.peer.wire_format)));
+        }
+        if let Some(report) = &self.compression {
+            out.push_str(&format!("compress out:    {}\n", codec_direction_label(&report.session.tx, &report.window.tx)));
+            out.push_str(&format!("decompress in:   {}\n", codec_direction_label(&report.session.rx, &report.window.rx)));
+        }
+        if let Some(dict) = &self.dictionary {
+            out.push_str(&format!("dictionary:      {}\n", dictionary_label(dict)));
+        }
+        out.push_str(&format!("roams:           {}\n", self.peer.roam_count));
+        out.push_str(&format!("session usage:   {} ({} pkts)\n", self.quota_label(), self.quota.usage.packets()));
+        out.push_str(&format!(
+            "session wire:    {} sent, {} received (goodput {} / {})\n",
+            format_bytes(self.quota.usage.wire_tx), format_bytes(self.quota.usage.wire_rx),
+            format_bytes(self.quota.usage.goodput_tx), format_bytes(self.quota.usage.goodput_rx)
+        ));
+        if let Some(reason) = self.quota.state.reason() {
+            out.push_str(&format!("quota:           {} ({})\n", self.quota.state.name(), reason));
+        }
+        out.push_str(&format!("ingress total:   {} ({} bytes, {})\n", format_bytes(self.total_tx), self.total_tx, self.totals_label()));
+        out.push_str(&format!("egress total:    {} ({} bytes, {})\n", format_bytes(self.total_rx), self.total_rx, self.totals_label()));
+        out.push_str(&format!("ingress rate:    {} ({})\n",
+            format_rate(self.recent_rate(&self.tx_history) as f64, self.rate_unit), self.wire_rate(&self.tx_history, &self.tx_wire_history)));
+        out.push_str(&format!("ingress avg:     {}\n", self.window_rates(&self.tx_history)));
+        out.push_str(&format!("egress rate:     {} ({})\n",
+            format_rate(self.recent_rate(&self.rx_history) as f64, self.rate_unit), self.wire_rate(&self.rx_history, &self.rx_wire_history)));
+        out.push_str(&format!("egress avg:      {}\n", self.window_rates(&self.rx_history)));
+        out.push_str(&format!("srtt:            {}\n", format_metric(self.quality.srtt_ms, "ms", 2)));
+        out.push_str(&format!("jitter:          {}\n", format_metric(self.quality.jitter_ms, "ms", 2)));
+        out.push_str(&format!("rtt (session):   {}\n", self.latency.session));
+        out.push_str(&format!("rtt (1 min):     {}\n", self.latency.window));
+        out.push_str(&format!("loss (arq):      {}\n", pct(self.quality.loss_rate)));
+        if let Some(echo) = self.echo_loss {
+            out.push_str(&format!("loss (echo):     up {} / down {}\n", pct(echo.forward), pct(echo.reverse)));
+        }
+        out.push_str(&format!(
+            "window:          {}/{} in flight ({}), {:.1} retransmits/s\n",
+            self.window.in_flight, self.window.capacity, format_bytes(self.window.bytes as u64), self.window.retransmit_rate
+        ));
+        let pacing = match (self.settings.is_some_and(|s| s.pacing), self.window.pacing_rate) {
+            (false, _) => "off".to_string(),
+            (true, None) => "on, waiting for an RTT sample".to_string(),
+            (true, Some(rate)) => format!("{:.0} frames/s", rate),
+        };
+        out.push_str(&format!("pacing:          {}\n", pacing));
+        if let Some(rate) = self.window.ecn_mark_rate {
+            out.push_str(&format!("ecn:             {:.1}% of received frames marked CE\n", rate * 100.0));
+        }
+        if self.telemetry_dropped > 0 {
+            out.push_str(&format!("telemetry lost:  {} updates (figures above may undercount)\n", self.telemetry_dropped));
+        }
+        out.push_str("\n## Counters\n");
+        out.push_str(&self.counter_lines());
+        out.push('\n');
+        out.push_str("\n## Frame mix\n");
+        out.push_str(&self.frame_mix_lines());
+        out.push('\n');
+        out.push_str("\n## Packet sizes\n");
+        out.push_str(&self.packet_size_lines());
+        out.push('\n');
+        out.push_str(&format!("\n## Overhead ({})\n", self.efficiency_title().to_lowercase()));
+        out.push_str(&self.overhead_lines());
+        out.push('\n');
+        out
+    }
+
+    /// Id of the bottom-most visible log line.
+    fn log_bottom(&self) -> u64 {
+        if self.log_view.follow {
+            self.logs.next_id().saturating_sub(1)
+        } else {
+            // The pinned line may have been evicted since; stay on the oldest full page.
+            self.log_view.anchor.max(self.oldest_log_bottom())
+        }
+    }
+
+    /// Lowest bottom line that still shows a full page of retained history.
+    fn oldest_log_bottom(&self) -> u64 {
+        let oldest_bottom = self.logs.first_id() + (self.log_view.rows as u64).saturating_sub(1);
+        oldest_bottom.min(self.logs.next_id().saturating_sub(1))
+    }
+
+    /// Stop auto-follow, pinning the view to the current bottom line.
+    fn pause_logs(&mut self) {
+        if self.log_view.follow {
+            self.log_view.anchor = self.log_bottom();
+            self.log_view.paused_at = self.logs.next_id();
+            self.log_view.follow = false;
+        }
+    }
+
+    fn resume_logs(&mut self) {
+        self.log_view.follow = true;
+    }
+
+    /// Scrolls towards older (negative) or newer (positive) lines. Any scroll pauses follow.
+    fn scroll_logs(&mut self, delta: i64) {
+        self.pause_logs();
+        let newest = self.logs.next_id().saturating_sub(1);
+        let target = self.log_bottom().saturating_add_signed(delta);
+        self.log_view.anchor = target.clamp(self.oldest_log_bottom(), newest);
+    }
+
+    /// Moves the view to the next older search match (or newer, for `N`) from the
+    /// last one visited, wrapping around. Pauses follow.
+    fn jump_to_match(&mut self, older: bool) {
+        let threshold = logging::threshold();
+        let from = self.search.current.unwrap_or_else(|| self.log_bottom() + 1);
+        let Some(id) = self.search.step(from, older, |id| self.logs.get(id).is_some_and(|(level, _)| *level >= threshold)) else {
+            return;
+        };
+        self.pause_logs();
+        self.log_view.anchor = id.max(self.oldest_log_bottom());
+    }
+
+    /// The wheel over the logs scrolls them (pausing follow, like the keys); a left
+    /// click selects a tab, or opens the full tab of an overview pane.
+    fn on_mouse(&mut self, mouse: MouseEvent) {
+        let (column, row) = (mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::ScrollUp if self.hitboxes.over_logs(column, row) => self.scroll_logs(-WHEEL_LINES),
+            MouseEventKind::ScrollDown if self.hitboxes.over_logs(column, row) => self.scroll_logs(WHEEL_LINES),
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(tab) = self.hitboxes.tab_at(column, row) {
+                    self.tab = tab;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// (min, avg, max) RTT over the graph window, if any samples exist.
+    fn rtt_summary(&self) -> Option<(f64, f64, f64)> {
+        let samples: Vec<f64> = self.rtt_history.range(self.window_start()..).flatten().copied().collect();
+        if samples.is_empty() {
+            return None;
+        }
+        let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let avg = samples.iter().sum::<f64>() / samples.len() as f64;
+        Some((min, avg, max))
     }
 }
 
-pub fn spawn_dashboard(rx: mpsc::Receiver<TelemetryUpdate>) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        // TUI boilerplate setup
-        enable_raw_mode().unwrap();
-        let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen, EnableMouseCapture).unwrap();
-        let backend = CrosstermBackend::new(stdout);
-        let mut terminal = Terminal::new(backend).unwrap();
-
-        let mut app = TelemetryState::new();
-        let tick_rate = Duration::from_millis(250);
+/// Name of the dashboard thread (the panic hook keys off it).
+const DASHBOARD_THREAD: &str = "dashboard";
+
+/// How often the headless consumer looks for a shutdown while the queue is idle.
+const HEADLESS_POLL: Duration = Duration::from_millis(250);
+
+/// Set when the dashboard runs without the alternate screen (`--inline`), so teardown
+/// (which may run from the panic hook) knows there is no screen to leave.
+static INLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set when mouse capture was turned on, so teardown only turns off what it must.
+static MOUSE: AtomicBool = AtomicBool::new(false);
+
+/// Runs the dashboard on its own thread, until a shutdown is requested (`q` requests one).
+/// If the terminal cannot be taken over (no TTY, raw mode refused), it runs headless instead.
+pub fn spawn_dashboard(rx: TelemetryReceiver, config: DashboardConfig) -> thread::JoinHandle<()> {
+    install_panic_hook();
+    spawn_named(DASHBOARD_THREAD, move || {
+        let mut terminal = match enter_terminal(config.inline, config.mouse) {
+            Ok(terminal) => terminal,
+            Err(e) => {
+                restore_terminal();
+                eprintln!("Dashboard unavailable ({}); running headless", e);
+                return run_headless(rx, config.shutdown);
+            }
+        };
+        let _guard = TerminalGuard;
+
+        let mut app = TelemetryState::new(config.log_history, config.graph_window, config.tick);
+        app.rate_unit = config.units;
+        if let Some(history) = &config.history {
+            app.replay_history(history);
+        }
+        let palette = config.palette;
+        // Results from background work (snapshot dumps) land here as log lines.
+        let (notice_tx, notice_rx) = mpsc::channel::<(Level, String)>();
+        // Key commands awaiting the core's reply (polled, never blocked on).
+        let mut in_flight: Vec<(&'static str, tokio::sync::oneshot::Receiver<String>)> = Vec::new();
         let mut last_tick = Instant::now();
+        let mut show_help = false;
 
         loop {
             // Draw UI
-            terminal.draw(|f| {
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([
-                        Constraint::Length(3),      // Status Bar
-                        Constraint::Percentage(40), // Traffic Graphs
-                        Constraint::Percentage(50), // System Logs
-                    ].as_ref())
-                    .split(f.size());
-
-                // 1. Status Bar
-                let header = Paragraph::new(format!(
-                    "RESILINET PROTOCOL (RSOCK-V2) | UPTIME: {:?} | INGRESS: {} | EGRESS: {} | LOSS: {:.2}% | JITTER: {:.1}ms", 
-                    app.start_time.elapsed(),
-                    format_bytes(app.total_tx),
-                    format_bytes(app.total_rx),
-                    app.loss_rate,
-                    app.jitter_ms
-                ))
-                .block(Block::default().borders(Borders::ALL).title(" EDGE GATEWAY TELEMETRY "));
-                f.render_widget(header, chunks[0]);
-
-                // 2. Traffic Graphs
-                let graph_chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-                    .split(chunks[1]);
-
-                let tx_spark = Sparkline::default()
-                    .block(Block::default().title("Ingress (IoT)").borders(Borders::ALL))
-                    .data(&app.tx_history)
-                    .style(Style::default().fg(Color::LightGreen)); // "Hacker" Green
-                f.render_widget(tx_spark, graph_chunks[0]);
-
-                let rx_spark = Sparkline::default()
-                    .block(Block::default().title("Egress (Cloud)").borders(Borders::ALL))
-                    .data(&app.rx_history)
-                    .style(Style::default().fg(Color::LightCyan)); // Sci-fi Cyan
-                f.render_widget(rx_spark, graph_chunks[1]);
-
-                // 3. Logs
-                let log_items: Vec<ListItem> = app.logs.iter()
-                    .rev()
-                    .take(20)
-                    .map(|l| ListItem::new(l.as_str()))
-                    .collect();
-                let log_list = List::new(log_items)
-                    .block(Block::default().title("GATEWAY EVENTS").borders(Borders::ALL));
-                f.render_widget(log_list, chunks[2]);
-
-            }).unwrap();
+            terminal.draw(|f| draw(f, &mut app, &palette, show_help)).unwrap();
 
             
-            if crossterm::event::poll(Duration::from_millis(0)).unwrap() {
-                if let Event::Key(key) = event::read().unwrap() {
-                    match key.code {
-                        KeyCode::Char('q') | KeyCode::Esc => break,
-                        _ => {}
+            // Sleep until a key arrives or the tick is due; telemetry waits for the next redraw.
+            if crossterm::event::poll(app.tick.saturating_sub(last_tick.elapsed())).unwrap() {
+                let event = event::read().unwrap();
+                if let Event::Mouse(mouse) = event {
+                    // Any click dismisses the help overlay, like Esc.
+                    if show_help && matches!(mouse.kind, MouseEventKind::Down(_)) {
+                        show_help = false;
+                    } else if !show_help {
+                        app.on_mouse(mouse);
+                    }
+                }
+                if let Event::Key(key) = event {
+                    if show_help && matches!(key.code, KeyCode::Esc | KeyCode::Char('?')) {
+                        show_help = false;
+                    } else if app.search.editing {
+                        match key.code {
+                            KeyCode::Char(c) => app.search.push(c, &app.logs),
+                            KeyCode::Backspace => app.search.pop(&app.logs),
+                            KeyCode::Enter => app.search.confirm(),
+                            KeyCode::Esc => app.search.clear(),
+                            _ => {}
+                        }
+                    } else if key.code == KeyCode::Esc && app.search.active() {
+                        app.search.clear();
+                    } else {
+                        match binding_for(key.code).map(|b| &b.action) {
+                            Some(Action::Quit) => {
+                                config.shutdown.request(StopReason::Quit);
+                                break;
+                            }
+                            Some(Action::Help) => show_help = true,
+                            Some(Action::SelectTab(tab)) => app.tab = *tab,
+                            Some(Action::NextTab) => app.tab = app.tab.next(),
+                            Some(Action::ScrollUp) => app.scroll_logs(-1),
+                            Some(Action::ScrollDown) => app.scroll_logs(1),
+                            Some(Action::PageUp) => app.scroll_logs(-(app.log_view.rows as i64)),
+                            Some(Action::PageDown) => app.scroll_logs(app.log_view.rows as i64),
+                            Some(Action::PauseLogs) => app.pause_logs(),
+                            Some(Action::Search) => {
+                                if !matches!(app.tab, Tab::Overview | Tab::Logs) {
+                                    app.tab = Tab::Logs;
+                                }
+                                app.search.start();
+                            }
+                            Some(Action::NextMatch) => app.jump_to_match(true),
+                            Some(Action::PrevMatch) => app.jump_to_match(false),
+                            Some(Action::FollowLogs) => app.resume_logs(),
+                            Some(Action::Snapshot) => spawn_snapshot_dump(&app, &config.dump_dir, notice_tx.clone()),
+                            Some(Action::ZoomIn) => app.zoom_graphs(true),
+                            Some(Action::ZoomOut) => app.zoom_graphs(false),
+                            Some(Action::ToggleGraphUnit) => app.graph_unit = app.graph_unit.toggle(),
+                            Some(Action::ToggleRateUnit) => app.rate_unit = app.rate_unit.toggle(),
+                            Some(Action::ToggleSizeView) => app.size_view = app.size_view.toggle(),
+                            Some(Action::CycleLogLevel) => {
+                                let level = logging::threshold().cycle();
+                                logging::set_threshold(level);
+                                app.push_log(Level::Info, &format!("UI: Log level set to {}", level.label()));
+                            }
+                            Some(Action::Core(label, command)) => {
+                                let (reply, reply_rx) = tokio::sync::oneshot::channel();
+                                match config.control.try_send(ControlRequest { command: command.clone(), reply }) {
+                                    Ok(()) => in_flight.push((label, reply_rx)),
+                                    Err(e) => app.push_log(Level::Warn, &format!("CMD: {} failed: {}", label, e)),
+                                }
+                            }
+                            None => {}
+                        }
                     }
                 }
             }
@@ -145,47 +997,1149 @@ pub fn spawn_dashboard(rx: mpsc::Receiver<TelemetryUpdate>) -> thread::JoinHandl
             // Data Ingestion
             while let Ok(msg) = rx.try_recv() {
                 match msg {
-                    TelemetryUpdate::Throughput { tx_bytes, rx_bytes } => {
+                    TelemetryUpdate::Throughput { tx_bytes, rx_bytes, tx_packets, rx_packets, tx_wire, rx_wire } => {
                         app.total_tx += tx_bytes;
                         app.total_rx += rx_bytes;
                         
                        
-                        let last_idx = app.tx_history.len() - 1;
-                        app.tx_history[last_idx] += tx_bytes;
-                        app.rx_history[last_idx] += rx_bytes;
+                        if let (Some(tx), Some(rx)) = (app.tx_history.back_mut(), app.rx_history.back_mut()) {
+                            *tx += tx_bytes;
+                            *rx += rx_bytes;
+                        }
+                        if let (Some(tx), Some(rx)) = (app.tx_packet_history.back_mut(), app.rx_packet_history.back_mut()) {
+                            *tx += tx_packets;
+                            *rx += rx_packets;
+                        }
+                        if let (Some(tx), Some(rx)) = (app.tx_wire_history.back_mut(), app.rx_wire_history.back_mut()) {
+                            *tx += tx_wire;
+                            *rx += rx_wire;
+                        }
+                    }
+                    TelemetryUpdate::LinkQuality(quality) => {
+                        app.quality = quality;
+                        if let Some(last) = app.rtt_history.back_mut() {
+                            *last = quality.srtt_ms;
+                        }
+                    }
+                    TelemetryUpdate::EchoLoss(loss) => {
+                        app.echo_loss = Some(loss);
+                    }
+                    TelemetryUpdate::Latency(latency) => {
+                        app.latency = latency;
+                    }
+                    TelemetryUpdate::PeerState(info) => {
+                        app.peer = info;
+                    }
+                    TelemetryUpdate::Settings(settings) => {
+                        app.settings = Some(settings);
+                    }
+                    TelemetryUpdate::Window(window) => {
+                        app.on_window(window, Instant::now());
+                    }
+                    TelemetryUpdate::Counters(snapshot) => {
+                        app.on_counters(snapshot, Instant::now());
+                    }
+                    TelemetryUpdate::PacketSizes(sizes) => {
+                        app.packet_sizes = sizes;
+                    }
+                    TelemetryUpdate::Overhead(overhead) => {
+                        app.overhead = overhead;
+                    }
+                    TelemetryUpdate::Dictionary(stats) => {
+                        app.dictionary = Some(stats);
                     }
-                    TelemetryUpdate::Log(msg) => {
-                        let timestamp = chrono::Local::now().format("%H:%M:%S");
-                        app.logs.push(format!("[{}] {}", timestamp, msg));
+                    TelemetryUpdate::Compression(report) => {
+                        app.compression = Some(report);
+                    }
+                    TelemetryUpdate::Quota(status) => {
+                        app.quota = status;
+                    }
+                    TelemetryUpdate::Log { level, msg } => {
+                        app.push_log(level, &msg);
+                    }
+                    TelemetryUpdate::Alerts(alerts) => {
+                        let fresh = alerts.iter().any(|a| !app.alerts.iter().any(|b| b.kind == a.kind));
+                        if fresh && config.alert_bell {
+                            let _ = io::stdout().write_all(b"\x07");
+                        }
+                        app.alerts = alerts;
+                    }
+                    TelemetryUpdate::Connection(state) => {
+                        if state != app.connection {
+                            app.connection = state;
+                            app.connection_since = Instant::now();
+                        }
+                    }
+                    TelemetryUpdate::StatsReset => {
+                        app.reset_totals(Instant::now());
+                    }
+                    TelemetryUpdate::Shutdown { reason } => {
+                        app.push_log(Level::Error, &format!("FATAL: {}", reason));
                     }
                 }
             }
 
+            app.telemetry_dropped = rx.dropped();
+
+            while let Ok((level, notice)) = notice_rx.try_recv() {
+                app.push_log(level, &notice);
+            }
+
+            in_flight.retain_mut(|(label, reply_rx)| match reply_rx.try_recv() {
+                Ok(reply) => {
+                    let level = if reply.starts_with("ERR") { Level::Warn } else { Level::Info };
+                    app.push_log(level, &format!("CMD: {}: {}", label, reply));
+                    false
+                }
+                Err(tokio::sync::oneshot::error::TryRecvError::Empty) => true,
+                Err(tokio::sync::oneshot::error::TryRecvError::Closed) => {
+                    app.push_log(Level::Warn, &format!("CMD: {} failed: no reply from core", label));
+                    false
+                }
+            });
+
+            if config.shutdown.reason().is_some() {
+                break;
+            }
+
          
-            if last_tick.elapsed() >= tick_rate {
+            if last_tick.elapsed() >= app.tick {
                 app.on_tick();
                 last_tick = Instant::now();
             }
         }
+    })
+}
+
+/// Quantity plotted by the traffic graphs, per tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GraphUnit {
+    Bytes,
+    Packets,
+}
+
+impl GraphUnit {
+    fn label(self, rate_unit: RateUnit) -> &'static str {
+        match self {
+            GraphUnit::Bytes => rate_unit.label(),
+            GraphUnit::Packets => "pkts",
+        }
+    }
+
+    fn toggle(self) -> Self {
+        match self {
+            GraphUnit::Bytes => GraphUnit::Packets,
+            GraphUnit::Packets => GraphUnit::Bytes,
+        }
+    }
+}
+
+/// Direction shown by the packet size histogram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SizeView {
+    Ingress,
+    Egress,
+}
+
+impl SizeView {
+    fn label(self) -> &'static str {
+        match self {
+            SizeView::Ingress => "ingress",
+            SizeView::Egress => "egress",
+        }
+    }
+
+    fn toggle(self) -> Self {
+        match self {
+            SizeView::Ingress => SizeView::Egress,
+            SizeView::Egress => SizeView::Ingress,
+        }
+    }
+}
+
+/// Dashboard tabs, in tab-bar order (number keys select them by position).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tab {
+    Overview,
+    Peers,
+    Counters,
+    Logs,
+}
 
+const TABS: [Tab; 4] = [Tab::Overview, Tab::Peers, Tab::Counters, Tab::Logs];
 
-        disable_raw_mode().unwrap();
-        execute!(
-            terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        ).unwrap();
-        terminal.show_cursor().unwrap();
+impl Tab {
+    fn title(self) -> &'static str {
+        match self {
+            Tab::Overview => "1 Overview",
+            Tab::Peers => "2 Peers",
+            Tab::Counters => "3 Counters",
+            Tab::Logs => "4 Logs",
+        }
+    }
+
+    fn next(self) -> Tab {
+        let i = TABS.iter().position(|t| *t == self).unwrap_or(0);
+        TABS[(i + 1) % TABS.len()]
+    }
+}
+
+/// Where each tab title lands in the tab bar: `Tabs` pads every title with a space
+/// on each side and puts a one-column divider between them.
+fn tab_areas(bar: Rect) -> Vec<(Rect, Tab)> {
+    let mut x = bar.x;
+    TABS.iter().map(|tab| {
+        let width = tab.title().len() as u16 + 2;
+        let area = Rect { x, y: bar.y, width, height: 1 }.intersection(bar);
+        x = x.saturating_add(width + 1);
+        (area, *tab)
+    }).collect()
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    (area.left()..area.right()).contains(&column) && (area.top()..area.bottom()).contains(&row)
+}
+
+fn block(title: String, palette: &Palette) -> Block<'static> {
+    Block::default().title(title).borders(Borders::ALL).border_style(palette.border)
+}
+
+/// Draws one frame: status bar and tab bar on every tab, then the selected tab's body.
+fn draw(f: &mut Frame, app: &mut TelemetryState, palette: &Palette, show_help: bool) {
+    // Narrow terminals fold the peer panel into a second status line.
+    let wide = f.size().width >= PEER_PANEL_MIN_WIDTH;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(if wide { 4 } else { 5 } + u16::from(!app.alerts.is_empty())), // Status Bar
+            Constraint::Length(1), // Tab Bar
+            Constraint::Min(0),    // Selected Tab
+        ])
+        .split(f.size());
+
+    render_status(f, app, palette, chunks[0], wide);
+    app.hitboxes = Hitboxes { tabs: tab_areas(chunks[1]), ..Hitboxes::default() };
+    let selected = TABS.iter().position(|t| *t == app.tab).unwrap_or(0);
+    let tabs = Tabs::new(TABS.iter().map(|t| t.title()))
+        .select(selected)
+        .highlight_style(palette.selected);
+    f.render_widget(tabs, chunks[1]);
+
+    match app.tab {
+        Tab::Overview => render_overview(f, app, palette, chunks[2], wide),
+        Tab::Peers => render_peers(f, app, palette, chunks[2]),
+        Tab::Counters => render_counters(f, app, palette, chunks[2]),
+        Tab::Logs => render_logs(f, app, palette, chunks[2]),
+    }
+
+    if show_help {
+        let lines = help_lines();
+        let width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 4;
+        let area = centered(f.size(), width, lines.len() as u16 + 2);
+        f.render_widget(Clear, area);
+        let help = Paragraph::new(lines).block(block(" KEYS (Esc or ? to close) ".to_string(), palette));
+        f.render_widget(help, area);
+    }
+}
+
+fn render_status(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect, wide: bool) {
+    let mut status = String::new();
+    if !app.alerts.is_empty() {
+        let details: Vec<&str> = app.alerts.iter().map(|a| a.detail.as_str()).collect();
+        let _ = writeln!(status, "ALERT: {}", details.join(" | "));
+    }
+    let _ = write!(status,
+        "RESILINET PROTOCOL (RSOCK-V2) | UPTIME: {:?} | INGRESS: {} ({}, {} pps; {}) | EGRESS: {} ({}, {} pps; {}) | LOSS: {} | JITTER: {} | RTT: {}",
+        app.start_time.elapsed(),
+        format_bytes(app.total_tx),
+        format_rate(app.recent_rate(&app.tx_history) as f64, app.rate_unit),
+        app.recent_rate(&app.tx_packet_history),
+        app.window_rates(&app.tx_history),
+        format_bytes(app.total_rx),
+        format_rate(app.recent_rate(&app.rx_history) as f64, app.rate_unit),
+        app.recent_rate(&app.rx_packet_history),
+        app.window_rates(&app.rx_history),
+        format_metric(app.quality.loss_rate.map(|l| l * 100.0), "%", 2),
+        format_metric(app.quality.jitter_ms, "ms", 1),
+        format_metric(app.quality.srtt_ms, "ms", 1)
+    );
+    if let Some(echo) = app.echo_loss {
+        status.push_str(&format!(
+            " | ECHO LOSS: UP {} DOWN {}",
+            format_metric(echo.forward.map(|l| l * 100.0), "%", 2),
+            format_metric(echo.reverse.map(|l| l * 100.0), "%", 2)
+        ));
+    }
+    if let Some(since) = app.totals_since {
+        let _ = write!(status, " | TOTALS RESET: {} ago", format_elapsed(since.elapsed()));
+    }
+    if app.telemetry_dropped > 0 {
+        let _ = write!(status, " | UI DROPPED: {} updates", app.telemetry_dropped);
+    }
+    if !wide {
+        status.push_str(&format!(
+            "\nPEER: {} [{}] | LAST AUTH: {} | ROAMS: {}",
+            app.peer.addr_label(), app.peer.state_label(), app.peer.last_auth_label(), app.peer.roam_count
+        ));
+    }
+    let _ = write!(status, "\nCIPHER: {} | KEY: {}", app.peer.cipher.map_or("-", |c| c.name()), app.peer.key_label());
+    if let Some(settings) = &app.settings {
+        let _ = write!(status,
+            " | OBFS: {} | COMPRESSION: {} | TRANSPORT: {}",
+            settings.obfs_label(), settings.compression_label(), settings.transport_label(app.peer.wire_format)
+        );
+    }
+    let header_style = if app.alerts.is_empty() { Style::default() } else { palette.alarm };
+    let badge = Span::styled(
+        format!(" {} {} ", app.connection.name(), format_elapsed(app.connection_since.elapsed())),
+        palette.connection(app.connection),
+    );
+    let header = Paragraph::new(status)
+        .style(header_style)
+        .block(block(" EDGE GATEWAY TELEMETRY ".to_string(), palette)
+            .title(Title::from(badge).alignment(Alignment::Right))
+            .style(header_style));
+    f.render_widget(header, area);
+}
+
+/// Graphs, peer panel, ARQ window, logs and counters on one screen.
+fn render_overview(f: &mut Frame, app: &mut TelemetryState, palette: &Palette, area: Rect, wide: bool) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(45), // Traffic Graphs
+            Constraint::Percentage(55), // System Logs
+        ])
+        .split(area);
+
+    // Traffic Graphs (+ peer panel when there is room)
+    let middle = if wide {
+        let split = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(60), Constraint::Length(34)])
+            .split(chunks[0]);
+        render_peer_panel(f, app, palette, split[1]);
+        app.hitboxes.panes.push((split[1], Tab::Peers));
+        split[0]
+    } else {
+        chunks[0]
+    };
+    let split = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(middle);
+    render_graphs(f, app, palette, split[0]);
+    render_window(f, app, palette, split[1]);
+
+    // Logs + counters when there is room
+    let log_area = if wide {
+        let split = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(60), Constraint::Length(44)])
+            .split(chunks[1]);
+        let counters_panel = Paragraph::new(app.counter_lines())
+            .block(block("COUNTERS".to_string(), palette));
+        f.render_widget(counters_panel, split[1]);
+        app.hitboxes.panes.push((split[1], Tab::Counters));
+        split[0]
+    } else {
+        chunks[1]
+    };
+    app.hitboxes.panes.push((log_area, Tab::Logs));
+    render_logs(f, app, palette, log_area);
+}
+
+fn render_peer_panel(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let peer_text = format!(
+        "Address:   {}\nState:     {}\nLast auth: {}\nRTT:       {}\nFormat:    {}\nCipher:    {}\nRoams:     {}\nUsage:     {}",
+        app.peer.addr_label(),
+        app.peer.state_label(),
+        app.peer.last_auth_label(),
+        format_metric(app.quality.srtt_ms, "ms", 1),
+        app.peer.wire_format.map_or("-".to_string(), |f| format!("{:?}", f)),
+        app.peer.cipher.map_or("-", |c| c.name()),
+        app.peer.roam_count,
+        app.quota_label(),
+    );
+    // The state is spelled out in the text; the border color only repeats it.
+    let peer_panel = Paragraph::new(peer_text)
+        .block(block("PEER".to_string(), palette).border_style(app.peer.state_style(palette)));
+    f.render_widget(peer_panel, area);
+}
+
+fn render_graphs(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let graph_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(33),
+            Constraint::Percentage(34),
+            Constraint::Percentage(33),
+        ])
+        .split(area);
+
+    // Long windows hold more ticks than there are columns: one point per column.
+    let columns = |i: usize| graph_chunks[i].width.saturating_sub(2).max(1) as usize;
+    let window = format_window(app.graph_window);
+    let start = app.window_start();
+
+    let (tx_history, rx_history) = app.traffic_histories();
+    let unit = app.graph_unit.label(app.rate_unit);
+    // The current rate in the graph's own unit, next to the history it tops; goodput with its wire rate.
+    let now = |bytes: &VecDeque<u64>, packets: &VecDeque<u64>, wire: &VecDeque<u64>| match app.graph_unit {
+        GraphUnit::Bytes => format!("{} ({})", format_rate(app.recent_rate(bytes) as f64, app.rate_unit), app.wire_rate(bytes, wire)),
+        GraphUnit::Packets => format!("{} pps", app.recent_rate(packets)),
+    };
+
+    let tx_data = TelemetryState::traffic_series(tx_history, start, columns(0));
+    let tx_spark = Sparkline::default()
+        .block(block(format!("Ingress (IoT) {} [{}] {}", window, unit, now(&app.tx_history, &app.tx_packet_history, &app.tx_wire_history)), palette))
+        .data(&tx_data)
+        .style(palette.tx);
+    f.render_widget(tx_spark, graph_chunks[0]);
+
+    let rx_data = TelemetryState::traffic_series(rx_history, start, columns(1));
+    let rx_spark = Sparkline::default()
+        .block(block(format!("Egress (Cloud) {} [{}] {}", window, unit, now(&app.rx_history, &app.rx_packet_history, &app.rx_wire_history)), palette))
+        .data(&rx_data)
+        .style(palette.rx);
+    f.render_widget(rx_spark, graph_chunks[1]);
+
+    let rtt_data = app.rtt_series(columns(2));
+    let rtt_title = match app.rtt_summary() {
+        Some((min, avg, max)) => format!("RTT {} {:.1}/{:.1}/{:.1} ms (min/avg/max)", window, min, avg, max),
+        None => format!("RTT {} (no samples)", window),
+    };
+    let rtt_spark = Sparkline::default()
+        .block(block(rtt_title, palette))
+        .data(&rtt_data)
+        .style(palette.rtt);
+    f.render_widget(rtt_spark, graph_chunks[2]);
+}
+
+/// ARQ window: occupancy gauge + in-flight bytes and retransmit rate.
+fn render_window(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let window_split = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(30), Constraint::Length(34)])
+        .split(area);
+    let stalled = app.window_stalled();
+    let occupancy = if app.window.capacity == 0 {
+        0.0
+    } else {
+        (app.window.in_flight as f64 / app.window.capacity as f64).min(1.0)
+    };
+    let gauge = Gauge::default()
+        .block(block(if stalled { "WINDOW STALLED".to_string() } else { "WINDOW".to_string() }, palette))
+        .gauge_style(if stalled { palette.stall } else { palette.gauge })
+        .ratio(occupancy)
+        .label(format!("{}/{} ({:.0}%){}", app.window.in_flight, app.window.capacity, occupancy * 100.0, app.pacing_label()));
+    f.render_widget(gauge, window_split[0]);
+    let arq_row = Paragraph::new(format!(
+        "{} in flight | RTX {:.1}/s",
+        format_bytes(app.window.bytes as u64),
+        app.window.retransmit_rate
+    ))
+    .block(block("ARQ".to_string(), palette));
+    f.render_widget(arq_row, window_split[1]);
+}
+
+/// The peer session and the link measurements behind it.
+fn render_peers(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let split = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(34), Constraint::Min(30)])
+        .split(area);
+    render_peer_panel(f, app, palette, split[0]);
+
+    let pct = |v: Option<f64>| format_metric(v.map(|l| l * 100.0), "%", 2);
+    let mut link = format!(
+        "Smoothed RTT:  {}\nJitter:        {}\nLoss (ARQ):    {}\n",
+        format_metric(app.quality.srtt_ms, "ms", 2),
+        format_metric(app.quality.jitter_ms, "ms", 2),
+        pct(app.quality.loss_rate),
+    );
+    match app.echo_loss {
+        Some(echo) => {
+            let _ = writeln!(link, "Loss (echo):   up {} / down {}", pct(echo.forward), pct(echo.reverse));
+        }
+        None => link.push_str("Loss (echo):   -\n"),
+    }
+    let _ = write!(link, "\nRTT, session:  {}\nRTT, 1 min:    {}", app.latency.session, app.latency.window);
+    if let Some(report) = &app.compression {
+        let _ = write!(
+            link,
+            "\n\nCompress out:  {}\nDecompress in: {}",
+            codec_direction_label(&report.session.tx, &report.window.tx),
+            codec_direction_label(&report.session.rx, &report.window.rx),
+        );
+    }
+    if let Some(dict) = &app.dictionary {
+        let _ = write!(link, "\n\nDictionary:    {}", dictionary_label(dict));
+    }
+    f.render_widget(Paragraph::new(link).block(block("LINK".to_string(), palette)), split[1]);
+}
+
+/// Frame and drop counters at full height next to the frame-type mix, with the ARQ window below.
+fn render_counters(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let split = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(area);
+    let panels = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(44), Constraint::Min(50)])
+        .split(split[0]);
+    let counters_panel = Paragraph::new(app.counter_lines())
+        .block(block("COUNTERS".to_string(), palette));
+    f.render_widget(counters_panel, panels[0]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        // Header plus one row per frame type (then per byte category), inside the borders.
+        .constraints([Constraint::Length(8), Constraint::Length(12), Constraint::Min(6)])
+        .split(panels[1]);
+    let mix_panel = Paragraph::new(app.frame_mix_lines())
+        .block(block("FRAME MIX".to_string(), palette));
+    f.render_widget(mix_panel, right[0]);
+    let overhead_panel = Paragraph::new(app.overhead_lines())
+        .block(block(app.efficiency_title(), palette));
+    f.render_widget(overhead_panel, right[1]);
+    render_packet_sizes(f, app, palette, right[2]);
+    render_window(f, app, palette, split[1]);
+}
+
+/// Histogram of inner packet sizes for one direction; bars are labelled with their share.
+fn render_packet_sizes(f: &mut Frame, app: &TelemetryState, palette: &Palette, area: Rect) {
+    let (counts, style) = match app.size_view {
+        SizeView::Ingress => (&app.packet_sizes.tx, palette.tx),
+        SizeView::Egress => (&app.packet_sizes.rx, palette.rx),
+    };
+    let total: u64 = counts.iter().sum();
+    let bars: Vec<Bar> = metrics::SIZE_BUCKET_LABELS.iter().zip(counts)
+        .map(|(label, &count)| Bar::default().value(count).label(Line::from(*label)).text_value(share(count, total)))
+        .collect();
+    // Share the inner width between the buckets, one column of gap each.
+    let inner = area.width.saturating_sub(2);
+    let width = (inner / metrics::SIZE_BUCKETS as u16).saturating_sub(1).max(1);
+    let title = format!("PACKET SIZES [{}] {} pkts", app.size_view.label(), total);
+    let chart = BarChart::default()
+        .block(block(title, palette))
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(width)
+        .bar_gap(1)
+        .bar_style(style);
+    f.render_widget(chart, area);
+}
+
+/// Log list (chronological, newest at the bottom). Sizes the scroll page to `area`.
+fn render_logs(f: &mut Frame, app: &mut TelemetryState, palette: &Palette, area: Rect) {
+    app.log_view.rows = area.height.saturating_sub(2).max(1) as usize;
+    app.hitboxes.logs = Some(area);
+    // Lines below the threshold are skipped, so walk back from the bottom
+    // until the page is full rather than showing a fixed id range.
+    app.search.refresh(&app.logs);
+    let bottom = app.log_bottom();
+    let threshold = logging::threshold();
+    let mut log_items: Vec<ListItem> = (app.logs.first_id()..=bottom).rev()
+        .filter_map(|id| app.logs.get(id).map(|entry| (id, entry)))
+        .filter(|(_, (level, _))| *level >= threshold)
+        .take(app.log_view.rows)
+        .map(|(id, (level, l))| {
+            let line = Line::from(vec![Span::raw(palette.level_marker(*level)), Span::raw(l.as_str())]);
+            let style = if app.search.current == Some(id) {
+                palette.selected
+            } else if app.search.is_match(id) {
+                palette.search_match
+            } else {
+                palette.level(*level)
+            };
+            ListItem::new(line).style(style)
+        })
+        .collect();
+    log_items.reverse();
+    let log_title = if app.log_view.follow {
+        Line::from(format!("GATEWAY EVENTS [>={}]", threshold.label()))
+    } else {
+        let new = app.logs.next_id() - app.log_view.paused_at;
+        Line::from(vec![
+            Span::raw(format!("GATEWAY EVENTS [>={}] ", threshold.label())),
+            Span::styled(format!("[PAUSED ({} new) - F to follow]", new), palette.alert),
+        ])
+    };
+    let mut log_block = Block::default().title(log_title).borders(Borders::ALL).border_style(palette.border);
+    if app.search.active() {
+        log_block = log_block.title_bottom(app.search.footer());
+    }
+    let log_list = List::new(log_items).block(log_block);
+    f.render_widget(log_list, area);
+}
+
+/// Telemetry consumer without a terminal UI: log lines go to stderr, everything else is dropped.
+/// Runs until a shutdown is requested, then prints what is still queued.
+pub fn spawn_headless(rx: TelemetryReceiver, shutdown: Shutdown) -> thread::JoinHandle<()> {
+    spawn_named("telemetry", move || run_headless(rx, shutdown))
+}
+
+fn run_headless(rx: TelemetryReceiver, shutdown: Shutdown) {
+    let print = |update: TelemetryUpdate| match update {
+        TelemetryUpdate::Log { level, msg } => eprintln!("{:<5} {}", level.label(), msg),
+        TelemetryUpdate::Shutdown { reason } => eprintln!("FATAL: {}", reason),
+        _ => {}
+    };
+    while shutdown.reason().is_none() {
+        match rx.recv_timeout(HEADLESS_POLL) {
+            Ok(update) => print(update),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+    while let Ok(update) = rx.try_recv() {
+        print(update);
+    }
+}
+
+fn spawn_named<T: Send + 'static>(name: &str, f: impl FnOnce() -> T + Send + 'static) -> thread::JoinHandle<T> {
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .expect("failed to spawn telemetry thread")
+}
+
+fn enter_terminal(inline: bool, mouse: bool) -> io::Result<Terminal<CrosstermBackend<io::Stdout>>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    if mouse {
+        MOUSE.store(true, Ordering::Relaxed);
+        execute!(stdout, EnableMouseCapture)?;
+    }
+    if inline {
+        // A full-height inline viewport scrolls the existing output up instead of hiding it.
+        INLINE.store(true, Ordering::Relaxed);
+        let (_, rows) = terminal::size()?;
+        let options = TerminalOptions { viewport: Viewport::Inline(rows) };
+        return Terminal::with_options(CrosstermBackend::new(stdout), options);
+    }
+    execute!(stdout, EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(stdout))
+}
+
+/// Returns the terminal to cooked mode on the main screen. Idempotent, never fails;
+/// a no-op unless the dashboard actually took the terminal over.
+pub fn restore_terminal() {
+    if !is_raw_mode_enabled().unwrap_or(false) {
+        return;
+    }
+    let _ = disable_raw_mode();
+    if MOUSE.load(Ordering::Relaxed) {
+        let _ = execute!(io::stdout(), DisableMouseCapture);
+    }
+    if INLINE.load(Ordering::Relaxed) {
+        // Leave the last frame in place and put the shell prompt below it.
+        let bottom = terminal::size().map_or(0, |(_, rows)| rows.saturating_sub(1));
+        let _ = execute!(io::stdout(), MoveTo(0, bottom), Show, Print("\r\n"));
+    } else {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    }
+}
+
+/// Restores the terminal when dropped, so unwinding out of the dashboard (or out of
+/// `main`, while the dashboard still owns the screen) never leaves it in raw mode.
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Restores the terminal before the default hook prints a panic, so the message is
+/// readable. Only panics that take the UI down (dashboard or main thread) do this:
+/// a panicking network task is restarted by the supervisor and the dashboard lives on.
+fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if matches!(thread::current().name(), Some(DASHBOARD_THREAD) | Some("main")) {
+            restore_terminal();
+        }
+        previous(info);
+    }));
+}
+
+
+/// What a dashboard key does.
+enum Action {
+    Quit,
+    Help,
+    SelectTab(Tab),
+    NextTab,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    PauseLogs,
+    FollowLogs,
+    Snapshot,
+    CycleLogLevel,
+    ZoomIn,
+    ZoomOut,
+    ToggleGraphUnit,
+    ToggleRateUnit,
+    ToggleSizeView,
+    Search,
+    NextMatch,
+    PrevMatch,
+    /// Sent to the core over the control channel; the label names it in replies.
+    Core(&'static str, Command),
+}
+
+struct Binding {
+    keys: &'static [KeyCode],
+    /// How the keys are written in the help overlay.
+    label: &'static str,
+    description: &'static str,
+    action: Action,
+}
+
+/// Every dashboard key. The input handler dispatches from this table and the '?'
+/// overlay is rendered from it, so a binding added here shows up in both.
+const KEYMAP: &[Binding] = &[
+    Binding { keys: &[KeyCode::Char('q'), KeyCode::Esc], label: "q, Esc", description: "Quit", action: Action::Quit },
+    Binding { keys: &[KeyCode::Char('?')], label: "?", description: "Show/hide this help", action: Action::Help },
+    Binding { keys: &[KeyCode::Char('1')], label: "1", description: "Overview tab", action: Action::SelectTab(Tab::Overview) },
+    Binding { keys: &[KeyCode::Char('2')], label: "2", description: "Peers tab", action: Action::SelectTab(Tab::Peers) },
+    Binding { keys: &[KeyCode::Char('3')], label: "3", description: "Counters tab", action: Action::SelectTab(Tab::Counters) },
+    Binding { keys: &[KeyCode::Char('4')], label: "4", description: "Logs tab", action: Action::SelectTab(Tab::Logs) },
+    Binding { keys: &[KeyCode::Tab], label: "Tab", description: "Next tab", action: Action::NextTab },
+    Binding { keys: &[KeyCode::Up], label: "Up", description: "Scroll logs up one line", action: Action::ScrollUp },
+    Binding { keys: &[KeyCode::Down], label: "Down", description: "Scroll logs down one line", action: Action::ScrollDown },
+    Binding { keys: &[KeyCode::PageUp], label: "PgUp", description: "Scroll logs up one page", action: Action::PageUp },
+    Binding { keys: &[KeyCode::PageDown], label: "PgDn", description: "Scroll logs down one page", action: Action::PageDown },
+    Binding { keys: &[KeyCode::Char('f')], label: "f", description: "Pause the log view", action: Action::PauseLogs },
+    Binding { keys: &[KeyCode::Char('F')], label: "F", description: "Follow new log lines", action: Action::FollowLogs },
+    Binding { keys: &[KeyCode::Char('l')], label: "l", description: "Cycle log level", action: Action::CycleLogLevel },
+    Binding { keys: &[KeyCode::Char('s')], label: "s", description: "Save logs and stats snapshot", action: Action::Snapshot },
+    Binding { keys: &[KeyCode::Char('+'), KeyCode::Char('=')], label: "+", description: "Longer graph window", action: Action::ZoomIn },
+    Binding { keys: &[KeyCode::Char('-')], label: "-", description: "Shorter graph window", action: Action::ZoomOut },
+    Binding { keys: &[KeyCode::Char('u')], label: "u", description: "Graph bytes / packets per tick", action: Action::ToggleGraphUnit },
+    Binding { keys: &[KeyCode::Char('b')], label: "b", description: "Show rates in bits / bytes per second", action: Action::ToggleRateUnit },
+    Binding { keys: &[KeyCode::Char('/')], label: "/", description: "Search logs (Enter keeps, Esc clears)", action: Action::Search },
+    Binding { keys: &[KeyCode::Char('n')], label: "n", description: "Next (older) search match", action: Action::NextMatch },
+    Binding { keys: &[KeyCode::Char('N')], label: "N", description: "Previous (newer) search match", action: Action::PrevMatch },
+    Binding { keys: &[KeyCode::Char('d')], label: "d", description: "Packet size histogram: ingress / egress", action: Action::ToggleSizeView },
+    Binding { keys: &[KeyCode::Char('r')], label: "r", description: "Rekey", action: Action::Core("rekey", Command::Rekey(None)) },
+    Binding { keys: &[KeyCode::Char('c')], label: "c", description: "Toggle compression", action: Action::Core("toggle-compression", Command::ToggleCompression) },
+    Binding { keys: &[KeyCode::Char('C')], label: "C", description: "Toggle the peer's compression", action: Action::Core("toggle-peer-compression", Command::TogglePeerCompression) },
+    Binding { keys: &[KeyCode::Char('j')], label: "j", description: "Toggle jitter", action: Action::Core("toggle-jitter", Command::ToggleJitter) },
+    Binding { keys: &[KeyCode::Char('P')], label: "P", description: "Toggle pacing", action: Action::Core("toggle-pacing", Command::TogglePacing) },
+    Binding { keys: &[KeyCode::Char('p')], label: "p", description: "Reconnect to peer", action: Action::Core("reconnect", Command::Reconnect) },
+    Binding { keys: &[KeyCode::Char('k')], label: "k", description: "Send heartbeat", action: Action::Core("heartbeat", Command::Heartbeat) },
+    Binding { keys: &[KeyCode::Char('z')], label: "z", description: "Zero the ingress/egress totals", action: Action::Core("reset-stats", Command::ResetStats) },
+];
+
+/// The binding for `code`, if any. Unbound keys are ignored.
+fn binding_for(code: KeyCode) -> Option<&'static Binding> {
+    KEYMAP.iter().find(|b| b.keys.contains(&code))
+}
+
+/// Help overlay text: one line per binding.
+fn help_lines() -> Vec<Line<'static>> {
+    let width = KEYMAP.iter().map(|b| b.label.len()).max().unwrap_or(0);
+    KEYMAP.iter()
+        .map(|b| Line::from(format!("{:<width$}  {}", b.label, b.description, width = width)))
+        .collect()
+}
+
+/// A `width` x `height` rectangle centered in `area`, clipped to fit.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Writes the log history and a stats snapshot to a timestamped file.
+///
+/// The data is copied on the UI thread (cheap), but the file I/O happens on a
+/// separate thread so a slow or stuck disk can never freeze the dashboard.
+/// The outcome comes back through `notice` as a log line.
+fn spawn_snapshot_dump(app: &TelemetryState, dir: &std::path::Path, notice: mpsc::Sender<(Level, String)>) {
+    let stamp = chrono::Local::now();
+    let path = dir.join(format!("resilinet-dump-{}.txt", stamp.format("%Y%m%d-%H%M%S")));
+    let stats = app.stats_report();
+    let logs: Vec<String> = (app.logs.first_id()..app.logs.next_id())
+        .filter_map(|id| app.logs.get(id))
+        .map(|(level, line)| format!("{:<5} {}", level.label(), line))
+        .collect();
+
+    thread::spawn(move || {
+        let result = std::fs::File::create(&path).and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            writeln!(out, "# ResiliNet snapshot {}", stamp.to_rfc3339())?;
+            writeln!(out, "\n## Stats\n{}", stats)?;
+            writeln!(out, "## Logs ({} lines)", logs.len())?;
+            for line in &logs {
+                writeln!(out, "{}", line)?;
+            }
+            out.flush()
+        });
+        let _ = notice.send(match result {
+            Ok(()) => (Level::Info, format!("DUMP: Snapshot written to {}", path.display())),
+            Err(e) => (Level::Error, format!("DUMP::Err: {}: {}", path.display(), e)),
+        });
+    });
+}
+
+/// Bounded log store addressed by absolute line ids.
+///
+/// Ids only ever increase, so a scroll position stored as an id stays pinned to the
+/// same line while new messages arrive. Lookup by id is O(1). Once `capacity` lines
+/// are held, each new line evicts the oldest and `first_id` advances. Every line keeps
+/// its level so the view can be re-filtered when the threshold changes.
+struct LogBuffer {
+    lines: VecDeque<(Level, String)>,
+    /// Id of `lines[0]`.
+    first_id: u64,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { lines: VecDeque::with_capacity(capacity.min(1024)), first_id: 0, capacity }
+    }
+
+    /// Appends a line written by `fill`. When full, the evicted line's allocation is
+    /// reused, so a busy tunnel at steady state formats logs without allocating.
+    fn push_with(&mut self, level: Level, fill: impl FnOnce(&mut String)) {
+        let mut line = if self.lines.len() >= self.capacity {
+            self.first_id += 1;
+            let (_, mut recycled) = self.lines.pop_front().unwrap_or((level, String::new()));
+            recycled.clear();
+            recycled
+        } else {
+            String::new()
+        };
+        fill(&mut line);
+        self.lines.push_back((level, line));
+    }
+
+    fn first_id(&self) -> u64 {
+        self.first_id
+    }
+
+    /// Id the next appended line will receive.
+    fn next_id(&self) -> u64 {
+        self.first_id + self.lines.len() as u64
+    }
+
+    fn get(&self, id: u64) -> Option<&(Level, String)> {
+        id.checked_sub(self.first_id).and_then(|i| self.lines.get(i as usize))
+    }
+}
+
+/// Incremental search over the log buffer ('/'), case-insensitive.
+///
+/// Matching line ids are cached rather than recomputed per keystroke or frame: new
+/// lines are checked once as they arrive, evicted ids fall off the front, and typing
+/// another character only re-checks lines that already matched. Deleting a character
+/// is the one edit that rescans the whole buffer.
+#[derive(Default)]
+struct LogSearch {
+    /// Lower-cased query; empty when no search is active.
+    query: String,
+    /// The query is being typed: keys go to it instead of the keymap.
+    editing: bool,
+    /// Ids of matching lines, ascending.
+    matches: VecDeque<u64>,
+    /// First id not yet checked against the query.
+    scanned_to: u64,
+    /// The match last jumped to with n/N.
+    current: Option<u64>,
+}
+
+impl LogSearch {
+    fn active(&self) -> bool {
+        self.editing || !self.query.is_empty()
+    }
+
+    fn start(&mut self) {
+        self.editing = true;
+    }
+
+    /// Stops editing; an empty query ends the search.
+    fn confirm(&mut self) {
+        self.editing = false;
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn push(&mut self, c: char, logs: &LogBuffer) {
+        let first = self.query.is_empty();
+        self.query.extend(c.to_lowercase());
+        if first {
+            self.scanned_to = logs.first_id();
+        } else {
+            // A longer query can only narrow the matches.
+            let query = &self.query;
+            self.matches.retain(|id| logs.get(*id).is_some_and(|(_, line)| contains_folded(line, query)));
+        }
+        self.current = None;
+    }
+
+    fn pop(&mut self, logs: &LogBuffer) {
+        self.query.pop();
+        self.matches.clear();
+        self.scanned_to = logs.first_id();
+        self.current = None;
+    }
+
+    /// Catches up with lines appended and evicted since the last call.
+    fn refresh(&mut self, logs: &LogBuffer) {
+        if self.query.is_empty() {
+            return;
+        }
+        while self.matches.front().is_some_and(|id| *id < logs.first_id()) {
+            self.matches.pop_front();
+        }
+        for id in self.scanned_to.max(logs.first_id())..logs.next_id() {
+            if logs.get(id).is_some_and(|(_, line)| contains_folded(line, &self.query)) {
+                self.matches.push_back(id);
+            }
+        }
+        self.scanned_to = logs.next_id();
+    }
+
+    fn is_match(&self, id: u64) -> bool {
+        !self.query.is_empty() && self.matches.binary_search(&id).is_ok()
+    }
+
+    /// The nearest match older (or newer) than `from` that is `visible`, wrapping
+    /// around at the ends. Becomes the current match.
+    fn step(&mut self, from: u64, older: bool, visible: impl Fn(u64) -> bool) -> Option<u64> {
+        let split = self.matches.partition_point(|id| *id < from);
+        let (before, after) = (self.matches.range(..split), self.matches.range(split..));
+        let found = if older {
+            before.rev().chain(after.rev()).copied().find(|id| *id != from && visible(*id))
+        } else {
+            after.chain(before).copied().find(|id| *id != from && visible(*id))
+        };
+        if found.is_some() {
+            self.current = found;
+        }
+        found
+    }
+
+    /// Footer for the log pane: the query and where n/N stand.
+    fn footer(&self) -> String {
+        let position = self.current
+            .and_then(|id| self.matches.binary_search(&id).ok())
+            .map_or(String::new(), |i| format!("{}/", i + 1));
+        if self.editing {
+            format!(" /{}_ ({} matches) Enter keep, Esc clear ", self.query, self.matches.len())
+        } else {
+            format!(" /{} [{}{}] n/N older/newer, Esc clear ", self.query, position, self.matches.len())
+        }
+    }
+}
+
+/// Case-insensitive substring test against an already lower-cased `needle`, without
+/// allocating a lower-cased copy of `haystack`.
+fn contains_folded(haystack: &str, needle: &str) -> bool {
+    haystack.char_indices().any(|(i, _)| {
+        let mut rest = haystack[i..].chars().flat_map(char::to_lowercase);
+        needle.chars().all(|c| rest.next() == Some(c))
     })
 }
 
+/// Scroll state of the log pane.
+struct LogView {
+    /// Auto-follow the newest line.
+    follow: bool,
+    /// Bottom visible line id while paused.
+    anchor: u64,
+    /// `next_id` at the moment follow was paused (for the "n new" counter).
+    paused_at: u64,
+    /// Visible rows at the last render (page size).
+    rows: usize,
+}
+
+/// Number of graph ticks in `window`.
+fn ticks_in(window: Duration, tick: Duration) -> usize {
+    (window.as_millis() / tick.as_millis().max(1)).max(1) as usize
+}
+
+/// Reduces `samples` to at most `width` points, folding each run of adjacent samples with `fold`.
+fn downsample<T>(samples: &[T], width: usize, fold: impl Fn(&[T]) -> u64) -> Vec<u64> {
+    let bucket = samples.len().div_ceil(width.max(1)).max(1);
+    samples.chunks(bucket).map(fold).collect()
+}
+
+/// Parses a graph window such as `90s`, `5m` or `1h` (bare numbers are seconds).
+pub fn parse_window(s: &str) -> Result<Duration, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = digits.parse().map_err(|_| format!("invalid window '{}' (expected e.g. 30s, 5m, 1h)", s))?;
+    let secs = match unit {
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 3600,
+        _ => return Err(format!("unknown unit '{}' (use s, m or h)", unit)),
+    };
+    if secs == 0 {
+        return Err("window must be at least 1s".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Compact window label for graph titles: `30s`, `5m`, `1h`.
+fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
 
-fn format_bytes(b: u64) -> String {
-    if b < 1024 {
-        format!("{} B", b)
-    } else if b < 1024 * 1024 {
-        format!("{:.1} KB", b as f64 / 1024.0)
+/// Time in a state, to the second up to an hour: `42s`, `12m05s`, `3h07m`.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// e.g. `10s: 1.01x, 40.2 µs/pkt | session: 1.85x, 120/5120 raw`. Outgoing, raw counts
+/// packets the heuristics sent uncompressed; incoming, packets that arrived that way.
+fn codec_direction_label(session: &DirectionTotals, window: &DirectionTotals) -> String {
+    let recent = if window.packets == 0 {
+        "idle".to_string()
     } else {
-        format!("{:.2} MB", b as f64 / 1024.0 / 1024.0)
+        format!("{}, {}", format_metric(window.ratio(), "x", 2), format_metric(window.micros_per_packet(), " µs/pkt", 1))
+    };
+    format!("10s: {} | session: {}, {}/{} raw", recent, format_metric(session.ratio(), "x", 2), session.uncompressed, session.packets)
+}
+
+/// e.g. `1a2b3c4d 2.41x on 5120 small packets, sample 2.38x vs 1.12x without`.
+fn dictionary_label(dict: &DictionaryStats) -> String {
+    if !dict.agreed && dict.packets == 0 {
+        return format!("{:08x} not agreed with the peer", dict.id);
+    }
+    let ratio = format_metric(dict.ratio(), "x", 2);
+    let mut label = format!("{:08x}{} {} on {} small packets", dict.id, if dict.agreed { "" } else { " (not agreed)" }, ratio, dict.packets);
+    if let Some((without, with)) = dict.sampled_ratios() {
+        let _ = write!(label, ", sample {:.2}x vs {:.2}x without", with, without);
+    }
+    label
+}
+
+/// `n` as a percentage of `total`, or a dash when there is nothing to divide.
+fn share(n: u64, total: u64) -> String {
+    if total == 0 { "-".to_string() } else { format!("{:.1}%", n as f64 / total as f64 * 100.0) }
+}
+
+/// Renders an optional measurement, or a dash while there is no data yet.
+fn format_metric(value: Option<f64>, unit: &str, precision: usize) -> String {
+    match value {
+        Some(v) => format!("{:.*}{}", precision, v, unit),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(logs: &mut LogBuffer, text: &str) {
+        logs.push_with(Level::Info, |line| line.push_str(text));
+    }
+
+    fn line(logs: &LogBuffer, id: u64) -> Option<&str> {
+        logs.get(id).map(|(_, line)| line.as_str())
+    }
+
+    #[test]
+    fn log_buffer_keeps_ids_until_full() {
+        let mut logs = LogBuffer::with_capacity(3);
+        for text in ["a", "b", "c"] {
+            push(&mut logs, text);
+        }
+        assert_eq!((logs.first_id(), logs.next_id()), (0, 3));
+        assert_eq!(line(&logs, 0), Some("a"));
+        assert_eq!(line(&logs, 2), Some("c"));
+        assert_eq!(line(&logs, 3), None);
+    }
+
+    #[test]
+    fn log_buffer_wraps_by_evicting_the_oldest() {
+        let mut logs = LogBuffer::with_capacity(3);
+        for text in ["a", "b", "c", "d", "e"] {
+            push(&mut logs, text);
+        }
+        assert_eq!((logs.first_id(), logs.next_id()), (2, 5));
+        assert_eq!(line(&logs, 1), None);
+        assert_eq!(line(&logs, 2), Some("c"));
+        assert_eq!(line(&logs, 4), Some("e"));
+        assert_eq!(logs.lines.len(), 3);
+    }
+
+    #[test]
+    fn recycled_lines_start_empty() {
+        let mut logs = LogBuffer::with_capacity(1);
+        push(&mut logs, "a long first line");
+        push(&mut logs, "x");
+        assert_eq!(line(&logs, 1), Some("x"));
+    }
+
+    #[test]
+    fn zero_capacity_still_holds_the_latest_line() {
+        let mut logs = LogBuffer::with_capacity(0);
+        push(&mut logs, "a");
+        push(&mut logs, "b");
+        assert_eq!((logs.first_id(), logs.next_id()), (1, 2));
+        assert_eq!(line(&logs, 1), Some("b"));
+    }
+
+    #[test]
+    fn paused_view_is_clamped_to_the_oldest_full_page_after_eviction() {
+        let mut app = TelemetryState::new(10, Duration::from_secs(60), Duration::from_secs(1));
+        app.log_view.rows = 4;
+        for i in 0..10 {
+            app.push_log(Level::Info, &i.to_string());
+        }
+        app.log_view.anchor = 5;
+        app.log_view.follow = false;
+        assert_eq!(app.log_bottom(), 5);
+
+        for i in 10..18 {
+            app.push_log(Level::Info, &i.to_string());
+        }
+        // Lines 0..8 are gone: the oldest page that fills 4 rows ends at line 11.
+        assert_eq!(app.logs.first_id(), 8);
+        assert_eq!(app.log_bottom(), 11);
+
+        app.resume_logs();
+        assert_eq!(app.log_bottom(), 17);
+    }
+
+    #[test]
+    fn oldest_page_is_never_past_the_newest_line() {
+        let mut app = TelemetryState::new(10, Duration::from_secs(60), Duration::from_secs(1));
+        app.log_view.rows = 20;
+        for i in 0..3 {
+            app.push_log(Level::Info, &i.to_string());
+        }
+        assert_eq!(app.oldest_log_bottom(), 2);
     }
 }
@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use anyhow::{Context as _, Result};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tun::Device as _;
+
+/// One queue of a multi-queue TUN device (`IFF_MULTI_QUEUE`), driven by tokio.
+///
+/// The kernel hashes flows across queues, so each queue gets its own TX/RX task pair
+/// and packet processing spreads over cores. `tun`'s async wrapper only exposes the
+/// first queue, hence this thin reactor-backed reader/writer over a duplicated fd.
+pub struct TunQueue {
+    fd: AsyncFd<File>,
+}
+
+/// Creates the interface with `queues` queues.
+///
+/// The returned device owns the original descriptors and must outlive the queues.
+pub fn open(config: &mut tun::Configuration, queues: usize) -> Result<(String, Vec<TunQueue>, tun::platform::Device)> {
+    config.queues(queues);
+    let mut device = tun::create(config).context("Failed to open multi-queue TUN device. Do you have root privileges?")?;
+    let name = device.name().unwrap_or_default();
+
+    let mut opened = Vec::with_capacity(queues);
+    for index in 0..queues {
+        let queue = device.queue(index).context("TUN device returned fewer queues than requested")?;
+        // O_NONBLOCK lives on the open file description, so it carries over to the dup.
+        queue.set_nonblock()?;
+        // SAFETY: the descriptor is owned by `device`, which is alive for this borrow.
+        let owned = unsafe { BorrowedFd::borrow_raw(queue.as_raw_fd()) }.try_clone_to_owned()?;
+        opened.push(TunQueue { fd: AsyncFd::new(File::from(owned))? });
+    }
+    Ok((name, opened, device))
+}
+
+impl AsyncRead for TunQueue {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|fd| fd.get_ref().read(unfilled)) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TunQueue {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            match guard.try_io(|fd| fd.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,97 @@
+use std::ffi::CString;
+use std::io;
+use tokio::sync::watch;
+
+/// Errors that mean the interface itself is gone (deleted by the operator, or removed
+/// on a device event), as opposed to a packet that failed. Linux reports a detached
+/// TUN as EBADFD on the still-open descriptor.
+#[cfg(target_os = "linux")]
+const VANISHED: [i32; 3] = [libc::ENODEV, libc::EBADF, libc::EBADFD];
+#[cfg(not(target_os = "linux"))]
+const VANISHED: [i32; 2] = [libc::ENODEV, libc::EBADF];
+
+pub fn vanished(e: &io::Error) -> bool {
+    e.raw_os_error().is_some_and(|code| VANISHED.contains(&code))
+}
+
+/// Whether an interface of this name exists. A deleted TUN only raises an error
+/// condition on its descriptor, which does not wake a pending read, so an idle
+/// interface has to be looked for.
+pub fn exists(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else { return false };
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call.
+    unsafe { libc::if_nametoindex(name.as_ptr()) != 0 }
+}
+
+/// Where an interface stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunHealth {
+    Up,
+    /// Gone: being recreated with its original configuration.
+    Recreating,
+    /// Could not be recreated; the tunnel is shutting down.
+    Failed,
+}
+
+/// One interface's health, with a generation that counts recreations.
+///
+/// **Recovery Model**: the TX and TUN writer tasks keep their queue handles across a
+/// recreation; the recovery task swaps new descriptors in behind them. The first task
+/// to hit a vanished error reports it, every task on that interface lets go of its
+/// handle, and they resume once the generation moves on.
+pub struct TunWatch {
+    state: watch::Sender<(u64, TunHealth)>,
+}
+
+impl TunWatch {
+    pub fn new() -> Self {
+        Self { state: watch::channel((0, TunHealth::Up)).0 }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<(u64, TunHealth)> {
+        self.state.subscribe()
+    }
+
+    pub fn health(&self) -> TunHealth {
+        self.state.borrow().1
+    }
+
+    /// Reports the interface gone, as seen on `generation`. True for the report that
+    /// starts a recovery; later ones (and stale ones) change nothing.
+    pub fn report_lost(&self, generation: u64) -> bool {
+        self.state.send_if_modified(|(current, health)| {
+            let fresh = *current == generation && *health == TunHealth::Up;
+            if fresh {
+                *health = TunHealth::Recreating;
+            }
+            fresh
+        })
+    }
+
+    /// The interface is back under new descriptors.
+    pub fn recreated(&self) {
+        self.state.send_modify(|(generation, health)| {
+            *generation += 1;
+            *health = TunHealth::Up;
+        });
+    }
+
+    pub fn failed(&self) {
+        self.state.send_modify(|(_, health)| *health = TunHealth::Failed);
+    }
+}
+
+/// The interface is no longer up at `generation`: the handle in use is stale.
+pub fn stale(health: &watch::Receiver<(u64, TunHealth)>, generation: u64) -> bool {
+    *health.borrow() != (generation, TunHealth::Up)
+}
+
+/// Waits for the interface to come back up after `generation`. `None` once recovery
+/// has given up (or the watch is gone).
+pub async fn wait_up(health: &mut watch::Receiver<(u64, TunHealth)>, generation: u64) -> Option<u64> {
+    let state = health
+        .wait_for(|&(g, h)| h == TunHealth::Failed || (g > generation && h == TunHealth::Up))
+        .await
+        .ok()?;
+    (state.1 == TunHealth::Up).then_some(state.0)
+}
@@ -0,0 +1,145 @@
+/// How throughput rates are shown, picked with `--units` (toggle at runtime with 'b').
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum RateUnit {
+    /// Decimal bits per second (kbit/s, Mbit/s, ...), as links are usually rated.
+    Bits,
+    /// Binary bytes per second (KB/s, MB/s, ...), matching the byte totals.
+    Bytes,
+}
+
+impl RateUnit {
+    pub fn toggle(self) -> Self {
+        match self {
+            RateUnit::Bits => RateUnit::Bytes,
+            RateUnit::Bytes => RateUnit::Bits,
+        }
+    }
+
+    /// Name shown in graph titles.
+    pub fn label(self) -> &'static str {
+        match self {
+            RateUnit::Bits => "bits",
+            RateUnit::Bytes => "bytes",
+        }
+    }
+}
+
+const BYTE_UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+const BIT_RATE_UNITS: [&str; 5] = ["bit/s", "kbit/s", "Mbit/s", "Gbit/s", "Tbit/s"];
+
+/// A byte count (session totals, window occupancy) in binary units up to TB.
+pub fn format_bytes(b: u64) -> String {
+    scaled(b as f64, 1024.0, &BYTE_UNITS)
+}
+
+/// A throughput of `bytes_per_sec` in `unit`.
+pub fn format_rate(bytes_per_sec: f64, unit: RateUnit) -> String {
+    match unit {
+        RateUnit::Bits => scaled(bytes_per_sec * 8.0, 1000.0, &BIT_RATE_UNITS),
+        RateUnit::Bytes => format!("{}/s", scaled(bytes_per_sec, 1024.0, &BYTE_UNITS)),
+    }
+}
+
+/// Divides by `base` until the value fits the unit. Whole numbers in the base unit,
+/// one decimal for the first step and two above. The check uses the rounded value,
+/// so 1023.96 KB becomes "1.00 MB" rather than "1024.0 KB".
+fn scaled(value: f64, base: f64, units: &[&str]) -> String {
+    let precision = |i: usize| i.min(2);
+    let rounded = |v: f64, i: usize| {
+        let factor = 10f64.powi(precision(i) as i32);
+        (v * factor).round() / factor
+    };
+    let mut value = value.max(0.0);
+    let mut i = 0;
+    while i + 1 < units.len() && rounded(value, i) >= base {
+        value /= base;
+        i += 1;
+    }
+    format!("{:.*} {}", precision(i), value, units[i])
+}
+
+/// Parses a byte size for the command line: a plain number, or one with a binary
+/// suffix (`K`, `M`, `G`, `T`, optionally followed by `B`), e.g. `512M` or `10GB`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, shift) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 10),
+        Some((i, 'M')) => (&digits[..i], 20),
+        Some((i, 'G')) => (&digits[..i], 30),
+        Some((i, 'T')) => (&digits[..i], 40),
+        _ => (digits, 0),
+    };
+    let n: u64 = number.trim().parse().map_err(|_| format!("invalid size '{}' (expected e.g. 1500, 64K, 512M, 10G)", s))?;
+    n.checked_mul(1 << shift).ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
+    const TB: u64 = 1024 * GB;
+
+    #[test]
+    fn bytes_in_the_base_unit_are_whole() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+    }
+
+    #[test]
+    fn bytes_step_up_at_each_boundary() {
+        assert_eq!(format_bytes(KB), "1.0 KB");
+        assert_eq!(format_bytes(999 * KB), "999.0 KB");
+        assert_eq!(format_bytes(MB), "1.00 MB");
+        assert_eq!(format_bytes(3 * GB / 2), "1.50 GB");
+        assert_eq!(format_bytes(5 * TB), "5.00 TB");
+    }
+
+    #[test]
+    fn rounding_up_moves_to_the_next_unit() {
+        // 1023.96 KB would print as "1024.0 KB".
+        assert_eq!(format_bytes(MB - 1), "1.00 MB");
+        assert_eq!(format_bytes(MB - 60), "1023.9 KB");
+    }
+
+    #[test]
+    fn terabytes_are_the_top_unit() {
+        assert_eq!(format_bytes(1024 * TB), "1024.00 TB");
+    }
+
+    #[test]
+    fn bit_rates_are_decimal() {
+        assert_eq!(format_rate(0.0, RateUnit::Bits), "0 bit/s");
+        assert_eq!(format_rate(124.0, RateUnit::Bits), "992 bit/s");
+        assert_eq!(format_rate(125.0, RateUnit::Bits), "1.0 kbit/s");
+        assert_eq!(format_rate(125_000.0, RateUnit::Bits), "1.00 Mbit/s");
+        assert_eq!(format_rate(1.25e9, RateUnit::Bits), "10.00 Gbit/s");
+    }
+
+    #[test]
+    fn bit_rates_round_before_choosing_the_unit() {
+        // 999.6 bit/s rounds to 1000 in whole bits.
+        assert_eq!(format_rate(124.95, RateUnit::Bits), "1.0 kbit/s");
+        assert_eq!(format_rate(124.9, RateUnit::Bits), "999 bit/s");
+    }
+
+    #[test]
+    fn byte_rates_match_the_totals() {
+        assert_eq!(format_rate(1536.0, RateUnit::Bytes), "1.5 KB/s");
+        assert_eq!(format_rate(MB as f64, RateUnit::Bytes), "1.00 MB/s");
+    }
+
+    #[test]
+    fn negative_rates_read_as_zero() {
+        assert_eq!(format_rate(-5.0, RateUnit::Bytes), "0 B/s");
+    }
+
+    #[test]
+    fn toggle_flips_between_the_two() {
+        assert_eq!(RateUnit::Bits.toggle(), RateUnit::Bytes);
+        assert_eq!(RateUnit::Bytes.toggle().toggle(), RateUnit::Bytes);
+    }
+}
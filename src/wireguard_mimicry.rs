@@ -0,0 +1,255 @@
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::obfuscation::{Obfuscator, Unwrapped};
+
+/// Message types; each is followed by three reserved zero bytes.
+const INITIATION: u8 = 1;
+const RESPONSE: u8 = 2;
+const TRANSPORT: u8 = 4;
+
+/// Every handshake message has a fixed length.
+const INITIATION_LEN: usize = 148;
+const RESPONSE_LEN: usize = 92;
+
+/// Transport data header: type, reserved, receiver index, counter.
+const DATA_HEADER: usize = 16;
+
+/// WireGuard pads plaintext to 16 bytes before sealing it, and the tag is 16 bytes,
+/// so transport data grows in these steps from a 32-byte keepalive up.
+const BLOCK: usize = 16;
+
+/// `--mimic wireguard`: every datagram dressed as a WireGuard message, for networks
+/// that allow-list WireGuard and throttle other UDP.
+///
+/// A handshake initiation (148 bytes) goes ahead of each Hello, with a random sender
+/// index; the other end answers with a handshake response (92 bytes) naming it as the
+/// receiver. Their key material and MACs are random. Every frame then travels as
+/// transport data addressed to the peer's index, with a counter that starts from 0 at
+/// each handshake. The frame fills what WireGuard would take for the sealed packet,
+/// padded to the 16-byte steps WireGuard pads to. The last padding byte says how much
+/// padding there is, masked with the counter so it doesn't stand out.
+pub struct WireGuardMimic {
+    /// Our sender index: the receiver index of the peer's transport data. Never 0 (see
+    /// `looks_dressed`).
+    local_index: u32,
+    /// The sender index of the peer's last handshake message; random until one arrives.
+    remote_index: AtomicU32,
+    /// Transport data counter.
+    counter: AtomicU64,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl WireGuardMimic {
+    pub fn new(rng: Arc<Mutex<StdRng>>) -> Self {
+        let (local_index, remote_index) = {
+            let mut rng = rng.lock();
+            (rng.gen_range(1..=u32::MAX), rng.gen_range(1..=u32::MAX))
+        };
+        Self { local_index, remote_index: AtomicU32::new(remote_index), counter: AtomicU64::new(0), rng }
+    }
+
+    /// A handshake message `len` bytes long from `local_index`: type, reserved zeros,
+    /// sender index, `receiver` (responses only), then random key material and MACs.
+    fn handshake(&self, kind: u8, receiver: Option<u32>, len: usize) -> Vec<u8> {
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&[kind, 0, 0, 0]);
+        message.extend_from_slice(&self.local_index.to_le_bytes());
+        if let Some(receiver) = receiver {
+            message.extend_from_slice(&receiver.to_le_bytes());
+        }
+        let start = message.len();
+        message.resize(len, 0);
+        self.rng.lock().fill(&mut message[start..]);
+        // A new handshake, a new session: its counter starts over.
+        self.counter.store(0, Ordering::Relaxed);
+        message
+    }
+}
+
+impl Obfuscator for WireGuardMimic {
+    fn preamble(&self) -> Vec<Vec<u8>> {
+        vec![self.handshake(INITIATION, None, INITIATION_LEN)]
+    }
+
+    fn wrap_outgoing<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        let len = self.wrapped_len(frame.len());
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&[TRANSPORT, 0, 0, 0]);
+        message.extend_from_slice(&self.remote_index.load(Ordering::Relaxed).to_le_bytes());
+        message.extend_from_slice(&counter.to_le_bytes());
+        message.extend_from_slice(frame);
+        let padding = len - message.len();
+        let start = message.len();
+        message.resize(len - 1, 0);
+        self.rng.lock().fill(&mut message[start..]);
+        message.push(padding as u8 ^ counter as u8);
+        Cow::Owned(message)
+    }
+
+    /// At least one byte of padding, to say how much there is.
+    fn wrapped_len(&self, frame_len: usize) -> usize {
+        DATA_HEADER + (frame_len / BLOCK + 1) * BLOCK
+    }
+
+    /// Transport data carries frames. Handshake messages end here, the peer's index
+    /// noted and an initiation answered.
+    fn unwrap_incoming(&self, datagram: &mut [u8], _from: SocketAddr) -> Unwrapped {
+        if !looks_dressed(datagram) {
+            return Unwrapped::Foreign("not a WireGuard message (peer lacks --mimic wireguard?)");
+        }
+        let index = |at: usize| u32::from_le_bytes([datagram[at], datagram[at + 1], datagram[at + 2], datagram[at + 3]]);
+        match datagram[0] {
+            INITIATION => {
+                self.remote_index.store(index(4), Ordering::Relaxed);
+                Unwrapped::Decoy { reply: Some(self.handshake(RESPONSE, Some(index(4)), RESPONSE_LEN)) }
+            }
+            RESPONSE if index(8) == self.local_index => {
+                self.remote_index.store(index(4), Ordering::Relaxed);
+                Unwrapped::Decoy { reply: None }
+            }
+            RESPONSE => Unwrapped::Foreign("a WireGuard response to another initiation"),
+            _ => {
+                let padding = (datagram[datagram.len() - 1] ^ datagram[8]) as usize;
+                let Some(len) = (datagram.len() - DATA_HEADER).checked_sub(padding).filter(|_| (1..=BLOCK).contains(&padding)) else {
+                    return Unwrapped::Foreign("WireGuard transport data with bad padding");
+                };
+                datagram.copy_within(DATA_HEADER..DATA_HEADER + len, 0);
+                Unwrapped::Frame(len)
+            }
+        }
+    }
+
+    fn needs_datagrams(&self) -> bool {
+        true
+    }
+}
+
+/// Whether a datagram is shaped like a WireGuard message: a known type, reserved
+/// zeros, a non-zero index after them, and that type's length. Also how an end without
+/// `--mimic wireguard` tells a peer that has it, once the datagram failed to decode.
+///
+/// The index rules out bincode frames: their little-endian sequence number can start
+/// `[1|2|4, 0, 0, 0]`, but its upper half, where the index would be, is zero.
+pub fn looks_dressed(datagram: &[u8]) -> bool {
+    if datagram.len() < 8 || datagram[1..4] != [0, 0, 0] || datagram[4..8] == [0, 0, 0, 0] {
+        return false;
+    }
+    match datagram[0] {
+        INITIATION => datagram.len() == INITIATION_LEN,
+        RESPONSE => datagram.len() == RESPONSE_LEN,
+        TRANSPORT => datagram.len() >= DATA_HEADER + BLOCK && (datagram.len() - DATA_HEADER).is_multiple_of(BLOCK),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SessionGuard;
+    use crate::obfuscation;
+    use crate::protocol::{WireFormat, WireFrame};
+
+    fn mimic(seed: u64) -> WireGuardMimic {
+        WireGuardMimic::new(Arc::new(Mutex::new(obfuscation::traffic_rng(Some(seed)))))
+    }
+
+    fn from() -> SocketAddr {
+        "192.0.2.1:51820".parse().unwrap()
+    }
+
+    fn le32(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    /// Runs the decoy handshake between the two: `a` initiates, `b` answers.
+    fn handshake(a: &WireGuardMimic, b: &WireGuardMimic) {
+        let mut initiation = a.preamble().remove(0);
+        let Unwrapped::Decoy { reply: Some(mut response) } = b.unwrap_incoming(&mut initiation, from()) else {
+            panic!("initiation not answered");
+        };
+        assert!(matches!(a.unwrap_incoming(&mut response, from()), Unwrapped::Decoy { reply: None }));
+    }
+
+    #[test]
+    fn handshake_messages_have_wireguards_shape() {
+        let (a, b) = (mimic(1), mimic(2));
+        let initiation = a.preamble().remove(0);
+        assert_eq!((initiation.len(), &initiation[..4], le32(&initiation[4..8])), (INITIATION_LEN, &[INITIATION, 0, 0, 0][..], a.local_index));
+        assert!(looks_dressed(&initiation));
+
+        let Unwrapped::Decoy { reply: Some(mut response) } = b.unwrap_incoming(&mut initiation.clone(), from()) else {
+            panic!("initiation not answered");
+        };
+        // From the responder's index, to the initiator's.
+        assert_eq!((response.len(), &response[..4]), (RESPONSE_LEN, &[RESPONSE, 0, 0, 0][..]));
+        assert_eq!((le32(&response[4..8]), le32(&response[8..12])), (b.local_index, a.local_index));
+        assert!(looks_dressed(&response));
+
+        // A response to someone else's initiation is not ours to take.
+        assert!(matches!(mimic(3).unwrap_incoming(&mut response.clone(), from()), Unwrapped::Foreign(_)));
+        assert!(matches!(a.unwrap_incoming(&mut response, from()), Unwrapped::Decoy { reply: None }));
+        assert_eq!(a.remote_index.load(Ordering::Relaxed), b.local_index);
+    }
+
+    #[test]
+    fn frames_round_trip_padded_to_the_block() {
+        let (a, b) = (mimic(1), mimic(2));
+        handshake(&a, &b);
+        for (counter, len) in [0, 1, 15, 16, 17, 31, 100, 1400].into_iter().enumerate() {
+            let frame: Vec<u8> = (0..len).map(|i| (i * 13 + 0xC1) as u8).collect();
+            let mut message = a.wrap_outgoing(&frame).into_owned();
+            assert_eq!(message.len(), a.wrapped_len(len));
+            // One to sixteen bytes of padding, to a whole block.
+            assert!((message.len() - DATA_HEADER).is_multiple_of(BLOCK) && (1..=BLOCK).contains(&(message.len() - DATA_HEADER - len)), "{} bytes", len);
+            assert_eq!(&message[..4], [TRANSPORT, 0, 0, 0]);
+            assert_eq!((le32(&message[4..8]), u64::from_le_bytes(message[8..16].try_into().unwrap())), (b.local_index, counter as u64));
+            assert!(looks_dressed(&message));
+            match b.unwrap_incoming(&mut message, from()) {
+                Unwrapped::Frame(n) => assert_eq!(&message[..n], &frame[..], "{} bytes", len),
+                _ => panic!("{}-byte frame not unwrapped", len),
+            }
+        }
+        // A new handshake starts the counter over.
+        handshake(&a, &b);
+        assert_eq!(&a.wrap_outgoing(&[0; 40])[8..16], [0; 8]);
+    }
+
+    #[test]
+    fn bad_padding_and_undressed_datagrams_are_foreign() {
+        let (a, b) = (mimic(1), mimic(2));
+        let mut message = a.wrap_outgoing(&[0x45; 40]).into_owned();
+        let last = message.len() - 1;
+        message[last] ^= 0x40;
+        assert!(matches!(b.unwrap_incoming(&mut message, from()), Unwrapped::Foreign(_)));
+        let mut frame = vec![0xC1; 100];
+        assert!(matches!(b.unwrap_incoming(&mut frame, from()), Unwrapped::Foreign(_)));
+    }
+
+    #[test]
+    fn our_own_frames_never_look_dressed() {
+        let guard = SessionGuard::new(&[3; 32]);
+        let overhead = WireFormat::Bincode.encode(&WireFrame::new_data(1, 0, Vec::new()), &guard).unwrap().len();
+        // Bincode starts with the sequence number, `[1|2|4, 0, 0, 0]` for early frames,
+        // at every length WireGuard has.
+        for seq in [1, 2, 4] {
+            for len in [INITIATION_LEN, RESPONSE_LEN, 32, 48, 160, 1424] {
+                let frame = WireFormat::Bincode.encode(&WireFrame::new_data(seq, 0, vec![0; len - overhead]), &guard).unwrap();
+                assert_eq!(frame.len(), len);
+                assert!(!looks_dressed(&frame), "bincode seq {} of {} bytes", seq, len);
+            }
+        }
+        for len in 0..200 {
+            for format in [WireFormat::Compact, WireFormat::Sealed] {
+                let frame = format.encode(&WireFrame::new_data(1, 0, vec![0; len]), &guard).unwrap();
+                assert!(!looks_dressed(&frame), "{:?} of {} bytes", format, frame.len());
+            }
+        }
+    }
+}